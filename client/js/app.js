@@ -22,7 +22,7 @@
       total : 0.0,
     };
 
-    fetch("http://localhost:8002/compute", {
+    fetch("http://localhost:8002/v1/compute", {
       method: "POST",
       body: JSON.stringify(data),
       headers: { "Content-type": "application/json" },
@@ -37,7 +37,7 @@
       alert("The order total for " + json.order_id + " has been updated to " + json.total);
       totalField.value = json.total;
     } else {
-      alert(json.message);
+      alert(json.error.message);
       totalField.value = 'Update upon Compute Order';
     }
   }
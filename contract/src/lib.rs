@@ -0,0 +1,325 @@
+//! Consumer-driven contracts between the services, in the manner of Pact. A
+//! consumer records the requests it sends a provider, and what it relies on
+//! in the responses, as a `Contract` in a JSON file. The consumer's tests
+//! answer its requests with these responses and check the requests; the
+//! provider's tests replay the requests and check the responses. Neither side
+//! can then change the wire contract without the other's tests failing.
+//!
+//! Requests match when they have the method and path, the headers listed and
+//! the JSON body. Responses match when they have the status, the headers
+//! listed and the body fields listed, with the same values, or only the same
+//! JSON type where a matching rule says so. Other headers and fields are
+//! ignored.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// What `consumer` expects of `provider`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Contract {
+    pub consumer: String,
+    pub provider: String,
+    pub interactions: Vec<Interaction>,
+}
+
+/// A request the consumer sends, and the response it expects.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub description: String,
+    /// What the provider's data must hold for this response, e.g. `78701 has
+    /// a rate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_state: Option<String>,
+    pub request: Request,
+    pub response: Response,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Headers the request carries, among others, by lowercase name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    /// Headers the response carries, among others, by lowercase name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    /// How body fields are matched, by JSON path, e.g. `$.error.message`. A
+    /// rule applies to everything within the field too; fields without one
+    /// are matched by value.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub matching_rules: BTreeMap<String, Matcher>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Matcher {
+    /// The same value.
+    Equality,
+    /// A value of the same JSON type. Arrays may not be empty, and each of
+    /// their elements matches the first expected one.
+    Type,
+}
+
+impl Contract {
+    pub fn parse(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+impl Request {
+    /// How a request sent with `method`, `path`, the headers `header` looks
+    /// up by lowercase name, and `body` differs from this one; empty when it
+    /// matches.
+    pub fn mismatches(
+        &self,
+        method: &str,
+        path: &str,
+        header: impl Fn(&str) -> Option<String>,
+        body: &[u8],
+    ) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if !method.eq_ignore_ascii_case(&self.method) {
+            mismatches.push(format!("method {} instead of {}", method, self.method));
+        }
+        if path != self.path {
+            mismatches.push(format!("path {} instead of {}", path, self.path));
+        }
+        mismatches.extend(header_mismatches(&self.headers, header));
+        if let Some(expected) = &self.body {
+            match serde_json::from_slice::<Value>(body) {
+                Ok(actual) if actual == *expected => {}
+                Ok(actual) => mismatches.push(format!("body {} instead of {}", actual, expected)),
+                Err(_) => mismatches.push(format!(
+                    "body {:?} instead of {}",
+                    String::from_utf8_lossy(body),
+                    expected
+                )),
+            }
+        }
+        mismatches
+    }
+}
+
+impl Response {
+    /// How a response with `status`, the headers `header` looks up by
+    /// lowercase name, and `body` differs from this one; empty when it
+    /// matches.
+    pub fn mismatches(
+        &self,
+        status: u16,
+        header: impl Fn(&str) -> Option<String>,
+        body: &[u8],
+    ) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if status != self.status {
+            mismatches.push(format!("status {} instead of {}", status, self.status));
+        }
+        mismatches.extend(header_mismatches(&self.headers, header));
+        if let Some(expected) = &self.body {
+            match serde_json::from_slice::<Value>(body) {
+                Ok(actual) => {
+                    self.match_value("$", expected, &actual, Matcher::Equality, &mut mismatches)
+                }
+                Err(_) => mismatches.push(format!(
+                    "body {:?} isn't JSON",
+                    String::from_utf8_lossy(body)
+                )),
+            }
+        }
+        mismatches
+    }
+
+    fn match_value(
+        &self,
+        path: &str,
+        expected: &Value,
+        actual: &Value,
+        matcher: Matcher,
+        mismatches: &mut Vec<String>,
+    ) {
+        let matcher = self.matching_rules.get(path).copied().unwrap_or(matcher);
+        match (expected, actual) {
+            (Value::Object(expected), Value::Object(actual)) => {
+                for (key, expected) in expected {
+                    let path = format!("{}.{}", path, key);
+                    match actual.get(key) {
+                        Some(actual) => {
+                            self.match_value(&path, expected, actual, matcher, mismatches)
+                        }
+                        None => mismatches.push(format!("{} is missing", path)),
+                    }
+                }
+            }
+            (Value::Array(expected), Value::Array(actual)) => match (matcher, expected.first()) {
+                (Matcher::Equality, _) if expected.len() != actual.len() => {
+                    mismatches.push(format!(
+                        "{} has {} elements instead of {}",
+                        path,
+                        actual.len(),
+                        expected.len()
+                    ))
+                }
+                (Matcher::Equality, _) => {
+                    for (index, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                        let path = format!("{}[{}]", path, index);
+                        self.match_value(&path, expected, actual, matcher, mismatches);
+                    }
+                }
+                (Matcher::Type, Some(_)) if actual.is_empty() => {
+                    mismatches.push(format!("{} is empty", path))
+                }
+                (Matcher::Type, Some(expected)) => {
+                    for (index, actual) in actual.iter().enumerate() {
+                        let path = format!("{}[{}]", path, index);
+                        self.match_value(&path, expected, actual, matcher, mismatches);
+                    }
+                }
+                (Matcher::Type, None) => {}
+            },
+            (expected, actual) => match matcher {
+                Matcher::Equality if expected != actual => {
+                    mismatches.push(format!("{} is {} instead of {}", path, actual, expected))
+                }
+                Matcher::Type if type_name(expected) != type_name(actual) => {
+                    mismatches.push(format!(
+                        "{} is {} instead of a {}",
+                        path,
+                        actual,
+                        type_name(expected)
+                    ))
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+fn header_mismatches(
+    expected: &BTreeMap<String, String>,
+    header: impl Fn(&str) -> Option<String>,
+) -> Vec<String> {
+    expected
+        .iter()
+        .filter_map(|(name, value)| match header(name) {
+            Some(actual) if actual == *value => None,
+            Some(actual) => Some(format!(
+                "header {} is {:?} instead of {:?}",
+                name, actual, value
+            )),
+            None => Some(format!("header {} is missing", name)),
+        })
+        .collect()
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rate_found() -> Response {
+        serde_json::from_value(json!({
+            "status": 200,
+            "body": {
+                "zip": "78701",
+                "rate": 0.0825,
+                "components": [{ "level": "state", "rate": 0.0625 }]
+            },
+            "matching_rules": { "$.rate": "type", "$.components": "type" }
+        }))
+        .unwrap()
+    }
+
+    fn no_headers(_: &str) -> Option<String> {
+        None
+    }
+
+    fn mismatches(response: &Response, body: Value) -> Vec<String> {
+        let mut mismatches = response.mismatches(200, no_headers, body.to_string().as_bytes());
+        mismatches.sort();
+        mismatches
+    }
+
+    #[test]
+    fn fields_match_by_value_unless_a_rule_says_type() {
+        let body = json!({
+            "zip": "78701",
+            "rate": 0.07,
+            "components": [{ "level": "city", "rate": 0.01 }, { "level": "state", "rate": 0.06 }]
+        });
+        assert!(mismatches(&rate_found(), body).is_empty());
+
+        let body = json!({ "zip": "10001", "rate": "0.07", "components": [] });
+        assert_eq!(
+            mismatches(&rate_found(), body),
+            [
+                "$.components is empty",
+                "$.rate is \"0.07\" instead of a number",
+                "$.zip is \"10001\" instead of \"78701\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn fields_missing_from_the_contract_are_ignored() {
+        let body = json!({
+            "zip": "78701",
+            "rate": 0.0825,
+            "jurisdiction": "Austin, TX",
+            "components": [{ "level": "state", "name": "Texas", "rate": 0.0625 }]
+        });
+        assert!(mismatches(&rate_found(), body).is_empty());
+
+        let body = json!({ "zip": "78701", "components": [{ "rate": 0.0625 }] });
+        assert_eq!(
+            mismatches(&rate_found(), body),
+            ["$.components[0].level is missing", "$.rate is missing"]
+        );
+    }
+
+    #[test]
+    fn requests_match_exactly() {
+        let request: Request = serde_json::from_value(json!({
+            "method": "POST",
+            "path": "/find_rate",
+            "headers": { "content-type": "application/json" },
+            "body": { "zip": "78701" }
+        }))
+        .unwrap();
+        let json_header =
+            |name: &str| (name == "content-type").then(|| "application/json".to_string());
+        let body = br#"{"zip":"78701"}"#;
+        assert!(request
+            .mismatches("POST", "/find_rate", json_header, body)
+            .is_empty());
+        assert_eq!(
+            request.mismatches("POST", "/get_rate", no_headers, b"78701"),
+            [
+                "path /get_rate instead of /find_rate",
+                "header content-type is missing",
+                "body 78701 instead of {\"zip\":\"78701\"}",
+            ]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! ISO 4217 currency codes and their minor units.
+//!
+//! Only currencies that have a minor unit are listed; funds codes such as
+//! precious metals (`XAU`) or testing codes (`XTS`) can't price an order.
+
+use crate::money::{Decimal, Rounding};
+
+/// Currencies without a minor unit, e.g. the yen.
+const NO_MINOR_UNIT: &[&str] = &[
+    "BIF", "CLP", "DJF", "GNF", "ISK", "JPY", "KMF", "KRW", "PYG", "RWF", "UGX", "UYI", "VND",
+    "VUV", "XAF", "XOF", "XPF",
+];
+
+/// Currencies with a minor unit of a thousandth, e.g. the Kuwaiti dinar.
+const THOUSANDTHS: &[&str] = &["BHD", "IQD", "JOD", "KWD", "LYD", "OMR", "TND"];
+
+/// Units of account with four decimals.
+const TEN_THOUSANDTHS: &[&str] = &["CLF", "UYW"];
+
+/// Currencies with cents.
+const CENTS: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BMD", "BND", "BOB", "BOV", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD", "CDF",
+    "CHE", "CHF", "CHW", "CNY", "COP", "COU", "CRC", "CUC", "CUP", "CVE", "CZK", "DKK", "DOP",
+    "DZD", "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GTQ",
+    "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS", "INR", "IRR", "JMD", "KES", "KGS", "KHR",
+    "KPW", "KYD", "KZT", "LAK", "LBP", "LKR", "LRD", "LSL", "MAD", "MDL", "MGA", "MKD", "MMK",
+    "MNT", "MOP", "MRU", "MUR", "MVR", "MWK", "MXN", "MXV", "MYR", "MZN", "NAD", "NGN", "NIO",
+    "NOK", "NPR", "NZD", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "QAR", "RON", "RSD", "RUB",
+    "SAR", "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SLL", "SOS", "SRD", "SSP", "STN",
+    "SVC", "SYP", "SZL", "THB", "TJS", "TMT", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "USD",
+    "USN", "UYU", "UZS", "VED", "VES", "WST", "XCD", "XCG", "YER", "ZAR", "ZMW", "ZWG", "ZWL",
+];
+
+/// The number of decimals of the currency's minor unit, or `None` when `code`
+/// isn't an ISO 4217 code of a currency with one. Codes are upper case.
+pub fn minor_units(code: &str) -> Option<u32> {
+    [
+        (NO_MINOR_UNIT, 0),
+        (CENTS, 2),
+        (THOUSANDTHS, 3),
+        (TEN_THOUSANDTHS, 4),
+    ]
+    .iter()
+    .find(|(codes, _)| codes.contains(&code))
+    .map(|(_, units)| *units)
+}
+
+/// Whether `code` is an ISO 4217 currency code, e.g. `USD`.
+pub fn is_valid(code: &str) -> bool {
+    minor_units(code).is_some()
+}
+
+/// Rounds `amount` to the minor unit of `code` by `rounding`; to cents for
+/// unknown codes.
+pub fn round(amount: Decimal, code: &str, rounding: Rounding) -> Decimal {
+    rounding.round(amount, minor_units(code).unwrap_or(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn knows_iso_4217_codes() {
+        assert!(is_valid("USD"));
+        assert!(is_valid("EUR"));
+        assert!(is_valid("JPY"));
+        assert!(!is_valid("usd"));
+        assert!(!is_valid("XXX"));
+        assert!(!is_valid("EURO"));
+    }
+
+    #[test]
+    fn rounds_to_the_minor_unit() {
+        let half_even = Rounding::HalfEven;
+        assert_eq!(round(dec("10.825"), "USD", half_even), dec("10.82"));
+        assert_eq!(round(dec("1082.5"), "JPY", half_even), dec("1082"));
+        assert_eq!(round(dec("1.08250"), "KWD", half_even), dec("1.082"));
+        assert_eq!(round(dec("1.08255"), "CLF", half_even), dec("1.0826"));
+        assert_eq!(round(dec("1082.5"), "JPY", Rounding::HalfUp), dec("1083"));
+    }
+
+    #[test]
+    fn every_code_is_listed_once() {
+        let mut codes: Vec<&str> = [NO_MINOR_UNIT, CENTS, THOUSANDTHS, TEN_THOUSANDTHS].concat();
+        let listed = codes.len();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), listed);
+        assert!(codes.iter().all(|code| code.len() == 3));
+    }
+}
@@ -0,0 +1,20 @@
+//! Request and response schemas shared by the `order_total` and
+//! `sales_tax_rate` services, so both always agree on what goes over the wire.
+//!
+//! Schemas live in a module per major version. Adding an optional field is a
+//! minor bump of `SCHEMA_VERSION`; renaming, removing or retyping a field
+//! needs a new major version module next to the old one.
+
+pub mod currency;
+pub mod money;
+pub mod v1;
+
+pub use money::{Decimal, Rounding};
+pub use v1::{
+    Conversion, ErrorEnvelope, ErrorResponse, JurisdictionLevel, LineItem, NormalizedAddress,
+    Order, OrderCancelled, OrderPriced, RateComponent, RateQuote, RateRequest, RateResponse,
+    RateSource, TaxComponent,
+};
+
+/// Semver version of the schemas re-exported at the crate root.
+pub const SCHEMA_VERSION: &str = "1.18.0";
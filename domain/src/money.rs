@@ -0,0 +1,151 @@
+//! Money amounts and tax rates as exact decimals.
+//!
+//! Amounts are rounded to cents with banker's rounding (half to even), so
+//! 10.825 becomes 10.82 and 10.835 becomes 10.84, unless an order names
+//! another `Rounding`. On the wire they are JSON numbers, as before; numeric
+//! strings such as `"19.99"` are accepted too.
+
+use rust_decimal::RoundingStrategy;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use utoipa::ToSchema;
+
+pub use rust_decimal::Decimal;
+
+/// How amounts are rounded to cents, or to the minor unit of their currency;
+/// jurisdictions differ.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// Half to even: 10.825 becomes 10.82 and 10.835 becomes 10.84.
+    #[default]
+    HalfEven,
+    /// Half away from zero: 10.825 becomes 10.83.
+    HalfUp,
+    /// Fractions of a cent are dropped: 10.829 becomes 10.82.
+    Truncate,
+}
+
+impl Rounding {
+    pub const ALL: [Rounding; 3] = [Rounding::HalfEven, Rounding::HalfUp, Rounding::Truncate];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rounding::HalfEven => "half_even",
+            Rounding::HalfUp => "half_up",
+            Rounding::Truncate => "truncate",
+        }
+    }
+
+    /// Rounds `amount` to `decimals` decimals.
+    pub fn round(self, amount: Decimal, decimals: u32) -> Decimal {
+        let strategy = match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        };
+        amount.round_dp_with_strategy(decimals, strategy)
+    }
+
+    /// Rounds `amount` to whole cents.
+    pub fn round_cents(self, amount: Decimal) -> Decimal {
+        self.round(amount, 2)
+    }
+}
+
+/// Rounds `amount` to whole cents, half to even.
+pub fn round_cents(amount: Decimal) -> Decimal {
+    Rounding::HalfEven.round_cents(amount)
+}
+
+/// Serde helpers for `#[serde(with = "domain::money::json_number")]`.
+pub mod json_number {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+        // Parsing the decimal text rounds to the nearest f64, whose shortest
+        // representation prints exactly as those decimals, e.g. 21.65, for up
+        // to 15 significant digits.
+        let text = value.to_string();
+        match text.parse::<f64>() {
+            Ok(number) => serializer.serialize_f64(number),
+            Err(_) => serializer.serialize_str(&text),
+        }
+    }
+
+    /// Accepts JSON numbers and numeric strings. Numbers are read from their
+    /// decimal text, so 19.99 stays exactly 19.99.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+        <Decimal as Deserialize>::deserialize(deserializer)
+    }
+}
+
+/// Like `json_number`, for optional amounts.
+pub mod json_number_opt {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Decimal>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => json_number::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Decimal>, D::Error> {
+        <Option<Decimal> as Deserialize>::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn rounds_half_to_even() {
+        assert_eq!(round_cents(dec("10.825")), dec("10.82"));
+        assert_eq!(round_cents(dec("10.835")), dec("10.84"));
+        assert_eq!(round_cents(dec("10.8251")), dec("10.83"));
+    }
+
+    #[test]
+    fn rounds_by_the_strategy() {
+        let round = |rounding: Rounding, amount| rounding.round_cents(dec(amount));
+        assert_eq!(round(Rounding::HalfEven, "10.825"), dec("10.82"));
+        assert_eq!(round(Rounding::HalfUp, "10.825"), dec("10.83"));
+        assert_eq!(round(Rounding::HalfUp, "10.8249"), dec("10.82"));
+        assert_eq!(round(Rounding::Truncate, "10.829"), dec("10.82"));
+        assert_eq!(round(Rounding::Truncate, "-10.829"), dec("-10.82"));
+        for rounding in Rounding::ALL {
+            let json = serde_json::to_string(&rounding).unwrap();
+            assert_eq!(json, format!("\"{}\"", rounding.as_str()));
+        }
+    }
+
+    #[test]
+    fn has_no_float_rounding_errors() {
+        // 19.99 * 1.0825 = 21.639175; in f32 this comes out as 21.639174.
+        assert_eq!(dec("19.99") * dec("1.0825"), dec("21.639175"));
+        assert_eq!(round_cents(dec("19.99") * dec("1.0825")), dec("21.64"));
+    }
+
+    #[test]
+    fn amounts_print_as_their_decimals() {
+        #[derive(serde::Serialize)]
+        struct Amount(#[serde(with = "json_number")] Decimal);
+
+        // 1 + 0.14 in f64 is one ulp off 1.14.
+        for amount in ["1.14", "21.65", "9999999999999.99"] {
+            let json = serde_json::to_string(&Amount(dec(amount))).unwrap();
+            assert_eq!(json, amount);
+        }
+    }
+}
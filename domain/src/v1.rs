@@ -0,0 +1,1003 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::currency;
+use crate::money::{self, Decimal, Rounding};
+
+/// An order to price. `subtotal`, `discount`, `shipping`, `tax` and `total`
+/// are filled in by the order_total service.
+///
+/// Orders list their products in `line_items`. Single-product orders of schema
+/// 1.1 and before give `product_id`, `quantity` and `subtotal` instead, and are
+/// priced as one taxable line item.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Order {
+    pub order_id: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<i32>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "money::json_number_opt"
+    )]
+    #[schema(value_type = Option<f64>)]
+    pub subtotal: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub line_items: Vec<LineItem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promo_code: Option<String>,
+    /// Taken off the subtotal before tax.
+    #[serde(
+        default,
+        skip_serializing_if = "Decimal::is_zero",
+        with = "money::json_number"
+    )]
+    #[schema(value_type = f64)]
+    pub discount: Decimal,
+    pub shipping_address: String,
+    /// 5-digit or ZIP+4; may be left out when the order_total service
+    /// validates addresses, which derives it from `shipping_address`.
+    #[serde(default)]
+    pub shipping_zip: String,
+    #[serde(default, with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub shipping: Decimal,
+    /// Whether the destination state taxes the shipping charge.
+    #[serde(default)]
+    pub shipping_taxable: bool,
+    #[serde(default, with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub tax: Decimal,
+    #[serde(default, with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub total: Decimal,
+    /// Set when the sales tax rate didn't come from the sales tax rate service.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_source: Option<RateSource>,
+    /// ISO 4217 code of the order's amounts, e.g. `EUR`; the order_total
+    /// service's base currency when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// The totals in the currency the order was sent in, when it was priced
+    /// in another one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub converted: Option<Conversion>,
+    /// The buyer's tax exemption certificate number, checked against the
+    /// order_total service's exemption registry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tax_exempt_id: Option<String>,
+    /// The exemption registry's reference for `tax_exempt_id`, set when the
+    /// order was priced without tax.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exemption_reference: Option<String>,
+    /// `tax` split by the jurisdictions levying it, when the rate provider
+    /// breaks its rates down. The components add up to `tax`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tax_breakdown: Vec<TaxComponent>,
+    /// `shipping_address` as the order_total service's address validator
+    /// normalized it, when it validates addresses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalized_address: Option<NormalizedAddress>,
+    /// The inventory service's reservation of the line items, when the
+    /// order_total service reserves stock for the orders it prices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reservation_id: Option<String>,
+    /// The saga that priced the order, when the order_total service prices
+    /// orders by a saga.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saga_id: Option<String>,
+    /// How the order's amounts were rounded to cents, set by the order_total
+    /// service; half to even when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rounding: Option<Rounding>,
+}
+
+/// A shipping address in its standard form.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct NormalizedAddress {
+    /// e.g. `123 MAIN ST, ANYTOWN, TX 78701`.
+    pub address: String,
+    /// The zip code of the address.
+    pub zip: String,
+    /// Whether `shipping_zip` was derived from the address, the order having
+    /// none.
+    #[serde(default)]
+    pub zip_derived: bool,
+}
+
+/// The totals of an order in the currency it was sent in. Each amount is
+/// converted back from the priced order at `exchange_rate` and rounded to the
+/// currency's minor unit as the order's amounts are.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Conversion {
+    /// ISO 4217 code, e.g. `EUR`.
+    pub currency: String,
+    /// Units of the priced order's currency per unit of `currency`.
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub exchange_rate: Decimal,
+    #[serde(default, with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub subtotal: Decimal,
+    #[serde(default, with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub discount: Decimal,
+    #[serde(default, with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub shipping: Decimal,
+    #[serde(default, with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub tax: Decimal,
+    #[serde(default, with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub total: Decimal,
+}
+
+impl Conversion {
+    /// `amount` of the priced order in `currency`.
+    fn convert_back(&self, amount: Decimal, rounding: Rounding) -> Decimal {
+        currency::round(amount / self.exchange_rate, &self.currency, rounding)
+    }
+}
+
+/// Where the sales tax rate of a priced order came from, when it wasn't the
+/// sales tax rate service.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RateSource {
+    /// The order_total service's fallback rate table, used while the sales
+    /// tax rate service is unavailable.
+    Fallback,
+    /// The order is tax exempt, so no rate was looked up and no tax charged.
+    Exempt,
+    /// A rate override configured by an operator for the shipping zip code,
+    /// which takes precedence over the sales tax rate service.
+    Override,
+}
+
+impl RateSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateSource::Fallback => "fallback",
+            RateSource::Exempt => "exempt",
+            RateSource::Override => "override",
+        }
+    }
+}
+
+/// A level of government levying part of a sales tax rate.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JurisdictionLevel {
+    State,
+    County,
+    City,
+    /// A transit, hospital or other special purpose district.
+    SpecialDistrict,
+}
+
+impl JurisdictionLevel {
+    pub const ALL: [JurisdictionLevel; 4] = [
+        JurisdictionLevel::State,
+        JurisdictionLevel::County,
+        JurisdictionLevel::City,
+        JurisdictionLevel::SpecialDistrict,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JurisdictionLevel::State => "state",
+            JurisdictionLevel::County => "county",
+            JurisdictionLevel::City => "city",
+            JurisdictionLevel::SpecialDistrict => "special_district",
+        }
+    }
+}
+
+/// The part of a sales tax rate levied by one jurisdiction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RateComponent {
+    pub level: JurisdictionLevel,
+    /// e.g. `Texas` or `Capital Metro`.
+    pub name: String,
+    #[serde(with = "money::json_number")]
+    pub rate: Decimal,
+}
+
+/// The part of an order's tax levied by one jurisdiction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct TaxComponent {
+    pub level: JurisdictionLevel,
+    pub name: String,
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub rate: Decimal,
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub tax: Decimal,
+}
+
+/// `quantity` units of one product. `tax` is filled in by the order_total service.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct LineItem {
+    pub product_id: i32,
+    pub quantity: i32,
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub unit_price: Decimal,
+    #[serde(default = "taxable_by_default")]
+    pub taxable: bool,
+    /// Shipping weight of one unit, in pounds.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "money::json_number_opt"
+    )]
+    #[schema(value_type = Option<f64>)]
+    pub unit_weight: Option<Decimal>,
+    /// This line's share of the order discount.
+    #[serde(
+        default,
+        skip_serializing_if = "Decimal::is_zero",
+        with = "money::json_number"
+    )]
+    #[schema(value_type = f64)]
+    pub discount: Decimal,
+    #[serde(default, with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub tax: Decimal,
+}
+
+/// Decimals of line amounts that count before rounding them to cents.
+const AMOUNT_DECIMALS: u32 = 12;
+
+fn taxable_by_default() -> bool {
+    true
+}
+
+impl LineItem {
+    /// The price of all units, rounded to cents by `rounding`.
+    pub fn amount(&self, rounding: Rounding) -> Decimal {
+        // A unit price divided off a subtotal ends in the remainder of the
+        // division, e.g. 9.99999... for 3 units of 10.00 / 3, which mustn't
+        // be truncated to 9.99.
+        let amount = self.unit_price * Decimal::from(self.quantity);
+        rounding.round_cents(amount.round_dp(AMOUNT_DECIMALS))
+    }
+}
+
+impl Order {
+    /// How the order's amounts are rounded to cents.
+    pub fn rounding(&self) -> Rounding {
+        self.rounding.unwrap_or_default()
+    }
+
+    /// Turns a single-product order into one line item. Does nothing for orders
+    /// that already list line items, or lack the single-product fields.
+    pub fn normalize(&mut self) {
+        if !self.line_items.is_empty() {
+            return;
+        }
+        if let (Some(product_id), Some(quantity), Some(subtotal)) =
+            (self.product_id, self.quantity, self.subtotal)
+        {
+            // The unit price may have more decimals than cents; amount() rounds
+            // the line back to exactly the subtotal.
+            let unit_price = if quantity > 0 {
+                subtotal / Decimal::from(quantity)
+            } else {
+                subtotal
+            };
+            self.line_items.push(LineItem {
+                product_id,
+                quantity,
+                unit_price,
+                taxable: true,
+                unit_weight: None,
+                discount: Decimal::ZERO,
+                tax: Decimal::ZERO,
+            });
+        }
+    }
+
+    /// The sum of all line items, before discount.
+    pub fn items_total(&self) -> Decimal {
+        let rounding = self.rounding();
+        self.line_items
+            .iter()
+            .map(|item| item.amount(rounding))
+            .sum()
+    }
+
+    /// Takes `discount` off the order, at most its whole value, spreading it
+    /// over the line items in proportion to their amounts so each is taxed on
+    /// what is actually paid for it.
+    pub fn apply_discount(&mut self, discount: Decimal) {
+        self.normalize();
+        let rounding = self.rounding();
+        let items_total = self.items_total();
+        let discount = rounding.round_cents(discount.min(items_total).max(Decimal::ZERO));
+        self.discount = discount;
+        for item in &mut self.line_items {
+            item.discount = Decimal::ZERO;
+        }
+        if discount.is_zero() {
+            return;
+        }
+        // The largest line takes what rounding the other shares leaves over.
+        let largest = (0..self.line_items.len())
+            .max_by_key(|&index| self.line_items[index].amount(rounding))
+            .unwrap_or_default();
+        let mut allocated = Decimal::ZERO;
+        for (index, item) in self.line_items.iter_mut().enumerate() {
+            if index != largest {
+                item.discount =
+                    rounding.round_cents(discount * item.amount(rounding) / items_total);
+                allocated += item.discount;
+            }
+        }
+        self.line_items[largest].discount = discount - allocated;
+    }
+
+    /// Shipping weight of all line items, in pounds; units without a weight
+    /// count as weightless.
+    pub fn weight(&self) -> Decimal {
+        self.line_items
+            .iter()
+            .filter_map(|item| Some(item.unit_weight? * Decimal::from(item.quantity)))
+            .sum()
+    }
+
+    /// Taxes every taxable line item, net of its discount, and the shipping
+    /// charge if taxable, at the given rate and sums everything into the
+    /// subtotal, tax and total. Each tax amount is rounded to cents by the
+    /// order's rounding, like the line amounts, so the totals add up.
+    pub fn apply_rate(&mut self, rate: Decimal) {
+        self.normalize();
+        let rounding = self.rounding();
+        let mut subtotal = Decimal::ZERO;
+        let mut tax = Decimal::ZERO;
+        for item in &mut self.line_items {
+            let amount = item.amount(rounding);
+            item.tax = if item.taxable {
+                rounding.round_cents((amount - item.discount) * rate)
+            } else {
+                Decimal::ZERO
+            };
+            subtotal += amount;
+            tax += item.tax;
+        }
+        if self.shipping_taxable {
+            tax += rounding.round_cents(self.shipping * rate);
+        }
+        self.subtotal = Some(subtotal);
+        self.tax = tax;
+        self.total = subtotal - self.discount + self.shipping + tax;
+        if let Some(mut conversion) = self.converted.take() {
+            conversion.subtotal = conversion.convert_back(subtotal, rounding);
+            conversion.discount = conversion.convert_back(self.discount, rounding);
+            conversion.shipping = conversion.convert_back(self.shipping, rounding);
+            conversion.tax = conversion.convert_back(tax, rounding);
+            conversion.total = conversion.convert_back(self.total, rounding);
+            self.converted = Some(conversion);
+        }
+    }
+
+    /// Splits `tax` over the jurisdictions levying the rate it was taxed at, in
+    /// proportion to their rates. The component with the largest rate takes
+    /// what rounding the others leave over, so the breakdown adds up to `tax`
+    /// exactly. Without components the breakdown is cleared.
+    pub fn apply_breakdown(&mut self, components: &[RateComponent]) {
+        self.tax_breakdown.clear();
+        let rate: Decimal = components.iter().map(|component| component.rate).sum();
+        if rate.is_zero() {
+            return;
+        }
+        let rounding = self.rounding();
+        let largest = (0..components.len())
+            .max_by_key(|&index| components[index].rate)
+            .unwrap_or_default();
+        let mut allocated = Decimal::ZERO;
+        for (index, component) in components.iter().enumerate() {
+            let tax = if index == largest {
+                Decimal::ZERO
+            } else {
+                rounding.round_cents(self.tax * component.rate / rate)
+            };
+            allocated += tax;
+            self.tax_breakdown.push(TaxComponent {
+                level: component.level,
+                name: component.name.clone(),
+                rate: component.rate,
+                tax,
+            });
+        }
+        self.tax_breakdown[largest].tax = self.tax - allocated;
+    }
+
+    /// Converts the prices of an order sent in another currency into `to`, at
+    /// `exchange_rate` units of `to` per unit of the order's currency, before
+    /// shipping, discounts and tax are added. Unit prices keep every decimal;
+    /// line amounts are rounded to cents as usual. `apply_rate` then reports
+    /// the totals in the original currency too.
+    pub fn convert(&mut self, to: &str, exchange_rate: Decimal) {
+        self.normalize();
+        for item in &mut self.line_items {
+            item.unit_price *= exchange_rate;
+        }
+        let rounding = self.rounding();
+        self.subtotal = self
+            .subtotal
+            .map(|subtotal| currency::round(subtotal * exchange_rate, to, rounding));
+        let from = self.currency.replace(to.to_string()).unwrap_or_default();
+        self.converted = Some(Conversion {
+            currency: from,
+            exchange_rate,
+            subtotal: Decimal::ZERO,
+            discount: Decimal::ZERO,
+            shipping: Decimal::ZERO,
+            tax: Decimal::ZERO,
+            total: Decimal::ZERO,
+        });
+    }
+}
+
+/// The sales tax rate service's answer for a zip code in the plain-text
+/// protocol, where the request body is the bare zip code. On the wire it's the
+/// bare rate, e.g. `0.0825`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(transparent)]
+pub struct RateResponse {
+    #[serde(with = "money::json_number")]
+    pub rate: Decimal,
+}
+
+/// A JSON rate lookup, sent as `application/json` to the sales tax rate
+/// service.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RateRequest {
+    pub zip: String,
+    /// `YYYY-MM-DD` day the rate was in effect on, for pricing past orders;
+    /// today's rate when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<String>,
+}
+
+/// The sales tax rate service's JSON answer to a `RateRequest`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RateQuote {
+    /// The zip code as requested.
+    pub zip: String,
+    #[serde(with = "money::json_number")]
+    pub rate: Decimal,
+    /// The taxing jurisdiction the rate belongs to, e.g. `Austin, TX`.
+    pub jurisdiction: String,
+    /// The rate split by the jurisdictions levying it, when known; their
+    /// rates add up to `rate`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<RateComponent>,
+}
+
+/// Published by the order_total service each time it prices an order, for
+/// downstream services such as fulfillment or analytics.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct OrderPriced {
+    pub order: Order,
+    /// The sales tax rate the order was priced at.
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub rate: Decimal,
+    /// RFC 3339 time of pricing.
+    pub priced_at: String,
+}
+
+/// Published by the order_total service when an order is cancelled. The
+/// order stays stored, with its `cancelled` status.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct OrderCancelled {
+    pub order: Order,
+    /// Why the order was cancelled, as given by the caller.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// RFC 3339 time of the cancellation.
+    pub cancelled_at: String,
+}
+
+/// The JSON body of every error response: `{"error": {...}}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorEnvelope,
+}
+
+/// An error, as found in error responses, batch results and queue replies.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ErrorEnvelope {
+    /// Machine-readable, e.g. `RATE_NOT_FOUND`.
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<Value>,
+    /// The `X-Request-Id` of the failed request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn order() -> Order {
+        Order {
+            order_id: 3,
+            product_id: None,
+            quantity: None,
+            subtotal: None,
+            line_items: vec![
+                LineItem {
+                    product_id: 5,
+                    quantity: 2,
+                    unit_price: dec("10.00"),
+                    taxable: true,
+                    unit_weight: Some(dec("1.5")),
+                    discount: dec("0"),
+                    tax: dec("0"),
+                },
+                LineItem {
+                    product_id: 6,
+                    quantity: 1,
+                    unit_price: dec("4.99"),
+                    taxable: false,
+                    unit_weight: None,
+                    discount: dec("0"),
+                    tax: dec("0"),
+                },
+            ],
+            promo_code: None,
+            discount: dec("0"),
+            shipping_address: "1 Congress Ave".into(),
+            shipping_zip: "78701".into(),
+            shipping: dec("0"),
+            shipping_taxable: false,
+            tax: dec("0"),
+            total: dec("0"),
+            rate_source: None,
+            currency: None,
+            converted: None,
+            tax_exempt_id: None,
+            exemption_reference: None,
+            tax_breakdown: Vec::new(),
+            normalized_address: None,
+            reservation_id: None,
+            saga_id: None,
+            rounding: None,
+        }
+    }
+
+    fn austin() -> Vec<RateComponent> {
+        let component = |level, name: &str, rate| RateComponent {
+            level,
+            name: name.into(),
+            rate: dec(rate),
+        };
+        vec![
+            component(JurisdictionLevel::State, "Texas", "0.0625"),
+            component(JurisdictionLevel::City, "Austin", "0.01"),
+            component(JurisdictionLevel::SpecialDistrict, "Capital Metro", "0.01"),
+        ]
+    }
+
+    fn single_product_order() -> Order {
+        Order {
+            product_id: Some(5),
+            quantity: Some(2),
+            subtotal: Some(dec("20.00")),
+            line_items: Vec::new(),
+            ..order()
+        }
+    }
+
+    #[test]
+    fn order_round_trips() {
+        for order in [order(), single_product_order()] {
+            let json = serde_json::to_string(&order).unwrap();
+            assert_eq!(serde_json::from_str::<Order>(&json).unwrap(), order);
+        }
+    }
+
+    #[test]
+    fn only_fallback_rates_are_marked() {
+        let json = serde_json::to_value(order()).unwrap();
+        assert!(json.get("rate_source").is_none());
+
+        let order = Order {
+            rate_source: Some(RateSource::Fallback),
+            ..order()
+        };
+        let json = serde_json::to_value(&order).unwrap();
+        assert_eq!(json["rate_source"], json!("fallback"));
+        assert_eq!(serde_json::from_value::<Order>(json).unwrap(), order);
+    }
+
+    #[test]
+    fn exempt_orders_carry_the_exemption_reference() {
+        let mut order = Order {
+            tax_exempt_id: Some("TX-12345".into()),
+            exemption_reference: Some("REG-0042".into()),
+            rate_source: Some(RateSource::Exempt),
+            ..order()
+        };
+        order.apply_rate(Decimal::ZERO);
+        assert_eq!(order.tax, dec("0"));
+        assert_eq!(order.total, dec("24.99"));
+
+        let json = serde_json::to_value(&order).unwrap();
+        assert_eq!(json["tax_exempt_id"], json!("TX-12345"));
+        assert_eq!(json["exemption_reference"], json!("REG-0042"));
+        assert_eq!(json["rate_source"], json!("exempt"));
+        assert_eq!(serde_json::from_value::<Order>(json).unwrap(), order);
+    }
+
+    #[test]
+    fn order_reads_the_single_product_payload() {
+        let payload = json!({
+            "order_id": 3,
+            "product_id": 5,
+            "quantity": 2,
+            "subtotal": 20.0,
+            "shipping_address": "1 Congress Ave",
+            "shipping_zip": "78701",
+            "total": 0.0
+        });
+        assert_eq!(
+            serde_json::from_value::<Order>(payload).unwrap(),
+            single_product_order()
+        );
+    }
+
+    #[test]
+    fn line_items_are_taxable_unless_stated() {
+        let item: LineItem =
+            serde_json::from_value(json!({ "product_id": 5, "quantity": 1, "unit_price": 3 }))
+                .unwrap();
+        assert!(item.taxable);
+    }
+
+    #[test]
+    fn amounts_are_numbers_and_may_be_strings() {
+        let mut payload = serde_json::to_value(single_product_order()).unwrap();
+        assert_eq!(payload["subtotal"], json!(20.0));
+        payload["subtotal"] = json!("20.00");
+        assert_eq!(
+            serde_json::from_value::<Order>(payload).unwrap(),
+            single_product_order()
+        );
+    }
+
+    #[test]
+    fn apply_rate_taxes_taxable_items_only() {
+        let mut order = order();
+        order.apply_rate(dec("0.0825"));
+        assert_eq!(order.line_items[0].tax, dec("1.65"));
+        assert_eq!(order.line_items[1].tax, dec("0"));
+        assert_eq!(order.subtotal, Some(dec("24.99")));
+        assert_eq!(order.tax, dec("1.65"));
+        assert_eq!(order.total, dec("26.64"));
+    }
+
+    #[test]
+    fn apply_rate_taxes_shipping_when_taxable() {
+        let mut order = order();
+        order.shipping = dec("7.00");
+        order.apply_rate(dec("0.0825"));
+        assert_eq!(order.tax, dec("1.65"));
+        assert_eq!(order.total, dec("33.64"));
+
+        order.shipping_taxable = true;
+        order.apply_rate(dec("0.0825"));
+        assert_eq!(order.tax, dec("2.23"));
+        assert_eq!(order.total, dec("34.22"));
+    }
+
+    #[test]
+    fn discounts_are_spread_over_the_lines_before_tax() {
+        let mut order = order();
+        order.apply_discount(dec("5.00"));
+        assert_eq!(order.line_items[0].discount, dec("4.00"));
+        assert_eq!(order.line_items[1].discount, dec("1.00"));
+        order.apply_rate(dec("0.0825"));
+        // 8.25% of 16.00; the second line isn't taxable.
+        assert_eq!(order.tax, dec("1.32"));
+        assert_eq!(order.total, dec("21.31"));
+    }
+
+    #[test]
+    fn discounts_cover_at_most_the_whole_order() {
+        let mut order = order();
+        order.apply_discount(dec("100"));
+        assert_eq!(order.discount, dec("24.99"));
+        order.apply_rate(dec("0.0825"));
+        assert_eq!(order.total, dec("0"));
+    }
+
+    #[test]
+    fn amounts_are_rounded_by_the_orders_rounding() {
+        let mut order = order();
+        order.line_items[0].unit_price = dec("10.0025");
+        order.apply_rate(dec("0.08125"));
+        // 20.005 for the first line, taxed 8.125% of 20.00.
+        assert_eq!(order.subtotal, Some(dec("24.99")));
+        assert_eq!(order.tax, dec("1.62"));
+
+        order.rounding = Some(Rounding::HalfUp);
+        order.apply_rate(dec("0.08125"));
+        // 8.125% of 20.01 is 1.6258125.
+        assert_eq!(order.subtotal, Some(dec("25.00")));
+        assert_eq!(order.tax, dec("1.63"));
+
+        order.rounding = Some(Rounding::Truncate);
+        order.apply_rate(dec("0.0829"));
+        // 8.29% of 20.00 is 1.658.
+        assert_eq!(order.tax, dec("1.65"));
+        assert_eq!(order.total, dec("26.64"));
+
+        // 3 units at 10.00 / 3 still come to 10.00.
+        let mut order = single_product_order();
+        order.quantity = Some(3);
+        order.subtotal = Some(dec("10.00"));
+        order.rounding = Some(Rounding::Truncate);
+        order.apply_rate(dec("0.0825"));
+        assert_eq!(order.subtotal, Some(dec("10.00")));
+        assert_eq!(order.total, dec("10.82"));
+    }
+
+    #[test]
+    fn converted_orders_report_totals_in_both_currencies() {
+        let mut order = single_product_order();
+        order.currency = Some("EUR".into());
+        order.convert("USD", dec("1.08"));
+        assert_eq!(order.currency.as_deref(), Some("USD"));
+        assert_eq!(order.subtotal, Some(dec("21.60")));
+        order.shipping = dec("5.00");
+        order.apply_rate(dec("0.0825"));
+        // 8.25% of 21.60, in dollars.
+        assert_eq!(order.tax, dec("1.78"));
+        assert_eq!(order.total, dec("28.38"));
+
+        let converted = order.converted.unwrap();
+        assert_eq!(converted.currency, "EUR");
+        assert_eq!(converted.subtotal, dec("20.00"));
+        assert_eq!(converted.shipping, dec("4.63"));
+        assert_eq!(converted.tax, dec("1.65"));
+        assert_eq!(converted.total, dec("26.28"));
+    }
+
+    #[test]
+    fn converted_totals_use_the_minor_unit_of_their_currency() {
+        let mut order = single_product_order();
+        order.currency = Some("JPY".into());
+        order.subtotal = Some(dec("3000"));
+        order.convert("USD", dec("0.0067"));
+        order.apply_rate(dec("0.0825"));
+        assert_eq!(order.total, dec("21.76"));
+        let converted = order.converted.unwrap();
+        assert_eq!(converted.subtotal, dec("3000"));
+        assert_eq!(converted.tax, dec("248"));
+        assert_eq!(converted.total, dec("3248"));
+    }
+
+    #[test]
+    fn tax_breakdown_adds_up_to_the_tax() {
+        let mut order = order();
+        order.line_items[0].unit_price = dec("10.03");
+        order.apply_rate(dec("0.0825"));
+        order.apply_breakdown(&austin());
+        // 8.25% of 20.06.
+        assert_eq!(order.tax, dec("1.65"));
+        let taxes: Vec<Decimal> = order.tax_breakdown.iter().map(|c| c.tax).collect();
+        assert_eq!(taxes, vec![dec("1.25"), dec("0.20"), dec("0.20")]);
+        assert_eq!(taxes.iter().sum::<Decimal>(), order.tax);
+
+        let json = serde_json::to_value(&order).unwrap();
+        assert_eq!(json["tax_breakdown"][2]["level"], json!("special_district"));
+        assert_eq!(serde_json::from_value::<Order>(json).unwrap(), order);
+    }
+
+    #[test]
+    fn tax_breakdown_is_cleared_without_components() {
+        let mut order = order();
+        order.apply_rate(dec("0.0825"));
+        order.apply_breakdown(&austin());
+        order.apply_breakdown(&[]);
+        assert!(order.tax_breakdown.is_empty());
+        assert!(serde_json::to_value(&order)
+            .unwrap()
+            .get("tax_breakdown")
+            .is_none());
+    }
+
+    #[test]
+    fn weight_counts_every_unit() {
+        assert_eq!(order().weight(), dec("3.0"));
+    }
+
+    #[test]
+    fn apply_rate_prices_a_single_product_order_as_one_line() {
+        let mut order = single_product_order();
+        order.apply_rate(dec("0.0825"));
+        assert_eq!(order.line_items.len(), 1);
+        assert_eq!(order.total, dec("21.65"));
+
+        let mut order = single_product_order();
+        order.quantity = Some(3);
+        order.subtotal = Some(dec("10.00"));
+        order.apply_rate(dec("0.0825"));
+        assert_eq!(order.subtotal, Some(dec("10.00")));
+        assert_eq!(order.total, dec("10.82"));
+
+        let mut order = single_product_order();
+        order.quantity = Some(1);
+        order.subtotal = Some(dec("19.99"));
+        order.apply_rate(dec("0.0825"));
+        assert_eq!(order.total, dec("21.64"));
+    }
+
+    #[test]
+    fn rate_response_is_a_bare_number() {
+        let rate = RateResponse {
+            rate: dec("0.0825"),
+        };
+        let body = serde_json::to_string(&rate).unwrap();
+        assert_eq!(body, "0.0825");
+        assert_eq!(serde_json::from_str::<RateResponse>(&body).unwrap(), rate);
+        assert_eq!(
+            serde_json::from_str::<RateResponse>("0.0825\n").unwrap(),
+            rate
+        );
+    }
+
+    #[test]
+    fn rate_quote_round_trips() {
+        let request: RateRequest = serde_json::from_str(r#"{"zip": "78701"}"#).unwrap();
+        assert_eq!(request.zip, "78701");
+        assert_eq!(request.as_of, None);
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({ "zip": "78701" })
+        );
+        let dated = RateRequest {
+            as_of: Some("2019-06-30".into()),
+            ..request
+        };
+        let json = serde_json::to_value(&dated).unwrap();
+        assert_eq!(json, json!({ "zip": "78701", "as_of": "2019-06-30" }));
+        assert_eq!(serde_json::from_value::<RateRequest>(json).unwrap(), dated);
+
+        let quote = RateQuote {
+            zip: "78701".into(),
+            rate: dec("0.0825"),
+            jurisdiction: "Austin, TX".into(),
+            components: Vec::new(),
+        };
+        let json = serde_json::to_value(&quote).unwrap();
+        assert_eq!(
+            json,
+            json!({ "zip": "78701", "rate": 0.0825, "jurisdiction": "Austin, TX" })
+        );
+        assert_eq!(serde_json::from_value::<RateQuote>(json).unwrap(), quote);
+    }
+
+    #[test]
+    fn rate_quotes_may_break_the_rate_down() {
+        let json = json!({
+            "zip": "78701",
+            "rate": 0.0825,
+            "jurisdiction": "Austin, TX",
+            "components": [
+                { "level": "state", "name": "Texas", "rate": 0.0625 },
+                { "level": "city", "name": "Austin", "rate": 0.01 },
+                { "level": "special_district", "name": "Capital Metro", "rate": 0.01 }
+            ]
+        });
+        let quote: RateQuote = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(quote.components, austin());
+        assert_eq!(serde_json::to_value(&quote).unwrap(), json);
+    }
+
+    #[test]
+    fn order_priced_round_trips() {
+        let mut order = order();
+        order.apply_rate(dec("0.0825"));
+        let event = OrderPriced {
+            order,
+            rate: dec("0.0825"),
+            priced_at: "2024-01-02T03:04:05.678Z".into(),
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["rate"], json!(0.0825));
+        assert_eq!(serde_json::from_value::<OrderPriced>(json).unwrap(), event);
+    }
+
+    #[test]
+    fn order_cancelled_round_trips() {
+        let event = OrderCancelled {
+            order: order(),
+            reason: Some("changed my mind".into()),
+            cancelled_at: "2024-01-02T03:04:05.678Z".into(),
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["reason"], json!("changed my mind"));
+        assert_eq!(
+            serde_json::from_value::<OrderCancelled>(json).unwrap(),
+            event
+        );
+    }
+
+    #[test]
+    fn error_envelope_round_trips() {
+        let envelope = ErrorEnvelope {
+            code: "RATE_NOT_FOUND".into(),
+            message: "no rate".into(),
+            details: Some(json!({ "shipping_zip": "00000" })),
+            request_id: Some("9f1c2d4e".into()),
+        };
+        let json = serde_json::to_string(&envelope).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ErrorEnvelope>(&json).unwrap(),
+            envelope
+        );
+    }
+
+    #[test]
+    fn error_envelope_without_details() {
+        let envelope = ErrorEnvelope {
+            code: "SHUTTING_DOWN".into(),
+            message: "The service is shutting down.".into(),
+            details: None,
+            request_id: None,
+        };
+        let json = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(
+            json,
+            json!({ "code": "SHUTTING_DOWN", "message": "The service is shutting down." })
+        );
+        assert_eq!(
+            serde_json::from_value::<ErrorEnvelope>(json).unwrap(),
+            envelope
+        );
+    }
+
+    #[test]
+    fn error_response_wraps_the_envelope() {
+        let response = ErrorResponse {
+            error: ErrorEnvelope {
+                code: "NOT_FOUND".into(),
+                message: "No such endpoint.".into(),
+                details: None,
+                request_id: Some("9f1c2d4e".into()),
+            },
+        };
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            json,
+            json!({ "error": {
+                "code": "NOT_FOUND",
+                "message": "No such endpoint.",
+                "request_id": "9f1c2d4e",
+            } })
+        );
+        assert_eq!(
+            serde_json::from_value::<ErrorResponse>(json).unwrap(),
+            response
+        );
+    }
+}
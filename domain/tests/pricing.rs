@@ -0,0 +1,196 @@
+//! Properties of pricing over arbitrary orders: totals are whole cents, add
+//! up, never fall below the subtotal, don't depend on how line items are
+//! ordered or grouped, and go over the wire unchanged, up to amounts in the
+//! trillions of dollars.
+
+use domain::money::{round_cents, Rounding};
+use domain::{Decimal, LineItem, Order};
+use proptest::prelude::*;
+
+/// Amounts from zero to `max_dollars`, in whole cents.
+fn cents(max_dollars: i64) -> impl Strategy<Value = Decimal> {
+    (0..=max_dollars * 100).prop_map(|cents| Decimal::new(cents, 2))
+}
+
+/// Sales tax rates from 0 to 20%, with up to 6 decimals, e.g. 0.08875.
+fn rate() -> impl Strategy<Value = Decimal> {
+    (0..=200_000i64).prop_map(|rate| Decimal::new(rate, 6))
+}
+
+/// Up to a thousand units of up to a billion dollars each.
+fn line_item() -> impl Strategy<Value = LineItem> {
+    (
+        1..=1000i32,
+        0..=1000i32,
+        cents(1_000_000_000),
+        any::<bool>(),
+    )
+        .prop_map(|(product_id, quantity, unit_price, taxable)| LineItem {
+            product_id,
+            quantity,
+            unit_price,
+            taxable,
+            unit_weight: None,
+            discount: Decimal::ZERO,
+            tax: Decimal::ZERO,
+        })
+}
+
+fn line_items() -> impl Strategy<Value = Vec<LineItem>> {
+    prop::collection::vec(line_item(), 1..8)
+}
+
+fn order(line_items: Vec<LineItem>) -> Order {
+    Order {
+        order_id: 1,
+        product_id: None,
+        quantity: None,
+        subtotal: None,
+        line_items,
+        promo_code: None,
+        discount: Decimal::ZERO,
+        shipping_address: "1 Congress Ave".into(),
+        shipping_zip: "78701".into(),
+        shipping: Decimal::ZERO,
+        shipping_taxable: false,
+        tax: Decimal::ZERO,
+        total: Decimal::ZERO,
+        rate_source: None,
+        currency: None,
+        converted: None,
+        tax_exempt_id: None,
+        exemption_reference: None,
+        tax_breakdown: Vec::new(),
+        normalized_address: None,
+        reservation_id: None,
+        saga_id: None,
+        rounding: None,
+    }
+}
+
+fn is_whole_cents(amount: Decimal) -> bool {
+    round_cents(amount) == amount
+}
+
+proptest! {
+    #[test]
+    fn total_is_at_least_the_subtotal(
+        line_items in line_items(),
+        shipping in cents(1_000_000),
+        shipping_taxable in any::<bool>(),
+        rate in rate(),
+    ) {
+        let mut order = order(line_items);
+        order.shipping = shipping;
+        order.shipping_taxable = shipping_taxable;
+        order.apply_rate(rate);
+        let subtotal = order.subtotal.unwrap();
+        prop_assert!(order.tax >= Decimal::ZERO);
+        prop_assert!(order.total >= subtotal);
+        prop_assert_eq!(order.total, subtotal + shipping + order.tax);
+    }
+
+    #[test]
+    fn amounts_are_whole_cents(
+        line_items in line_items(),
+        discount in cents(1_000_000_000),
+        shipping in cents(1_000_000),
+        rate in rate(),
+    ) {
+        let mut order = order(line_items);
+        order.shipping = shipping;
+        order.shipping_taxable = true;
+        order.apply_discount(discount);
+        order.apply_rate(rate);
+        prop_assert!(is_whole_cents(order.subtotal.unwrap()));
+        prop_assert!(is_whole_cents(order.discount));
+        prop_assert!(is_whole_cents(order.tax));
+        prop_assert!(is_whole_cents(order.total));
+        for item in &order.line_items {
+            prop_assert!(is_whole_cents(item.discount));
+            prop_assert!(is_whole_cents(item.tax));
+        }
+    }
+
+    #[test]
+    fn tax_is_off_by_at_most_half_a_cent_per_taxed_amount(
+        line_items in line_items(),
+        discount in cents(1_000_000),
+        shipping in cents(1_000_000),
+        rate in rate(),
+    ) {
+        let mut order = order(line_items);
+        order.shipping = shipping;
+        order.shipping_taxable = true;
+        order.apply_discount(discount);
+        order.apply_rate(rate);
+        let taxed: Vec<Decimal> = order
+            .line_items
+            .iter()
+            .filter(|item| item.taxable)
+            .map(|item| item.amount(Rounding::HalfEven) - item.discount)
+            .chain([shipping])
+            .collect();
+        let exact: Decimal = taxed.iter().map(|amount| amount * rate).sum();
+        let tolerance = Decimal::new(5, 3) * Decimal::from(taxed.len());
+        prop_assert!((order.tax - exact).abs() <= tolerance);
+    }
+
+    #[test]
+    fn line_items_add_up_in_any_order_and_grouping(
+        line_items in line_items(),
+        split in any::<prop::sample::Index>(),
+        rate in rate(),
+    ) {
+        let mut whole = order(line_items.clone());
+        whole.apply_rate(rate);
+
+        let mut reversed = order(line_items.iter().rev().cloned().collect());
+        reversed.apply_rate(rate);
+        prop_assert_eq!(reversed.subtotal, whole.subtotal);
+        prop_assert_eq!(reversed.tax, whole.tax);
+        prop_assert_eq!(reversed.total, whole.total);
+
+        let (first, second) = line_items.split_at(split.index(line_items.len() + 1));
+        let mut first = order(first.to_vec());
+        let mut second = order(second.to_vec());
+        first.apply_rate(rate);
+        second.apply_rate(rate);
+        let subtotal = first.subtotal.unwrap_or_default() + second.subtotal.unwrap_or_default();
+        prop_assert_eq!(Some(subtotal), whole.subtotal);
+        prop_assert_eq!(first.tax + second.tax, whole.tax);
+        prop_assert_eq!(first.total + second.total, whole.total);
+    }
+
+    #[test]
+    fn single_product_orders_keep_their_subtotal(
+        subtotal in cents(1_000_000_000_000),
+        quantity in 1..=10_000i32,
+    ) {
+        let mut order = Order {
+            product_id: Some(1),
+            quantity: Some(quantity),
+            subtotal: Some(subtotal),
+            ..order(Vec::new())
+        };
+        order.normalize();
+        prop_assert_eq!(order.items_total(), subtotal);
+    }
+
+    // Amounts are JSON numbers, read back from their shortest decimal text,
+    // which is exact for up to 15 significant digits: 10 trillion dollars.
+    #[test]
+    fn priced_orders_round_trip_through_json(
+        line_items in line_items(),
+        discount in cents(1_000_000),
+        shipping in cents(1_000_000),
+        rate in rate(),
+    ) {
+        let mut order = order(line_items);
+        order.shipping = shipping;
+        order.apply_discount(discount);
+        order.apply_rate(rate);
+        let json = serde_json::to_string(&order).unwrap();
+        prop_assert_eq!(serde_json::from_str::<Order>(&json).unwrap(), order);
+    }
+}
@@ -0,0 +1,177 @@
+//! Load generator for the order_total HTTP API, in the manner of oha: prices
+//! orders on `/v1/compute` with `--concurrency` requests in flight, for
+//! `--duration` or `--requests`, then reports the throughput, the statuses
+//! and the latency percentiles. The orders are shipped to each of `--zips`
+//! in turn, so the number of zip codes sets how many distinct rates the
+//! service looks up, and with it the hit ratio of its rate cache.
+
+use clap::Parser;
+use reqwest::header::CONTENT_TYPE;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Parser)]
+#[command(name = "loadgen", about = "Load tests the order_total HTTP API")]
+struct Args {
+    /// URL of the pricing endpoint.
+    #[arg(long, default_value = "http://localhost:8002/v1/compute")]
+    url: String,
+    /// Requests in flight at any time.
+    #[arg(short, long, default_value_t = 16)]
+    concurrency: usize,
+    /// How long to send requests for, e.g. `30s`, unless `--requests` is given.
+    #[arg(short, long, value_parser = humantime::parse_duration, default_value = "10s")]
+    duration: Duration,
+    /// Number of requests to send.
+    #[arg(short = 'n', long)]
+    requests: Option<u64>,
+    /// Shipping zip codes of the orders, comma-separated, used in turn.
+    #[arg(long, value_delimiter = ',', default_value = "78701")]
+    zips: Vec<String>,
+    /// Time allowed for each request.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "10s")]
+    timeout: Duration,
+}
+
+/// How a request went: its latency, until the whole response was read, and
+/// its status, or none when it got no response.
+struct Outcome {
+    latency: Duration,
+    status: Option<u16>,
+}
+
+// WASI has no threads, so tokio_wasi only offers the current-thread runtime.
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let args = Arc::new(Args::parse());
+    if args.concurrency == 0 || args.zips.is_empty() {
+        return Err("--concurrency and --zips may not be empty".into());
+    }
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(args.concurrency)
+        .build()?;
+
+    let start = Instant::now();
+    let deadline = args.requests.is_none().then(|| start + args.duration);
+    let next = Arc::new(AtomicU64::new(0));
+    let workers: Vec<_> = (0..args.concurrency)
+        .map(|_| tokio::spawn(send(client.clone(), args.clone(), next.clone(), deadline)))
+        .collect();
+    let mut outcomes = Vec::new();
+    for worker in workers {
+        outcomes.extend(worker.await?);
+    }
+    print!("{}", report(outcomes, start.elapsed()));
+    Ok(())
+}
+
+/// Sends requests one after the other, taking their index from `next`, until
+/// `--requests` are sent or `deadline` has passed.
+async fn send(
+    client: reqwest::Client,
+    args: Arc<Args>,
+    next: Arc<AtomicU64>,
+    deadline: Option<Instant>,
+) -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+    loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        let sent = args.requests.is_some_and(|requests| index >= requests);
+        let expired = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        if sent || expired {
+            return outcomes;
+        }
+        let zip = &args.zips[index as usize % args.zips.len()];
+        let start = Instant::now();
+        let response = client
+            .post(&args.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(order(index, zip))
+            .timeout(args.timeout)
+            .send()
+            .await;
+        let status = match response {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                response.bytes().await.ok().map(|_| status)
+            }
+            Err(_) => None,
+        };
+        outcomes.push(Outcome {
+            latency: start.elapsed(),
+            status,
+        });
+    }
+}
+
+/// The order of `order.json`, with an id of its own, shipped to `zip`.
+fn order(index: u64, zip: &str) -> String {
+    serde_json::json!({
+        "order_id": (index % i32::MAX as u64) + 1,
+        "product_id": 321,
+        "quantity": 2,
+        "subtotal": 20.0,
+        "shipping_address": "123 Main St, Anytown USA",
+        "shipping_zip": zip,
+        "total": 0.0
+    })
+    .to_string()
+}
+
+fn report(mut outcomes: Vec<Outcome>, elapsed: Duration) -> String {
+    let mut report = String::new();
+    let throughput = outcomes.len() as f64 / elapsed.as_secs_f64();
+    let _ = writeln!(
+        report,
+        "Requests:  {} in {:.2}s, {:.1} req/s",
+        outcomes.len(),
+        elapsed.as_secs_f64(),
+        throughput
+    );
+
+    let mut statuses = BTreeMap::new();
+    let mut failed = 0;
+    for outcome in &outcomes {
+        match outcome.status {
+            Some(status) => *statuses.entry(status).or_insert(0) += 1,
+            None => failed += 1,
+        }
+    }
+    let statuses: Vec<String> = statuses
+        .iter()
+        .map(|(status, count)| format!("{} x {}", status, count))
+        .collect();
+    let _ = writeln!(report, "Statuses:  {}", statuses.join(", "));
+    if failed > 0 {
+        let _ = writeln!(report, "Failed:    {} without a response", failed);
+    }
+
+    if outcomes.is_empty() {
+        return report;
+    }
+    outcomes.sort_by_key(|outcome| outcome.latency);
+    let latency = |quantile: f64| {
+        // The nearest rank: the smallest latency of at least `quantile` of
+        // the requests.
+        let rank = (quantile * outcomes.len() as f64).ceil() as usize;
+        millis(outcomes[rank.clamp(1, outcomes.len()) - 1].latency)
+    };
+    let _ = writeln!(
+        report,
+        "Latency:   min {}, p50 {}, p90 {}, p99 {}, p99.9 {}, max {}",
+        millis(outcomes[0].latency),
+        latency(0.5),
+        latency(0.9),
+        latency(0.99),
+        latency(0.999),
+        latency(1.0)
+    );
+    report
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,151 @@
+//! The live stream of pricing activity at `GET /events`: every `OrderPriced`
+//! and `OrderCancelled` event, sent as a Server-Sent Event to each connected
+//! client, such as a dashboard.
+//!
+//! Events go through a bounded broadcast channel. A client reading slower
+//! than orders are priced falls behind by at most `stream.capacity` events;
+//! past that it skips the oldest and is told how many with a `lagged` event,
+//! so memory use doesn't grow with slow clients.
+
+use domain::{OrderCancelled, OrderPriced};
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Response};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use crate::config::StreamConfig;
+use crate::error::AppError;
+use crate::events::EventPublisher;
+use crate::SHUTDOWN;
+
+/// One priced or cancelled order, serialized once for every client.
+#[derive(Debug)]
+struct Activity {
+    /// `order_priced` or `order_cancelled`.
+    event: &'static str,
+    shipping_zip: String,
+    data: String,
+}
+
+/// The filters of `GET /events`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EventsQuery {
+    /// Only orders shipped to ZIP codes starting with these digits.
+    pub zip_prefix: Option<String>,
+}
+
+impl EventsQuery {
+    fn validate(&self) -> Result<(), AppError> {
+        match &self.zip_prefix {
+            Some(prefix)
+                if prefix.is_empty()
+                    || prefix.len() > 5
+                    || !prefix.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                Err(AppError::InvalidPayload(format!(
+                    "zip_prefix must be 1 to 5 digits, not {:?}",
+                    prefix
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn matches(&self, activity: &Activity) -> bool {
+        self.zip_prefix
+            .as_deref()
+            .is_none_or(|prefix| activity.shipping_zip.starts_with(prefix))
+    }
+}
+
+/// Broadcasts priced and cancelled orders to the clients of `GET /events`.
+pub struct ActivityStream {
+    sender: broadcast::Sender<Arc<Activity>>,
+    heartbeat: Duration,
+}
+
+impl ActivityStream {
+    pub fn new(config: &StreamConfig) -> Self {
+        let (sender, _) = broadcast::channel(config.capacity.max(1));
+        Self {
+            sender,
+            heartbeat: Duration::from_secs(config.heartbeat_secs.max(1)),
+        }
+    }
+
+    /// A `text/event-stream` response sending every order priced or
+    /// cancelled from now on that matches `query`, until the client
+    /// disconnects or the service shuts down. A comment is sent every
+    /// `stream.heartbeat_secs` so proxies don't close an idle connection.
+    pub fn subscribe(&self, query: EventsQuery) -> Result<Response<Body>, AppError> {
+        query.validate()?;
+        let mut activities = self.sender.subscribe();
+        let mut heartbeats = tokio::time::interval(self.heartbeat);
+        let (mut body, response_body) = Body::channel();
+        tokio::spawn(async move {
+            let shutdown = SHUTDOWN.triggered();
+            tokio::pin!(shutdown);
+            // The first tick is immediate and opens the stream.
+            loop {
+                let chunk = tokio::select! {
+                    received = activities.recv() => match received {
+                        Ok(activity) if query.matches(&activity) => {
+                            format!("event: {}\ndata: {}\n\n", activity.event, activity.data)
+                        }
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(skipped)) => {
+                            format!("event: lagged\ndata: {{\"skipped\":{}}}\n\n", skipped)
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = heartbeats.tick() => ": heartbeat\n\n".to_string(),
+                    _ = &mut shutdown => break,
+                };
+                // Fails once the client has disconnected.
+                if body.send_data(chunk.into()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::builder()
+            .header(CONTENT_TYPE, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache")
+            .body(response_body)
+            .unwrap())
+    }
+}
+
+impl ActivityStream {
+    fn broadcast<T: serde::Serialize>(&self, event: &'static str, shipping_zip: &str, data: &T) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let data = match serde_json::to_string(data) {
+            Ok(data) => data,
+            Err(err) => {
+                warn!(error = %err, "cannot serialize activity event");
+                return;
+            }
+        };
+        // Fails only when the last client disconnected meanwhile.
+        let _ = self.sender.send(Arc::new(Activity {
+            event,
+            shipping_zip: shipping_zip.to_string(),
+            data,
+        }));
+    }
+}
+
+impl EventPublisher for ActivityStream {
+    fn publish(&self, event: &OrderPriced) {
+        self.broadcast("order_priced", &event.order.shipping_zip, event);
+    }
+
+    fn publish_cancelled(&self, event: &OrderCancelled) {
+        self.broadcast("order_cancelled", &event.order.shipping_zip, event);
+    }
+}
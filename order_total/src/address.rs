@@ -0,0 +1,246 @@
+//! Where shipping addresses are validated and normalized, when they are: by
+//! static rules, or by an address validation service, falling back to the
+//! static rules while it is unavailable. The normalized address carries the
+//! zip code orders without one are then shipped to.
+
+use domain::{ErrorResponse, NormalizedAddress};
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::header::ACCEPT;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::{AddressConfig, AddressValidatorKind};
+use crate::error::AppError;
+use crate::postal::{self, PostalCode};
+use crate::request_id;
+
+/// Validates and normalizes shipping addresses.
+pub trait AddressValidator: Send + Sync {
+    /// `address` in its standard form, with its zip code: the one in the
+    /// address, which must agree with `zip` when both are given, or else
+    /// `zip`. Fails with `InvalidAddress`.
+    fn normalize<'a>(
+        &'a self,
+        address: &'a str,
+        zip: &'a str,
+    ) -> BoxFuture<'a, Result<NormalizedAddress, AppError>>;
+}
+
+/// The validator selected by the configuration, if any; a validation service
+/// is called with `client`.
+pub fn from_config(
+    config: &AddressConfig,
+    client: reqwest::Client,
+) -> Option<Box<dyn AddressValidator>> {
+    match config.validator {
+        AddressValidatorKind::None => None,
+        AddressValidatorKind::Static => Some(Box::new(StaticValidator)),
+        AddressValidatorKind::Http => Some(Box::new(HttpValidator::new(config, client))),
+    }
+}
+
+/// Street suffixes and unit designators, and their USPS abbreviations.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("APARTMENT", "APT"),
+    ("AVENUE", "AVE"),
+    ("BOULEVARD", "BLVD"),
+    ("COURT", "CT"),
+    ("DRIVE", "DR"),
+    ("EAST", "E"),
+    ("HIGHWAY", "HWY"),
+    ("LANE", "LN"),
+    ("NORTH", "N"),
+    ("PARKWAY", "PKWY"),
+    ("PLACE", "PL"),
+    ("ROAD", "RD"),
+    ("SOUTH", "S"),
+    ("STREET", "ST"),
+    ("SUITE", "STE"),
+    ("WEST", "W"),
+];
+
+/// Normalizes addresses written as `street, city, state zip` without asking
+/// anyone: uppercase, without punctuation or a trailing country, with the
+/// street suffixes of the first line abbreviated. It can't tell whether the
+/// address exists.
+pub struct StaticValidator;
+
+impl StaticValidator {
+    fn normalize_now(&self, address: &str, zip: &str) -> Result<NormalizedAddress, AppError> {
+        let address = address.to_ascii_uppercase().replace('.', "");
+        let mut lines: Vec<Vec<&str>> = address
+            .split(',')
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .filter(|words| !words.is_empty())
+            .collect();
+        if let Some(line) = lines.last_mut() {
+            if matches!(line.last(), Some(&"USA") | Some(&"US")) {
+                line.pop();
+            }
+        }
+        lines.retain(|words| !words.is_empty());
+        if let Some(street) = lines.first_mut() {
+            for word in street.iter_mut() {
+                if let Some((_, abbreviation)) = ABBREVIATIONS.iter().find(|(long, _)| long == word)
+                {
+                    *word = abbreviation;
+                }
+            }
+        }
+
+        let found = match lines.last().and_then(|line| line.last()) {
+            Some(word) if matches!(postal::parse(word), PostalCode::Us(_)) => {
+                Some(word.to_string())
+            }
+            _ => None,
+        };
+        let zip = zip.trim();
+        let zip = match (found, zip) {
+            (Some(found), "") => found,
+            (Some(found), zip) => {
+                if let (PostalCode::Us(given), PostalCode::Us(in_address)) =
+                    (postal::parse(zip), postal::parse(&found))
+                {
+                    if given != in_address {
+                        return Err(AppError::InvalidAddress(format!(
+                            "the address is in zip code {}, not {}",
+                            found, zip
+                        )));
+                    }
+                }
+                zip.to_string()
+            }
+            (None, "") => {
+                return Err(AppError::InvalidAddress(
+                    "the address has no zip code".into(),
+                ))
+            }
+            (None, zip) => {
+                if let Some(line) = lines.last_mut() {
+                    line.push(zip);
+                }
+                zip.to_string()
+            }
+        };
+        let address: Vec<String> = lines.iter().map(|words| words.join(" ")).collect();
+        Ok(NormalizedAddress {
+            address: address.join(", "),
+            zip,
+            zip_derived: false,
+        })
+    }
+}
+
+impl AddressValidator for StaticValidator {
+    fn normalize<'a>(
+        &'a self,
+        address: &'a str,
+        zip: &'a str,
+    ) -> BoxFuture<'a, Result<NormalizedAddress, AppError>> {
+        futures::future::ready(self.normalize_now(address, zip)).boxed()
+    }
+}
+
+/// The body of an address validation.
+#[derive(Serialize)]
+struct AddressRequest<'a> {
+    address: &'a str,
+    zip: &'a str,
+}
+
+/// The service's answer for a valid address.
+#[derive(Deserialize)]
+struct AddressResponse {
+    address: String,
+    zip: String,
+}
+
+/// An address validation service, asked with `POST {"address": ..., "zip":
+/// ...}`, `zip` being empty when unknown. It answers valid addresses with
+/// `200 {"address": ..., "zip": ...}` and rejects others with `422` and an
+/// error body.
+pub struct HttpValidator {
+    url: String,
+    timeout: Duration,
+    client: reqwest::Client,
+    /// Used while the service is unavailable, when enabled.
+    fallback: Option<StaticValidator>,
+}
+
+impl HttpValidator {
+    pub fn new(config: &AddressConfig, client: reqwest::Client) -> Self {
+        Self {
+            url: config.url.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            client,
+            fallback: config.fallback.then_some(StaticValidator),
+        }
+    }
+
+    async fn normalize_or_fall_back(
+        &self,
+        address: &str,
+        zip: &str,
+    ) -> Result<NormalizedAddress, AppError> {
+        match (self.call(address, zip).await, &self.fallback) {
+            (Err(AppError::AddressValidatorUnavailable(reason)), Some(fallback)) => {
+                warn!(reason = %reason, "address validator unavailable, using the static rules");
+                fallback.normalize_now(address, zip)
+            }
+            (result, _) => result,
+        }
+    }
+
+    async fn call(&self, address: &str, zip: &str) -> Result<NormalizedAddress, AppError> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(ACCEPT, "application/json")
+            .timeout(self.timeout)
+            .json(&AddressRequest { address, zip });
+        if let Some(request_id) = request_id::current() {
+            request = request.header(request_id::REQUEST_ID_HEADER, request_id);
+        }
+        let response = request.send().await.map_err(|err| {
+            AppError::AddressValidatorUnavailable(if err.is_timeout() {
+                format!("no answer within {} ms", self.timeout.as_millis())
+            } else {
+                err.to_string()
+            })
+        })?;
+        match response.status().as_u16() {
+            200 => response
+                .json::<AddressResponse>()
+                .await
+                .map(|body| NormalizedAddress {
+                    address: body.address,
+                    zip: body.zip,
+                    zip_derived: false,
+                })
+                .map_err(|err| {
+                    AppError::AddressValidatorUnavailable(format!("invalid response: {}", err))
+                }),
+            422 => Err(AppError::InvalidAddress(
+                match response.json::<ErrorResponse>().await {
+                    Ok(body) => body.error.message,
+                    Err(_) => "rejected by the address validation service".into(),
+                },
+            )),
+            status => Err(AppError::AddressValidatorUnavailable(format!(
+                "unexpected status {}",
+                status
+            ))),
+        }
+    }
+}
+
+impl AddressValidator for HttpValidator {
+    fn normalize<'a>(
+        &'a self,
+        address: &'a str,
+        zip: &'a str,
+    ) -> BoxFuture<'a, Result<NormalizedAddress, AppError>> {
+        self.normalize_or_fall_back(address, zip).boxed()
+    }
+}
@@ -0,0 +1,230 @@
+//! The admin endpoints. They are served on their own listener, at
+//! `server.admin_port`, so they can be firewalled apart from the public API,
+//! or on the public port when `server.admin_port` is 0.
+
+use anyhow::Error;
+use hyper::server::conn::AddrStream;
+use hyper::service::make_service_fn;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tower::ServiceBuilder;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::rate_overrides::RateOverride;
+use crate::router::{self, Router};
+use crate::state::AppState;
+use crate::{body, logging, middleware, response_build, response_build_with_status, SHUTDOWN};
+
+/// Marks the requests received on the admin listener.
+#[derive(Debug, Clone, Copy)]
+pub struct AdminListener;
+
+/// The body of `GET` and `PUT /admin/log_level`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct LogLevel {
+    /// Filter in `RUST_LOG` syntax, e.g. `info,order_total=debug`.
+    pub level: String,
+}
+
+/// What is running.
+#[derive(Serialize, ToSchema)]
+pub struct BuildInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Commit the service was built from, when `GIT_COMMIT` was set at build
+    /// time.
+    pub git_commit: Option<&'static str>,
+    pub schema_version: &'static str,
+    /// Cargo features the service was built with.
+    pub features: Vec<&'static str>,
+}
+
+/// Whether `req` came in on the admin listener.
+pub fn on_admin_listener(req: &Request<Body>) -> bool {
+    req.extensions().get::<AdminListener>().is_some()
+}
+
+/// Binds the admin listener and serves it in the background until shutdown.
+pub fn start(state: AppState, port: u16) -> anyhow::Result<()> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        let service = ServiceBuilder::new()
+            .map_request(|mut req: Request<Body>| {
+                req.extensions_mut().insert(AdminListener);
+                req
+            })
+            .service(middleware::stack(state.clone(), conn.remote_addr()));
+        async move { Ok::<_, Infallible>(service) }
+    });
+    let server = Server::try_bind(&addr)?
+        .serve(make_svc)
+        .with_graceful_shutdown(SHUTDOWN.triggered());
+    info!(port, "admin server started");
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!(error = %e, "admin server error");
+        }
+    });
+    Ok(())
+}
+
+/// The routes of the admin endpoints.
+pub fn routes() -> Router {
+    Router::new()
+        // Start a graceful shutdown, for runtimes that don't deliver signals
+        .route(Method::POST, "/admin/shutdown", |_, _| async {
+            SHUTDOWN.trigger("admin request");
+            Ok(response_build_with_status(
+                StatusCode::ACCEPTED,
+                "{\"status\":\"shutting down\"}",
+            ))
+        })
+        // Inspect and flush the rate cache
+        .route(Method::GET, "/admin/cache", |state, _| async move {
+            json(&state.rate_cache.info())
+        })
+        .route(Method::DELETE, "/admin/cache", |state, _| async move {
+            let flushed = state.rate_cache.flush();
+            Ok(response_build(&format!("{{\"flushed\":{}}}", flushed)))
+        })
+        .route(Method::GET, "/admin/config", |_, _| async {
+            json(&AppConfig::current().redacted())
+        })
+        .route(Method::GET, "/admin/log_level", |_, _| async {
+            json(&LogLevel {
+                level: logging::level(),
+            })
+        })
+        .route(Method::PUT, "/admin/log_level", set_log_level)
+        .route(
+            Method::GET,
+            "/admin/circuit_breaker",
+            |state, _| async move { json(&state.circuit_breaker.status()) },
+        )
+        .route(Method::GET, "/admin/build_info", |_, _| async {
+            json(&build_info())
+        })
+        .route(Method::GET, "/admin/jobs", |state, _| async move {
+            json(&state.scheduler.status())
+        })
+        // List and replay the webhook deliveries that failed every attempt
+        .route(
+            Method::GET,
+            "/admin/webhooks/failed",
+            |state, _| async move { json(&state.webhooks.failed()) },
+        )
+        .route(
+            Method::POST,
+            "/admin/webhooks/failed/{id}/replay",
+            replay_webhook,
+        )
+        // List and re-drive the orders the queue consumer failed to price
+        .route(
+            Method::GET,
+            "/admin/queue/dead_letters",
+            |state, _| async move {
+                let letters = match &state.dead_letters {
+                    Some(dead_letters) => dead_letters.list(),
+                    None => Vec::new(),
+                };
+                json(&letters)
+            },
+        )
+        .route(
+            Method::POST,
+            "/admin/queue/dead_letters/{id}/redrive",
+            redrive_dead_letter,
+        )
+        // List, add and remove the rate overrides
+        .route(
+            Method::GET,
+            "/admin/rate_overrides",
+            |state, _| async move { json(&state.rate_overrides.list()) },
+        )
+        .route(Method::POST, "/admin/rate_overrides", add_rate_override)
+        .route(
+            Method::DELETE,
+            "/admin/rate_overrides/{id}",
+            remove_rate_override,
+        )
+}
+
+async fn set_log_level(_state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let byte_stream = body::read(req).await?;
+    let LogLevel { level } = serde_json::from_slice(&byte_stream)?;
+    logging::set_level(&level).map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+    info!(level = %level, "log level changed");
+    json(&LogLevel {
+        level: logging::level(),
+    })
+}
+
+async fn replay_webhook(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let id: String = router::param(&req, "id")?;
+    let replayed = state.webhooks.replay(&id)?;
+    let body = serde_json::to_string_pretty(&replayed).map_err(Error::from)?;
+    Ok(response_build_with_status(StatusCode::ACCEPTED, &body))
+}
+
+async fn redrive_dead_letter(
+    state: AppState,
+    req: Request<Body>,
+) -> Result<Response<Body>, AppError> {
+    let id: String = router::param(&req, "id")?;
+    let redriven = match &state.dead_letters {
+        Some(dead_letters) => dead_letters.redrive(&id)?,
+        None => return Err(AppError::DeadLetterNotFound(id)),
+    };
+    let body = serde_json::to_string_pretty(&redriven).map_err(Error::from)?;
+    Ok(response_build_with_status(StatusCode::ACCEPTED, &body))
+}
+
+async fn add_rate_override(
+    state: AppState,
+    req: Request<Body>,
+) -> Result<Response<Body>, AppError> {
+    let byte_stream = body::read(req).await?;
+    let rate_override: RateOverride = serde_json::from_slice(&byte_stream)?;
+    let added = state.rate_overrides.add(rate_override)?;
+    info!(zip = %added.rate_override.zip, rate = %added.rate_override.rate, "rate override added");
+    let body = serde_json::to_string_pretty(&added).map_err(Error::from)?;
+    Ok(response_build_with_status(StatusCode::CREATED, &body))
+}
+
+async fn remove_rate_override(
+    state: AppState,
+    req: Request<Body>,
+) -> Result<Response<Body>, AppError> {
+    let id: String = router::param(&req, "id")?;
+    let removed = state.rate_overrides.remove(&id)?;
+    info!(zip = %removed.zip, "rate override removed");
+    json(&removed)
+}
+
+fn json<T: Serialize>(value: &T) -> Result<Response<Body>, AppError> {
+    let body = serde_json::to_string_pretty(value).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+fn build_info() -> BuildInfo {
+    let features = [
+        ("nats", cfg!(feature = "nats")),
+        ("tls", cfg!(feature = "tls")),
+    ];
+    BuildInfo {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: option_env!("GIT_COMMIT"),
+        schema_version: domain::SCHEMA_VERSION,
+        features: features
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect(),
+    }
+}
@@ -0,0 +1,55 @@
+//! Pricing as of a past day: `?as_of=YYYY-MM-DD` on `/compute`, `/quote` and
+//! `/compute_batch` prices orders at the rates in effect on that day, in UTC,
+//! so historical orders can be reprocessed. The day is sent to the sales tax
+//! rate service with each lookup, keys the cached rates and lookups apart
+//! from today's, and selects the rate overrides applying on it. The `file`
+//! and `memory` providers and the fallback table have one rate per zip code,
+//! whatever the day.
+
+use hyper::Request;
+use serde::Deserialize;
+use std::future::Future;
+use std::time::SystemTime;
+
+use crate::error::AppError;
+use crate::reports;
+
+/// The query parameter naming the day.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AsOfQuery {
+    as_of: Option<String>,
+}
+
+tokio::task_local! {
+    static AS_OF: Option<String>;
+}
+
+/// The day named by the `as_of` query parameter of `req`, if any. Fails on
+/// one that isn't a `YYYY-MM-DD` date.
+pub fn from_query<B>(req: &Request<B>) -> Result<Option<String>, AppError> {
+    let query: AsOfQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or(""))
+        .map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+    let as_of = query.as_of.filter(|day| !day.is_empty());
+    if let Some(day) = &as_of {
+        reports::parse_date(day)?;
+    }
+    Ok(as_of)
+}
+
+/// Runs `future` as of `day`, available through `current`; as of today
+/// without one.
+pub async fn scope<F: Future>(day: Option<String>, future: F) -> F::Output {
+    AS_OF.scope(day, future).await
+}
+
+/// The day the request being served prices orders as of, if not today.
+pub fn current() -> Option<String> {
+    AS_OF.try_with(|day| day.clone()).ok().flatten()
+}
+
+/// The day the request being served prices orders as of: the one it names,
+/// or today.
+pub fn day() -> String {
+    current().unwrap_or_else(|| reports::format_date(SystemTime::now()))
+}
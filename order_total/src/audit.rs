@@ -0,0 +1,187 @@
+//! The audit trail: every priced order, with the rate it was priced at, where
+//! that rate came from, who asked and how long it took, and every reload of
+//! the configuration, appended to a JSON lines file that is rotated by size.
+//! Under WasmEdge the file's directory must be mapped with `--dir`.
+
+use anyhow::Context;
+use domain::money::{self, Decimal};
+use domain::{Order, RateSource};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::config::AuditConfig;
+use crate::service::Rate;
+use crate::{as_of, auth, request_id, tenants};
+
+/// One pricing decision.
+#[derive(Debug, Serialize)]
+pub struct AuditRecord<'a> {
+    /// RFC 3339 time of pricing.
+    pub at: String,
+    pub request_id: Option<String>,
+    /// The authenticated caller, when authentication is on.
+    pub caller: Option<String>,
+    /// The tenant of the request, when it has one.
+    pub tenant: Option<String>,
+    /// The order as received, before shipping, discounts and tax.
+    pub order: &'a Order,
+    #[serde(with = "money::json_number")]
+    pub rate: Decimal,
+    /// `provider`, `cache`, `fallback`, `exempt` or `override`.
+    pub rate_source: &'static str,
+    /// The past day the order was priced as of, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<String>,
+    /// The exemption registry's reference of a tax exempt order.
+    pub exemption_reference: Option<String>,
+    #[serde(with = "money::json_number")]
+    pub tax: Decimal,
+    #[serde(with = "money::json_number")]
+    pub total: Decimal,
+    /// `half_even`, `half_up` or `truncate`.
+    pub rounding: &'static str,
+    pub latency_ms: u64,
+}
+
+impl<'a> AuditRecord<'a> {
+    /// The record of `received`, priced as `priced` at `rate` in `latency`.
+    pub fn new(received: &'a Order, priced: &Order, rate: &Rate, latency: Duration) -> Self {
+        let rate_source = match rate.source {
+            Some(RateSource::Fallback) => "fallback",
+            Some(RateSource::Exempt) => "exempt",
+            Some(RateSource::Override) => "override",
+            None if rate.cached => "cache",
+            None => "provider",
+        };
+        Self {
+            at: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            request_id: request_id::current(),
+            caller: auth::current_caller(),
+            tenant: tenants::current_tenant(),
+            order: received,
+            rate: rate.value,
+            rate_source,
+            as_of: as_of::current(),
+            exemption_reference: priced.exemption_reference.clone(),
+            tax: priced.tax,
+            total: priced.total,
+            rounding: priced.rounding().as_str(),
+            latency_ms: latency.as_millis() as u64,
+        }
+    }
+}
+
+/// A reload of the configuration, told apart from pricing decisions by its
+/// `event`.
+#[derive(Debug, Serialize)]
+pub struct ConfigReloadRecord<'a> {
+    /// RFC 3339 time of the reload.
+    pub at: String,
+    pub event: &'static str,
+    /// Keys of the settings changed, such as `cache.ttl_secs`.
+    pub changed: &'a [String],
+    /// Those of `changed` waiting for a restart to take effect.
+    pub restart_required: &'a [String],
+}
+
+impl<'a> ConfigReloadRecord<'a> {
+    pub fn new(changed: &'a [String], restart_required: &'a [String]) -> Self {
+        Self {
+            at: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            event: "config_reloaded",
+            changed,
+            restart_required,
+        }
+    }
+}
+
+/// The audit log of the configuration, when enabled.
+pub fn from_config(config: &AuditConfig) -> anyhow::Result<Option<AuditLog>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let log = AuditLog::open(Path::new(&config.path), config.max_bytes, config.max_files)
+        .with_context(|| format!("cannot open the audit log {}", config.path))?;
+    Ok(Some(log))
+}
+
+#[derive(Debug)]
+struct Inner {
+    file: File,
+    /// Bytes in the current file.
+    size: u64,
+}
+
+/// Appends records to a file, which is moved to `<path>.1` once it would
+/// grow past `max_bytes`, shifting older files up to `<path>.<max_files>`.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    inner: Mutex<Inner>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path, max_bytes: u64, max_files: usize) -> anyhow::Result<Self> {
+        let file = append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            inner: Mutex::new(Inner { file, size }),
+        })
+    }
+
+    pub fn record<T: Serialize>(&self, record: &T) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut inner = self.inner.lock().unwrap();
+        if self.max_bytes > 0 && inner.size > 0 && inner.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+            *inner = Inner {
+                file: append(&self.path)?,
+                size: 0,
+            };
+        }
+        inner.file.write_all(line.as_bytes())?;
+        inner.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, dropping the oldest, and moves the
+    /// current file to `<path>.1`.
+    fn rotate(&self) -> anyhow::Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        let oldest = self.rotated(self.max_files);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+}
+
+fn append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
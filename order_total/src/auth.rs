@@ -0,0 +1,155 @@
+use hyper::header::{HeaderMap, AUTHORIZATION};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use tracing::{debug, warn};
+
+use crate::config::{AuthConfig, TenantConfig};
+use crate::error::AppError;
+use crate::jwt::JwtVerifier;
+use crate::routing::Access;
+
+/// The header carrying a static API key.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+tokio::task_local! {
+    static CALLER: Option<String>;
+}
+
+/// What the credentials of a request give access to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Role {
+    Client,
+    Admin,
+}
+
+/// Checks the credentials of requests against the access rule of their
+/// route: an API key in `X-Api-Key`, or an API key or JWT as bearer token.
+/// With nothing configured every request is let through.
+pub struct Authenticator {
+    api_keys: Vec<String>,
+    admin_api_keys: Vec<String>,
+    jwt: Option<JwtVerifier>,
+    admin_scope: String,
+}
+
+impl Authenticator {
+    /// The API keys of `tenants` are accepted as those of `config`. `client`
+    /// fetches the JWKS document, when one is configured.
+    pub fn new(
+        config: &AuthConfig,
+        tenants: &HashMap<String, TenantConfig>,
+        client: reqwest::Client,
+    ) -> Self {
+        let tenant_keys = tenants.values().flat_map(|tenant| tenant.api_keys.clone());
+        Self {
+            api_keys: config.api_keys.iter().cloned().chain(tenant_keys).collect(),
+            admin_api_keys: config.admin_api_keys.clone(),
+            jwt: JwtVerifier::new(&config.jwt, client),
+            admin_scope: config.jwt.admin_scope.clone(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || !self.admin_api_keys.is_empty() || self.jwt.is_some()
+    }
+
+    /// Fails with `Unauthorized` without valid credentials, and with
+    /// `Forbidden` when they don't give the required access. Returns who the
+    /// caller is, unless the route is public or authentication is off.
+    pub async fn authorize(
+        &self,
+        headers: &HeaderMap,
+        access: Access,
+    ) -> Result<Option<String>, AppError> {
+        let required = match access {
+            Access::Public => return Ok(None),
+            _ if !self.is_enabled() => return Ok(None),
+            Access::Client => Role::Client,
+            Access::Admin => Role::Admin,
+        };
+        let credential = credential(headers)
+            .ok_or_else(|| AppError::Unauthorized("missing credentials".into()))?;
+        let (role, caller) = self.role(credential).await?;
+        if role < required {
+            return Err(AppError::Forbidden);
+        }
+        Ok(Some(caller))
+    }
+
+    /// The role of the credential, and the caller's identity: the subject of
+    /// a token, or the fingerprint of an API key.
+    async fn role(&self, credential: &str) -> Result<(Role, String), AppError> {
+        if contains(&self.admin_api_keys, credential) {
+            return Ok((Role::Admin, key_fingerprint(credential)));
+        }
+        if contains(&self.api_keys, credential) {
+            return Ok((Role::Client, key_fingerprint(credential)));
+        }
+        let jwt = match &self.jwt {
+            Some(jwt) if credential.contains('.') => jwt,
+            _ => return Err(AppError::Unauthorized("invalid API key".into())),
+        };
+        match jwt.verify(credential).await {
+            Ok(claims) => {
+                debug!(subject = ?claims.sub, "token accepted");
+                let role = if claims.has_scope(&self.admin_scope) {
+                    Role::Admin
+                } else {
+                    Role::Client
+                };
+                let caller = claims
+                    .sub
+                    .map_or_else(|| "token".to_string(), |subject| format!("sub:{}", subject));
+                Ok((role, caller))
+            }
+            Err(err) => {
+                warn!(error = %err, "token rejected");
+                Err(AppError::Unauthorized(err.to_string()))
+            }
+        }
+    }
+}
+
+/// Runs `future` with `caller`, as returned by `authorize`, available through
+/// `current_caller`.
+pub async fn scope<F: Future>(caller: Option<String>, future: F) -> F::Output {
+    CALLER.scope(caller, future).await
+}
+
+/// The authenticated caller of the request being served, if any.
+pub fn current_caller() -> Option<String> {
+    CALLER.try_with(|caller| caller.clone()).ok().flatten()
+}
+
+/// Names an API key without giving it away, e.g. `key:3f2a9c1e`.
+fn key_fingerprint(key: &str) -> String {
+    let digest = Sha256::digest(key.as_bytes());
+    let hex: String = digest[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("key:{}", hex)
+}
+
+/// The API key of `X-Api-Key`, or else the bearer token.
+pub fn credential(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get(API_KEY_HEADER) {
+        return key.to_str().ok();
+    }
+    let authorization = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = authorization.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// Compares in constant time, so response times don't give away how much of a
+/// key was guessed right.
+fn contains(keys: &[String], candidate: &str) -> bool {
+    keys.iter().fold(false, |found, key| {
+        found | constant_time_eq(key.as_bytes(), candidate.as_bytes())
+    })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
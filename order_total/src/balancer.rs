@@ -0,0 +1,199 @@
+//! Client-side load balancing over the endpoints of the sales tax rate
+//! service, configured as a list or discovered. Each lookup goes to the next
+//! endpoint in turn, or to the one with the fewest lookups in flight, and an
+//! endpoint failing `max_failures` calls in a row is ejected from the
+//! rotation for `ejection_ms`.
+
+use prometheus::{IntCounter, IntGauge};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::{BalancingStrategy, LoadBalancerConfig};
+use crate::metrics::Metrics;
+
+/// An endpoint and what is known of its health.
+struct Endpoint {
+    url: String,
+    /// Lookups sent and not answered yet.
+    outstanding: AtomicUsize,
+    /// Failed calls since the last successful one.
+    failures: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn new(url: String) -> Self {
+        Self {
+            url,
+            outstanding: AtomicUsize::new(0),
+            failures: AtomicU32::new(0),
+            ejected_until: Mutex::new(None),
+        }
+    }
+}
+
+/// An endpoint picked for a call, counted as outstanding until dropped.
+pub struct Lease {
+    endpoint: Arc<Endpoint>,
+}
+
+impl Lease {
+    pub fn url(&self) -> &str {
+        &self.endpoint.url
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.endpoint.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The endpoints of the sales tax rate service and how calls are spread
+/// over them.
+pub struct Balancer {
+    strategy: BalancingStrategy,
+    /// 0 never ejects an endpoint.
+    max_failures: u32,
+    ejection: Duration,
+    endpoints: RwLock<Vec<Arc<Endpoint>>>,
+    next: AtomicUsize,
+    in_rotation: IntGauge,
+    ejections: IntCounter,
+}
+
+impl Balancer {
+    /// A balancer without endpoints yet.
+    pub fn new(config: &LoadBalancerConfig, metrics: &Metrics) -> Self {
+        Self {
+            strategy: config.strategy,
+            max_failures: config.max_failures,
+            ejection: Duration::from_millis(config.ejection_ms),
+            endpoints: RwLock::new(Vec::new()),
+            next: AtomicUsize::new(0),
+            in_rotation: metrics.upstream_instances.clone(),
+            ejections: metrics.upstream_ejections.clone(),
+        }
+    }
+
+    /// Balances over `urls` from now on. Endpoints kept from before keep
+    /// their health.
+    pub fn set_endpoints(&self, urls: Vec<String>) {
+        let mut endpoints = self.endpoints.write().unwrap();
+        let updated: Vec<Arc<Endpoint>> = urls
+            .into_iter()
+            .map(|url| {
+                endpoints
+                    .iter()
+                    .find(|endpoint| endpoint.url == url)
+                    .cloned()
+                    .unwrap_or_else(|| Arc::new(Endpoint::new(url)))
+            })
+            .collect();
+        *endpoints = updated;
+        self.in_rotation
+            .set(available(&endpoints, Instant::now()).len() as i64);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.read().unwrap().is_empty()
+    }
+
+    /// The URLs of all endpoints, ejected or not.
+    pub fn urls(&self) -> Vec<String> {
+        let endpoints = self.endpoints.read().unwrap();
+        endpoints
+            .iter()
+            .map(|endpoint| endpoint.url.clone())
+            .collect()
+    }
+
+    /// The endpoint of the next call, by the strategy, among those not
+    /// ejected; among all of them when every one is ejected.
+    pub fn pick(&self) -> Option<Lease> {
+        let endpoints = self.endpoints.read().unwrap();
+        let now = Instant::now();
+        let readmitted = endpoints
+            .iter()
+            .filter(|endpoint| readmit(endpoint, now))
+            .count();
+        if readmitted > 0 {
+            self.in_rotation
+                .set(available(&endpoints, now).len() as i64);
+        }
+        let mut candidates = available(&endpoints, now);
+        if candidates.is_empty() {
+            candidates = endpoints.iter().collect();
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let count = candidates.len();
+        let endpoint = match self.strategy {
+            BalancingStrategy::RoundRobin => candidates[start % count],
+            // Ties go round robin.
+            BalancingStrategy::LeastOutstanding => (0..count)
+                .map(|offset| candidates[(start + offset) % count])
+                .min_by_key(|endpoint| endpoint.outstanding.load(Ordering::Relaxed))
+                .unwrap(),
+        };
+        endpoint.outstanding.fetch_add(1, Ordering::Relaxed);
+        Some(Lease {
+            endpoint: endpoint.clone(),
+        })
+    }
+
+    /// Records the outcome of a call to the endpoint of `lease`, ejecting
+    /// the endpoint after `max_failures` failed calls in a row.
+    pub fn record(&self, lease: &Lease, success: bool) {
+        let endpoint = &lease.endpoint;
+        if success {
+            endpoint.failures.store(0, Ordering::Relaxed);
+            return;
+        }
+        let failures = endpoint.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.max_failures == 0 || failures < self.max_failures {
+            return;
+        }
+        endpoint.failures.store(0, Ordering::Relaxed);
+        *endpoint.ejected_until.lock().unwrap() = Some(Instant::now() + self.ejection);
+        self.ejections.inc();
+        warn!(
+            url = %endpoint.url,
+            failures,
+            ejection_ms = self.ejection.as_millis() as u64,
+            "sales tax rate service endpoint ejected"
+        );
+        let endpoints = self.endpoints.read().unwrap();
+        self.in_rotation
+            .set(available(&endpoints, Instant::now()).len() as i64);
+    }
+}
+
+/// The endpoints not ejected at `now`.
+fn available(endpoints: &[Arc<Endpoint>], now: Instant) -> Vec<&Arc<Endpoint>> {
+    endpoints
+        .iter()
+        .filter(|endpoint| {
+            let ejected_until = endpoint.ejected_until.lock().unwrap();
+            ejected_until.is_none_or(|until| until <= now)
+        })
+        .collect()
+}
+
+/// Ends the ejection of `endpoint` if it is over at `now`, and tells whether
+/// it did.
+fn readmit(endpoint: &Endpoint, now: Instant) -> bool {
+    let mut ejected_until = endpoint.ejected_until.lock().unwrap();
+    match *ejected_until {
+        Some(until) if until <= now => {
+            *ejected_until = None;
+            info!(url = %endpoint.url, "sales tax rate service endpoint readmitted");
+            true
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,172 @@
+use domain::{Decimal, ErrorEnvelope, Order};
+use futures::future::join_all;
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use utoipa::ToSchema;
+
+use crate::codec::{self, Format};
+use crate::error::AppError;
+use crate::inventory::Reservation;
+use crate::response_build_as;
+use crate::service::{
+    apply_rate, audit, check_exemption, convert_currency, fetch_rate, normalize_address,
+    prepare_order, record_order, reserve_stock, set_rounding, Rate,
+};
+use crate::state::AppState;
+use crate::validation;
+
+/// The outcome for one order of a batch; a failing order doesn't fail the batch.
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum BatchEntry {
+    Ok { index: usize, order: Box<Order> },
+    Error { index: usize, error: ErrorEnvelope },
+}
+
+/// An order of a batch as received, for the audit log, ready to be taxed,
+/// and its stock reservation.
+type Prepared = (Order, Order, Option<Reservation>);
+
+#[derive(Serialize, ToSchema)]
+pub struct BatchResponse {
+    results: Vec<BatchEntry>,
+}
+
+/// One line of the CSV summary of a batch: the totals of a priced order, or
+/// the error of a failed one.
+#[derive(Serialize)]
+struct SummaryRow<'a> {
+    index: usize,
+    status: &'static str,
+    order_id: Option<i32>,
+    shipping_zip: Option<&'a str>,
+    currency: Option<&'a str>,
+    subtotal: Option<Decimal>,
+    discount: Option<Decimal>,
+    shipping: Option<Decimal>,
+    tax: Option<Decimal>,
+    total: Option<Decimal>,
+    error_code: Option<&'a str>,
+    error_message: Option<&'a str>,
+}
+
+impl<'a> SummaryRow<'a> {
+    fn new(entry: &'a BatchEntry) -> Self {
+        match entry {
+            BatchEntry::Ok { index, order } => Self {
+                index: *index,
+                status: "ok",
+                order_id: Some(order.order_id),
+                shipping_zip: Some(&order.shipping_zip),
+                currency: order.currency.as_deref(),
+                subtotal: order.subtotal,
+                discount: Some(order.discount),
+                shipping: Some(order.shipping),
+                tax: Some(order.tax),
+                total: Some(order.total),
+                error_code: None,
+                error_message: None,
+            },
+            BatchEntry::Error { index, error } => Self {
+                index: *index,
+                status: "error",
+                order_id: None,
+                shipping_zip: None,
+                currency: None,
+                subtotal: None,
+                discount: None,
+                shipping: None,
+                tax: None,
+                total: None,
+                error_code: Some(&error.code),
+                error_message: Some(&error.message),
+            },
+        }
+    }
+}
+
+/// Prices a JSON array of orders. Every distinct zip code of an order that
+/// isn't tax exempt is looked up once, all lookups run concurrently, and each
+/// order gets its own result entry, answered in `format`: JSON, or a CSV
+/// summary with one row per order.
+pub async fn handle_batch(
+    state: &AppState,
+    body: &[u8],
+    format: Format,
+) -> Result<Response<Body>, AppError> {
+    let start = Instant::now();
+    let items: Vec<Value> = serde_json::from_slice(body)?;
+    let parsed: Vec<Result<Prepared, AppError>> =
+        join_all(items.into_iter().map(|item| async move {
+            let mut order: Order = serde_json::from_value(item)?;
+            normalize_address(state, &mut order).await?;
+            validation::validate(&order)?;
+            let received = order.clone();
+            set_rounding(state, &mut order);
+            convert_currency(state, &mut order).await?;
+            check_exemption(state, &mut order).await?;
+            prepare_order(state, &mut order)?;
+            let reservation = reserve_stock(state, &mut order).await?;
+            Ok((received, order, reservation))
+        }))
+        .await;
+
+    let zips: HashSet<&str> = parsed
+        .iter()
+        .filter_map(|order| order.as_ref().ok())
+        .filter(|(_, order, _)| order.exemption_reference.is_none())
+        .map(|(_, order, _)| order.shipping_zip.as_str())
+        .collect();
+    let lookups = zips
+        .into_iter()
+        .map(|zip| async move { (zip.to_string(), fetch_rate(state, zip).await) });
+    let rates: HashMap<String, Result<Rate, AppError>> =
+        join_all(lookups).await.into_iter().collect();
+
+    let results: Vec<BatchEntry> = parsed
+        .into_iter()
+        .enumerate()
+        .map(|(index, order)| {
+            let priced = order.map_err(|err| err.envelope()).and_then(
+                |(received, mut order, reservation)| {
+                    let rate = match order.exemption_reference {
+                        Some(_) => Ok(Rate::exempt()),
+                        None => rates[&order.shipping_zip].clone(),
+                    };
+                    match rate {
+                        Ok(rate) => {
+                            apply_rate(&mut order, &rate);
+                            record_order(state, &order, rate.value, None);
+                            audit(state, &received, &order, &rate, start.elapsed());
+                            if let Some(reservation) = reservation {
+                                reservation.keep();
+                            }
+                            Ok(order)
+                        }
+                        // Dropping the reservation releases it.
+                        Err(err) => Err(err.envelope()),
+                    }
+                },
+            );
+            match priced {
+                Ok(order) => BatchEntry::Ok {
+                    index,
+                    order: Box::new(order),
+                },
+                Err(error) => BatchEntry::Error { index, error },
+            }
+        })
+        .collect();
+
+    match format {
+        Format::Csv => {
+            let rows: Vec<SummaryRow> = results.iter().map(SummaryRow::new).collect();
+            let body = codec::csv(&rows)?;
+            Ok(response_build_as(StatusCode::OK, format, body))
+        }
+        _ => format.respond(&BatchResponse { results }),
+    }
+}
@@ -0,0 +1,47 @@
+//! Reading request bodies. `hyper::body::to_bytes` buffers whatever it is sent,
+//! so bodies are read here chunk by chunk up to the configured size, then
+//! decompressed if sent with a `Content-Encoding`.
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::CONTENT_LENGTH;
+use hyper::{Body, Request};
+
+use crate::codec::{self, Format};
+use crate::compression;
+use crate::error::AppError;
+use crate::MAX_BODY_BYTES;
+
+/// The body of `req`, decompressed, failing with `PayloadTooLarge` past the
+/// size limit. The limit holds both before and after decompression.
+pub async fn read(req: Request<Body>) -> Result<Bytes, AppError> {
+    let limit = *MAX_BODY_BYTES;
+    let encoding = compression::request_encoding(req.headers())?;
+    let announced = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if matches!(announced, Some(length) if length > limit as u64) {
+        return Err(AppError::PayloadTooLarge(limit));
+    }
+
+    let mut body = req.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(AppError::PayloadTooLarge(limit));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    match encoding {
+        Some(encoding) => compression::decompress(encoding, &bytes, limit),
+        None => Ok(bytes.into()),
+    }
+}
+
+/// Rejects bodies that aren't declared as JSON. Requests without a
+/// `Content-Type` are taken to be JSON.
+pub fn require_json(req: &Request<Body>) -> Result<(), AppError> {
+    codec::request_format(req, &[Format::Json]).map(|_| ())
+}
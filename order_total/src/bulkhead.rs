@@ -0,0 +1,213 @@
+//! Bulkheads: caps on the calls in flight to one dependency or through one
+//! handler, so a slow sales tax rate service or a burst of orders ties up a
+//! bounded share of the runtime rather than all of it. A call over the cap
+//! waits for a slot up to the queue timeout, then fails with `OVERLOADED`.
+//!
+//! Calls wait in one queue per priority class. A freed slot goes to the
+//! longest waiting call of the class whose turn it is, the classes taking
+//! turns by weight, e.g. four interactive calls for one batch call.
+
+use prometheus::{IntCounter, IntGauge};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use crate::config::{BulkheadConfig, PriorityConfig};
+use crate::error::AppError;
+use crate::metrics::Metrics;
+use crate::priority::{self, Priority};
+
+pub struct Bulkhead {
+    name: &'static str,
+    /// None without a cap.
+    slots: Option<Slots>,
+    queue_timeout: Duration,
+    /// By priority class.
+    rejections: [IntCounter; 2],
+    waiting: [IntGauge; 2],
+}
+
+/// The slots of a capped bulkhead, and the calls waiting for one.
+struct Slots {
+    capacity: usize,
+    /// By priority class.
+    weights: [u32; 2],
+    max_queued: [usize; 2],
+    state: Mutex<SlotState>,
+}
+
+struct SlotState {
+    in_flight: usize,
+    /// The calls waiting for a slot, by priority class. A call that gave up
+    /// waiting has its receiver closed.
+    waiting: [VecDeque<oneshot::Sender<()>>; 2],
+    /// The slots each class may still be handed before the weights are
+    /// replenished.
+    credits: [u32; 2],
+}
+
+impl Bulkhead {
+    /// The bulkhead `name` of `config`, shared between the classes by
+    /// `priority`; rejected and waiting calls are counted in `metrics` under
+    /// that name.
+    pub fn new(
+        name: &'static str,
+        config: &BulkheadConfig,
+        priority: &PriorityConfig,
+        metrics: &Metrics,
+    ) -> Self {
+        let weights = [
+            priority.interactive.weight.max(1),
+            priority.batch.weight.max(1),
+        ];
+        let labels = |class: Priority| [name, class.as_str()];
+        Self {
+            name,
+            slots: (config.max_concurrent > 0).then(|| Slots {
+                capacity: config.max_concurrent,
+                weights,
+                max_queued: [priority.interactive.max_queued, priority.batch.max_queued],
+                state: Mutex::new(SlotState {
+                    in_flight: 0,
+                    waiting: Default::default(),
+                    credits: weights,
+                }),
+            }),
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+            rejections: Priority::ALL.map(|class| {
+                metrics
+                    .bulkhead_rejections
+                    .with_label_values(&labels(class))
+            }),
+            waiting: Priority::ALL
+                .map(|class| metrics.bulkhead_waiting.with_label_values(&labels(class))),
+        }
+    }
+
+    /// Runs `call` once a slot is free, holding it until `call` is done. The
+    /// call waits in the queue of the class of the request being served.
+    pub async fn run<T>(
+        &self,
+        call: impl Future<Output = Result<T, AppError>>,
+    ) -> Result<T, AppError> {
+        let slots = match &self.slots {
+            Some(slots) => slots,
+            None => return call.await,
+        };
+        let class = priority::current();
+        let _slot = match self.acquire(slots, class).await {
+            Some(slot) => slot,
+            None => {
+                self.rejections[class.index()].inc();
+                return Err(AppError::Overloaded(self.name.to_string()));
+            }
+        };
+        call.await
+    }
+
+    /// A slot, at once if one is free and no call is waiting, or else once
+    /// handed over within the queue timeout. None when the queue of `class`
+    /// is full or the wait times out.
+    async fn acquire<'a>(&self, slots: &'a Slots, class: Priority) -> Option<Slot<'a>> {
+        let receiver = {
+            let mut state = slots.state.lock().unwrap();
+            state.forget_abandoned();
+            if state.in_flight < slots.capacity && state.waiting.iter().all(VecDeque::is_empty) {
+                state.in_flight += 1;
+                return Some(Slot { slots });
+            }
+            let queue = &mut state.waiting[class.index()];
+            let max_queued = slots.max_queued[class.index()];
+            if max_queued > 0 && queue.len() >= max_queued {
+                return None;
+            }
+            let (sender, receiver) = oneshot::channel();
+            queue.push_back(sender);
+            receiver
+        };
+        let mut waiter = Waiter {
+            slots,
+            receiver,
+            gauge: &self.waiting[class.index()],
+        };
+        waiter.gauge.inc();
+        match tokio::time::timeout(self.queue_timeout, &mut waiter.receiver).await {
+            Ok(Ok(())) => Some(Slot { slots }),
+            // Dropping the waiter gives back a slot handed over meanwhile.
+            _ => None,
+        }
+    }
+}
+
+impl Slots {
+    /// Hands the slot of a finished call to the next waiting call, or frees
+    /// it.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(class) = self.next_class(&mut state) {
+            let waiter = state.waiting[class].pop_front().unwrap();
+            if waiter.send(()).is_ok() {
+                return;
+            }
+        }
+        state.in_flight -= 1;
+    }
+
+    /// The class whose waiting call gets the next slot: weighted round robin
+    /// over the classes with waiting calls, interactive first in each round.
+    fn next_class(&self, state: &mut SlotState) -> Option<usize> {
+        state.forget_abandoned();
+        let waiting: Vec<usize> = (0..Priority::ALL.len())
+            .filter(|class| !state.waiting[*class].is_empty())
+            .collect();
+        if waiting.iter().all(|class| state.credits[*class] == 0) {
+            state.credits = self.weights;
+        }
+        let class = waiting
+            .into_iter()
+            .find(|class| state.credits[*class] > 0)?;
+        state.credits[class] -= 1;
+        Some(class)
+    }
+}
+
+impl SlotState {
+    /// Drops the calls that gave up waiting.
+    fn forget_abandoned(&mut self) {
+        for queue in &mut self.waiting {
+            queue.retain(|waiter| !waiter.is_closed());
+        }
+    }
+}
+
+/// A slot held by a call, handed over or freed when dropped.
+struct Slot<'a> {
+    slots: &'a Slots,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.slots.release();
+    }
+}
+
+/// A call waiting for a slot. Dropped, timed out or cancelled, it stops
+/// waiting, and passes on a slot it was handed in the meantime.
+struct Waiter<'a> {
+    slots: &'a Slots,
+    receiver: oneshot::Receiver<()>,
+    gauge: &'a IntGauge,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.gauge.dec();
+        self.receiver.close();
+        // A slot handed over but not taken goes to the next call.
+        if let Ok(()) = self.receiver.try_recv() {
+            self.slots.release();
+        }
+    }
+}
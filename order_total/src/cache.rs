@@ -0,0 +1,333 @@
+use domain::money::{self, Decimal};
+use domain::RateComponent;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+use crate::rates::Quote;
+
+#[derive(Debug)]
+struct Entry {
+    quote: Quote,
+    inserted_at: Instant,
+    /// Logical clock value of the last read or write, used for LRU eviction.
+    last_used: u64,
+    /// Reads of the rate, kept when it is refreshed.
+    hits: u64,
+    /// Whether a caller has been told to refresh this stale entry.
+    refreshing: bool,
+}
+
+/// A rate found in the cache.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cached {
+    Fresh(Quote),
+    /// Past its time to live but within the stale tolerance. `refresh` is set
+    /// for the one caller that should look the rate up again.
+    Stale {
+        quote: Quote,
+        refresh: bool,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+    ttl: Duration,
+    stale: Duration,
+}
+
+/// A line of the snapshot file.
+#[derive(Serialize, Deserialize)]
+struct SnapshotEntry {
+    key: String,
+    #[serde(with = "money::json_number")]
+    rate: Decimal,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    components: Vec<RateComponent>,
+    /// Unix seconds the rate was cached at.
+    cached_at: u64,
+}
+
+/// A snapshot of one cache entry, as reported by the admin endpoint.
+#[derive(Serialize, ToSchema)]
+pub struct CacheEntryInfo {
+    pub zip: String,
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub rate: Decimal,
+    pub age_seconds: u64,
+    /// Past its time to live, served only while it is refreshed.
+    pub stale: bool,
+}
+
+/// A snapshot of the whole cache, as reported by the admin endpoint.
+#[derive(Serialize, ToSchema)]
+pub struct CacheInfo {
+    pub ttl_seconds: u64,
+    pub stale_seconds: u64,
+    pub max_entries: usize,
+    pub size: usize,
+    pub entries: Vec<CacheEntryInfo>,
+}
+
+/// Sales tax rates by zip code, kept for a time to live, which can be changed
+/// with `set_ttl`. When full, the least recently used entry makes room for a
+/// new one.
+///
+/// With a stale tolerance, expired entries are still served for that long,
+/// stale-while-revalidate, while one caller refreshes them.
+#[derive(Debug)]
+pub struct RateCache {
+    max_entries: usize,
+    inner: Mutex<Inner>,
+}
+
+impl RateCache {
+    pub fn new(ttl: Duration, stale: Duration, max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            inner: Mutex::new(Inner {
+                ttl,
+                stale,
+                ..Inner::default()
+            }),
+        }
+    }
+
+    /// Changes the time to live and the stale tolerance, of the entries
+    /// already cached too.
+    pub fn set_ttl(&self, ttl: Duration, stale: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.ttl = ttl;
+        inner.stale = stale;
+    }
+
+    pub fn get(&self, zip: &str) -> Option<Cached> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let (clock, ttl, stale) = (inner.clock, inner.ttl, inner.stale);
+        let expired = match inner.entries.get_mut(zip) {
+            Some(entry) if entry.inserted_at.elapsed() < ttl => {
+                entry.last_used = clock;
+                entry.hits += 1;
+                return Some(Cached::Fresh(entry.quote.clone()));
+            }
+            Some(entry) if entry.inserted_at.elapsed() < ttl + stale => {
+                entry.last_used = clock;
+                entry.hits += 1;
+                let refresh = !entry.refreshing;
+                entry.refreshing = true;
+                return Some(Cached::Stale {
+                    quote: entry.quote.clone(),
+                    refresh,
+                });
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            inner.entries.remove(zip);
+        }
+        None
+    }
+
+    /// Lets the next caller refresh a stale entry, after a failed refresh.
+    pub fn refresh_failed(&self, zip: &str) {
+        if let Some(entry) = self.inner.lock().unwrap().entries.get_mut(zip) {
+            entry.refreshing = false;
+        }
+    }
+
+    pub fn insert(&self, zip: &str, quote: Quote) {
+        self.insert_at(zip, quote, Instant::now());
+    }
+
+    /// Caches a rate looked up at `cached_at`, such as by another replica,
+    /// for what is left of its time to live, and tells whether it was still
+    /// fresh. A rate past its time to live isn't cached.
+    pub fn insert_cached_at(&self, zip: &str, quote: Quote, cached_at: SystemTime) -> bool {
+        let age = cached_at.elapsed().unwrap_or_default();
+        if age >= self.inner.lock().unwrap().ttl {
+            return false;
+        }
+        match Instant::now().checked_sub(age) {
+            Some(inserted_at) => {
+                self.insert_at(zip, quote, inserted_at);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// How long a rate is served: its time to live and stale tolerance.
+    pub fn served_for(&self) -> Duration {
+        let inner = self.inner.lock().unwrap();
+        inner.ttl + inner.stale
+    }
+
+    fn insert_at(&self, zip: &str, quote: Quote, inserted_at: Instant) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let hits = inner.entries.get(zip).map_or(0, |entry| entry.hits);
+        if !inner.entries.contains_key(zip) && inner.entries.len() >= self.max_entries {
+            let lru = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(zip, _)| zip.clone());
+            if let Some(lru) = lru {
+                inner.entries.remove(&lru);
+            }
+        }
+        inner.entries.insert(
+            zip.to_string(),
+            Entry {
+                quote,
+                inserted_at,
+                last_used: clock,
+                hits,
+                refreshing: false,
+            },
+        );
+    }
+
+    /// The keys of the `count` most read rates still cached, most read first.
+    pub fn hot(&self, count: usize) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        let mut entries: Vec<(&String, &Entry)> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.hits > 0)
+            .filter(|(_, entry)| entry.inserted_at.elapsed() < inner.ttl + inner.stale)
+            .collect();
+        entries.sort_by(|a, b| b.1.hits.cmp(&a.1.hits).then_with(|| a.0.cmp(b.0)));
+        entries
+            .into_iter()
+            .take(count)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Writes the rates still served to `path`, one JSON line each, and
+    /// returns how many there were. The file is replaced once written, so a
+    /// crash while writing leaves the previous snapshot.
+    pub fn save_snapshot(&self, path: &Path) -> anyhow::Result<usize> {
+        let now = SystemTime::now();
+        let entries: Vec<SnapshotEntry> = {
+            let inner = self.inner.lock().unwrap();
+            inner
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.inserted_at.elapsed() < inner.ttl + inner.stale)
+                .map(|(key, entry)| SnapshotEntry {
+                    key: key.clone(),
+                    rate: entry.quote.rate,
+                    components: entry.quote.components.clone(),
+                    cached_at: unix_secs(now - entry.inserted_at.elapsed()),
+                })
+                .collect()
+        };
+        let partial = path.with_extension("partial");
+        let mut file = BufWriter::new(File::create(&partial)?);
+        for entry in &entries {
+            serde_json::to_writer(&mut file, entry)?;
+            file.write_all(b"\n")?;
+        }
+        file.into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&partial, path)?;
+        Ok(entries.len())
+    }
+
+    /// Caches the rates of the snapshot at `path` cached less than `max_age`
+    /// ago and still served, and returns how many there were, and how many
+    /// lines were skipped as unreadable, such as the last line of a snapshot
+    /// cut short. A missing snapshot has no rates.
+    pub fn load_snapshot(&self, path: &Path, max_age: Duration) -> anyhow::Result<(usize, usize)> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+            Err(err) => return Err(err.into()),
+        };
+        let served_for = {
+            let inner = self.inner.lock().unwrap();
+            inner.ttl + inner.stale
+        };
+        let now = unix_secs(SystemTime::now());
+        let (mut loaded, mut skipped) = (0, 0);
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: SnapshotEntry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let age = Duration::from_secs(now.saturating_sub(entry.cached_at));
+            if age >= max_age || age >= served_for {
+                continue;
+            }
+            if let Some(inserted_at) = Instant::now().checked_sub(age) {
+                let quote = Quote {
+                    rate: entry.rate,
+                    components: entry.components,
+                };
+                self.insert_at(&entry.key, quote, inserted_at);
+                loaded += 1;
+            }
+        }
+        Ok((loaded, skipped))
+    }
+
+    /// Drops every entry and returns how many there were.
+    pub fn flush(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let count = inner.entries.len();
+        inner.entries.clear();
+        count
+    }
+
+    pub fn info(&self) -> CacheInfo {
+        let inner = self.inner.lock().unwrap();
+        let mut entries: Vec<CacheEntryInfo> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.inserted_at.elapsed() < inner.ttl + inner.stale)
+            .map(|(zip, entry)| CacheEntryInfo {
+                zip: zip.clone(),
+                rate: entry.quote.rate,
+                age_seconds: entry.inserted_at.elapsed().as_secs(),
+                stale: entry.inserted_at.elapsed() >= inner.ttl,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.zip.cmp(&b.zip));
+        CacheInfo {
+            ttl_seconds: inner.ttl.as_secs(),
+            stale_seconds: inner.stale.as_secs(),
+            max_entries: self.max_entries,
+            size: entries.len(),
+            entries,
+        }
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
@@ -0,0 +1,92 @@
+//! Fault injection, for resilience demos and tests: when `chaos.enabled`, a
+//! share of the API requests and of the calls to the sales tax rate service
+//! is delayed or failed at random. Inbound faults fail requests with
+//! `INJECTED_FAULT`; upstream faults answer the call with a `503`, which the
+//! retries and the circuit breaker handle like any other. A delay longer than
+//! `upstream.timeout_ms` times the call out.
+//!
+//! The draws come from one random number generator, seeded by `chaos.seed`
+//! when set, so the same calls in the same order meet the same faults.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::{ChaosConfig, FaultConfig};
+
+/// What happens to a request or call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Delay(Duration),
+    Fail,
+}
+
+pub struct FaultInjector {
+    inbound: FaultConfig,
+    upstream: FaultConfig,
+    rng: Mutex<StdRng>,
+}
+
+impl FaultInjector {
+    /// The injector of `config`, or none when fault injection is off. Fails
+    /// on percentages out of 0-100 or adding up to more than 100.
+    pub fn from_config(config: &ChaosConfig) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        for (name, faults) in [("inbound", &config.inbound), ("upstream", &config.upstream)] {
+            let total = faults.failure_percent + faults.delay_percent;
+            if faults.failure_percent < 0.0
+                || faults.delay_percent < 0.0
+                || !(0.0..=100.0).contains(&total)
+            {
+                anyhow::bail!(
+                    "chaos.{}: failure_percent and delay_percent must be between 0 and 100, and add up to 100 at most",
+                    name
+                );
+            }
+        }
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Ok(Some(Self {
+            inbound: config.inbound.clone(),
+            upstream: config.upstream.clone(),
+            rng: Mutex::new(rng),
+        }))
+    }
+
+    /// The fault of an API request, if any.
+    pub fn inbound(&self) -> Option<Fault> {
+        self.draw(&self.inbound)
+    }
+
+    /// The fault of a call to the sales tax rate service, if any.
+    pub fn upstream(&self) -> Option<Fault> {
+        self.draw(&self.upstream)
+    }
+
+    /// One draw per request, faulty or not, so a seed always gives the same
+    /// sequence of faults.
+    fn draw(&self, faults: &FaultConfig) -> Option<Fault> {
+        let roll = self.rng.lock().unwrap().gen_range(0.0..100.0);
+        if roll < faults.failure_percent {
+            Some(Fault::Fail)
+        } else if roll < faults.failure_percent + faults.delay_percent {
+            Some(Fault::Delay(Duration::from_millis(faults.delay_ms)))
+        } else {
+            None
+        }
+    }
+}
+
+/// The answer of a failed call to the sales tax rate service.
+pub fn failed_upstream_response() -> reqwest::Response {
+    let response = hyper::Response::builder()
+        .status(hyper::StatusCode::SERVICE_UNAVAILABLE)
+        .body("injected fault")
+        .unwrap();
+    reqwest::Response::from(response)
+}
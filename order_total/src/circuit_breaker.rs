@@ -0,0 +1,122 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+/// Where the breaker currently stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls go through; consecutive failures are being counted.
+    Closed,
+    /// Calls are rejected right away until the cooldown has passed.
+    Open,
+    /// The cooldown has passed and a single trial call is allowed through.
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    /// When the current half-open trial call started, if one is running.
+    trial_started_at: Option<Instant>,
+}
+
+/// A snapshot of the breaker, as reported by the admin endpoint.
+#[derive(Serialize, ToSchema)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub failure_threshold: u32,
+    /// While open, how long until a trial call is let through.
+    pub retry_after_ms: Option<u64>,
+}
+
+/// A circuit breaker guarding the sales tax rate service, so requests fail
+/// fast while it is down instead of each waiting for a connection timeout.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            inner: Mutex::new(Inner {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                trial_started_at: None,
+            }),
+        }
+    }
+
+    /// Asks for permission to make a call. While the circuit is open this
+    /// returns how long the caller should wait before trying again.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::Open => {
+                let elapsed = inner.opened_at.map_or(self.cooldown, |at| at.elapsed());
+                if elapsed >= self.cooldown {
+                    inner.state = CircuitState::HalfOpen;
+                    inner.trial_started_at = Some(Instant::now());
+                    Ok(())
+                } else {
+                    Err(self.cooldown - elapsed)
+                }
+            }
+            CircuitState::HalfOpen => match inner.trial_started_at {
+                // Only one trial call at a time; the others wait it out. A trial
+                // that never reported back (e.g. its request was cancelled)
+                // stops blocking others after a cooldown.
+                Some(started) if started.elapsed() < self.cooldown => Err(Duration::from_secs(1)),
+                _ => {
+                    inner.trial_started_at = Some(Instant::now());
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let inner = self.inner.lock().unwrap();
+        let retry_after = match (inner.state, inner.opened_at) {
+            (CircuitState::Open, Some(at)) => Some(self.cooldown.saturating_sub(at.elapsed())),
+            _ => None,
+        };
+        CircuitStatus {
+            state: inner.state,
+            consecutive_failures: inner.consecutive_failures,
+            failure_threshold: self.failure_threshold,
+            retry_after_ms: retry_after.map(|delay| delay.as_millis() as u64),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = CircuitState::Closed;
+        inner.consecutive_failures = 0;
+        inner.opened_at = None;
+        inner.trial_started_at = None;
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        inner.trial_started_at = None;
+        if inner.state == CircuitState::HalfOpen
+            || inner.consecutive_failures >= self.failure_threshold
+        {
+            inner.state = CircuitState::Open;
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Content negotiation: the formats request bodies are read in, from their
+//! `Content-Type`, and responses written in, from the `Accept` header. The
+//! services work on JSON; other request formats are transcoded to JSON as
+//! they come in, so validation, idempotency and error messages don't depend
+//! on the format.
+
+use hyper::body::Bytes;
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::AppError;
+use crate::response_build_as;
+
+/// A serialization format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    /// Compact binary JSON, for service-to-service calls.
+    MessagePack,
+    /// Only written, as a table of one record per row.
+    Csv,
+}
+
+impl Format {
+    /// The media type of responses in this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    /// The format of a media type, without parameters and in lower case.
+    fn of_media_type(essence: &str) -> Option<Format> {
+        match essence {
+            "application/json" => Some(Format::Json),
+            essence if essence.starts_with("application/") && essence.ends_with("+json") => {
+                Some(Format::Json)
+            }
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            "text/csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    /// `body`, read in this format, as JSON.
+    pub fn to_json(self, body: Bytes) -> Result<Bytes, AppError> {
+        match self {
+            Format::Json => Ok(body),
+            Format::MessagePack => {
+                let value: Value = rmp_serde::from_slice(&body)
+                    .map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+                Ok(serde_json::to_vec(&value)?.into())
+            }
+            Format::Csv => Err(AppError::UnsupportedMediaType(self.content_type().into())),
+        }
+    }
+
+    /// `value` in this format; JSON is pretty-printed. CSV responses are
+    /// written with `csv` instead.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, AppError> {
+        match self {
+            Format::Json => {
+                serde_json::to_vec_pretty(value).map_err(|err| anyhow::Error::from(err).into())
+            }
+            Format::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(|err| anyhow::Error::from(err).into())
+            }
+            Format::Csv => Err(anyhow::anyhow!("CSV takes a list of rows").into()),
+        }
+    }
+
+    /// A `200` response with `value` in this format.
+    pub fn respond<T: Serialize>(self, value: &T) -> Result<Response<Body>, AppError> {
+        Ok(response_build_as(StatusCode::OK, self, self.encode(value)?))
+    }
+}
+
+/// `rows` as a CSV table, with a header row named after the fields.
+pub fn csv<R: Serialize>(rows: &[R]) -> Result<Vec<u8>, AppError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row).map_err(anyhow::Error::from)?;
+    }
+    writer
+        .into_inner()
+        .map_err(|err| anyhow::anyhow!("cannot write CSV: {}", err.error()).into())
+}
+
+fn essence(media_type: &str) -> String {
+    media_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// The format of the request body, one of `accepted`. Requests without a
+/// `Content-Type` are taken to be JSON; others fail with
+/// `UnsupportedMediaType`.
+pub fn request_format(req: &Request<Body>, accepted: &[Format]) -> Result<Format, AppError> {
+    let content_type = match req.headers().get(CONTENT_TYPE) {
+        Some(value) => value.to_str().unwrap_or_default(),
+        None => return Ok(Format::Json),
+    };
+    Format::of_media_type(&essence(content_type))
+        .filter(|format| accepted.contains(format))
+        .ok_or_else(|| AppError::UnsupportedMediaType(content_type.to_string()))
+}
+
+/// The response format the client prefers among `offered`, by the quality
+/// values of its `Accept` header. The first offered format is the default,
+/// for requests without `Accept` or accepting `*/*`; `NotAcceptable` when the
+/// client accepts none of them.
+pub fn negotiate(req: &Request<Body>, offered: &[Format]) -> Result<Format, AppError> {
+    let accept = match req.headers().get(ACCEPT).and_then(|v| v.to_str().ok()) {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return Ok(offered[0]),
+    };
+    preferences(accept)
+        .iter()
+        .find_map(|(_, media_range)| offered_match(media_range, offered))
+        .ok_or_else(|| AppError::NotAcceptable(accept.to_string()))
+}
+
+/// The values of an `Accept` or `Accept-Encoding` header, most preferred
+/// first, without those the client refuses (`q=0`).
+pub fn preferences(header: &str) -> Vec<(f32, &str)> {
+    let mut values: Vec<(f32, &str)> = header
+        .split(',')
+        .map(|value| {
+            let mut params = value.split(';');
+            let value = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (quality, value)
+        })
+        .filter(|(quality, value)| *quality > 0.0 && !value.is_empty())
+        .collect();
+    // Stable, so equally preferred values keep the client's order.
+    values.sort_by(|a, b| b.0.total_cmp(&a.0));
+    values
+}
+
+/// The format of `offered` matching a media range such as `text/*`.
+fn offered_match(media_range: &str, offered: &[Format]) -> Option<Format> {
+    match media_range.to_ascii_lowercase().as_str() {
+        "*/*" => Some(offered[0]),
+        range if range.ends_with("/*") => {
+            let kind = &range[..range.len() - 1];
+            offered
+                .iter()
+                .copied()
+                .find(|format| format.content_type().starts_with(kind))
+        }
+        range => Format::of_media_type(range).filter(|format| offered.contains(format)),
+    }
+}
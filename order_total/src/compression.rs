@@ -0,0 +1,187 @@
+//! Compressed bodies: request bodies sent with a `Content-Encoding` are
+//! decompressed as they are read, and responses are compressed in the
+//! encoding the client prefers by its `Accept-Encoding`, once they reach
+//! `compression.min_bytes`. gzip, deflate and brotli are supported.
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{
+    HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY,
+};
+use hyper::{Body, HeaderMap, Response};
+use std::io::{self, Read, Write};
+use tracing::warn;
+
+use crate::codec;
+use crate::config::AppConfig;
+use crate::error::AppError;
+
+/// Brotli quality, 0 to 11, and window size, as a power of two: fast enough
+/// for responses compressed on every request.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const BUFFER_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    /// The zlib format, as HTTP's `deflate` is.
+    Deflate,
+}
+
+impl Encoding {
+    /// In order of preference, for clients accepting several equally.
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "br" => Some(Encoding::Brotli),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut compressed = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(
+                        &mut compressed,
+                        BUFFER_SIZE,
+                        BROTLI_QUALITY,
+                        BROTLI_WINDOW,
+                    );
+                    writer.write_all(data)?;
+                }
+                Ok(compressed)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    fn decoder<'a>(self, data: &'a [u8]) -> Box<dyn Read + 'a> {
+        match self {
+            Encoding::Brotli => Box::new(brotli::Decompressor::new(data, BUFFER_SIZE)),
+            Encoding::Gzip => Box::new(GzDecoder::new(data)),
+            Encoding::Deflate => Box::new(ZlibDecoder::new(data)),
+        }
+    }
+}
+
+/// The encoding of a request body, from its `Content-Encoding`; `None` when
+/// it isn't compressed. Fails with `UnsupportedEncoding` for other encodings,
+/// or several stacked ones.
+pub fn request_encoding(headers: &HeaderMap) -> Result<Option<Encoding>, AppError> {
+    let content_encoding = match headers.get(CONTENT_ENCODING) {
+        Some(value) => value.to_str().unwrap_or_default().trim(),
+        None => return Ok(None),
+    };
+    if content_encoding.is_empty() || content_encoding.eq_ignore_ascii_case("identity") {
+        return Ok(None);
+    }
+    Encoding::from_name(content_encoding)
+        .map(Some)
+        .ok_or_else(|| AppError::UnsupportedEncoding(content_encoding.to_string()))
+}
+
+/// Decompresses a request body, failing with `PayloadTooLarge` once it grows
+/// past `limit`, so a small compressed body can't expand without bound.
+pub fn decompress(encoding: Encoding, body: &[u8], limit: usize) -> Result<Bytes, AppError> {
+    let mut decompressed = Vec::new();
+    encoding
+        .decoder(body)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|err| {
+            AppError::InvalidPayload(format!("invalid {} body: {}", encoding.name(), err))
+        })?;
+    if decompressed.len() > limit {
+        return Err(AppError::PayloadTooLarge(limit));
+    }
+    Ok(decompressed.into())
+}
+
+/// The encoding to compress a response in, by the client's
+/// `Accept-Encoding`, if it accepts one.
+pub fn accepted(headers: &HeaderMap) -> Option<Encoding> {
+    let accept_encoding = headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+    let preferences = codec::preferences(accept_encoding);
+    let best = preferences.first()?.0;
+    Encoding::ALL.into_iter().find(|encoding| {
+        preferences
+            .iter()
+            .take_while(|(quality, _)| *quality >= best)
+            .any(|(_, name)| *name == "*" || Encoding::from_name(name) == Some(*encoding))
+    })
+}
+
+/// Compresses a response in `encoding` when compression is enabled and its
+/// body is buffered, not already encoded and at least `compression.min_bytes`
+/// long. Streamed bodies, such as `GET /events`, are left alone.
+pub async fn compress(response: Response<Body>, encoding: Option<Encoding>) -> Response<Body> {
+    let config = &AppConfig::get().compression;
+    let encoding = match encoding {
+        Some(encoding) if config.enabled => encoding,
+        _ => return response,
+    };
+    let length = response.body().size_hint().exact();
+    let compressible = matches!(length, Some(length) if length >= config.min_bytes as u64)
+        && !response.headers().contains_key(CONTENT_ENCODING)
+        && !is_event_stream(response.headers());
+    if !compressible {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // The body is in memory already, so this doesn't wait.
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            warn!(error = %err, "cannot read the response body to compress");
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    match encoding.compress(&body) {
+        Ok(compressed) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+            parts
+                .headers
+                .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+            Response::from_parts(parts, Body::from(compressed))
+        }
+        Err(err) => {
+            warn!(error = %err, encoding = encoding.name(), "cannot compress response");
+            Response::from_parts(parts, Body::from(body))
+        }
+    }
+}
+
+pub fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"))
+}
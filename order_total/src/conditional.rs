@@ -0,0 +1,102 @@
+//! Conditional `GET` requests: successful `GET` responses carry an `ETag`,
+//! a hash of their body, and requests whose `If-None-Match` has it again are
+//! answered `304 Not Modified` without the body, so gateways and browsers can
+//! revalidate what they cached. Their `Cache-Control` is set by route, from
+//! `http_cache.cache_control`.
+//!
+//! ETags are weak, as the response may be compressed afterwards, and compared
+//! weakly, as `If-None-Match` is.
+
+use hyper::body::HttpBody;
+use hyper::header::{
+    HeaderValue, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG,
+};
+use hyper::{Body, Method, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::compression;
+use crate::config::HttpCacheConfig;
+
+/// Sets the `ETag` and the `Cache-Control` of the route `route` on a
+/// successful `GET` response, and turns it into `304 Not Modified` when the
+/// request's `If-None-Match` has its ETag. Streamed bodies, such as
+/// `GET /events`, and responses setting their own `Cache-Control` are left
+/// alone.
+pub async fn apply(
+    config: &HttpCacheConfig,
+    method: &Method,
+    route: &str,
+    if_none_match: Option<HeaderValue>,
+    mut response: Response<Body>,
+) -> Response<Body> {
+    let cacheable = (method == Method::GET || method == Method::HEAD)
+        && response.status() == StatusCode::OK
+        && !response.headers().contains_key(CACHE_CONTROL);
+    if !cacheable || is_streamed(&response) {
+        return response;
+    }
+    if let Some(cache_control) = config.cache_control(route) {
+        match HeaderValue::from_str(cache_control) {
+            Ok(value) => {
+                response.headers_mut().insert(CACHE_CONTROL, value);
+            }
+            Err(_) => warn!(route, "invalid Cache-Control in http_cache.cache_control"),
+        }
+    }
+    if !config.etags {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // The body is in memory already, so this doesn't wait.
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            warn!(error = %err, "cannot read the response body to tag");
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let etag = etag(&body);
+    let not_modified = if_none_match
+        .as_ref()
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| matches(value, &etag));
+    parts.headers.insert(
+        ETAG,
+        HeaderValue::from_str(&etag).expect("ETags are visible ASCII"),
+    );
+    if not_modified {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.remove(CONTENT_TYPE);
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// A weak ETag of `body`: the first 16 bytes of its SHA-256, in hex.
+fn etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("W/\"{}\"", hex)
+}
+
+/// Whether `If-None-Match`, `*` or a list of ETags, matches `etag`, comparing
+/// them weakly.
+fn matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .any(|tag| opaque(tag) == opaque(etag))
+}
+
+/// Bodies not in memory, or already encoded.
+fn is_streamed(response: &Response<Body>) -> bool {
+    response.body().size_hint().exact().is_none()
+        || response.headers().contains_key(CONTENT_ENCODING)
+        || compression::is_event_stream(response.headers())
+}
@@ -0,0 +1,124 @@
+//! Cross-origin requests from browsers. Every response carries the CORS
+//! headers allowing its `Origin`, when `cors.allowed_origins` lists it, and
+//! preflight requests are answered here, before authentication, whatever their
+//! path.
+
+use anyhow::{bail, Context};
+use hyper::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+    ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use crate::config::CorsConfig;
+
+/// The CORS policy of the HTTP API.
+#[derive(Debug)]
+pub struct Cors {
+    /// Allowed origins, in lower case; `None` allows any.
+    origins: Option<Vec<String>>,
+    methods: HeaderValue,
+    /// `None` allows whatever headers a preflight request asks for.
+    headers: Option<HeaderValue>,
+    max_age: HeaderValue,
+    credentials: bool,
+}
+
+impl Cors {
+    pub fn from_config(config: &CorsConfig) -> anyhow::Result<Self> {
+        let any_origin = config.allowed_origins.iter().any(|origin| origin == "*");
+        if any_origin && config.allow_credentials {
+            bail!("cors.allow_credentials needs an explicit list of cors.allowed_origins, not *");
+        }
+        for method in &config.allowed_methods {
+            Method::from_bytes(method.as_bytes())
+                .with_context(|| format!("invalid method {:?} in cors.allowed_methods", method))?;
+        }
+        let methods = HeaderValue::from_str(&config.allowed_methods.join(", "))
+            .context("invalid cors.allowed_methods")?;
+        let headers = if config.allowed_headers.iter().any(|header| header == "*") {
+            None
+        } else {
+            let headers = HeaderValue::from_str(&config.allowed_headers.join(", "))
+                .context("invalid cors.allowed_headers")?;
+            Some(headers)
+        };
+        Ok(Self {
+            origins: (!any_origin).then(|| {
+                config
+                    .allowed_origins
+                    .iter()
+                    .map(|origin| origin.trim_end_matches('/').to_ascii_lowercase())
+                    .collect()
+            }),
+            methods,
+            headers,
+            max_age: HeaderValue::from(config.max_age_secs),
+            credentials: config.allow_credentials,
+        })
+    }
+
+    /// The answer to `req` if it is a preflight request: what it may send,
+    /// and for how long the browser may remember it. Whether its origin is
+    /// allowed is added by `apply`, as for any response.
+    pub fn preflight(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        let headers = req.headers();
+        let is_preflight = req.method() == Method::OPTIONS
+            && headers.contains_key(ORIGIN)
+            && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+        if !is_preflight {
+            return None;
+        }
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(ACCESS_CONTROL_ALLOW_METHODS, self.methods.clone())
+            .header(ACCESS_CONTROL_MAX_AGE, self.max_age.clone());
+        let allowed_headers = match &self.headers {
+            Some(allowed) => Some(allowed.clone()),
+            None => headers.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned(),
+        };
+        if let Some(allowed_headers) = allowed_headers {
+            response = response.header(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+            if self.headers.is_none() {
+                response = response.header(VARY, "Access-Control-Request-Headers");
+            }
+        }
+        Some(response.body(Body::empty()).unwrap())
+    }
+
+    /// Adds the headers allowing `origin`, that of the request, to read the
+    /// response. Any origin is allowed with `*`, sent even without one, as to
+    /// clients other than browsers it doesn't matter.
+    pub fn apply(&self, origin: Option<&HeaderValue>, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        let origins = match &self.origins {
+            Some(origins) => origins,
+            None => {
+                headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+                return;
+            }
+        };
+        // The answer depends on the origin, so caches must tell them apart.
+        headers.append(VARY, HeaderValue::from_static("Origin"));
+        let origin = match origin {
+            Some(origin) if allowed(origins, origin) => origin,
+            _ => return,
+        };
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+}
+
+fn allowed(origins: &[String], origin: &HeaderValue) -> bool {
+    let origin = match origin.to_str() {
+        Ok(origin) => origin.to_ascii_lowercase(),
+        Err(_) => return false,
+    };
+    origins.contains(&origin)
+}
@@ -0,0 +1,177 @@
+//! Dead letters: what failed every attempt, kept for an operator to look at
+//! and send again from the admin endpoints. Failed webhook deliveries and
+//! orders of the `queue` run mode that couldn't be priced each have their
+//! log, a JSON lines file read back at startup.
+
+use anyhow::Context;
+use domain::ErrorEnvelope;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::config::QueueConfig;
+use crate::error::AppError;
+
+/// Messages re-driven but not yet taken by the queue consumer; later ones
+/// stay in the log.
+const MAX_REDRIVEN_MESSAGES: usize = 256;
+
+/// An entry of a dead-letter log, which entries are taken out of by id.
+pub trait DeadLetterEntry: Clone + Serialize + DeserializeOwned {
+    fn id(&self) -> &str;
+}
+
+/// The entries, in memory and in a JSON lines file that is read back at
+/// startup. The file is only created by the first entry.
+#[derive(Debug)]
+pub struct DeadLetterLog<T> {
+    path: PathBuf,
+    entries: Mutex<Vec<T>>,
+}
+
+impl<T: DeadLetterEntry> DeadLetterLog<T> {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    entries.push(serde_json::from_str(&line)?);
+                }
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// The entries, oldest first.
+    pub fn list(&self) -> Vec<T> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn push(&self, entry: T) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut entries = self.entries.lock().unwrap();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())?;
+        entries.push(entry);
+        Ok(())
+    }
+
+    /// Removes the entry `id` and rewrites the file without it.
+    pub fn take(&self, id: &str) -> anyhow::Result<Option<T>> {
+        let mut entries = self.entries.lock().unwrap();
+        let index = match entries.iter().position(|entry| entry.id() == id) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let mut remaining = String::new();
+        for entry in entries.iter().filter(|entry| entry.id() != id) {
+            remaining.push_str(&serde_json::to_string(entry)?);
+            remaining.push('\n');
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, remaining)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(Some(entries.remove(index)))
+    }
+}
+
+/// An order of the `queue` run mode that failed pricing on every attempt.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeadLetter {
+    pub id: String,
+    /// Subject the order was consumed from.
+    pub subject: String,
+    /// Where its result was published.
+    pub reply_to: String,
+    /// The order, as it was consumed.
+    #[schema(value_type = Object)]
+    pub payload: Value,
+    pub attempts: u32,
+    /// Why the last attempt failed.
+    pub error: ErrorEnvelope,
+    /// RFC 3339 time of the last attempt.
+    pub failed_at: String,
+}
+
+impl DeadLetterEntry for DeadLetter {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// The dead letters of the queue consumer, and the channel re-driving them
+/// to it.
+pub struct QueueDeadLetters {
+    log: DeadLetterLog<DeadLetter>,
+    redrive: mpsc::Sender<DeadLetter>,
+    redriven: Mutex<Option<mpsc::Receiver<DeadLetter>>>,
+}
+
+impl QueueDeadLetters {
+    pub fn from_config(config: &QueueConfig) -> anyhow::Result<Self> {
+        let path = Path::new(&config.dead_letter_path);
+        let log = DeadLetterLog::open(path)
+            .with_context(|| format!("cannot read the queue dead-letter log {}", path.display()))?;
+        let (redrive, redriven) = mpsc::channel(MAX_REDRIVEN_MESSAGES);
+        Ok(Self {
+            log,
+            redrive,
+            redriven: Mutex::new(Some(redriven)),
+        })
+    }
+
+    /// The orders that failed every attempt, oldest first.
+    pub fn list(&self) -> Vec<DeadLetter> {
+        self.log.list()
+    }
+
+    pub fn push(&self, letter: DeadLetter) {
+        if let Err(err) = self.log.push(letter) {
+            warn!(
+                error = format!("{:#}", err),
+                "cannot record queue dead letter"
+            );
+        }
+    }
+
+    /// Takes a dead letter out of the log and hands it to the consumer, to be
+    /// priced again and its result published to its reply subject. It stays
+    /// in the log while too many are waiting for the consumer.
+    pub fn redrive(&self, id: &str) -> Result<DeadLetter, AppError> {
+        let letter = self
+            .log
+            .take(id)?
+            .ok_or_else(|| AppError::DeadLetterNotFound(id.to_string()))?;
+        if let Err(err) = self.redrive.try_send(letter.clone()) {
+            let letter = match err {
+                mpsc::error::TrySendError::Full(letter) => letter,
+                mpsc::error::TrySendError::Closed(letter) => letter,
+            };
+            self.push(letter);
+            return Err(AppError::Overloaded("redrive".into()));
+        }
+        info!(dead_letter_id = id, subject = %letter.subject, "re-driving dead letter");
+        Ok(letter)
+    }
+
+    /// The re-driven dead letters, for the consumer, which takes them once.
+    pub fn redriven(&self) -> Option<mpsc::Receiver<DeadLetter>> {
+        self.redriven.lock().unwrap().take()
+    }
+}
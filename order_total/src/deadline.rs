@@ -0,0 +1,200 @@
+//! The deadline of the request being served: when its caller stops waiting
+//! for the answer, `server.request_timeout_ms` after it came in, or sooner
+//! when the caller gives a shorter budget with `X-Request-Deadline` or
+//! `grpc-timeout`. The calls made to price it don't outlive it: the sales tax
+//! rate service gets what is left of it as its own timeout. Work shared by
+//! several requests, such as a coalesced rate lookup, runs until the latest
+//! of their deadlines instead.
+
+use hyper::HeaderMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::AppError;
+
+/// The header of a gRPC call's timeout, e.g. `250m` for 250 ms.
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+/// The header of the time a caller stops waiting, as an RFC 3339 UTC timestamp,
+/// e.g. `2026-10-15T09:13:05.250Z`, or in milliseconds since the Unix epoch.
+pub const REQUEST_DEADLINE_HEADER: &str = "x-request-deadline";
+
+tokio::task_local! {
+    static DEADLINE: Deadline;
+}
+
+/// The deadline calls are made within.
+enum Deadline {
+    /// That of the request being served.
+    At(Instant),
+    /// That of work shared by several requests.
+    Shared(SharedDeadline),
+}
+
+/// The deadline of work shared by several requests: the latest of theirs,
+/// pushed back as requests join the work, and none once one without a
+/// deadline does.
+#[derive(Clone)]
+pub struct SharedDeadline(Arc<Mutex<Option<Instant>>>);
+
+impl SharedDeadline {
+    /// The deadline of the request being served, for work it starts.
+    pub fn current() -> Self {
+        Self(Arc::new(Mutex::new(current())))
+    }
+
+    /// Pushes the deadline back to that of the request being served, when it
+    /// is later.
+    pub fn join(&self) {
+        let mut latest = self.0.lock().unwrap();
+        *latest = match (*latest, current()) {
+            (Some(latest), Some(joined)) => Some(latest.max(joined)),
+            _ => None,
+        };
+    }
+
+    /// Runs `future` within this deadline, as it is pushed back.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        DEADLINE.scope(Deadline::Shared(self), future).await
+    }
+}
+
+/// The deadline of the request being served, if any.
+fn current() -> Option<Instant> {
+    DEADLINE
+        .try_with(|deadline| match deadline {
+            Deadline::At(deadline) => Some(*deadline),
+            Deadline::Shared(shared) => *shared.0.lock().unwrap(),
+        })
+        .ok()
+        .flatten()
+}
+
+/// Runs `future` with the deadline `timeout` from now, or the one it runs
+/// within if that is sooner, available through `remaining`.
+pub async fn scope<F: Future>(timeout: Duration, future: F) -> F::Output {
+    let deadline = Instant::now() + timeout;
+    let deadline = current().map_or(deadline, |outer| outer.min(deadline));
+    DEADLINE.scope(Deadline::At(deadline), future).await
+}
+
+/// Waits for `future` until the deadline of the request being served,
+/// failing with `DeadlineExceeded` once it has passed.
+pub async fn until_deadline<F: Future>(future: F) -> Result<F::Output, AppError> {
+    check()?;
+    match remaining() {
+        Some(left) => tokio::time::timeout(left, future)
+            .await
+            .map_err(|_| AppError::DeadlineExceeded),
+        None => Ok(future.await),
+    }
+}
+
+/// Runs `future` within `timeout`, as the deadline of the calls it makes,
+/// failing with `RequestTimeout` once it is over.
+pub async fn within<T, F>(timeout: Duration, future: F) -> Result<T, AppError>
+where
+    F: Future<Output = Result<T, AppError>>,
+{
+    within_budget(timeout, None, future).await
+}
+
+/// Runs `future` as `within` does, or within the caller's `budget` when that
+/// is shorter, failing with `DeadlineExceeded` once it is spent, and at once
+/// when it already is.
+pub async fn within_budget<T, E, F>(
+    timeout: Duration,
+    budget: Option<Duration>,
+    future: F,
+) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: From<AppError>,
+{
+    match budget {
+        Some(budget) if budget.is_zero() => Err(AppError::DeadlineExceeded.into()),
+        Some(budget) if budget < timeout => tokio::time::timeout(budget, scope(budget, future))
+            .await
+            .unwrap_or_else(|_| Err(AppError::DeadlineExceeded.into())),
+        _ => tokio::time::timeout(timeout, scope(timeout, future))
+            .await
+            .unwrap_or_else(|_| Err(AppError::RequestTimeout(timeout).into())),
+    }
+}
+
+/// The time left until the deadline of the request being served, if any.
+pub fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Fails with `DeadlineExceeded` once the deadline of the request being
+/// served has passed, so no call is made that nobody waits for.
+pub fn check() -> Result<(), AppError> {
+    match remaining() {
+        Some(left) if left.is_zero() => Err(AppError::DeadlineExceeded),
+        _ => Ok(()),
+    }
+}
+
+/// The time a caller gives a request, from now: the shorter of its
+/// `X-Request-Deadline` and `grpc-timeout`, zero when the deadline is past.
+/// Invalid values are ignored.
+pub fn budget(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let deadline = header(REQUEST_DEADLINE_HEADER).and_then(parse_request_deadline);
+    let timeout = header(GRPC_TIMEOUT_HEADER).and_then(parse_grpc_timeout);
+    deadline.into_iter().chain(timeout).min()
+}
+
+/// The time left until an `X-Request-Deadline` value, zero when it is past.
+/// The caller's clock is trusted.
+pub fn parse_request_deadline(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let deadline = if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        UNIX_EPOCH.checked_add(Duration::from_millis(value.parse().ok()?))?
+    } else {
+        humantime::parse_rfc3339_weak(value).ok()?
+    };
+    Some(
+        deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
+
+/// The time `remaining` from now, as an `X-Request-Deadline` value.
+pub fn request_deadline(remaining: Duration) -> String {
+    humantime::format_rfc3339_millis(SystemTime::now() + remaining).to_string()
+}
+
+/// A `grpc-timeout` value: at most 8 digits and a unit, `H`, `M`, `S`, `m`,
+/// `u` or `n`.
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let unit = value.chars().last()?;
+    let amount = &value[..value.len() - unit.len_utf8()];
+    if amount.is_empty() || amount.len() > 8 || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+    Some(match unit {
+        'H' => Duration::from_secs(amount * 3600),
+        'M' => Duration::from_secs(amount * 60),
+        'S' => Duration::from_secs(amount),
+        'm' => Duration::from_millis(amount),
+        'u' => Duration::from_micros(amount),
+        'n' => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// `timeout` as a `grpc-timeout` value, in whole milliseconds rounded up, or
+/// in seconds past what 8 digits of milliseconds can hold.
+pub fn grpc_timeout(timeout: Duration) -> String {
+    let millis = timeout.as_nanos().div_ceil(1_000_000);
+    if millis < 100_000_000 {
+        format!("{}m", millis)
+    } else {
+        format!("{}S", timeout.as_secs().min(99_999_999))
+    }
+}
@@ -0,0 +1,150 @@
+//! Deduplication of identical orders: an order with the same `order_id` and
+//! content as one of the same tenant priced within the window is answered
+//! with that order's result rather than priced, stored and published again,
+//! and one arriving while the first is being priced waits for it. Unlike
+//! idempotency keys, it needs nothing from clients, so it also catches the
+//! double submissions of those not sending any.
+//!
+//! Only priced orders answer their duplicates; after a failure, the next
+//! identical order is priced for real.
+
+use domain::Order;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::as_of;
+use crate::config::{DedupConfig, TenantConfig};
+use crate::error::AppError;
+use crate::metrics::Metrics;
+use crate::singleflight::SingleFlight;
+use crate::tenants;
+
+struct Entry {
+    order: Order,
+    priced_at: Instant,
+}
+
+/// Recently priced orders by tenant, `order_id` and content hash.
+pub struct Deduplicator {
+    window: Duration,
+    /// The windows of the tenants setting their own.
+    tenant_windows: HashMap<String, Duration>,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+    in_flight: SingleFlight<Result<Order, AppError>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Deduplicator {
+    pub fn new(
+        config: &DedupConfig,
+        tenants: &HashMap<String, TenantConfig>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            window: Duration::from_millis(config.window_ms),
+            tenant_windows: tenants
+                .iter()
+                .filter_map(|(id, tenant)| {
+                    let window = Duration::from_millis(tenant.dedup_window_ms?);
+                    Some((id.clone(), window))
+                })
+                .collect(),
+            max_entries: config.max_entries,
+            entries: Mutex::new(HashMap::new()),
+            in_flight: SingleFlight::new(),
+            metrics,
+        }
+    }
+
+    /// The result of `price` for `order`, or that of an identical order of
+    /// the current tenant priced within its window.
+    pub async fn run<F, Fut>(&self, order: Order, price: F) -> Result<Order, AppError>
+    where
+        F: FnOnce(Order) -> Fut,
+        Fut: Future<Output = Result<Order, AppError>> + Send + 'static,
+    {
+        let tenant = tenants::current_tenant();
+        let window = tenant
+            .as_ref()
+            .and_then(|tenant| self.tenant_windows.get(tenant))
+            .copied()
+            .unwrap_or(self.window);
+        if window.is_zero() || self.max_entries == 0 {
+            return price(order).await;
+        }
+        let key = key(tenant.as_deref(), &order);
+        let order_id = order.order_id;
+        if let Some(priced) = self.recent(&key, window) {
+            return Ok(self.deduplicated(priced, order_id));
+        }
+        let (outcome, joined) = self.in_flight.run(&key, price(order)).await;
+        match outcome {
+            Ok(priced) if joined => Ok(self.deduplicated(priced, order_id)),
+            Ok(priced) => {
+                self.remember(key, priced.clone());
+                Ok(priced)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The order priced under `key` within `window`, if any.
+    fn recent(&self, key: &str, window: Duration) -> Option<Order> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| entry.priced_at.elapsed() < window)
+            .map(|entry| entry.order.clone())
+    }
+
+    fn remember(&self, key: String, order: Order) {
+        // No entry answers duplicates for longer than the longest window.
+        let longest = self
+            .tenant_windows
+            .values()
+            .copied()
+            .fold(self.window, Duration::max);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.priced_at.elapsed() < longest);
+        if entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.priced_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                order,
+                priced_at: Instant::now(),
+            },
+        );
+    }
+
+    fn deduplicated(&self, priced: Order, order_id: i32) -> Order {
+        self.metrics.deduplicated_orders.inc();
+        debug!(order_id, "answered a duplicate order");
+        priced
+    }
+}
+
+/// The tenant, `order_id` and SHA-256 of the content of `order`, as received,
+/// and the past day it is priced as of, if any.
+fn key(tenant: Option<&str>, order: &Order) -> String {
+    let content = serde_json::to_vec(order).expect("orders serialize");
+    let digest = Sha256::digest(&content);
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let key = format!("{}/{}/{}", tenant.unwrap_or(""), order.order_id, hex);
+    match as_of::current() {
+        Some(day) => format!("{}@{}", key, day),
+        None => key,
+    }
+}
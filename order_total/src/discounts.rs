@@ -0,0 +1,60 @@
+use domain::{Decimal, Order};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::error::AppError;
+use crate::validation::FieldError;
+
+/// What a promo code takes off an order.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Discount {
+    /// A percentage of the line items, e.g. `10` for 10% off.
+    Percent(Decimal),
+    /// A fixed amount, at most the value of the line items.
+    Amount(Decimal),
+}
+
+/// Promo codes and their discounts. Codes are case-insensitive.
+#[derive(Debug, Default)]
+pub struct Discounts {
+    codes: HashMap<String, Discount>,
+}
+
+impl Discounts {
+    pub fn new(codes: &HashMap<String, Discount>) -> Self {
+        Self {
+            codes: codes
+                .iter()
+                .map(|(code, discount)| (code.to_uppercase(), *discount))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, code: &str) -> Option<Discount> {
+        self.codes.get(&code.trim().to_uppercase()).copied()
+    }
+
+    /// Takes the discount of the order's promo code, if any, off the order.
+    /// Call before applying the sales tax rate, so tax is computed on the
+    /// discounted amounts.
+    pub fn apply(&self, order: &mut Order) -> Result<(), AppError> {
+        let code = match &order.promo_code {
+            Some(code) => code,
+            None => return Ok(()),
+        };
+        let discount = self.get(code).ok_or_else(|| {
+            AppError::Validation(vec![FieldError {
+                field: "promo_code".to_string(),
+                message: "is not a known promo code",
+            }])
+        })?;
+        order.normalize();
+        let amount = match discount {
+            Discount::Percent(percent) => order.items_total() * percent / Decimal::ONE_HUNDRED,
+            Discount::Amount(amount) => amount,
+        };
+        order.apply_discount(amount);
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+//! Discovery of the instances of the sales tax rate service, from a DNS SRV
+//! record or the Consul catalog, for deployments running several of them.
+//! The instances are looked up again every `refresh_secs` and probed, and
+//! the healthy ones handed to the load balancer.
+//!
+//! Resolver crates depend on a networking stack that doesn't run on
+//! WasmEdge, hence the minimal DNS client asking for SRV records over UDP.
+//! tokio_wasi has no UDP sockets either, so the query goes out on a
+//! non-blocking WasmEdge socket polled for the answer.
+
+use anyhow::{bail, Context};
+use futures::future::{join_all, BoxFuture, FutureExt};
+use serde::Deserialize;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tracing::{info, warn};
+use wasmedge_wasi_socket::UdpSocket;
+
+use crate::balancer::Balancer;
+use crate::config::{DiscoveryConfig, DiscoveryMode, UpstreamConfig};
+use crate::error::AppError;
+
+/// An instance of the service, as discovered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Instance {
+    host: String,
+    port: u16,
+    /// Instances of the lowest priority are called, the others only when
+    /// none of those is healthy.
+    priority: u16,
+}
+
+/// Where instances are looked up.
+trait Resolver: Send + Sync {
+    fn resolve(&self) -> BoxFuture<'_, anyhow::Result<Vec<Instance>>>;
+}
+
+/// Keeps the endpoints of a load balancer up to date with the discovered
+/// instances of the sales tax rate service.
+pub struct Discovery {
+    resolver: Box<dyn Resolver>,
+    /// What comes before and after `host:port` in the URL of an instance:
+    /// the scheme and path of the first `upstream.url`.
+    scheme: String,
+    path: String,
+    client: reqwest::Client,
+    health_check: Option<Duration>,
+    balancer: Arc<Balancer>,
+    /// Held while the instances are looked up, so that lookups don't overlap.
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+impl Discovery {
+    /// The discovery of `config`, refreshing the endpoints of `balancer` in
+    /// the background, or none when the URLs of `upstream.url` are called as
+    /// they are. Must be called within the runtime.
+    pub fn from_config(
+        config: &UpstreamConfig,
+        client: reqwest::Client,
+        balancer: Arc<Balancer>,
+    ) -> anyhow::Result<Option<Arc<Self>>> {
+        let discovery = &config.discovery;
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let resolver: Box<dyn Resolver> = match discovery.mode {
+            DiscoveryMode::Static => return Ok(None),
+            DiscoveryMode::DnsSrv => Box::new(SrvResolver {
+                name: discovery.srv_name.clone(),
+                nameserver: nameserver(discovery)?,
+                timeout,
+            }),
+            DiscoveryMode::Consul => Box::new(ConsulResolver {
+                url: format!(
+                    "{}/v1/health/service/{}?passing=true",
+                    discovery.consul_url.trim_end_matches('/'),
+                    discovery.consul_service
+                ),
+                client: client.clone(),
+                timeout,
+            }),
+        };
+        let url = config.urls().into_iter().next().unwrap_or_default();
+        let (scheme, rest) = url
+            .split_once("://")
+            .with_context(|| format!("upstream.url {:?} has no scheme", config.url))?;
+        let path = rest.find('/').map_or("", |start| &rest[start..]);
+        let discovery = Arc::new(Self {
+            resolver,
+            scheme: scheme.to_string(),
+            path: path.to_string(),
+            client,
+            health_check: discovery
+                .health_check
+                .then(|| Duration::from_millis(discovery.health_check_timeout_ms)),
+            balancer,
+            refreshing: tokio::sync::Mutex::new(()),
+        });
+        let refresh = Duration::from_secs(config.discovery.refresh_secs.max(1));
+        tokio::spawn(refresh_periodically(Arc::downgrade(&discovery), refresh));
+        Ok(Some(discovery))
+    }
+
+    /// Looks the instances up unless that was done already. Fails with
+    /// `UpstreamUnavailable` when none was found.
+    pub async fn ready(&self) -> Result<(), AppError> {
+        if self.balancer.is_empty() {
+            let _refreshing = self.refreshing.lock().await;
+            if self.balancer.is_empty() {
+                self.update().await;
+            }
+        }
+        if self.balancer.is_empty() {
+            return Err(AppError::UpstreamUnavailable(
+                "no instance of the sales tax rate service was discovered".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn refresh(&self) {
+        let _refreshing = self.refreshing.lock().await;
+        self.update().await;
+    }
+
+    /// Looks the instances up and probes them. The last known instances stay
+    /// in rotation when none is found, and all instances found are used when
+    /// none passes its health check.
+    async fn update(&self) {
+        let instances = match self.resolver.resolve().await {
+            Ok(instances) if !instances.is_empty() => instances,
+            Ok(_) => {
+                warn!("no sales tax rate service instance discovered");
+                return;
+            }
+            Err(err) => {
+                warn!(error = %err, "sales tax rate service discovery failed");
+                return;
+            }
+        };
+        let mut found: Vec<(u16, String)> = instances
+            .into_iter()
+            .map(|instance| {
+                // IPv6 addresses are bracketed in URLs.
+                let host = if instance.host.contains(':') {
+                    format!("[{}]", instance.host)
+                } else {
+                    instance.host
+                };
+                let url = format!("{}://{}:{}{}", self.scheme, host, instance.port, self.path);
+                (instance.priority, url)
+            })
+            .collect();
+        if let Some(timeout) = self.health_check {
+            let probes = found.iter().map(|(_, url)| self.probe(url, timeout));
+            let healthy = join_all(probes).await;
+            let passing: Vec<(u16, String)> = found
+                .iter()
+                .zip(healthy)
+                .filter(|(_, healthy)| *healthy)
+                .map(|(instance, _)| instance.clone())
+                .collect();
+            if passing.is_empty() {
+                warn!("no sales tax rate service instance passed its health check");
+            } else {
+                found = passing;
+            }
+        }
+        let priority = found.iter().map(|(priority, _)| *priority).min();
+        let endpoints: Vec<String> = found
+            .into_iter()
+            .filter(|(instance_priority, _)| Some(*instance_priority) == priority)
+            .map(|(_, url)| url)
+            .collect();
+        info!(
+            instances = endpoints.len(),
+            "sales tax rate service instances refreshed"
+        );
+        self.balancer.set_endpoints(endpoints);
+    }
+
+    /// Any answer that isn't a server error shows the instance is up, as for
+    /// the readiness check.
+    async fn probe(&self, url: &str, timeout: Duration) -> bool {
+        match self.client.get(url).timeout(timeout).send().await {
+            Ok(response) => !response.status().is_server_error(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Refreshes the instances every `interval`, for as long as the discovery is
+/// in use.
+async fn refresh_periodically(discovery: Weak<Discovery>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        match discovery.upgrade() {
+            Some(discovery) => discovery.refresh().await,
+            None => return,
+        }
+    }
+}
+
+/// The DNS server of the configuration, or else the first of
+/// `/etc/resolv.conf`, on port 53 unless given.
+fn nameserver(config: &DiscoveryConfig) -> anyhow::Result<SocketAddr> {
+    let server = match &config.nameserver {
+        Some(server) => server.clone(),
+        None => {
+            let resolv_conf = std::fs::read_to_string("/etc/resolv.conf")
+                .context("no upstream.discovery.nameserver, and no /etc/resolv.conf")?;
+            resolv_conf
+                .lines()
+                .find_map(|line| line.trim().strip_prefix("nameserver"))
+                .map(|server| server.trim().to_string())
+                .context("no nameserver in /etc/resolv.conf")?
+        }
+    };
+    if let Ok(address) = server.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    let ip = server
+        .parse::<IpAddr>()
+        .with_context(|| format!("invalid nameserver {:?}", server))?;
+    Ok(SocketAddr::new(ip, 53))
+}
+
+/// The targets of an SRV record, asked from a DNS server.
+struct SrvResolver {
+    name: String,
+    nameserver: SocketAddr,
+    timeout: Duration,
+}
+
+impl SrvResolver {
+    async fn query(&self) -> anyhow::Result<Vec<Instance>> {
+        let local: SocketAddr = if self.nameserver.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.as_ref().set_nonblocking(true)?;
+        let id: u16 = rand::random();
+        socket.send_to(&dns::srv_query(id, &self.name)?, self.nameserver)?;
+        let mut answer = [0; dns::MAX_UDP_MESSAGE];
+        let answered = receive_from(&socket, self.nameserver, &mut answer);
+        let len = tokio::time::timeout(self.timeout, answered)
+            .await
+            .with_context(|| {
+                format!(
+                    "no answer from {} within {:?}",
+                    self.nameserver, self.timeout
+                )
+            })??;
+        dns::parse_srv_answer(id, &answer[..len])
+            .with_context(|| format!("invalid answer for {}", self.name))
+    }
+}
+
+/// How often a socket waiting for an answer is polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The next datagram `socket`, a non-blocking one, receives from `peer`;
+/// datagrams from anyone else are dropped.
+async fn receive_from(
+    socket: &UdpSocket,
+    peer: SocketAddr,
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    loop {
+        match socket.recv_from(buf) {
+            Ok((len, sender)) if sender == peer => return Ok(len),
+            Ok(_) => continue,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                tokio::time::sleep(POLL_INTERVAL).await
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+impl Resolver for SrvResolver {
+    fn resolve(&self) -> BoxFuture<'_, anyhow::Result<Vec<Instance>>> {
+        self.query().boxed()
+    }
+}
+
+/// The healthy instances of a service, from a Consul agent's health API.
+struct ConsulResolver {
+    url: String,
+    client: reqwest::Client,
+    timeout: Duration,
+}
+
+/// An entry of `GET /v1/health/service/<name>`; only the fields used.
+#[derive(Deserialize)]
+struct ServiceEntry {
+    #[serde(rename = "Node")]
+    node: ConsulNode,
+    #[serde(rename = "Service")]
+    service: ConsulService,
+}
+
+#[derive(Deserialize)]
+struct ConsulNode {
+    #[serde(rename = "Address")]
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct ConsulService {
+    /// Empty when the service uses the address of its node.
+    #[serde(rename = "Address", default)]
+    address: String,
+    #[serde(rename = "Port")]
+    port: u16,
+}
+
+impl ConsulResolver {
+    async fn query(&self) -> anyhow::Result<Vec<Instance>> {
+        let response = self
+            .client
+            .get(&self.url)
+            .timeout(self.timeout)
+            .send()
+            .await?
+            .error_for_status()?;
+        let entries: Vec<ServiceEntry> = response.json().await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| Instance {
+                host: if entry.service.address.is_empty() {
+                    entry.node.address
+                } else {
+                    entry.service.address
+                },
+                port: entry.service.port,
+                priority: 0,
+            })
+            .collect())
+    }
+}
+
+impl Resolver for ConsulResolver {
+    fn resolve(&self) -> BoxFuture<'_, anyhow::Result<Vec<Instance>>> {
+        self.query().boxed()
+    }
+}
+
+/// Just enough of the DNS wire format (RFC 1035, RFC 2782) to ask for an SRV
+/// record and read the answer.
+mod dns {
+    use super::*;
+
+    /// Answers over UDP are at most this long without EDNS.
+    pub const MAX_UDP_MESSAGE: usize = 512;
+    const TYPE_SRV: u16 = 33;
+    const CLASS_IN: u16 = 1;
+    const FLAG_RESPONSE: u16 = 0x8000;
+    const FLAG_TRUNCATED: u16 = 0x0200;
+    const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+    const RCODE_NAME_ERROR: u16 = 3;
+
+    /// A recursive query for the SRV record of `name`.
+    pub fn srv_query(id: u16, name: &str) -> anyhow::Result<Vec<u8>> {
+        let mut query = Vec::with_capacity(MAX_UDP_MESSAGE);
+        query.extend_from_slice(&id.to_be_bytes());
+        query.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+        // One question, no answer, authority or additional records.
+        query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.trim_end_matches('.').split('.') {
+            if label.is_empty() || label.len() > 63 {
+                bail!("invalid SRV record name {:?}", name);
+            }
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&TYPE_SRV.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        Ok(query)
+    }
+
+    /// The targets of the SRV records answering query `id`; none for a name
+    /// that doesn't exist. Weights are ignored.
+    pub fn parse_srv_answer(id: u16, message: &[u8]) -> anyhow::Result<Vec<Instance>> {
+        let header = |index: usize| u16_at(message, index * 2);
+        if header(0)? != id {
+            bail!("answer to another query");
+        }
+        let flags = header(1)?;
+        if flags & FLAG_RESPONSE == 0 {
+            bail!("not an answer");
+        }
+        if flags & FLAG_TRUNCATED != 0 {
+            bail!("answer truncated");
+        }
+        match flags & 0x000f {
+            0 => {}
+            RCODE_NAME_ERROR => return Ok(Vec::new()),
+            rcode => bail!("DNS error {}", rcode),
+        }
+        let questions = header(2)?;
+        let answers = header(3)?;
+        let mut position = 12;
+        for _ in 0..questions {
+            position = skip_name(message, position)? + 4;
+        }
+        let mut instances = Vec::new();
+        for _ in 0..answers {
+            position = skip_name(message, position)?;
+            let record_type = u16_at(message, position)?;
+            let data_len = u16_at(message, position + 8)? as usize;
+            let data = position + 10;
+            if data + data_len > message.len() {
+                bail!("record past the end of the answer");
+            }
+            if record_type == TYPE_SRV {
+                let (target, _) = read_name(message, data + 6)?;
+                instances.push(Instance {
+                    host: target,
+                    port: u16_at(message, data + 4)?,
+                    priority: u16_at(message, data)?,
+                });
+            }
+            position = data + data_len;
+        }
+        Ok(instances)
+    }
+
+    fn u16_at(message: &[u8], position: usize) -> anyhow::Result<u16> {
+        match message.get(position..position + 2) {
+            Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+            None => bail!("answer cut short"),
+        }
+    }
+
+    /// The position after the name at `position`.
+    fn skip_name(message: &[u8], mut position: usize) -> anyhow::Result<usize> {
+        loop {
+            let len = *message.get(position).context("answer cut short")? as usize;
+            match len {
+                0 => return Ok(position + 1),
+                // A pointer ends the name.
+                len if len & 0xc0 == 0xc0 => return Ok(position + 2),
+                len => position += 1 + len,
+            }
+        }
+    }
+
+    /// The name at `position`, following compression pointers, and the
+    /// position after it.
+    fn read_name(message: &[u8], mut position: usize) -> anyhow::Result<(String, usize)> {
+        let mut labels: Vec<String> = Vec::new();
+        let mut end = None;
+        // Pointers go backwards in well-formed messages; this bounds loops.
+        for _ in 0..message.len() {
+            let len = *message.get(position).context("answer cut short")? as usize;
+            if len == 0 {
+                return Ok((labels.join("."), end.unwrap_or(position + 1)));
+            }
+            if len & 0xc0 == 0xc0 {
+                end.get_or_insert(position + 2);
+                position = (u16_at(message, position)? & 0x3fff) as usize;
+                continue;
+            }
+            let label = message
+                .get(position + 1..position + 1 + len)
+                .context("answer cut short")?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            position += 1 + len;
+        }
+        bail!("looping name compression")
+    }
+}
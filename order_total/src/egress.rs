@@ -0,0 +1,261 @@
+//! The proxies outbound calls go through. Each HTTP client gets an `Egress`
+//! as its only proxy, which picks the proxy of every call: the route of the
+//! dependency called, told apart by the host and port of its URL, or else
+//! `egress.proxy` or the proxy of the environment for the scheme, unless the
+//! host is one of `NO_PROXY` or `egress.no_proxy`.
+//!
+//! A proxy set on a client turns off reqwest's own reading of the
+//! environment, hence the variables are read here as well.
+
+use anyhow::{bail, Context};
+use reqwest::Url;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::config::{AppConfig, EgressConfig};
+
+/// The dependencies a route can be configured for.
+const ROUTES: [&str; 9] = [
+    "upstream",
+    "consul",
+    "exemptions",
+    "address",
+    "inventory",
+    "payments",
+    "webhooks",
+    "auth",
+    "telemetry",
+];
+
+/// Where a call goes: through this proxy, or directly when none.
+type Hop = Option<Url>;
+
+/// The proxies of the calls of one HTTP client.
+#[derive(Debug)]
+pub struct Egress {
+    http_proxy: Option<Url>,
+    https_proxy: Option<Url>,
+    no_proxy: Vec<String>,
+    /// `host:port` of the dependencies with a route, and the route.
+    routes: Vec<(String, Hop)>,
+    /// The route of the calls to any other host, over the proxies above.
+    rest: Option<Hop>,
+}
+
+impl Egress {
+    /// The proxies of the client shared by the dependencies other than the
+    /// sales tax rate service.
+    pub fn shared(config: &AppConfig) -> anyhow::Result<Self> {
+        let mut dependencies = vec![
+            ("exemptions", vec![config.exemptions.url.clone()]),
+            ("address", vec![config.address.url.clone()]),
+            ("inventory", vec![config.inventory.url.clone()]),
+            ("payments", vec![config.payments.url.clone()]),
+            (
+                "webhooks",
+                config
+                    .webhooks
+                    .endpoints
+                    .iter()
+                    .chain(config.tenants.values().flat_map(|tenant| &tenant.webhooks))
+                    .map(|endpoint| endpoint.url.clone())
+                    .collect(),
+            ),
+        ];
+        dependencies.extend(
+            config
+                .auth
+                .jwt
+                .jwks_url
+                .clone()
+                .map(|url| ("auth", vec![url])),
+        );
+        dependencies.extend(
+            config
+                .telemetry
+                .otlp_endpoint
+                .clone()
+                .map(|url| ("telemetry", vec![url])),
+        );
+        let egress = Self::new(&config.egress, dependencies, None)?;
+        if let Some(proxy) = egress.http_proxy.as_ref().or(egress.https_proxy.as_ref()) {
+            info!(proxy = %redacted(proxy), "outbound calls go through a proxy");
+        }
+        Ok(egress)
+    }
+
+    /// The proxies of the client of the sales tax rate service. Discovered
+    /// instances can't be told apart by their host, so every call takes the
+    /// `upstream` route, except those to the Consul agent.
+    pub fn upstream(config: &AppConfig) -> anyhow::Result<Self> {
+        let dependencies = vec![("consul", vec![config.upstream.discovery.consul_url.clone()])];
+        Self::new(&config.egress, dependencies, Some("upstream"))
+    }
+
+    /// The proxies of `config`, with the routes of `dependencies`, given by
+    /// name and URLs, and the route named `rest` for any other host.
+    fn new(
+        config: &EgressConfig,
+        dependencies: Vec<(&str, Vec<String>)>,
+        rest: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let mut hops = Vec::new();
+        for (name, route) in &config.routes {
+            if !ROUTES.contains(&name.as_str()) {
+                bail!(
+                    "unknown egress route {:?}, expected one of {}",
+                    name,
+                    ROUTES.join(", ")
+                );
+            }
+            let hop = match (&route.proxy, route.direct) {
+                (Some(_), true) => bail!("egress.routes.{} sets both proxy and direct", name),
+                (Some(proxy), false) => {
+                    Some(Some(parse_proxy(proxy).with_context(|| {
+                        format!("invalid egress.routes.{}.proxy", name)
+                    })?))
+                }
+                (None, true) => Some(None),
+                (None, false) => None,
+            };
+            if let Some(hop) = hop {
+                hops.push((name.as_str(), hop));
+            }
+        }
+        let route_of = |name: &str| {
+            hops.iter()
+                .find(|(route, _)| *route == name)
+                .map(|(_, hop)| hop.clone())
+        };
+        let mut routes = Vec::new();
+        for (name, urls) in dependencies {
+            if let Some(hop) = route_of(name) {
+                routes.extend(
+                    urls.iter()
+                        .filter_map(|url| Url::parse(url).ok())
+                        .filter_map(|url| authority(&url))
+                        .map(|authority| (authority, hop.clone())),
+                );
+            }
+        }
+
+        let (mut http_proxy, mut https_proxy, mut no_proxy) = (None, None, Vec::new());
+        if config.use_env {
+            http_proxy = env_proxy(&["HTTP_PROXY", "http_proxy"])?;
+            https_proxy = env_proxy(&["HTTPS_PROXY", "https_proxy"])?;
+            if let Some(hosts) = env(&["NO_PROXY", "no_proxy"]) {
+                no_proxy.extend(
+                    hosts
+                        .split(',')
+                        .map(normalize)
+                        .filter(|host| !host.is_empty()),
+                );
+            }
+        }
+        if let Some(proxy) = &config.proxy {
+            let proxy = parse_proxy(proxy).context("invalid egress.proxy")?;
+            http_proxy = Some(proxy.clone());
+            https_proxy = Some(proxy);
+        }
+        no_proxy.extend(config.no_proxy.iter().map(|host| normalize(host)));
+        Ok(Self {
+            http_proxy,
+            https_proxy,
+            no_proxy,
+            routes,
+            rest: rest.and_then(route_of),
+        })
+    }
+
+    /// The proxy `url` is called through, if any.
+    pub fn proxy_for(&self, url: &Url) -> Option<Url> {
+        let authority = authority(url)?;
+        if let Some((_, hop)) = self.routes.iter().find(|(route, _)| *route == authority) {
+            return hop.clone();
+        }
+        if let Some(hop) = &self.rest {
+            return hop.clone();
+        }
+        let host = url
+            .host_str()?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let host = host.to_ascii_lowercase();
+        let bypassed = self
+            .no_proxy
+            .iter()
+            .any(|rule| rule == "*" || host == *rule || host.ends_with(&format!(".{}", rule)));
+        if bypassed {
+            return None;
+        }
+        match url.scheme() {
+            "https" => self.https_proxy.clone(),
+            _ => self.http_proxy.clone(),
+        }
+    }
+
+    /// The egress as the proxy of a client.
+    pub fn into_proxy(self) -> reqwest::Proxy {
+        let egress = Arc::new(self);
+        reqwest::Proxy::custom(move |url| egress.proxy_for(url))
+    }
+}
+
+/// `host:port` of `url`, with the default port of its scheme.
+fn authority(url: &Url) -> Option<String> {
+    Some(format!(
+        "{}:{}",
+        url.host_str()?,
+        url.port_or_known_default()?
+    ))
+}
+
+/// A `NO_PROXY` entry as matched against hosts: lowercase, without the dot
+/// or wildcard before a domain.
+fn normalize(host: &str) -> String {
+    let host = host.trim().to_ascii_lowercase();
+    host.trim_start_matches("*.").trim_start_matches('.').into()
+}
+
+/// A proxy URL; `http` is assumed without a scheme, as in `proxy:3128`.
+fn parse_proxy(proxy: &str) -> anyhow::Result<Url> {
+    let url = if proxy.contains("://") {
+        Url::parse(proxy)
+    } else {
+        Url::parse(&format!("http://{}", proxy))
+    }
+    .with_context(|| format!("{:?} is not a URL", proxy))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        bail!("{:?} is not an http or https proxy", proxy);
+    }
+    Ok(url)
+}
+
+/// The first of the environment variables `names` set to a proxy.
+fn env_proxy(names: &[&str]) -> anyhow::Result<Option<Url>> {
+    for name in names {
+        if let Some(proxy) = env(&[*name]) {
+            return parse_proxy(&proxy)
+                .with_context(|| format!("invalid {}", name))
+                .map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// The first of the environment variables `names` set to anything but
+/// blanks.
+fn env(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+}
+
+/// `proxy` without its credentials, for the logs.
+fn redacted(proxy: &Url) -> String {
+    let mut proxy = proxy.clone();
+    let _ = proxy.set_username("");
+    let _ = proxy.set_password(None);
+    proxy.to_string()
+}
@@ -0,0 +1,504 @@
+use domain::{ErrorEnvelope, ErrorResponse};
+use hyper::header::{HeaderValue, ALLOW, CONNECTION, RETRY_AFTER, UPGRADE, WWW_AUTHENTICATE};
+use hyper::{Body, Method, Response, StatusCode};
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::lifecycle::Transition;
+use crate::request_id;
+use crate::response_build_with_status;
+use crate::router;
+use crate::validation::FieldError;
+
+/// Everything that can go wrong while serving a request. Handlers return this
+/// and the server turns it into a JSON error response, `{"error": {...}}`,
+/// with a matching status.
+#[derive(Debug, Clone)]
+pub enum AppError {
+    /// The request body could not be parsed as an order.
+    InvalidPayload(String),
+    /// The request body is larger than the given limit, in bytes.
+    PayloadTooLarge(usize),
+    /// The request body has this content type instead of JSON.
+    UnsupportedMediaType(String),
+    /// The request body is compressed with this unsupported `Content-Encoding`.
+    UnsupportedEncoding(String),
+    /// The endpoint can't answer in any media type of this `Accept` header.
+    NotAcceptable(String),
+    /// The request body is valid JSON but lacks a required field.
+    MissingField(String),
+    /// The order is well-formed but breaks the rules listed.
+    Validation(Vec<FieldError>),
+    /// The sales tax rate service could not be reached or answered badly.
+    UpstreamUnavailable(String),
+    /// The sales tax rate service didn't answer within the upstream timeout.
+    UpstreamTimeout(Duration),
+    /// Serving the request took longer than the request timeout.
+    RequestTimeout(Duration),
+    /// The deadline the caller gave the request passed before it was served.
+    DeadlineExceeded,
+    /// The circuit breaker is open; the caller may retry after the given delay.
+    CircuitOpen(Duration),
+    /// The server is draining before shutdown and takes no new requests.
+    ShuttingDown,
+    /// Fault injection failed the request, see `chaos`.
+    InjectedFault,
+    /// A bulkhead of this name had no free slot within its queue timeout, or
+    /// load shedding turned the request away.
+    Overloaded(String),
+    /// The sales tax rate service has no rate for this zip code.
+    RateNotFound(String),
+    /// The order is shipped outside the US, to this postal code, and to the
+    /// country of this ISO 3166 code when its shape tells.
+    UnsupportedJurisdiction(String, Option<&'static str>),
+    /// There is no exchange rate from this currency to the base currency.
+    UnsupportedCurrency(String),
+    /// The exemption registry doesn't know this tax exemption certificate, or
+    /// not for the order's destination.
+    InvalidTaxExemption(String),
+    /// The tax exemption registry could not be reached or answered badly.
+    ExemptionRegistryUnavailable(String),
+    /// The shipping address was rejected by the address validator, for this
+    /// reason.
+    InvalidAddress(String),
+    /// The address validation service could not be reached or answered
+    /// badly, and there is no fallback.
+    AddressValidatorUnavailable(String),
+    /// The inventory service can't reserve the order's items, for this
+    /// reason.
+    InsufficientStock(String),
+    /// The inventory service could not be reached or answered badly.
+    InventoryUnavailable(String),
+    /// The payment service declined to authorize the order's total, for this
+    /// reason.
+    PaymentDeclined(String),
+    /// The payment service could not be reached or answered badly.
+    PaymentServiceUnavailable(String),
+    /// No saga has this id.
+    SagaNotFound(String),
+    /// No order with this id has been priced.
+    OrderNotFound(i32),
+    /// No failed webhook delivery has this id.
+    DeliveryNotFound(String),
+    /// No dead letter of the queue consumer has this id.
+    DeadLetterNotFound(String),
+    /// No rate override added with the admin API has this id.
+    RateOverrideNotFound(String),
+    /// No endpoint has this method and path.
+    NotFound,
+    /// The path exists, but only with these methods.
+    MethodNotAllowed(Vec<Method>),
+    /// The endpoint only speaks WebSocket, and the request isn't a valid
+    /// WebSocket handshake.
+    UpgradeRequired(String),
+    /// The order's status doesn't allow this transition.
+    InvalidTransition(i32, Transition),
+    /// The idempotency key was already used for a different request.
+    IdempotencyKeyReused(String),
+    /// A request with this idempotency key is still being served.
+    IdempotencyKeyInUse(String),
+    /// The request carries no credentials, or invalid ones.
+    Unauthorized(String),
+    /// The credentials are valid but don't give access to this endpoint.
+    Forbidden,
+    /// The request names a tenant that isn't configured.
+    UnknownTenant(String),
+    /// The request names no tenant, and `tenancy.required` is on.
+    TenantRequired,
+    /// The client sent too many requests; it may retry after the given delay.
+    RateLimited(Duration),
+    /// Anything else. Shared, as errors of a coalesced rate lookup are handed
+    /// to every caller waiting for it.
+    Internal(Arc<anyhow::Error>),
+}
+
+/// Conversion of a handler outcome into an HTTP response.
+pub trait IntoResponse {
+    fn into_response(self) -> Response<Body>;
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::InvalidPayload(_)
+            | AppError::MissingField(_)
+            | AppError::UnknownTenant(_)
+            | AppError::TenantRequired => StatusCode::BAD_REQUEST,
+            AppError::Validation(_)
+            | AppError::RateNotFound(_)
+            | AppError::UnsupportedJurisdiction(..)
+            | AppError::UnsupportedCurrency(_)
+            | AppError::InvalidTaxExemption(_)
+            | AppError::InvalidAddress(_)
+            | AppError::IdempotencyKeyReused(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::IdempotencyKeyInUse(_)
+            | AppError::InvalidTransition(..)
+            | AppError::InsufficientStock(_) => StatusCode::CONFLICT,
+            AppError::OrderNotFound(_)
+            | AppError::DeliveryNotFound(_)
+            | AppError::DeadLetterNotFound(_)
+            | AppError::RateOverrideNotFound(_)
+            | AppError::SagaNotFound(_)
+            | AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::PaymentDeclined(_) => StatusCode::PAYMENT_REQUIRED,
+            AppError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::UpgradeRequired(_) => StatusCode::UPGRADE_REQUIRED,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) | AppError::UnsupportedEncoding(_) => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            AppError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden => StatusCode::FORBIDDEN,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::UpstreamUnavailable(_)
+            | AppError::ExemptionRegistryUnavailable(_)
+            | AppError::AddressValidatorUnavailable(_)
+            | AppError::InventoryUnavailable(_)
+            | AppError::PaymentServiceUnavailable(_) => StatusCode::BAD_GATEWAY,
+            AppError::UpstreamTimeout(_)
+            | AppError::RequestTimeout(_)
+            | AppError::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+            AppError::CircuitOpen(_)
+            | AppError::ShuttingDown
+            | AppError::InjectedFault
+            | AppError::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::InvalidPayload(_) => "INVALID_PAYLOAD",
+            AppError::MissingField(_) => "MISSING_FIELD",
+            AppError::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            AppError::UnsupportedMediaType(_) => "UNSUPPORTED_MEDIA_TYPE",
+            AppError::UnsupportedEncoding(_) => "UNSUPPORTED_ENCODING",
+            AppError::NotAcceptable(_) => "NOT_ACCEPTABLE",
+            AppError::Validation(_) => "VALIDATION_FAILED",
+            AppError::UpstreamUnavailable(_) => "UPSTREAM_UNAVAILABLE",
+            AppError::UpstreamTimeout(_) => "UPSTREAM_TIMEOUT",
+            AppError::RequestTimeout(_) => "REQUEST_TIMEOUT",
+            AppError::DeadlineExceeded => "DEADLINE_EXCEEDED",
+            AppError::CircuitOpen(_) => "CIRCUIT_OPEN",
+            AppError::ShuttingDown => "SHUTTING_DOWN",
+            AppError::InjectedFault => "INJECTED_FAULT",
+            AppError::Overloaded(_) => "OVERLOADED",
+            AppError::RateNotFound(_) => "RATE_NOT_FOUND",
+            AppError::UnsupportedJurisdiction(..) => "UNSUPPORTED_JURISDICTION",
+            AppError::UnsupportedCurrency(_) => "UNSUPPORTED_CURRENCY",
+            AppError::InvalidTaxExemption(_) => "INVALID_TAX_EXEMPTION",
+            AppError::ExemptionRegistryUnavailable(_) => "EXEMPTION_REGISTRY_UNAVAILABLE",
+            AppError::InvalidAddress(_) => "INVALID_ADDRESS",
+            AppError::AddressValidatorUnavailable(_) => "ADDRESS_VALIDATOR_UNAVAILABLE",
+            AppError::InsufficientStock(_) => "INSUFFICIENT_STOCK",
+            AppError::InventoryUnavailable(_) => "INVENTORY_UNAVAILABLE",
+            AppError::PaymentDeclined(_) => "PAYMENT_DECLINED",
+            AppError::PaymentServiceUnavailable(_) => "PAYMENT_SERVICE_UNAVAILABLE",
+            AppError::OrderNotFound(_) => "ORDER_NOT_FOUND",
+            AppError::DeliveryNotFound(_) => "DELIVERY_NOT_FOUND",
+            AppError::DeadLetterNotFound(_) => "DEAD_LETTER_NOT_FOUND",
+            AppError::RateOverrideNotFound(_) => "RATE_OVERRIDE_NOT_FOUND",
+            AppError::SagaNotFound(_) => "SAGA_NOT_FOUND",
+            AppError::NotFound => "NOT_FOUND",
+            AppError::MethodNotAllowed(_) => "METHOD_NOT_ALLOWED",
+            AppError::UpgradeRequired(_) => "UPGRADE_REQUIRED",
+            AppError::InvalidTransition(..) => "INVALID_TRANSITION",
+            AppError::IdempotencyKeyReused(_) => "IDEMPOTENCY_KEY_REUSED",
+            AppError::IdempotencyKeyInUse(_) => "IDEMPOTENCY_KEY_IN_USE",
+            AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::Forbidden => "FORBIDDEN",
+            AppError::UnknownTenant(_) => "UNKNOWN_TENANT",
+            AppError::TenantRequired => "TENANT_REQUIRED",
+            AppError::RateLimited(_) => "RATE_LIMITED",
+            AppError::Internal(_) => "INTERNAL",
+        }
+    }
+
+    /// The error envelope describing this error, tagged with the id of the
+    /// request being served.
+    pub fn envelope(&self) -> ErrorEnvelope {
+        ErrorEnvelope {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            details: self.details(),
+            request_id: request_id::current(),
+        }
+    }
+
+    /// The body of the error response.
+    pub fn body(&self) -> String {
+        let response = ErrorResponse {
+            error: self.envelope(),
+        };
+        // Serializing a struct of strings and JSON values cannot fail.
+        serde_json::to_string(&response).unwrap()
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            AppError::InvalidPayload(reason) => Some(json!({ "reason": reason })),
+            AppError::MissingField(field) => Some(json!({ "field": field })),
+            AppError::PayloadTooLarge(limit) => Some(json!({ "limit_bytes": limit })),
+            AppError::UnsupportedMediaType(content_type) => {
+                Some(json!({ "content_type": content_type }))
+            }
+            AppError::UnsupportedEncoding(encoding) => {
+                Some(json!({ "content_encoding": encoding }))
+            }
+            AppError::NotAcceptable(accept) => Some(json!({ "accept": accept })),
+            AppError::Validation(errors) => Some(json!({ "errors": errors })),
+            AppError::UpstreamUnavailable(reason)
+            | AppError::ExemptionRegistryUnavailable(reason)
+            | AppError::InvalidAddress(reason)
+            | AppError::AddressValidatorUnavailable(reason)
+            | AppError::InsufficientStock(reason)
+            | AppError::InventoryUnavailable(reason)
+            | AppError::PaymentDeclined(reason)
+            | AppError::PaymentServiceUnavailable(reason) => Some(json!({ "reason": reason })),
+            AppError::UpstreamTimeout(timeout) | AppError::RequestTimeout(timeout) => {
+                Some(json!({ "timeout_ms": timeout.as_millis() as u64 }))
+            }
+            AppError::CircuitOpen(delay) | AppError::RateLimited(delay) => {
+                Some(json!({ "retry_after_seconds": retry_after_seconds(*delay) }))
+            }
+            AppError::Overloaded(limit) => Some(json!({ "limit": limit })),
+            AppError::RateNotFound(zip) => Some(json!({ "shipping_zip": zip })),
+            AppError::UnsupportedJurisdiction(zip, Some(country)) => {
+                Some(json!({ "shipping_zip": zip, "country": country }))
+            }
+            AppError::UnsupportedJurisdiction(zip, None) => Some(json!({ "shipping_zip": zip })),
+            AppError::UnsupportedCurrency(currency) => Some(json!({ "currency": currency })),
+            AppError::InvalidTaxExemption(id) => Some(json!({ "tax_exempt_id": id })),
+            AppError::OrderNotFound(order_id) => Some(json!({ "order_id": order_id })),
+            AppError::DeliveryNotFound(id) => Some(json!({ "delivery_id": id })),
+            AppError::DeadLetterNotFound(id) => Some(json!({ "dead_letter_id": id })),
+            AppError::RateOverrideNotFound(id) => Some(json!({ "rate_override_id": id })),
+            AppError::SagaNotFound(id) => Some(json!({ "saga_id": id })),
+            AppError::UnknownTenant(tenant) => Some(json!({ "tenant": tenant })),
+            AppError::MethodNotAllowed(allowed) => {
+                let allowed: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+                Some(json!({ "allowed": allowed }))
+            }
+            AppError::InvalidTransition(order_id, transition) => Some(json!({
+                "order_id": order_id,
+                "status": transition.from,
+                "requested_status": transition.to,
+            })),
+            AppError::IdempotencyKeyReused(key) | AppError::IdempotencyKeyInUse(key) => {
+                Some(json!({ "idempotency_key": key }))
+            }
+            AppError::Unauthorized(reason) | AppError::UpgradeRequired(reason) => {
+                Some(json!({ "reason": reason }))
+            }
+            AppError::ShuttingDown
+            | AppError::DeadlineExceeded
+            | AppError::InjectedFault
+            | AppError::NotFound
+            | AppError::Forbidden
+            | AppError::TenantRequired
+            | AppError::Internal(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::InvalidPayload(_) => write!(f, "the request body is not a valid order"),
+            // Field names are spelled out, e.g. "missing field shipping zip".
+            AppError::MissingField(field) => write!(f, "missing field {}", field.replace('_', " ")),
+            AppError::PayloadTooLarge(limit) => {
+                write!(f, "The request body is larger than {} bytes.", limit)
+            }
+            AppError::UnsupportedMediaType(_) => write!(
+                f,
+                "The request body must be JSON (application/json), or MessagePack \
+                 (application/msgpack) for /compute."
+            ),
+            AppError::UnsupportedEncoding(_) => write!(
+                f,
+                "The request body must be sent uncompressed, or with gzip, deflate or br \
+                 encoding."
+            ),
+            AppError::NotAcceptable(_) => {
+                write!(f, "None of the accepted media types can be produced.")
+            }
+            AppError::Validation(errors) => {
+                let fields: Vec<&str> = errors.iter().map(|error| error.field.as_str()).collect();
+                write!(f, "The order has invalid fields: {}.", fields.join(", "))
+            }
+            AppError::UpstreamUnavailable(_) => {
+                write!(f, "The sales tax rate service is unavailable.")
+            }
+            AppError::UpstreamTimeout(_) => {
+                write!(f, "The sales tax rate service did not answer in time.")
+            }
+            AppError::RequestTimeout(_) => write!(f, "The order could not be priced in time."),
+            AppError::DeadlineExceeded => {
+                write!(f, "The order could not be priced before the request's deadline.")
+            }
+            AppError::CircuitOpen(_) => write!(
+                f,
+                "The sales tax rate service is temporarily unavailable, please retry later."
+            ),
+            AppError::ShuttingDown => write!(f, "The service is shutting down."),
+            AppError::InjectedFault => {
+                write!(f, "The request failed on purpose, to test resilience.")
+            }
+            AppError::Overloaded(_) => write!(
+                f,
+                "The service is too busy to take the request, please retry later."
+            ),
+            AppError::RateNotFound(zip) => write!(
+                f,
+                "The zip code ({}) in the order does not have a corresponding sales tax rate.",
+                zip
+            ),
+            AppError::UnsupportedJurisdiction(zip, _) => write!(
+                f,
+                "The postal code ({}) in the order is outside the US; sales tax is only computed for US zip codes.",
+                zip
+            ),
+            AppError::UnsupportedCurrency(currency) => write!(
+                f,
+                "Orders in {} cannot be priced: there is no exchange rate for it.",
+                currency
+            ),
+            AppError::InvalidTaxExemption(id) => {
+                write!(f, "The tax exemption {} is not valid for this order.", id)
+            }
+            AppError::ExemptionRegistryUnavailable(_) => {
+                write!(f, "The tax exemption registry is unavailable.")
+            }
+            AppError::InvalidAddress(reason) => {
+                write!(f, "The shipping address is not valid: {}.", reason)
+            }
+            AppError::AddressValidatorUnavailable(_) => {
+                write!(f, "The address validation service is unavailable.")
+            }
+            AppError::InsufficientStock(reason) => {
+                write!(f, "The order's items could not be reserved: {}.", reason)
+            }
+            AppError::InventoryUnavailable(_) => {
+                write!(f, "The inventory service is unavailable.")
+            }
+            AppError::PaymentDeclined(reason) => {
+                write!(f, "The payment was declined: {}.", reason)
+            }
+            AppError::PaymentServiceUnavailable(_) => {
+                write!(f, "The payment service is unavailable.")
+            }
+            AppError::OrderNotFound(order_id) => {
+                write!(f, "No order with id {} has been priced.", order_id)
+            }
+            AppError::DeliveryNotFound(id) => {
+                write!(f, "No failed webhook delivery has id {}.", id)
+            }
+            AppError::DeadLetterNotFound(id) => write!(f, "No dead letter has id {}.", id),
+            AppError::RateOverrideNotFound(id) => {
+                write!(f, "No rate override has id {}.", id)
+            }
+            AppError::SagaNotFound(id) => write!(f, "No saga has id {}.", id),
+            AppError::NotFound => write!(f, "No such endpoint."),
+            AppError::MethodNotAllowed(_) => {
+                write!(f, "The endpoint doesn't support this method.")
+            }
+            AppError::UpgradeRequired(_) => {
+                write!(f, "This endpoint only speaks WebSocket.")
+            }
+            AppError::InvalidTransition(order_id, transition) => write!(
+                f,
+                "Order {} is {} and cannot become {}: {}.",
+                order_id,
+                transition.from,
+                transition.to,
+                transition.reason()
+            ),
+            AppError::IdempotencyKeyReused(_) => write!(
+                f,
+                "The idempotency key was already used for a different request."
+            ),
+            AppError::IdempotencyKeyInUse(_) => write!(
+                f,
+                "A request with this idempotency key is still in progress, please retry later."
+            ),
+            AppError::Unauthorized(_) => write!(f, "Valid credentials are required."),
+            AppError::Forbidden => write!(f, "The credentials don't give access to this endpoint."),
+            AppError::UnknownTenant(tenant) => write!(f, "There is no tenant {}.", tenant),
+            AppError::TenantRequired => write!(f, "Requests must name their tenant."),
+            AppError::RateLimited(_) => write!(f, "Too many requests, please retry later."),
+            AppError::Internal(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response<Body> {
+        let body = self.body();
+        let mut response = response_build_with_status(self.status(), &body);
+        match self {
+            AppError::CircuitOpen(delay) | AppError::RateLimited(delay) => {
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(retry_after_seconds(delay)));
+            }
+            AppError::Unauthorized(_) => {
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            AppError::UpgradeRequired(_) => {
+                response
+                    .headers_mut()
+                    .insert(UPGRADE, HeaderValue::from_static("websocket"));
+            }
+            AppError::MethodNotAllowed(allowed) => {
+                response
+                    .headers_mut()
+                    .insert(ALLOW, router::allow(&allowed));
+            }
+            // Make keep-alive clients reconnect, to an instance that isn't going away.
+            AppError::ShuttingDown => {
+                response
+                    .headers_mut()
+                    .insert(CONNECTION, HeaderValue::from_static("close"));
+            }
+            _ => (),
+        }
+        response
+    }
+}
+
+/// Retry-After is expressed in whole seconds, rounded up so clients don't come back early.
+fn retry_after_seconds(delay: Duration) -> u64 {
+    let seconds = delay.as_secs();
+    if delay.subsec_nanos() > 0 {
+        seconds + 1
+    } else {
+        seconds
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        // serde_json reports missing fields as "missing field `name` at line L column C".
+        let message = err.to_string();
+        if let Some(rest) = message.strip_prefix("missing field `") {
+            if let Some(end) = rest.find('`') {
+                return AppError::MissingField(rest[..end].to_string());
+            }
+        }
+        AppError::InvalidPayload(message)
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        AppError::Internal(Arc::new(err))
+    }
+}
@@ -0,0 +1,235 @@
+//! `OrderPriced` and `OrderCancelled` events for downstream services such as
+//! fulfillment or analytics.
+
+use anyhow::Context;
+use domain::{OrderCancelled, OrderPriced};
+use futures::future::{BoxFuture, FutureExt};
+
+use crate::config::{EventPublisherKind, EventsConfig};
+use crate::store::{OrderRecord, OrderStore};
+
+/// Takes events to publish. Publishing never blocks or fails the request that
+/// priced the order; events that can't be delivered are logged and dropped.
+pub trait EventPublisher: Send + Sync {
+    fn publish(&self, event: &OrderPriced);
+
+    /// Publishes `event` and waits until it has been handed to the broker,
+    /// for the outbox relay. Publishers without a broker take it at once.
+    fn deliver<'a>(&'a self, event: &'a OrderPriced) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.publish(event);
+        async { Ok(()) }.boxed()
+    }
+
+    /// Takes the event of a cancelled order, which doesn't go through the
+    /// outbox. Publishers that only announce priced orders drop it.
+    fn publish_cancelled(&self, _event: &OrderCancelled) {}
+}
+
+/// Drops every event, for deployments without a message broker.
+#[derive(Debug, Default)]
+pub struct NoopPublisher;
+
+impl EventPublisher for NoopPublisher {
+    fn publish(&self, _event: &OrderPriced) {}
+}
+
+/// The event announcing a priced order.
+pub fn order_priced(record: &OrderRecord) -> OrderPriced {
+    OrderPriced {
+        order: record.order.clone(),
+        rate: record.rate,
+        priced_at: record.priced_at.clone(),
+    }
+}
+
+/// The event announcing a cancelled order.
+pub fn order_cancelled(record: &OrderRecord) -> OrderCancelled {
+    OrderCancelled {
+        order: record.order.clone(),
+        reason: record.cancellation_reason.clone(),
+        cancelled_at: record
+            .status_changed_at
+            .clone()
+            .unwrap_or_else(|| record.priced_at.clone()),
+    }
+}
+
+/// The publisher selected by the configuration. Must be called within the
+/// runtime, which runs the NATS connection.
+pub fn from_config(config: &EventsConfig) -> anyhow::Result<Box<dyn EventPublisher>> {
+    match config.publisher {
+        EventPublisherKind::None => Ok(Box::new(NoopPublisher)),
+        #[cfg(feature = "nats")]
+        EventPublisherKind::Nats => Ok(Box::new(nats::NatsPublisher::start(
+            &config.nats_url,
+            &config.subject,
+            &config.cancelled_subject,
+        )?)),
+        #[cfg(not(feature = "nats"))]
+        EventPublisherKind::Nats => {
+            anyhow::bail!("order_total was built without the `nats` feature")
+        }
+    }
+}
+
+/// Publishes up to `batch` events waiting in the outbox of `orders`, oldest
+/// first, and takes them out once delivered; returns how many were. An event
+/// that can't be delivered stays in the outbox, with the ones after it, until
+/// the next run; one delivered but not yet taken out when the service stops
+/// is published again.
+pub async fn relay(
+    orders: &dyn OrderStore,
+    events: &dyn EventPublisher,
+    batch: usize,
+) -> anyhow::Result<usize> {
+    let entries = orders.outbox(batch).context("cannot read the outbox")?;
+    let mut delivered = 0;
+    for entry in entries {
+        let event = order_priced(&entry.record);
+        events.deliver(&event).await.with_context(|| {
+            format!("cannot publish the event of order {}", event.order.order_id)
+        })?;
+        orders.mark_sent(entry.id)?;
+        delivered += 1;
+    }
+    Ok(delivered)
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use anyhow::Context;
+    use domain::{OrderCancelled, OrderPriced};
+    use futures::future::{BoxFuture, FutureExt};
+    use tokio::sync::{mpsc, oneshot};
+    use tracing::warn;
+
+    use super::EventPublisher;
+    use crate::nats::{Client, Message};
+
+    /// Events waiting for the connection; later events are dropped.
+    const MAX_QUEUED_EVENTS: usize = 1024;
+
+    /// An event to publish, its subject, and where to tell whether it was
+    /// published.
+    type Queued = (String, Vec<u8>, Option<oneshot::Sender<anyhow::Result<()>>>);
+
+    /// Queues events for a background task that publishes them to their NATS
+    /// subject, connecting again whenever the connection drops.
+    pub struct NatsPublisher {
+        queue: mpsc::Sender<Queued>,
+        subject: String,
+        cancelled_subject: String,
+    }
+
+    impl NatsPublisher {
+        pub fn start(url: &str, subject: &str, cancelled_subject: &str) -> anyhow::Result<Self> {
+            for subject in [subject, cancelled_subject] {
+                anyhow::ensure!(
+                    !subject.is_empty() && !subject.contains(char::is_whitespace),
+                    "invalid NATS subject {:?}",
+                    subject
+                );
+            }
+            let (queue, events) = mpsc::channel(MAX_QUEUED_EVENTS);
+            tokio::spawn(run(url.to_string(), events));
+            Ok(Self {
+                queue,
+                subject: subject.to_string(),
+                cancelled_subject: cancelled_subject.to_string(),
+            })
+        }
+
+        /// Queues `event` without waiting for it to be published.
+        fn enqueue<T: serde::Serialize>(&self, subject: &str, event: &T, order_id: i32) {
+            let payload = match serde_json::to_vec(event) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    warn!(error = %err, "cannot serialize event");
+                    return;
+                }
+            };
+            if self
+                .queue
+                .try_send((subject.to_string(), payload, None))
+                .is_err()
+            {
+                warn!(order_id, "event queue full, dropping event");
+            }
+        }
+    }
+
+    impl EventPublisher for NatsPublisher {
+        fn publish(&self, event: &OrderPriced) {
+            self.enqueue(&self.subject, event, event.order.order_id);
+        }
+
+        fn publish_cancelled(&self, event: &OrderCancelled) {
+            self.enqueue(&self.cancelled_subject, event, event.order.order_id);
+        }
+
+        fn deliver<'a>(&'a self, event: &'a OrderPriced) -> BoxFuture<'a, anyhow::Result<()>> {
+            async move {
+                let payload = serde_json::to_vec(event)?;
+                let (done, published) = oneshot::channel();
+                self.queue
+                    .send((self.subject.clone(), payload, Some(done)))
+                    .await
+                    .map_err(|_| anyhow::anyhow!("event publisher stopped"))?;
+                published.await.context("event publisher stopped")?
+            }
+            .boxed()
+        }
+    }
+
+    async fn run(url: String, mut events: mpsc::Receiver<Queued>) {
+        // The receiver of the connection closes when the connection is lost.
+        let mut connection: Option<(Client, mpsc::Receiver<Message>)> = None;
+        loop {
+            let lost = async {
+                match connection.as_mut() {
+                    Some((_, messages)) => while messages.recv().await.is_some() {},
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                event = events.recv() => {
+                    let (subject, payload, done) = match event {
+                        Some(event) => event,
+                        None => return,
+                    };
+                    let published = publish(&mut connection, &url, &subject, &payload).await;
+                    match done {
+                        Some(done) => {
+                            let _ = done.send(published);
+                        }
+                        None => {
+                            if let Err(err) = published {
+                                warn!(error = %err, "cannot publish event");
+                            }
+                        }
+                    }
+                }
+                _ = lost => connection = None,
+            }
+        }
+    }
+
+    /// Publishes `payload`, connecting first when not connected. The
+    /// connection is dropped when publishing fails.
+    async fn publish(
+        connection: &mut Option<(Client, mpsc::Receiver<Message>)>,
+        url: &str,
+        subject: &str,
+        payload: &[u8],
+    ) -> anyhow::Result<()> {
+        if connection.is_none() {
+            *connection = Some(Client::connect(url).await?);
+        }
+        let (client, _) = connection.as_ref().unwrap();
+        let published = client.publish(subject, payload).await;
+        if published.is_err() {
+            *connection = None;
+        }
+        published.context("cannot publish event to NATS")
+    }
+}
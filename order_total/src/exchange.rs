@@ -0,0 +1,102 @@
+//! Where exchange rates come from, for orders sent in another currency than
+//! the base currency: nowhere, an exchange rate table file, or a fixed table in
+//! the configuration.
+
+use anyhow::{bail, ensure, Context};
+use domain::{currency, Decimal};
+use futures::future::{BoxFuture, FutureExt};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::config::{CurrencyConfig, ExchangeProviderKind};
+use crate::error::AppError;
+
+/// Looks up exchange rates into the base currency.
+pub trait ExchangeRateProvider: Send + Sync {
+    /// Units of the base currency per unit of `currency`, or
+    /// `UnsupportedCurrency`.
+    fn rate<'a>(&'a self, currency: &'a str) -> BoxFuture<'a, Result<Decimal, AppError>>;
+}
+
+/// The provider selected by the configuration.
+pub fn from_config(config: &CurrencyConfig) -> anyhow::Result<Box<dyn ExchangeRateProvider>> {
+    ensure!(
+        currency::minor_units(&config.base) == Some(2),
+        "currency.base must be the ISO 4217 code of a currency with cents, not {:?}",
+        config.base
+    );
+    Ok(match config.provider {
+        ExchangeProviderKind::None => Box::new(TableProvider::default()),
+        ExchangeProviderKind::File => Box::new(TableProvider::load(Path::new(&config.path))?),
+        ExchangeProviderKind::Memory => Box::new(TableProvider::new(config.rates.clone())?),
+    })
+}
+
+/// A fixed table of exchange rates into the base currency, read from a file or
+/// given in the configuration. The empty table rejects every other currency.
+#[derive(Debug, Default)]
+pub struct TableProvider {
+    rates: HashMap<String, Decimal>,
+}
+
+/// A row of a CSV exchange rate table.
+#[derive(Debug, Deserialize)]
+struct RateRow {
+    currency: String,
+    rate: Decimal,
+}
+
+impl TableProvider {
+    pub fn new(rates: HashMap<String, Decimal>) -> anyhow::Result<Self> {
+        for (code, rate) in &rates {
+            ensure!(
+                currency::is_valid(code),
+                "{:?} is not an ISO 4217 currency code",
+                code
+            );
+            ensure!(
+                rate.is_sign_positive() && !rate.is_zero(),
+                "the exchange rate of {} must be positive",
+                code
+            );
+        }
+        Ok(Self { rates })
+    }
+
+    /// Reads a CSV table with `currency` and `rate` columns, or a JSON object
+    /// of rates by currency, e.g. `{"EUR": 1.08}`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let context = || format!("cannot read the exchange rate table {}", path.display());
+        let file = File::open(path).with_context(context)?;
+        let rates = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => read_csv(file).with_context(context)?,
+            Some("json") => serde_json::from_reader(BufReader::new(file)).with_context(context)?,
+            _ => bail!(
+                "the exchange rate table {} is neither a .csv nor a .json file",
+                path.display()
+            ),
+        };
+        Self::new(rates).with_context(context)
+    }
+}
+
+fn read_csv(reader: impl Read) -> csv::Result<HashMap<String, Decimal>> {
+    csv::Reader::from_reader(reader)
+        .deserialize::<RateRow>()
+        .map(|row| row.map(|row| (row.currency, row.rate)))
+        .collect()
+}
+
+impl ExchangeRateProvider for TableProvider {
+    fn rate<'a>(&'a self, currency: &'a str) -> BoxFuture<'a, Result<Decimal, AppError>> {
+        let rate = self
+            .rates
+            .get(currency)
+            .copied()
+            .ok_or_else(|| AppError::UnsupportedCurrency(currency.to_string()));
+        futures::future::ready(rate).boxed()
+    }
+}
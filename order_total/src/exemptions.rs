@@ -0,0 +1,169 @@
+//! Where tax exemption certificates are checked: nowhere, an exemption table
+//! file, or a tax exemption registry service.
+
+use anyhow::{bail, Context};
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::header::ACCEPT;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::{ExemptionRegistryKind, ExemptionsConfig};
+use crate::error::AppError;
+use crate::request_id;
+
+/// Checks tax exemption certificates.
+pub trait ExemptionRegistry: Send + Sync {
+    /// The registry's reference for the exemption `id` of an order shipped to
+    /// `zip`, or `InvalidTaxExemption`.
+    fn verify<'a>(&'a self, id: &'a str, zip: &'a str) -> BoxFuture<'a, Result<String, AppError>>;
+}
+
+/// The registry selected by the configuration; a registry service is called
+/// with `client`.
+pub fn from_config(
+    config: &ExemptionsConfig,
+    client: reqwest::Client,
+) -> anyhow::Result<Box<dyn ExemptionRegistry>> {
+    Ok(match config.registry {
+        ExemptionRegistryKind::None => Box::new(TableRegistry::default()),
+        ExemptionRegistryKind::File => Box::new(TableRegistry::load(Path::new(&config.path))?),
+        ExemptionRegistryKind::Http => Box::new(HttpRegistry::new(config, client)),
+    })
+}
+
+/// A fixed table of exemption references by certificate number, read from a
+/// file. The empty table rejects every exemption.
+#[derive(Debug, Default)]
+pub struct TableRegistry {
+    references: HashMap<String, String>,
+}
+
+/// A row of a CSV exemption table.
+#[derive(Debug, Deserialize)]
+struct ExemptionRow {
+    id: String,
+    reference: String,
+}
+
+impl TableRegistry {
+    pub fn new(references: HashMap<String, String>) -> Self {
+        Self {
+            references: references
+                .into_iter()
+                .map(|(id, reference)| (id.trim().to_string(), reference))
+                .collect(),
+        }
+    }
+
+    /// Reads a CSV table with `id` and `reference` columns, or a JSON object
+    /// of references by certificate number, e.g. `{"TX-12345": "REG-0042"}`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let context = || format!("cannot read the exemption table {}", path.display());
+        let file = File::open(path).with_context(context)?;
+        let references = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => read_csv(file).with_context(context)?,
+            Some("json") => serde_json::from_reader(BufReader::new(file)).with_context(context)?,
+            _ => bail!(
+                "the exemption table {} is neither a .csv nor a .json file",
+                path.display()
+            ),
+        };
+        Ok(Self::new(references))
+    }
+}
+
+fn read_csv(reader: impl Read) -> csv::Result<HashMap<String, String>> {
+    csv::Reader::from_reader(reader)
+        .deserialize::<ExemptionRow>()
+        .map(|row| row.map(|row| (row.id, row.reference)))
+        .collect()
+}
+
+impl ExemptionRegistry for TableRegistry {
+    fn verify<'a>(&'a self, id: &'a str, _zip: &'a str) -> BoxFuture<'a, Result<String, AppError>> {
+        let reference = self
+            .references
+            .get(id.trim())
+            .cloned()
+            .ok_or_else(|| AppError::InvalidTaxExemption(id.to_string()));
+        futures::future::ready(reference).boxed()
+    }
+}
+
+/// The body of an exemption lookup.
+#[derive(Serialize)]
+struct ExemptionRequest<'a> {
+    tax_exempt_id: &'a str,
+    shipping_zip: &'a str,
+}
+
+/// The registry's answer for a valid exemption.
+#[derive(Deserialize)]
+struct ExemptionResponse {
+    reference: String,
+}
+
+/// A tax exemption registry service, asked with `POST {"tax_exempt_id": ...,
+/// "shipping_zip": ...}`. It answers valid exemptions with `200
+/// {"reference": ...}` and any other with `404`.
+pub struct HttpRegistry {
+    url: String,
+    timeout: Duration,
+    client: reqwest::Client,
+}
+
+impl HttpRegistry {
+    pub fn new(config: &ExemptionsConfig, client: reqwest::Client) -> Self {
+        Self {
+            url: config.url.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            client,
+        }
+    }
+
+    async fn call(&self, id: &str, zip: &str) -> Result<String, AppError> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(ACCEPT, "application/json")
+            .timeout(self.timeout)
+            .json(&ExemptionRequest {
+                tax_exempt_id: id,
+                shipping_zip: zip,
+            });
+        if let Some(request_id) = request_id::current() {
+            request = request.header(request_id::REQUEST_ID_HEADER, request_id);
+        }
+        let response = request.send().await.map_err(|err| {
+            AppError::ExemptionRegistryUnavailable(if err.is_timeout() {
+                format!("no answer within {} ms", self.timeout.as_millis())
+            } else {
+                err.to_string()
+            })
+        })?;
+        match response.status().as_u16() {
+            200 => response
+                .json::<ExemptionResponse>()
+                .await
+                .map(|body| body.reference)
+                .map_err(|err| {
+                    AppError::ExemptionRegistryUnavailable(format!("invalid response: {}", err))
+                }),
+            404 => Err(AppError::InvalidTaxExemption(id.to_string())),
+            status => Err(AppError::ExemptionRegistryUnavailable(format!(
+                "unexpected status {}",
+                status
+            ))),
+        }
+    }
+}
+
+impl ExemptionRegistry for HttpRegistry {
+    fn verify<'a>(&'a self, id: &'a str, zip: &'a str) -> BoxFuture<'a, Result<String, AppError>> {
+        self.call(id, zip).boxed()
+    }
+}
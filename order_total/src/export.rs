@@ -0,0 +1,167 @@
+//! Exports of the stored orders at `GET /orders/export`, for analysts: CSV
+//! for spreadsheets, or JSON lines of the whole records for pipelines. Orders
+//! are read from the store a page at a time and each page is sent once
+//! written, as a chunk of the response, so neither the service nor the client
+//! holds the whole export.
+
+use domain::Decimal;
+use hyper::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use hyper::{Body, Response};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::state::AppState;
+use crate::store::{Cursor, OrderQuery, OrderRecord};
+
+/// Orders read from the store, and sent, at a time.
+const PAGE_SIZE: usize = 500;
+
+/// The columns of the CSV export, as named by `Row`.
+const COLUMNS: [&str; 12] = [
+    "order_id",
+    "status",
+    "priced_at",
+    "shipping_zip",
+    "currency",
+    "rate",
+    "subtotal",
+    "discount",
+    "shipping",
+    "tax",
+    "total",
+    "cancellation_reason",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// One JSON record per line.
+    Jsonl,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "orders.csv",
+            ExportFormat::Jsonl => "orders.jsonl",
+        }
+    }
+
+    /// `records` in this format, after the CSV header on the first page.
+    fn write(self, records: &[OrderRecord], first: bool) -> anyhow::Result<Vec<u8>> {
+        match self {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(Vec::new());
+                if first {
+                    writer.write_record(COLUMNS)?;
+                }
+                for record in records {
+                    writer.serialize(Row::new(record))?;
+                }
+                writer
+                    .into_inner()
+                    .map_err(|err| anyhow::anyhow!("cannot write CSV: {}", err.error()))
+            }
+            ExportFormat::Jsonl => {
+                let mut lines = Vec::new();
+                for record in records {
+                    serde_json::to_writer(&mut lines, record)?;
+                    lines.push(b'\n');
+                }
+                Ok(lines)
+            }
+        }
+    }
+}
+
+/// The totals of an order, as a row of the CSV export.
+#[derive(Serialize)]
+struct Row<'a> {
+    order_id: i32,
+    status: &'static str,
+    priced_at: &'a str,
+    shipping_zip: &'a str,
+    currency: Option<&'a str>,
+    rate: Decimal,
+    subtotal: Option<Decimal>,
+    discount: Decimal,
+    shipping: Decimal,
+    tax: Decimal,
+    total: Decimal,
+    cancellation_reason: Option<&'a str>,
+}
+
+impl<'a> Row<'a> {
+    fn new(record: &'a OrderRecord) -> Self {
+        let order = &record.order;
+        Self {
+            order_id: order.order_id,
+            status: record.status.as_str(),
+            priced_at: &record.priced_at,
+            shipping_zip: &order.shipping_zip,
+            currency: order.currency.as_deref(),
+            rate: record.rate,
+            subtotal: order.subtotal,
+            discount: order.discount,
+            shipping: order.shipping,
+            tax: order.tax,
+            total: order.total,
+            cancellation_reason: record.cancellation_reason.as_deref(),
+        }
+    }
+}
+
+/// A chunked response with the orders matching `query`, in its order, in
+/// `format`. The orders are read and sent in the background, until the last
+/// one or until the client disconnects; should the store fail meanwhile, the
+/// response is cut short rather than ended as if complete.
+pub fn respond(state: AppState, mut query: OrderQuery, format: ExportFormat) -> Response<Body> {
+    query.offset = 0;
+    query.limit = PAGE_SIZE;
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut first = true;
+        loop {
+            let page = state.orders.list(&query).and_then(|page| {
+                let chunk = format.write(&page.orders, first)?;
+                Ok((chunk, page.next_cursor))
+            });
+            let (chunk, next_cursor) = match page {
+                Ok(page) => page,
+                Err(err) => {
+                    warn!(error = format!("{:#}", err), "cannot export the orders");
+                    sender.abort();
+                    return;
+                }
+            };
+            first = false;
+            // Fails once the client has disconnected.
+            if !chunk.is_empty() && sender.send_data(chunk.into()).await.is_err() {
+                return;
+            }
+            match next_cursor.as_deref().and_then(Cursor::decode) {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => return,
+            }
+        }
+    });
+    Response::builder()
+        .header(CONTENT_TYPE, format.content_type())
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", format.file_name()),
+        )
+        .body(body)
+        .unwrap()
+}
@@ -0,0 +1,401 @@
+//! Feature flags, turning features on for some callers only, or off for all
+//! without a restart: the batch endpoint, the fallback rates and hedging.
+//! Their rules come from a file, see `feature_flags.example.toml`, read again
+//! when it changes, or from a flag service polled over HTTP, answering the
+//! same rules as JSON or the features of the Unleash client API.
+//!
+//! A flag is evaluated for the caller of the request being served: the
+//! fingerprint of its API key, as in `key:3f2a9c1e`, or the subject of its
+//! token, as in `sub:alice`. A flag without rule is on, the feature then only
+//! depending on its configuration.
+
+use anyhow::{bail, Context};
+use arc_swap::ArcSwap;
+use figment::providers::{Format, Toml, Yaml};
+use figment::Figment;
+use reqwest::header::AUTHORIZATION;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::auth;
+use crate::config::{FlagProviderKind, FlagsConfig};
+use crate::metrics::Metrics;
+
+/// The features behind a flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Flag {
+    /// `/compute_batch`, which answers `404` while off.
+    BatchEndpoint,
+    /// The fallback rates of `rates.fallback`.
+    FallbackRates,
+    /// The hedged calls of `hedging`.
+    Hedging,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 3] = [Flag::BatchEndpoint, Flag::FallbackRates, Flag::Hedging];
+
+    /// The name of the flag in the rules.
+    pub fn name(self) -> &'static str {
+        match self {
+            Flag::BatchEndpoint => "batch_endpoint",
+            Flag::FallbackRates => "fallback_rates",
+            Flag::Hedging => "hedging",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|flag| flag.name() == name)
+    }
+}
+
+/// Who a flag is on for.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+struct Rule {
+    /// Off for everyone when false, whatever the other settings.
+    enabled: bool,
+    /// Callers the flag is on for, whatever the rollout.
+    callers: Vec<String>,
+    /// Callers the flag is off for, whatever the rollout.
+    except: Vec<String>,
+    /// Share of the callers the flag is on for, from 0 to 100. Every caller
+    /// falls in a bucket of its own for each flag, so a caller keeps its
+    /// answer while the share grows. Requests without a caller only count
+    /// with 100.
+    rollout_percent: f64,
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            callers: Vec::new(),
+            except: Vec::new(),
+            rollout_percent: 100.0,
+        }
+    }
+}
+
+impl Rule {
+    fn is_on(&self, flag: Flag, caller: Option<&str>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let caller = match caller {
+            Some(caller) => caller,
+            None => return self.rollout_percent >= 100.0,
+        };
+        if self.except.iter().any(|except| except == caller) {
+            return false;
+        }
+        if self.callers.iter().any(|listed| listed == caller) {
+            return true;
+        }
+        bucket(flag, caller) < self.rollout_percent
+    }
+}
+
+/// The bucket of `caller` for `flag`, from 0 to 100.
+fn bucket(flag: Flag, caller: &str) -> f64 {
+    let digest = Sha256::digest(format!("{}:{}", flag.name(), caller).as_bytes());
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    f64::from(value % 10_000) / 100.0
+}
+
+type Rules = HashMap<Flag, Rule>;
+
+/// Where the rules are read from.
+enum Source {
+    File(PathBuf),
+    Http {
+        url: String,
+        api_token: Option<String>,
+        client: reqwest::Client,
+        timeout: Duration,
+    },
+}
+
+/// The rules of the flags, refreshed in the background.
+pub struct Flags {
+    rules: ArcSwap<Rules>,
+    source: Option<Source>,
+    metrics: Arc<Metrics>,
+}
+
+impl Flags {
+    /// The flags of `config`, with the rules of its file read at once and
+    /// those of a flag service fetched in the background, with `client`;
+    /// every flag is on until they are. Must be called within the runtime.
+    pub fn from_config(
+        config: &FlagsConfig,
+        client: reqwest::Client,
+        metrics: Arc<Metrics>,
+    ) -> anyhow::Result<Arc<Self>> {
+        let source = match config.provider {
+            FlagProviderKind::None => None,
+            FlagProviderKind::File => {
+                let path = config
+                    .path
+                    .as_ref()
+                    .context("flags.provider is file, but flags.path isn't set")?;
+                Some(Source::File(PathBuf::from(path)))
+            }
+            FlagProviderKind::Http => Some(Source::Http {
+                url: config
+                    .url
+                    .clone()
+                    .context("flags.provider is http, but flags.url isn't set")?,
+                api_token: config.api_token.clone(),
+                client,
+                timeout: Duration::from_millis(config.timeout_ms),
+            }),
+        };
+        let rules = match &source {
+            Some(Source::File(path)) => {
+                load(path).with_context(|| format!("cannot read the flags of {:?}", path))?
+            }
+            _ => Rules::new(),
+        };
+        let flags = Arc::new(Self {
+            rules: ArcSwap::from_pointee(rules),
+            source,
+            metrics,
+        });
+        if flags.source.is_some() {
+            let interval = Duration::from_millis(config.refresh_ms.max(10));
+            tokio::spawn(refresh_periodically(Arc::downgrade(&flags), interval));
+        }
+        Ok(flags)
+    }
+
+    /// Whether `flag` is on for the caller of the request being served.
+    pub fn is_enabled(&self, flag: Flag) -> bool {
+        self.is_enabled_for(flag, auth::current_caller().as_deref())
+    }
+
+    /// Whether `flag` is on for `caller`, or for requests without a caller.
+    pub fn is_enabled_for(&self, flag: Flag, caller: Option<&str>) -> bool {
+        self.rules
+            .load()
+            .get(&flag)
+            .is_none_or(|rule| rule.is_on(flag, caller))
+    }
+
+    /// Reads the rules again, keeping the current ones when that fails.
+    async fn refresh(&self) {
+        let rules = match &self.source {
+            Some(Source::File(path)) => load(path),
+            Some(Source::Http {
+                url,
+                api_token,
+                client,
+                timeout,
+            }) => fetch(url, api_token.as_deref(), client, *timeout).await,
+            None => return,
+        };
+        match rules {
+            Ok(rules) => {
+                self.metrics
+                    .flag_refreshes
+                    .with_label_values(&["success"])
+                    .inc();
+                if **self.rules.load() != rules {
+                    let names: Vec<&str> = rules.keys().map(|flag| flag.name()).collect();
+                    info!(flags = ?names, "feature flags changed");
+                    self.rules.store(Arc::new(rules));
+                }
+            }
+            Err(err) => {
+                self.metrics
+                    .flag_refreshes
+                    .with_label_values(&["failure"])
+                    .inc();
+                warn!(
+                    error = format!("{:#}", err),
+                    "cannot refresh the feature flags"
+                );
+            }
+        }
+    }
+}
+
+/// Refreshes the rules every `interval`, those of a file only when it
+/// changed, for as long as the flags are in use. The rules of a flag service
+/// are fetched at once.
+async fn refresh_periodically(flags: Weak<Flags>, interval: Duration) {
+    let mut seen = flags.upgrade().and_then(|flags| match &flags.source {
+        Some(Source::File(path)) => version(path),
+        _ => None,
+    });
+    let mut wait = Duration::ZERO;
+    loop {
+        tokio::time::sleep(wait).await;
+        wait = interval;
+        let flags = match flags.upgrade() {
+            Some(flags) => flags,
+            None => return,
+        };
+        if let Some(Source::File(path)) = &flags.source {
+            // A file being replaced may be missing for a moment.
+            let current = version(path);
+            if current.is_none() || current == seen {
+                continue;
+            }
+            seen = current;
+        }
+        flags.refresh().await;
+    }
+}
+
+/// The modification time and size of `file`, which change with its content.
+fn version(file: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(file).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Reads the rules of a TOML, YAML or JSON file. Unknown flags are refused,
+/// as they are most likely misspelled.
+fn load(path: &Path) -> anyhow::Result<Rules> {
+    if !path.exists() {
+        bail!("file not found");
+    }
+    // JSON is read as YAML, of which it is a subset.
+    let is_yaml = matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("yaml") | Some("yml") | Some("json")
+    );
+    let figment = if is_yaml {
+        Figment::from(Yaml::file(path))
+    } else {
+        Figment::from(Toml::file(path))
+    };
+    let named: HashMap<String, Rule> = figment.extract()?;
+    let mut rules = Rules::new();
+    for (name, rule) in named {
+        let flag = Flag::from_name(&name).with_context(|| {
+            let names: Vec<&str> = Flag::ALL.iter().map(|flag| flag.name()).collect();
+            format!(
+                "unknown flag {:?}, expected one of {}",
+                name,
+                names.join(", ")
+            )
+        })?;
+        rules.insert(flag, rule);
+    }
+    Ok(rules)
+}
+
+/// Fetches the rules from a flag service. Flags the service knows of that
+/// aren't ours, as other applications' in Unleash, are skipped.
+async fn fetch(
+    url: &str,
+    api_token: Option<&str>,
+    client: &reqwest::Client,
+    timeout: Duration,
+) -> anyhow::Result<Rules> {
+    let mut request = client.get(url).timeout(timeout);
+    if let Some(token) = api_token {
+        request = request.header(AUTHORIZATION, token);
+    }
+    let body: Value = request
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("the flag service didn't answer JSON")?;
+    let named: Vec<(String, Rule)> = match body.get("features") {
+        Some(features) => serde_json::from_value::<Vec<UnleashFeature>>(features.clone())
+            .context("invalid Unleash features")?
+            .into_iter()
+            .map(|feature| (feature.name.clone(), feature.into_rule()))
+            .collect(),
+        None => serde_json::from_value::<HashMap<String, Rule>>(body)
+            .context("invalid flag rules")?
+            .into_iter()
+            .collect(),
+    };
+    Ok(named
+        .into_iter()
+        .filter_map(|(name, rule)| Flag::from_name(&name).map(|flag| (flag, rule)))
+        .collect())
+}
+
+/// A feature of the Unleash client API, `GET /api/client/features`.
+#[derive(Debug, Deserialize)]
+struct UnleashFeature {
+    name: String,
+    enabled: bool,
+    #[serde(default)]
+    strategies: Vec<UnleashStrategy>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnleashStrategy {
+    name: String,
+    #[serde(default)]
+    parameters: HashMap<String, Value>,
+}
+
+impl UnleashFeature {
+    /// The rule of the feature: on for the callers any of its strategies
+    /// picks. Of the standard strategies, `default`, `userWithId`,
+    /// `flexibleRollout` and `gradualRolloutUserId` are understood, with the
+    /// caller as user ID; the others pick nobody. Callers don't fall in the
+    /// same buckets as with Unleash's SDKs.
+    fn into_rule(self) -> Rule {
+        if self.strategies.is_empty() {
+            return Rule {
+                enabled: self.enabled,
+                ..Rule::default()
+            };
+        }
+        let mut rule = Rule {
+            enabled: self.enabled,
+            rollout_percent: 0.0,
+            ..Rule::default()
+        };
+        for strategy in &self.strategies {
+            let parameter = |name: &str| match strategy.parameters.get(name) {
+                Some(Value::String(value)) => Some(value.clone()),
+                Some(Value::Number(value)) => Some(value.to_string()),
+                _ => None,
+            };
+            let percent =
+                |name: &str| parameter(name).and_then(|percent| percent.trim().parse().ok());
+            match strategy.name.as_str() {
+                "default" => rule.rollout_percent = 100.0,
+                "userWithId" => rule.callers.extend(
+                    parameter("userIds")
+                        .unwrap_or_default()
+                        .split(',')
+                        .map(|caller| caller.trim().to_string())
+                        .filter(|caller| !caller.is_empty()),
+                ),
+                "flexibleRollout" | "gradualRolloutUserId" => {
+                    let name = if strategy.name == "flexibleRollout" {
+                        "rollout"
+                    } else {
+                        "percentage"
+                    };
+                    if let Some(percent) = percent(name) {
+                        rule.rollout_percent = rule.rollout_percent.max(percent);
+                    }
+                }
+                other => warn!(
+                    feature = %self.name,
+                    strategy = other,
+                    "unsupported Unleash strategy, ignored"
+                ),
+            }
+        }
+        rule
+    }
+}
@@ -0,0 +1,333 @@
+//! The GraphQL API at `/graphql`: the `computeTotal` mutation prices orders
+//! like `POST /compute` and the `taxRate` query looks up the rate of a zip
+//! code, both through the pricing service. Amounts are `Decimal` strings.
+//! Resolvers find the `AppState` in the data of the request.
+
+use async_graphql::{
+    Context, EmptySubscription, ErrorExtensions, InputObject, Object, Schema, SimpleObject,
+};
+use domain::Decimal;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Response};
+
+use crate::error::AppError;
+use crate::service;
+use crate::state::AppState;
+
+type OrderSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+lazy_static! {
+    static ref SCHEMA: OrderSchema =
+        Schema::build(QueryRoot, MutationRoot, EmptySubscription).finish();
+}
+
+/// An order to price, as in the body of `POST /compute`.
+#[derive(InputObject)]
+struct OrderInput {
+    order_id: i32,
+    product_id: Option<i32>,
+    quantity: Option<i32>,
+    subtotal: Option<Decimal>,
+    #[graphql(default)]
+    line_items: Vec<LineItemInput>,
+    promo_code: Option<String>,
+    shipping_address: String,
+    /// May be left out when the service validates addresses, which derives
+    /// it from `shipping_address`.
+    #[graphql(default)]
+    shipping_zip: String,
+    /// ISO 4217 code of the prices, e.g. `EUR`; the base currency when not
+    /// given.
+    currency: Option<String>,
+    /// The buyer's tax exemption certificate number.
+    tax_exempt_id: Option<String>,
+}
+
+#[derive(InputObject)]
+struct LineItemInput {
+    product_id: i32,
+    quantity: i32,
+    unit_price: Decimal,
+    #[graphql(default = true)]
+    taxable: bool,
+    /// Shipping weight of one unit, in pounds.
+    unit_weight: Option<Decimal>,
+}
+
+impl From<OrderInput> for domain::Order {
+    fn from(input: OrderInput) -> Self {
+        Self {
+            order_id: input.order_id,
+            product_id: input.product_id,
+            quantity: input.quantity,
+            subtotal: input.subtotal,
+            line_items: input.line_items.into_iter().map(Into::into).collect(),
+            promo_code: input.promo_code,
+            discount: Decimal::ZERO,
+            shipping_address: input.shipping_address,
+            shipping_zip: input.shipping_zip,
+            shipping: Decimal::ZERO,
+            shipping_taxable: false,
+            tax: Decimal::ZERO,
+            total: Decimal::ZERO,
+            rate_source: None,
+            currency: input.currency,
+            converted: None,
+            tax_exempt_id: input.tax_exempt_id,
+            exemption_reference: None,
+            tax_breakdown: Vec::new(),
+            normalized_address: None,
+            reservation_id: None,
+            saga_id: None,
+            rounding: None,
+        }
+    }
+}
+
+impl From<LineItemInput> for domain::LineItem {
+    fn from(input: LineItemInput) -> Self {
+        Self {
+            product_id: input.product_id,
+            quantity: input.quantity,
+            unit_price: input.unit_price,
+            taxable: input.taxable,
+            unit_weight: input.unit_weight,
+            discount: Decimal::ZERO,
+            tax: Decimal::ZERO,
+        }
+    }
+}
+
+/// A priced order.
+#[derive(SimpleObject)]
+struct Order {
+    order_id: i32,
+    product_id: Option<i32>,
+    quantity: Option<i32>,
+    subtotal: Option<Decimal>,
+    line_items: Vec<LineItem>,
+    promo_code: Option<String>,
+    /// Taken off the subtotal before tax.
+    discount: Decimal,
+    shipping_address: String,
+    shipping_zip: String,
+    shipping: Decimal,
+    /// Whether the destination state taxes the shipping charge.
+    shipping_taxable: bool,
+    tax: Decimal,
+    total: Decimal,
+    /// `fallback` when the rate came from the fallback rate table, `exempt`
+    /// for tax exempt orders.
+    rate_source: Option<String>,
+    currency: Option<String>,
+    /// The totals in the currency the order was sent in, when it was priced
+    /// in another one.
+    converted: Option<Conversion>,
+    tax_exempt_id: Option<String>,
+    /// The exemption registry's reference, when the order was priced without
+    /// tax.
+    exemption_reference: Option<String>,
+    /// The tax split by the jurisdictions levying it, when the rate provider
+    /// breaks its rates down.
+    tax_breakdown: Vec<TaxComponent>,
+    /// The shipping address in its standard form, when the service validates
+    /// addresses.
+    normalized_address: Option<NormalizedAddress>,
+    /// The inventory service's reservation of the line items, when the
+    /// service reserves stock.
+    reservation_id: Option<String>,
+    /// The saga that priced the order, when the service prices orders by a
+    /// saga.
+    saga_id: Option<String>,
+    /// How the amounts were rounded to cents: `half_even`, `half_up` or
+    /// `truncate`.
+    rounding: Option<String>,
+}
+
+#[derive(SimpleObject)]
+struct NormalizedAddress {
+    address: String,
+    zip: String,
+    /// Whether `shipping_zip` was derived from the address, the order having
+    /// none.
+    zip_derived: bool,
+}
+
+/// The part of an order's tax levied by one jurisdiction.
+#[derive(SimpleObject)]
+struct TaxComponent {
+    /// `state`, `county`, `city` or `special_district`.
+    level: String,
+    name: String,
+    rate: Decimal,
+    tax: Decimal,
+}
+
+/// The totals of an order in the currency it was sent in, each rounded to
+/// the currency's minor unit.
+#[derive(SimpleObject)]
+struct Conversion {
+    currency: String,
+    /// Units of the order's currency per unit of `currency`.
+    exchange_rate: Decimal,
+    subtotal: Decimal,
+    discount: Decimal,
+    shipping: Decimal,
+    tax: Decimal,
+    total: Decimal,
+}
+
+#[derive(SimpleObject)]
+struct LineItem {
+    product_id: i32,
+    quantity: i32,
+    unit_price: Decimal,
+    taxable: bool,
+    unit_weight: Option<Decimal>,
+    /// This line's share of the order discount.
+    discount: Decimal,
+    tax: Decimal,
+}
+
+impl From<domain::Order> for Order {
+    fn from(order: domain::Order) -> Self {
+        Self {
+            order_id: order.order_id,
+            product_id: order.product_id,
+            quantity: order.quantity,
+            subtotal: order.subtotal,
+            line_items: order.line_items.into_iter().map(Into::into).collect(),
+            promo_code: order.promo_code,
+            discount: order.discount,
+            shipping_address: order.shipping_address,
+            shipping_zip: order.shipping_zip,
+            shipping: order.shipping,
+            shipping_taxable: order.shipping_taxable,
+            tax: order.tax,
+            total: order.total,
+            rate_source: order.rate_source.map(|source| source.as_str().to_string()),
+            currency: order.currency,
+            converted: order.converted.map(Into::into),
+            tax_exempt_id: order.tax_exempt_id,
+            exemption_reference: order.exemption_reference,
+            tax_breakdown: order.tax_breakdown.into_iter().map(Into::into).collect(),
+            normalized_address: order.normalized_address.map(Into::into),
+            reservation_id: order.reservation_id,
+            saga_id: order.saga_id,
+            rounding: order.rounding.map(|rounding| rounding.as_str().to_string()),
+        }
+    }
+}
+
+impl From<domain::NormalizedAddress> for NormalizedAddress {
+    fn from(address: domain::NormalizedAddress) -> Self {
+        Self {
+            address: address.address,
+            zip: address.zip,
+            zip_derived: address.zip_derived,
+        }
+    }
+}
+
+impl From<domain::TaxComponent> for TaxComponent {
+    fn from(component: domain::TaxComponent) -> Self {
+        Self {
+            level: component.level.as_str().to_string(),
+            name: component.name,
+            rate: component.rate,
+            tax: component.tax,
+        }
+    }
+}
+
+impl From<domain::Conversion> for Conversion {
+    fn from(conversion: domain::Conversion) -> Self {
+        Self {
+            currency: conversion.currency,
+            exchange_rate: conversion.exchange_rate,
+            subtotal: conversion.subtotal,
+            discount: conversion.discount,
+            shipping: conversion.shipping,
+            tax: conversion.tax,
+            total: conversion.total,
+        }
+    }
+}
+
+impl From<domain::LineItem> for LineItem {
+    fn from(item: domain::LineItem) -> Self {
+        Self {
+            product_id: item.product_id,
+            quantity: item.quantity,
+            unit_price: item.unit_price,
+            taxable: item.taxable,
+            unit_weight: item.unit_weight,
+            discount: item.discount,
+            tax: item.tax,
+        }
+    }
+}
+
+struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The sales tax rate of a zip code.
+    async fn tax_rate(&self, ctx: &Context<'_>, zip: String) -> async_graphql::Result<Decimal> {
+        service::fetch_rate(ctx.data_unchecked::<AppState>(), &zip)
+            .await
+            .map(|rate| rate.value)
+            .map_err(error)
+    }
+}
+
+struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Prices an order: adds shipping, takes off the promo code's discount and
+    /// applies the sales tax rate of the shipping zip code.
+    async fn compute_total(
+        &self,
+        ctx: &Context<'_>,
+        order: OrderInput,
+    ) -> async_graphql::Result<Order> {
+        service::price_order(ctx.data_unchecked::<AppState>(), order.into())
+            .await
+            .map(Order::from)
+            .map_err(error)
+    }
+}
+
+/// A GraphQL error carrying the code and details of the error envelope in its
+/// extensions.
+fn error(err: AppError) -> async_graphql::Error {
+    let envelope = err.envelope();
+    async_graphql::Error::new(err.to_string()).extend_with(|_, extensions| {
+        extensions.set("code", err.code());
+        if let Some(Ok(details)) = envelope.details.map(async_graphql::Value::from_json) {
+            extensions.set("details", details);
+        }
+    })
+}
+
+/// `POST /graphql`. Errors are reported in the GraphQL response, so only a
+/// body that isn't a GraphQL request fails the HTTP request.
+pub async fn handle(state: AppState, byte_stream: &[u8]) -> Result<Response<Body>, AppError> {
+    let request: async_graphql::Request = serde_json::from_slice(byte_stream)?;
+    let response = SCHEMA.execute(request.data(state)).await;
+    let body = serde_json::to_string(&response).map_err(anyhow::Error::from)?;
+    let mut response = crate::response_build(&body);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Ok(response)
+}
+
+/// `GET /graphql`: the schema in SDL, for clients and code generators.
+pub fn sdl() -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(SCHEMA.sdl()))
+        .unwrap()
+}
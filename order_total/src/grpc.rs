@@ -0,0 +1,290 @@
+//! The gRPC API: `ComputeOrderTotal` of `proto/order_total.proto`, served over
+//! HTTP/2 on its own port. tonic's transport doesn't run on WasmEdge, so the
+//! unary call framing is done here on top of hyper.
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use prost::Message;
+use proto::{ComputeOrderTotalRequest, ComputeOrderTotalResponse, COMPUTE_ORDER_TOTAL_PATH};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tracing::{info, warn, Instrument};
+
+use crate::body;
+use crate::error::AppError;
+use crate::priority::{self, Priority};
+use crate::routing::Access;
+use crate::service::price_order;
+use crate::state::AppState;
+use crate::{auth, deadline, request_id, request_timeout, tenants, SHUTDOWN};
+
+// The gRPC status codes we answer with.
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const DEADLINE_EXCEEDED: u32 = 4;
+const NOT_FOUND: u32 = 5;
+const ALREADY_EXISTS: u32 = 6;
+const PERMISSION_DENIED: u32 = 7;
+const RESOURCE_EXHAUSTED: u32 = 8;
+const FAILED_PRECONDITION: u32 = 9;
+const ABORTED: u32 = 10;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+const UNAVAILABLE: u32 = 14;
+const UNAUTHENTICATED: u32 = 16;
+
+/// A failed call, as sent in the `grpc-status` and `grpc-message` headers.
+#[derive(Debug)]
+struct Status {
+    code: u32,
+    message: String,
+}
+
+impl From<AppError> for Status {
+    fn from(err: AppError) -> Self {
+        let code = match &err {
+            AppError::InvalidPayload(_)
+            | AppError::MissingField(_)
+            | AppError::Validation(_)
+            | AppError::UnsupportedCurrency(_)
+            | AppError::UnsupportedJurisdiction(..)
+            | AppError::InvalidTaxExemption(_)
+            | AppError::InvalidAddress(_)
+            | AppError::UnknownTenant(_)
+            | AppError::TenantRequired => INVALID_ARGUMENT,
+            AppError::RateNotFound(_)
+            | AppError::OrderNotFound(_)
+            | AppError::DeliveryNotFound(_)
+            | AppError::DeadLetterNotFound(_)
+            | AppError::RateOverrideNotFound(_)
+            | AppError::SagaNotFound(_)
+            | AppError::NotFound => NOT_FOUND,
+            AppError::MethodNotAllowed(_) => UNIMPLEMENTED,
+            AppError::InvalidTransition(..)
+            | AppError::InsufficientStock(_)
+            | AppError::PaymentDeclined(_) => FAILED_PRECONDITION,
+            AppError::IdempotencyKeyReused(_) => ALREADY_EXISTS,
+            AppError::IdempotencyKeyInUse(_) => ABORTED,
+            AppError::UpstreamTimeout(_)
+            | AppError::RequestTimeout(_)
+            | AppError::DeadlineExceeded => DEADLINE_EXCEEDED,
+            AppError::UpstreamUnavailable(_)
+            | AppError::ExemptionRegistryUnavailable(_)
+            | AppError::AddressValidatorUnavailable(_)
+            | AppError::InventoryUnavailable(_)
+            | AppError::PaymentServiceUnavailable(_)
+            | AppError::CircuitOpen(_)
+            | AppError::ShuttingDown
+            | AppError::InjectedFault
+            | AppError::Overloaded(_) => UNAVAILABLE,
+            AppError::Unauthorized(_) => UNAUTHENTICATED,
+            AppError::Forbidden => PERMISSION_DENIED,
+            AppError::RateLimited(_) | AppError::PayloadTooLarge(_) => RESOURCE_EXHAUSTED,
+            AppError::UnsupportedMediaType(_)
+            | AppError::UnsupportedEncoding(_)
+            | AppError::NotAcceptable(_)
+            | AppError::UpgradeRequired(_) => INVALID_ARGUMENT,
+            AppError::Internal(_) => INTERNAL,
+        };
+        Self {
+            code,
+            message: format!("{}: {}", err.code(), err),
+        }
+    }
+}
+
+/// The body of a unary response: at most one message, then the trailers.
+struct UnaryBody {
+    message: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl HttpBody for UnaryBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Infallible>>> {
+        Poll::Ready(self.message.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Infallible>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.message.is_none() && self.trailers.is_none()
+    }
+}
+
+/// Serves the gRPC API on `port` until shutdown.
+pub async fn run(state: AppState, port: u16) -> hyper::Result<()> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        let remote_addr = conn.remote_addr();
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                req.extensions_mut().insert(remote_addr);
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(serve_call(state, req).await) }
+            }))
+        }
+    });
+    info!(port, "gRPC server started");
+    crate::http2_options(Server::bind(&addr).http2_only(true))
+        .serve(make_svc)
+        .with_graceful_shutdown(SHUTDOWN.triggered())
+        .await
+}
+
+/// Serves one call, tagged with a request id like HTTP requests.
+async fn serve_call(state: AppState, req: Request<Body>) -> Response<UnaryBody> {
+    let start = Instant::now();
+    let path = req.uri().path().to_string();
+    let method = if path == COMPUTE_ORDER_TOTAL_PATH {
+        "ComputeOrderTotal"
+    } else {
+        "unmatched"
+    };
+    let request_id = request_id::from_request(&req);
+    let span = tracing::info_span!("grpc", request_id = %request_id, %path);
+
+    let result = request_id::scope(request_id.clone(), async {
+        let result: Result<Vec<u8>, Status> = if SHUTDOWN.is_draining() {
+            Err(AppError::ShuttingDown.into())
+        } else {
+            // A caller waiting less than the request timeout sets the
+            // deadline of the call, and of the calls made to price it.
+            let budget = deadline::budget(req.headers());
+            // Calls are interactive unless they say otherwise.
+            let class = Priority::from_headers(req.headers()).unwrap_or(Priority::Interactive);
+            state
+                .metrics
+                .priority_requests
+                .with_label_values(&[class.as_str()])
+                .inc();
+            let call = priority::scope(class, call(&state, req));
+            deadline::within_budget(request_timeout(), budget, call).await
+        };
+        let latency_ms = start.elapsed().as_millis() as u64;
+        match &result {
+            Ok(_) => info!(code = OK, latency_ms, "call completed"),
+            Err(status) => {
+                warn!(code = status.code, error = %status.message, latency_ms, "call failed")
+            }
+        }
+        result
+    })
+    .instrument(span)
+    .await;
+
+    let code = result.as_ref().map_or_else(|status| status.code, |_| OK);
+    state
+        .metrics
+        .grpc_requests
+        .with_label_values(&[method, &code.to_string()])
+        .inc();
+    let mut response = match result {
+        Ok(message) => ok_response(message),
+        Err(status) => error_response(&status),
+    };
+    if let Ok(value) = request_id.parse() {
+        response
+            .headers_mut()
+            .insert(request_id::REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+async fn call(state: &AppState, req: Request<Body>) -> Result<Vec<u8>, Status> {
+    if req.uri().path() != COMPUTE_ORDER_TOTAL_PATH {
+        return Err(Status {
+            code: UNIMPLEMENTED,
+            message: format!("unknown method {}", req.uri().path()),
+        });
+    }
+    state.rate_limiter.check(&req)?;
+    let caller = state.auth.authorize(req.headers(), Access::Client).await?;
+    let tenant = state.tenants.admit(req.headers())?;
+    let body = body::read(req).await?;
+    let request: ComputeOrderTotalRequest = decode(&body)?;
+    let order = request
+        .order
+        .ok_or_else(|| AppError::MissingField("order".into()))?;
+    let order =
+        domain::Order::try_from(order).map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+    let tenant = tenant.map(|tenant| tenant.id.clone());
+    let order = tenants::scope(tenant, auth::scope(caller, price_order(state, order))).await?;
+    Ok(ComputeOrderTotalResponse {
+        order: Some(order.into()),
+    }
+    .encode_to_vec())
+}
+
+/// Reads the single length-prefixed message of a unary call.
+fn decode<M: Message + Default>(body: &[u8]) -> Result<M, Status> {
+    match body {
+        [0, a, b, c, d, message @ ..]
+            if u32::from_be_bytes([*a, *b, *c, *d]) as usize == message.len() =>
+        {
+            M::decode(message).map_err(|err| AppError::InvalidPayload(err.to_string()).into())
+        }
+        [1, ..] => Err(Status {
+            code: UNIMPLEMENTED,
+            message: "compressed messages are not supported".into(),
+        }),
+        _ => Err(AppError::InvalidPayload("malformed gRPC message frame".into()).into()),
+    }
+}
+
+fn ok_response(message: Vec<u8>) -> Response<UnaryBody> {
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(OK));
+    Response::builder()
+        .header(CONTENT_TYPE, "application/grpc")
+        .body(UnaryBody {
+            message: Some(frame.into()),
+            trailers: Some(trailers),
+        })
+        .unwrap()
+}
+
+/// A trailers-only response: the status goes in the headers, without a body.
+fn error_response(status: &Status) -> Response<UnaryBody> {
+    Response::builder()
+        .header(CONTENT_TYPE, "application/grpc")
+        .header("grpc-status", status.code)
+        .header("grpc-message", percent_encode(&status.message))
+        .body(UnaryBody {
+            message: None,
+            trailers: None,
+        })
+        .unwrap()
+}
+
+/// `grpc-message` is percent-encoded UTF-8.
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'%' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,428 @@
+//! The sales tax rate service over gRPC: `FindRate` of
+//! `proto/sales_tax_rate.proto`, called instead of `POST /find_rate` when
+//! `upstream.url` is a `grpc://` URL. As for the gRPC API we serve, tonic's
+//! transport doesn't run on WasmEdge, so the unary call framing is done here
+//! on top of hyper's HTTP/2 client, in plaintext.
+
+use domain::RateQuote;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, CONTENT_TYPE, TE};
+use hyper::{Body, Client, Request, StatusCode, Uri};
+use prost::Message;
+use proto::{FindRateRequest, FindRateResponse, FIND_RATE_PATH};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tracing::warn;
+
+use crate::as_of;
+use crate::balancer::Balancer;
+use crate::chaos::{Fault, FaultInjector};
+use crate::config::UpstreamConfig;
+use crate::deadline;
+use crate::discovery::Discovery;
+use crate::error::AppError;
+use crate::hedging::Hedging;
+use crate::metrics::Metrics;
+use crate::rates::{Quote, TaxRateProvider, UpstreamCalls};
+use crate::request_id;
+use crate::retry::{RetryPolicy, Retryable};
+use crate::telemetry::{self, Span, SpanKind};
+use crate::validation::FieldError;
+
+/// The scheme of `upstream.url` selecting this provider.
+pub const GRPC_SCHEME: &str = "grpc://";
+
+// The gRPC status codes we tell apart.
+const OK: u32 = 0;
+const CANCELLED: u32 = 1;
+const INVALID_ARGUMENT: u32 = 3;
+const DEADLINE_EXCEEDED: u32 = 4;
+const NOT_FOUND: u32 = 5;
+const ALREADY_EXISTS: u32 = 6;
+const PERMISSION_DENIED: u32 = 7;
+const RESOURCE_EXHAUSTED: u32 = 8;
+const FAILED_PRECONDITION: u32 = 9;
+const ABORTED: u32 = 10;
+const OUT_OF_RANGE: u32 = 11;
+const UNIMPLEMENTED: u32 = 12;
+const UNAVAILABLE: u32 = 14;
+const UNAUTHENTICATED: u32 = 16;
+
+/// How a call failed.
+#[derive(Debug)]
+enum CallError {
+    /// The service answered with this status and message.
+    Status(u32, String),
+    /// No answer: the connection failed or the response was malformed.
+    Unavailable(String),
+    /// No answer within this time.
+    Timeout(Duration),
+}
+
+impl CallError {
+    /// An answer about the zip code, rather than a failing service.
+    fn is_answer(&self) -> bool {
+        matches!(self, CallError::Status(NOT_FOUND | INVALID_ARGUMENT, _))
+    }
+}
+
+/// Statuses are retried as their HTTP equivalents in `retryable_statuses`,
+/// and failures without an answer always.
+impl Retryable for Result<FindRateResponse, CallError> {
+    fn is_retryable(&self, retryable_statuses: &[u16]) -> bool {
+        match self {
+            Ok(_) => false,
+            Err(CallError::Status(code, _)) => retryable_statuses.contains(&http_status(*code)),
+            Err(CallError::Unavailable(_) | CallError::Timeout(_)) => true,
+        }
+    }
+}
+
+/// The HTTP status of a gRPC status code, as gRPC gateways map them.
+fn http_status(code: u32) -> u16 {
+    match code {
+        OK => 200,
+        CANCELLED => 499,
+        INVALID_ARGUMENT | FAILED_PRECONDITION | OUT_OF_RANGE => 400,
+        DEADLINE_EXCEEDED => 504,
+        NOT_FOUND => 404,
+        ALREADY_EXISTS | ABORTED => 409,
+        PERMISSION_DENIED => 403,
+        RESOURCE_EXHAUSTED => 429,
+        UNIMPLEMENTED => 501,
+        UNAVAILABLE => 503,
+        UNAUTHENTICATED => 401,
+        _ => 500,
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Status(code, message) => write!(f, "grpc-status {}: {}", code, message),
+            CallError::Unavailable(reason) => f.write_str(reason),
+            CallError::Timeout(timeout) => write!(f, "no answer within {:?}", timeout),
+        }
+    }
+}
+
+/// The sales tax rate service's gRPC API, at the `grpc://` URLs of
+/// `upstream.url` or at its discovered instances. Each call gets the upstream
+/// timeout or what is left of the deadline of the request being served,
+/// whichever is shorter, and sends it along as its `grpc-timeout`.
+pub struct GrpcProvider {
+    url: String,
+    balancer: Arc<Balancer>,
+    discovery: Option<Arc<Discovery>>,
+    timeout_ms: AtomicU64,
+    retry: RetryPolicy,
+    hedging: Option<Hedging>,
+    client: Client<HttpConnector, Body>,
+    metrics: Arc<Metrics>,
+    faults: Option<Arc<FaultInjector>>,
+}
+
+impl GrpcProvider {
+    pub fn new(config: &UpstreamConfig, calls: UpstreamCalls) -> Self {
+        let mut client = Client::builder();
+        client
+            .http2_only(true)
+            .pool_max_idle_per_host(config.pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_millis(config.pool_idle_timeout_ms));
+        if config.http2_keep_alive_interval_ms > 0 {
+            client
+                .http2_keep_alive_interval(Duration::from_millis(
+                    config.http2_keep_alive_interval_ms,
+                ))
+                .http2_keep_alive_timeout(Duration::from_millis(
+                    config.http2_keep_alive_timeout_ms,
+                ));
+        }
+        Self {
+            url: config.url.clone(),
+            balancer: calls.balancer,
+            discovery: calls.discovery,
+            timeout_ms: AtomicU64::new(config.timeout_ms),
+            retry: calls.retry,
+            hedging: calls.hedging,
+            client: client.build_http(),
+            metrics: calls.metrics,
+            faults: calls.faults,
+        }
+    }
+
+    /// Time allowed for each call.
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.load(Ordering::Relaxed))
+    }
+
+    async fn call(&self, zip: &str) -> Result<Quote, AppError> {
+        deadline::check()?;
+        if let Some(discovery) = &self.discovery {
+            discovery.ready().await?;
+        }
+        let result = self
+            .retry
+            .run(|| async {
+                match &self.hedging {
+                    Some(hedging) => hedging.run(&self.metrics, || self.attempt(zip)).await,
+                    None => self.attempt(zip).await,
+                }
+            })
+            .await;
+        match result {
+            Ok(response) => RateQuote::try_from(response)
+                .map(|quote| Quote::with_components(quote.rate, quote.components))
+                .map_err(|err| {
+                    AppError::UpstreamUnavailable(format!("invalid rate in response: {}", err))
+                }),
+            Err(CallError::Status(NOT_FOUND, _)) => Err(AppError::RateNotFound(zip.to_string())),
+            // The service turned the zip code down: the caller's mistake.
+            Err(CallError::Status(INVALID_ARGUMENT, _)) => {
+                Err(AppError::Validation(vec![FieldError {
+                    field: "shipping_zip".to_string(),
+                    message: "is not a valid zip code",
+                }]))
+            }
+            Err(CallError::Status(DEADLINE_EXCEEDED, _) | CallError::Timeout(_)) => {
+                Err(deadline::check()
+                    .err()
+                    .unwrap_or(AppError::UpstreamTimeout(self.timeout())))
+            }
+            Err(err) => Err(AppError::UpstreamUnavailable(err.to_string())),
+        }
+    }
+
+    /// One call, to the endpoint picked by the load balancer, traced and
+    /// counted.
+    async fn attempt(&self, zip: &str) -> Result<FindRateResponse, CallError> {
+        let start = Instant::now();
+        let lease = self.balancer.pick();
+        let url = lease
+            .as_ref()
+            .map_or(self.url.as_str(), |lease| lease.url());
+        let mut span = Span::start_child("FindRate", SpanKind::Client);
+        span.set_attribute("rpc.system", "grpc");
+        span.set_attribute("rpc.method", "FindRate");
+        span.set_attribute("http.url", url);
+        let fault = self.faults.as_ref().and_then(|faults| faults.upstream());
+        if let Some(Fault::Delay(delay)) = fault {
+            tokio::time::sleep(delay).await;
+        }
+        // An injected delay counts against the timeout. The deadline is read
+        // last, so a call it cuts short ends past it.
+        let timeout = self.timeout().saturating_sub(start.elapsed());
+        let timeout = deadline::remaining().map_or(timeout, |left| left.min(timeout));
+        let result = match fault {
+            Some(Fault::Fail) => Err(CallError::Status(UNAVAILABLE, "injected fault".into())),
+            _ => match request(url, zip, timeout, &span) {
+                Ok(request) => tokio::time::timeout(timeout, self.exchange(request))
+                    .await
+                    .unwrap_or(Err(CallError::Timeout(timeout))),
+                Err(err) => Err(err),
+            },
+        };
+        let outcome = match &result {
+            Ok(_) => "success",
+            Err(err) if err.is_answer() => "success",
+            Err(err) => {
+                warn!(error = %err, "sales tax rate service call failed");
+                span.set_error();
+                "failure"
+            }
+        };
+        if let Err(CallError::Status(code, _)) = &result {
+            span.set_attribute("rpc.grpc.status_code", *code);
+        }
+        span.end();
+        if let Some(lease) = &lease {
+            self.balancer.record(lease, outcome == "success");
+        }
+        self.metrics
+            .upstream_requests
+            .with_label_values(&[outcome])
+            .inc();
+        self.metrics
+            .upstream_request_duration
+            .with_label_values(&[outcome])
+            .observe(start.elapsed().as_secs_f64());
+        if let (Some(hedging), "success") = (&self.hedging, outcome) {
+            hedging.record(start.elapsed());
+        }
+        result
+    }
+
+    /// Sends `request` and reads its single response message, and the status
+    /// from the trailers, or from the headers of a trailers-only response.
+    async fn exchange(&self, request: Request<Body>) -> Result<FindRateResponse, CallError> {
+        let unavailable = |err: hyper::Error| CallError::Unavailable(err.to_string());
+        let response = self.client.request(request).await.map_err(unavailable)?;
+        if response.status() != StatusCode::OK {
+            return Err(CallError::Unavailable(format!(
+                "unexpected status {}",
+                response.status().as_u16()
+            )));
+        }
+        let (parts, mut body) = response.into_parts();
+        let mut message = Vec::new();
+        while let Some(chunk) = body.data().await {
+            message.extend_from_slice(&chunk.map_err(unavailable)?);
+        }
+        let trailers = body.trailers().await.map_err(unavailable)?;
+        let headers = match &trailers {
+            Some(trailers) if trailers.contains_key("grpc-status") => trailers,
+            _ => &parts.headers,
+        };
+        match status(headers)? {
+            (OK, _) => decode(&message),
+            (code, message) => Err(CallError::Status(code, message)),
+        }
+    }
+
+    /// Any endpoint accepting connections shows the service is up.
+    async fn reachable(&self, timeout: Duration) -> bool {
+        if let Some(discovery) = &self.discovery {
+            if discovery.ready().await.is_err() {
+                return false;
+            }
+        }
+        for url in self.balancer.urls() {
+            let authority = match url
+                .parse::<Uri>()
+                .ok()
+                .and_then(|uri| uri.into_parts().authority)
+            {
+                Some(authority) => authority,
+                None => continue,
+            };
+            let connect = TcpStream::connect(authority.as_str());
+            if let Ok(Ok(_)) = tokio::time::timeout(timeout, connect).await {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl TaxRateProvider for GrpcProvider {
+    fn rate<'a>(&'a self, zip: &'a str) -> BoxFuture<'a, Result<Quote, AppError>> {
+        self.call(zip).boxed()
+    }
+
+    fn is_ready(&self, timeout: Duration) -> BoxFuture<'_, bool> {
+        self.reachable(timeout).boxed()
+    }
+
+    /// The timeout, and the endpoints unless they are discovered.
+    fn reconfigure(&self, upstream: &UpstreamConfig) {
+        self.timeout_ms
+            .store(upstream.timeout_ms, Ordering::Relaxed);
+        if self.discovery.is_none() {
+            self.balancer.set_endpoints(upstream.urls());
+        }
+    }
+}
+
+/// The `FindRate` call of `zip` to the service at `url`, whose path, if any,
+/// is ignored.
+fn request(
+    url: &str,
+    zip: &str,
+    timeout: Duration,
+    span: &Span,
+) -> Result<Request<Body>, CallError> {
+    let authority = url
+        .parse::<Uri>()
+        .ok()
+        .and_then(|uri| uri.into_parts().authority)
+        .ok_or_else(|| CallError::Unavailable(format!("invalid upstream URL {:?}", url)))?;
+    let message = FindRateRequest {
+        zip: zip.to_string(),
+        as_of: as_of::current().unwrap_or_default(),
+    }
+    .encode_to_vec();
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    let mut request = Request::post(format!("http://{}{}", authority, FIND_RATE_PATH))
+        .header(CONTENT_TYPE, "application/grpc")
+        .header(TE, "trailers")
+        .header(
+            deadline::GRPC_TIMEOUT_HEADER,
+            deadline::grpc_timeout(timeout),
+        )
+        .header(
+            telemetry::TRACEPARENT_HEADER,
+            span.context().to_traceparent(),
+        );
+    if let Some(request_id) = request_id::current() {
+        request = request.header(request_id::REQUEST_ID_HEADER, request_id);
+    }
+    request
+        .body(Body::from(frame))
+        .map_err(|err| CallError::Unavailable(err.to_string()))
+}
+
+/// The `grpc-status` and decoded `grpc-message` of a response.
+fn status(headers: &HeaderMap) -> Result<(u32, String), CallError> {
+    let code = headers
+        .get("grpc-status")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| CallError::Unavailable("response without a grpc-status".into()))?;
+    let message = headers
+        .get("grpc-message")
+        .map(|value| percent_decode(value.as_bytes()))
+        .unwrap_or_default();
+    Ok((code, message))
+}
+
+/// Reads the single length-prefixed message of a unary response.
+fn decode(body: &[u8]) -> Result<FindRateResponse, CallError> {
+    match body {
+        [0, a, b, c, d, message @ ..]
+            if u32::from_be_bytes([*a, *b, *c, *d]) as usize == message.len() =>
+        {
+            FindRateResponse::decode(message)
+                .map_err(|err| CallError::Unavailable(format!("invalid response: {}", err)))
+        }
+        [1, ..] => Err(CallError::Unavailable(
+            "compressed messages are not supported".into(),
+        )),
+        _ => Err(CallError::Unavailable(
+            "malformed gRPC message frame".into(),
+        )),
+    }
+}
+
+/// `grpc-message` is percent-encoded UTF-8.
+fn percent_decode(message: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(message.len());
+    let mut bytes = message.iter();
+    while let Some(&byte) = bytes.next() {
+        let escaped = match byte {
+            b'%' => {
+                let hex = bytes
+                    .as_slice()
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok());
+                hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(escaped) => {
+                decoded.push(escaped);
+                bytes.nth(1);
+            }
+            None => decoded.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
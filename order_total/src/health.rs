@@ -0,0 +1,77 @@
+use hyper::{Body, Response, StatusCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::rates::TaxRateProvider;
+use crate::{response_build, response_build_with_status};
+
+/// Readiness means the rate provider can look up rates, i.e. the sales tax
+/// rate service answers, and the rate cache is warmed up. Probes come in
+/// often, so the outcome of a check is reused for a while.
+pub struct ReadinessCheck {
+    rates: Arc<dyn TaxRateProvider>,
+    timeout: Duration,
+    cache_for: Duration,
+    last: Mutex<Option<(Instant, bool)>>,
+    warming_up: AtomicBool,
+}
+
+impl ReadinessCheck {
+    pub fn new(rates: Arc<dyn TaxRateProvider>, timeout: Duration, cache_for: Duration) -> Self {
+        Self {
+            rates,
+            timeout,
+            cache_for,
+            last: Mutex::new(None),
+            warming_up: AtomicBool::new(false),
+        }
+    }
+
+    /// Reports not ready until `warmed_up`.
+    pub fn warming_up(&self) {
+        self.warming_up.store(true, Ordering::Relaxed);
+    }
+
+    pub fn warmed_up(&self) {
+        self.warming_up.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_warming_up(&self) -> bool {
+        self.warming_up.load(Ordering::Relaxed)
+    }
+
+    pub async fn is_ready(&self) -> bool {
+        if let Some((checked_at, ready)) = *self.last.lock().unwrap() {
+            if checked_at.elapsed() < self.cache_for {
+                return ready;
+            }
+        }
+        let ready = self.rates.is_ready(self.timeout).await;
+        *self.last.lock().unwrap() = Some((Instant::now(), ready));
+        ready
+    }
+}
+
+/// Liveness: the process is up and serving requests.
+pub fn healthz() -> Response<Body> {
+    response_build("{\"status\":\"ok\"}")
+}
+
+/// Readiness: 200 while the sales tax rate service is reachable, once the rate
+/// cache is warmed up, 503 otherwise.
+pub async fn readyz(check: &ReadinessCheck) -> Response<Body> {
+    if check.is_warming_up() {
+        response_build_with_status(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "{\"status\":\"not ready\",\"reason\":\"rate cache warming up\"}",
+        )
+    } else if check.is_ready().await {
+        response_build("{\"status\":\"ready\"}")
+    } else {
+        response_build_with_status(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "{\"status\":\"not ready\",\"reason\":\"sales tax rate service unreachable\"}",
+        )
+    }
+}
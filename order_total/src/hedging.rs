@@ -0,0 +1,97 @@
+//! Hedged calls to the sales tax rate service: when a call hasn't answered
+//! within the `hedging.percentile` latency of the recent calls, a second one
+//! is sent and whichever answers first is taken, the other one cancelled. A
+//! call stuck on a slow connection or instance then costs about the usual
+//! latency plus the hedge delay, for a few percent more calls. Calls aren't
+//! hedged for the callers the `hedging` flag is off for.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::HedgingConfig;
+use crate::flags::{Flag, Flags};
+use crate::metrics::Metrics;
+
+/// Latencies needed before calls are hedged, so the first slow calls don't
+/// set a hedge delay for everyone.
+const MIN_SAMPLES: usize = 20;
+
+pub struct Hedging {
+    percentile: f64,
+    min_delay: Duration,
+    window: usize,
+    /// The latencies of the last `window` answered calls.
+    latencies: Mutex<VecDeque<Duration>>,
+    flags: Arc<Flags>,
+}
+
+impl Hedging {
+    /// The hedging of `config`, or none when it is off.
+    pub fn from_config(config: &HedgingConfig, flags: Arc<Flags>) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        Some(Self {
+            percentile: config.percentile.clamp(0.0, 100.0),
+            min_delay: Duration::from_millis(config.min_delay_ms),
+            window: config.window.max(MIN_SAMPLES),
+            latencies: Mutex::new(VecDeque::new()),
+            flags,
+        })
+    }
+
+    /// Records how long an answered call took.
+    pub fn record(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == self.window {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    /// How long to wait for a call before hedging it: the percentile latency
+    /// of the recent calls, at least `min_delay`. None until enough calls
+    /// were recorded.
+    pub fn delay(&self) -> Option<Duration> {
+        let mut latencies: Vec<Duration> = self.latencies.lock().unwrap().iter().copied().collect();
+        if latencies.len() < MIN_SAMPLES {
+            return None;
+        }
+        latencies.sort();
+        // The nearest rank: the smallest latency of at least `percentile` of
+        // the calls.
+        let rank = (self.percentile / 100.0 * latencies.len() as f64).ceil() as usize;
+        let latency = latencies[rank.clamp(1, latencies.len()) - 1];
+        Some(latency.max(self.min_delay))
+    }
+
+    /// Runs `call`, and a second one if the first takes longer than the
+    /// hedge delay, returning whichever finishes first. The other one is
+    /// dropped, which cancels its request.
+    pub async fn run<F, Fut, T>(&self, metrics: &Metrics, mut call: F) -> T
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let first = call();
+        let delay = match self.delay() {
+            Some(delay) if self.flags.is_enabled(Flag::Hedging) => delay,
+            _ => return first.await,
+        };
+        tokio::pin!(first);
+        tokio::select! {
+            output = &mut first => return output,
+            _ = tokio::time::sleep(delay) => {}
+        }
+        let hedge = call();
+        tokio::pin!(hedge);
+        let (output, winner) = tokio::select! {
+            output = &mut first => (output, "first"),
+            output = &mut hedge => (output, "hedge"),
+        };
+        metrics.hedged_requests.with_label_values(&[winner]).inc();
+        output
+    }
+}
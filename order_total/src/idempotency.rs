@@ -0,0 +1,367 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::body::Bytes;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::error::AppError;
+use crate::metrics::Metrics;
+use crate::response_build_with_status;
+use crate::shared_cache::SharedCache;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on responses replayed from an earlier request with the same key.
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+#[derive(Debug)]
+enum State {
+    InFlight,
+    Done(Stored),
+}
+
+/// A response kept for replay, in the format it was negotiated in.
+#[derive(Debug, Clone)]
+struct Stored {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+impl Stored {
+    fn response(&self) -> Response<Body> {
+        let mut response = response_build_with_status(self.status, "");
+        *response.body_mut() = Body::from(self.body.clone());
+        if let Some(content_type) = &self.content_type {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, content_type.clone());
+        }
+        response
+    }
+}
+
+/// A key as kept in the shared cache: the response is missing while the
+/// replica that claimed the key is handling its request.
+#[derive(Serialize, Deserialize)]
+struct SharedEntry {
+    fingerprint: u64,
+    response: Option<SharedResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SharedResponse {
+    status: u16,
+    content_type: Option<String>,
+    /// Base64.
+    body: String,
+}
+
+impl SharedResponse {
+    fn new(stored: &Stored) -> Self {
+        Self {
+            status: stored.status.as_u16(),
+            content_type: stored
+                .content_type
+                .as_ref()
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            body: STANDARD.encode(&stored.body),
+        }
+    }
+
+    fn stored(&self) -> Option<Stored> {
+        Some(Stored {
+            status: StatusCode::from_u16(self.status).ok()?,
+            content_type: match &self.content_type {
+                Some(value) => Some(value.parse().ok()?),
+                None => None,
+            },
+            body: STANDARD.decode(&self.body).ok()?.into(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Hash of the request body, to tell a retry from a reused key.
+    fingerprint: u64,
+    state: State,
+    inserted_at: Instant,
+}
+
+/// Responses by `Idempotency-Key`, kept for a fixed time to live, so a client
+/// retrying a request gets the original response instead of a recomputation.
+/// Server errors aren't kept, so those requests can be retried for real.
+///
+/// With a shared cache, keys are claimed there too, so a retry reaching
+/// another replica is answered the same. While the shared cache is
+/// unreachable, each replica goes on with its own keys.
+pub struct IdempotencyStore {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+    shared: Option<Arc<dyn SharedCache>>,
+    metrics: Arc<Metrics>,
+}
+
+/// A request holding its key until its response is stored. Dropping it
+/// without finishing, e.g. when the request times out, releases the key.
+pub struct Pending<'a> {
+    store: &'a IdempotencyStore,
+    key: String,
+    fingerprint: u64,
+    finished: bool,
+    /// Whether the key was claimed in the shared cache, to be released there
+    /// too.
+    shared: bool,
+}
+
+enum Begin<'a> {
+    New(Pending<'a>),
+    Replay(Stored),
+}
+
+/// The `Idempotency-Key` of the request, if it has one.
+pub fn key(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// The same on every replica.
+fn fingerprint(request: &[u8]) -> u64 {
+    let digest = Sha256::digest(request);
+    u64::from_be_bytes(
+        digest[..8]
+            .try_into()
+            .expect("SHA-256 digests are 32 bytes"),
+    )
+}
+
+fn shared_key(key: &str) -> String {
+    format!("idempotency:{}", key)
+}
+
+fn replayed(stored: &Stored) -> Response<Body> {
+    let mut response = stored.response();
+    response
+        .headers_mut()
+        .insert(REPLAYED_HEADER, "true".parse().unwrap());
+    response
+}
+
+impl IdempotencyStore {
+    pub fn new(
+        ttl: Duration,
+        max_entries: usize,
+        shared: Option<Arc<dyn SharedCache>>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            shared,
+            metrics,
+        }
+    }
+
+    /// Runs `handler` for the first request with `key`, and answers later
+    /// requests with the same key and body with its stored response.
+    pub async fn serve<F>(
+        &self,
+        key: &str,
+        request: &[u8],
+        handler: F,
+    ) -> Result<Response<Body>, AppError>
+    where
+        F: Future<Output = Result<Response<Body>, AppError>>,
+    {
+        if self.max_entries == 0 {
+            return handler.await;
+        }
+        let mut pending = match self.begin(key, request)? {
+            Begin::New(pending) => pending,
+            Begin::Replay(stored) => return Ok(replayed(&stored)),
+        };
+        if let Some(stored) = pending.claim_shared().await? {
+            let response = replayed(&stored);
+            pending.finish(stored).await;
+            return Ok(response);
+        }
+        match handler.await {
+            Ok(response) => {
+                let (parts, body) = response.into_parts();
+                let body = hyper::body::to_bytes(body)
+                    .await
+                    .map_err(anyhow::Error::from)?;
+                pending
+                    .finish(Stored {
+                        status: parts.status,
+                        content_type: parts.headers.get(CONTENT_TYPE).cloned(),
+                        body: body.clone(),
+                    })
+                    .await;
+                Ok(Response::from_parts(parts, Body::from(body)))
+            }
+            Err(err) => {
+                pending
+                    .finish(Stored {
+                        status: err.status(),
+                        content_type: None,
+                        body: err.body().into(),
+                    })
+                    .await;
+                Err(err)
+            }
+        }
+    }
+
+    fn begin(&self, key: &str, request: &[u8]) -> Result<Begin<'_>, AppError> {
+        let fingerprint = fingerprint(request);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.inserted_at.elapsed() < self.ttl);
+        if let Some(entry) = entries.get(key) {
+            if entry.fingerprint != fingerprint {
+                return Err(AppError::IdempotencyKeyReused(key.to_string()));
+            }
+            return match &entry.state {
+                State::InFlight => Err(AppError::IdempotencyKeyInUse(key.to_string())),
+                State::Done(stored) => Ok(Begin::Replay(stored.clone())),
+            };
+        }
+        if entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key.to_string(),
+            Entry {
+                fingerprint,
+                state: State::InFlight,
+                inserted_at: Instant::now(),
+            },
+        );
+        Ok(Begin::New(Pending {
+            store: self,
+            key: key.to_string(),
+            fingerprint,
+            finished: false,
+            shared: false,
+        }))
+    }
+}
+
+impl Pending<'_> {
+    /// Claims the key in the shared cache, if there is one. Returns the
+    /// response of another replica that answered a request with the key,
+    /// and fails as `begin` does when the key is in use or reused there.
+    async fn claim_shared(&mut self) -> Result<Option<Stored>, AppError> {
+        let shared = match &self.store.shared {
+            Some(shared) => shared,
+            None => return Ok(None),
+        };
+        let key = shared_key(&self.key);
+        let claim = SharedEntry {
+            fingerprint: self.fingerprint,
+            response: None,
+        };
+        let claim = serde_json::to_vec(&claim).expect("idempotency keys serialize");
+        let lookups = &self.store.metrics.shared_cache_lookups;
+        let existing = async {
+            // A request doesn't outlive its timeout, so neither does the
+            // claim of a replica stopped while handling one.
+            let ttl = crate::request_timeout().min(self.store.ttl);
+            if shared.set_if_missing(&key, &claim, ttl).await? {
+                return Ok(None);
+            }
+            // Expired since, or released by a replica whose request failed.
+            let value = shared.get(&key).await?;
+            let entry = value.and_then(|value| serde_json::from_slice::<SharedEntry>(&value).ok());
+            Ok::<_, anyhow::Error>(entry)
+        };
+        let existing = match existing.await {
+            Ok(existing) => existing,
+            Err(err) => {
+                warn!(error = format!("{:#}", err), key = %self.key, "cannot claim the idempotency key in the shared cache");
+                lookups.with_label_values(&["idempotency", "error"]).inc();
+                return Ok(None);
+            }
+        };
+        let entry = match existing {
+            Some(entry) => entry,
+            None => {
+                lookups.with_label_values(&["idempotency", "miss"]).inc();
+                self.shared = true;
+                return Ok(None);
+            }
+        };
+        lookups.with_label_values(&["idempotency", "hit"]).inc();
+        if entry.fingerprint != self.fingerprint {
+            return Err(AppError::IdempotencyKeyReused(self.key.clone()));
+        }
+        match entry.response.as_ref().and_then(SharedResponse::stored) {
+            Some(stored) => Ok(Some(stored)),
+            None => Err(AppError::IdempotencyKeyInUse(self.key.clone())),
+        }
+    }
+
+    /// Stores the response for replay; server errors release the key instead.
+    async fn finish(mut self, stored: Stored) {
+        if stored.status.is_server_error() {
+            return;
+        }
+        if self.shared {
+            self.share(&stored).await;
+        }
+        if let Some(entry) = self.store.entries.lock().unwrap().get_mut(&self.key) {
+            entry.state = State::Done(stored);
+        }
+        self.finished = true;
+    }
+
+    async fn share(&self, stored: &Stored) {
+        if let Some(shared) = &self.store.shared {
+            let entry = SharedEntry {
+                fingerprint: self.fingerprint,
+                response: Some(SharedResponse::new(stored)),
+            };
+            let value = serde_json::to_vec(&entry).expect("idempotency keys serialize");
+            let key = shared_key(&self.key);
+            if let Err(err) = shared.set(&key, &value, self.store.ttl).await {
+                warn!(error = format!("{:#}", err), key = %self.key, "cannot store the response in the shared cache");
+            }
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.store.entries.lock().unwrap().remove(&self.key);
+        if let (true, Some(shared)) = (self.shared, self.store.shared.clone()) {
+            let key = shared_key(&self.key);
+            tokio::spawn(async move {
+                if let Err(err) = shared.delete(&key).await {
+                    warn!(error = format!("{:#}", err), key = %key, "cannot release the idempotency key in the shared cache");
+                }
+            });
+        }
+    }
+}
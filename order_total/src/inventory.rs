@@ -0,0 +1,203 @@
+//! Stock reservations at an inventory service, the first step of pricing an
+//! order when enabled: the line items of the order are reserved before it is
+//! priced, and the reservation is released again, as compensation, when
+//! pricing fails, times out or is cancelled. Only an order priced to the end
+//! keeps its reservation.
+
+use domain::{ErrorResponse, Order};
+use prometheus::IntCounterVec;
+use reqwest::header::ACCEPT;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::InventoryConfig;
+use crate::error::AppError;
+use crate::metrics::Metrics;
+use crate::request_id;
+
+/// An inventory service, asked with `POST <url> {"order_id": ..., "items":
+/// [{"product_id": ..., "quantity": ...}]}`. It answers a reservation with
+/// `201 {"reservation_id": ...}`, or `409` with an error body when some item
+/// is out of stock, and releases reservation `id` on `DELETE <url>/<id>`.
+pub struct Inventory {
+    url: String,
+    timeout: Duration,
+    client: reqwest::Client,
+    releases: IntCounterVec,
+}
+
+/// The body of a reservation.
+#[derive(Serialize)]
+struct ReservationRequest {
+    order_id: i32,
+    items: Vec<ReservedItem>,
+}
+
+#[derive(Serialize)]
+struct ReservedItem {
+    product_id: i32,
+    quantity: i32,
+}
+
+/// The service's answer for a reservation.
+#[derive(Deserialize)]
+struct ReservationResponse {
+    reservation_id: String,
+}
+
+/// Stock held for an order being priced. Dropped without `keep`, e.g. on an
+/// error or when the request is cancelled, it is released in the background.
+pub struct Reservation {
+    inventory: Arc<Inventory>,
+    id: String,
+    kept: bool,
+}
+
+impl Reservation {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Holds the stock for good: the order was priced.
+    pub fn keep(mut self) {
+        self.kept = true;
+    }
+
+    /// Releases the stock now rather than in the background.
+    pub async fn release(mut self) -> Result<(), AppError> {
+        self.kept = true;
+        self.inventory
+            .compensate(&self.id, request_id::current())
+            .await
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.kept {
+            return;
+        }
+        let inventory = self.inventory.clone();
+        let id = std::mem::take(&mut self.id);
+        let request_id = request_id::current();
+        tokio::spawn(async move {
+            // Failures are logged and counted.
+            let _ = inventory.compensate(&id, request_id).await;
+        });
+    }
+}
+
+impl Inventory {
+    /// The inventory service of `config`, called with `client`, or none when
+    /// stock isn't reserved.
+    pub fn from_config(
+        config: &InventoryConfig,
+        client: reqwest::Client,
+        metrics: &Metrics,
+    ) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        Some(Self {
+            url: config.url.trim_end_matches('/').to_string(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            client,
+            releases: metrics.inventory_releases.clone(),
+        })
+    }
+
+    /// Reserves the line items of `order`. Fails with `InsufficientStock`
+    /// when the service can't hold them all.
+    pub async fn reserve(self: &Arc<Self>, order: &Order) -> Result<Reservation, AppError> {
+        let body = ReservationRequest {
+            order_id: order.order_id,
+            items: order
+                .line_items
+                .iter()
+                .map(|item| ReservedItem {
+                    product_id: item.product_id,
+                    quantity: item.quantity,
+                })
+                .collect(),
+        };
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(ACCEPT, "application/json")
+            .timeout(self.timeout)
+            .json(&body);
+        if let Some(request_id) = request_id::current() {
+            request = request.header(request_id::REQUEST_ID_HEADER, request_id);
+        }
+        let response = request.send().await.map_err(|err| self.unavailable(err))?;
+        match response.status().as_u16() {
+            200 | 201 => response
+                .json::<ReservationResponse>()
+                .await
+                .map(|body| Reservation {
+                    inventory: self.clone(),
+                    id: body.reservation_id,
+                    kept: false,
+                })
+                .map_err(|err| {
+                    AppError::InventoryUnavailable(format!("invalid response: {}", err))
+                }),
+            409 => Err(AppError::InsufficientStock(
+                match response.json::<ErrorResponse>().await {
+                    Ok(body) => body.error.message,
+                    Err(_) => "not enough stock for the order".into(),
+                },
+            )),
+            status => Err(AppError::InventoryUnavailable(format!(
+                "unexpected status {}",
+                status
+            ))),
+        }
+    }
+
+    /// Releases reservation `id`, on behalf of request `request_id`. A
+    /// reservation the service doesn't know is as good as released.
+    async fn release(&self, id: &str, request_id: Option<String>) -> Result<(), AppError> {
+        let mut request = self
+            .client
+            .delete(format!("{}/{}", self.url, id))
+            .timeout(self.timeout);
+        if let Some(request_id) = request_id {
+            request = request.header(request_id::REQUEST_ID_HEADER, request_id);
+        }
+        let response = request.send().await.map_err(|err| self.unavailable(err))?;
+        match response.status().as_u16() {
+            200..=299 | 404 => Ok(()),
+            status => Err(AppError::InventoryUnavailable(format!(
+                "unexpected status {}",
+                status
+            ))),
+        }
+    }
+
+    /// Releases reservation `id`, logging and counting the outcome.
+    async fn compensate(&self, id: &str, request_id: Option<String>) -> Result<(), AppError> {
+        let result = self.release(id, request_id).await;
+        match &result {
+            Ok(()) => {
+                info!(reservation_id = %id, "stock reservation released");
+                self.releases.with_label_values(&["released"]).inc();
+            }
+            Err(err) => {
+                warn!(error = %err, reservation_id = %id, "failed to release stock reservation");
+                self.releases.with_label_values(&["failed"]).inc();
+            }
+        }
+        result
+    }
+
+    fn unavailable(&self, err: reqwest::Error) -> AppError {
+        AppError::InventoryUnavailable(if err.is_timeout() {
+            format!("no answer within {} ms", self.timeout.as_millis())
+        } else {
+            err.to_string()
+        })
+    }
+}
@@ -0,0 +1,257 @@
+//! Verification of bearer JWTs: HS256 tokens with a shared secret and RS256
+//! tokens with the keys of a JWKS document. jsonwebtoken builds on ring, which
+//! doesn't support `wasm32-wasi`, so signatures are checked with the RustCrypto
+//! crates.
+
+use anyhow::{anyhow, bail, Context};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::config::JwtConfig;
+
+/// Clock skew tolerated on `exp` and `nbf`.
+const LEEWAY_SECS: u64 = 60;
+
+/// Tokens naming an unknown key trigger a new fetch of the JWKS document, at
+/// most this often.
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// The claims we check or use.
+#[derive(Deserialize)]
+pub struct Claims {
+    pub sub: Option<String>,
+    exp: Option<u64>,
+    nbf: Option<u64>,
+    iss: Option<String>,
+    #[serde(default)]
+    aud: Audience,
+    /// Space-separated scopes, as in OAuth 2.
+    #[serde(default)]
+    scope: String,
+}
+
+impl Claims {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope.split(' ').any(|granted| granted == scope)
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    #[default]
+    None,
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::None => false,
+            Audience::One(one) => one == audience,
+            Audience::Many(many) => many.iter().any(|one| one == audience),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+/// Public keys of a JWKS document, by key id.
+struct Keys {
+    fetched_at: Instant,
+    by_id: HashMap<String, RsaPublicKey>,
+}
+
+pub struct JwtVerifier {
+    hs256_secret: Option<Vec<u8>>,
+    jwks_url: Option<String>,
+    refresh: Duration,
+    issuer: Option<String>,
+    audience: Option<String>,
+    keys: Mutex<Option<Keys>>,
+    /// Fetches the JWKS document.
+    client: reqwest::Client,
+}
+
+impl JwtVerifier {
+    /// `None` when neither a secret nor a JWKS document is configured.
+    pub fn new(config: &JwtConfig, client: reqwest::Client) -> Option<Self> {
+        if config.hs256_secret.is_none() && config.jwks_url.is_none() {
+            return None;
+        }
+        Some(Self {
+            hs256_secret: config.hs256_secret.clone().map(String::into_bytes),
+            jwks_url: config.jwks_url.clone(),
+            refresh: Duration::from_secs(config.jwks_refresh_secs),
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            keys: Mutex::new(None),
+            client,
+        })
+    }
+
+    /// The claims of `token` if its signature and claims are valid.
+    pub async fn verify(&self, token: &str) -> anyhow::Result<Claims> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature)) if parts.next().is_none() => {
+                (header, payload, signature)
+            }
+            _ => bail!("malformed token"),
+        };
+        let signed = &token[..header.len() + 1 + payload.len()];
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .context("malformed signature")?;
+        let header: Header =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).context("malformed header")?)
+                .context("malformed header")?;
+
+        match header.alg.as_str() {
+            "HS256" => {
+                let secret = self
+                    .hs256_secret
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("HS256 tokens are not accepted"))?;
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+                mac.update(signed.as_bytes());
+                mac.verify_slice(&signature)
+                    .map_err(|_| anyhow!("invalid signature"))?;
+            }
+            "RS256" => {
+                let key = self.key(header.kid.as_deref()).await?;
+                key.verify(
+                    Pkcs1v15Sign::new::<Sha256>(),
+                    &Sha256::digest(signed.as_bytes()),
+                    &signature,
+                )
+                .map_err(|_| anyhow!("invalid signature"))?;
+            }
+            alg => bail!("unsupported algorithm {}", alg),
+        }
+
+        let claims: Claims = serde_json::from_slice(
+            &URL_SAFE_NO_PAD
+                .decode(payload)
+                .context("malformed payload")?,
+        )
+        .context("malformed payload")?;
+        self.check(&claims)?;
+        Ok(claims)
+    }
+
+    fn check(&self, claims: &Claims) -> anyhow::Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match claims.exp {
+            Some(exp) if exp + LEEWAY_SECS < now => bail!("expired token"),
+            Some(_) => (),
+            None => bail!("token without expiry"),
+        }
+        if matches!(claims.nbf, Some(nbf) if nbf > now + LEEWAY_SECS) {
+            bail!("token not valid yet");
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.iss.as_ref() != Some(issuer) {
+                bail!("unexpected issuer");
+            }
+        }
+        if let Some(audience) = &self.audience {
+            if !claims.aud.contains(audience) {
+                bail!("unexpected audience");
+            }
+        }
+        Ok(())
+    }
+
+    /// The RS256 key with the given id, fetching the JWKS document when the
+    /// keys are stale or don't include it. Tokens without a key id are accepted
+    /// when the document holds a single key.
+    async fn key(&self, kid: Option<&str>) -> anyhow::Result<RsaPublicKey> {
+        let url = self
+            .jwks_url
+            .as_ref()
+            .ok_or_else(|| anyhow!("RS256 tokens are not accepted"))?;
+        let lookup = |keys: &Keys| match kid {
+            Some(kid) => keys.by_id.get(kid).cloned(),
+            None if keys.by_id.len() == 1 => keys.by_id.values().next().cloned(),
+            None => None,
+        };
+
+        let refetch = match &*self.keys.lock().unwrap() {
+            Some(keys) if keys.fetched_at.elapsed() < self.refresh => match lookup(keys) {
+                Some(key) => return Ok(key),
+                None => keys.fetched_at.elapsed() >= MIN_REFETCH_INTERVAL,
+            },
+            _ => true,
+        };
+        if refetch {
+            match fetch_keys(&self.client, url).await {
+                Ok(keys) => {
+                    info!(keys = keys.by_id.len(), "fetched JWKS");
+                    *self.keys.lock().unwrap() = Some(keys);
+                }
+                // Keep using the keys we have.
+                Err(err) => warn!(error = %err, "cannot fetch JWKS"),
+            }
+        }
+        self.keys
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(lookup)
+            .ok_or_else(|| anyhow!("unknown signing key"))
+    }
+}
+
+async fn fetch_keys(client: &reqwest::Client, url: &str) -> anyhow::Result<Keys> {
+    let set: JwkSet = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let mut by_id = HashMap::new();
+    for jwk in set.keys.into_iter().filter(|jwk| jwk.kty == "RSA") {
+        let (n, e) = match (&jwk.n, &jwk.e) {
+            (Some(n), Some(e)) => (URL_SAFE_NO_PAD.decode(n)?, URL_SAFE_NO_PAD.decode(e)?),
+            _ => continue,
+        };
+        let key = RsaPublicKey::new(BigUint::from_bytes_be(&n), BigUint::from_bytes_be(&e))?;
+        by_id.insert(jwk.kid.unwrap_or_default(), key);
+    }
+    Ok(Keys {
+        fetched_at: Instant::now(),
+        by_id,
+    })
+}
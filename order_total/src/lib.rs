@@ -0,0 +1,761 @@
+//! The order_total service: prices orders with the sales tax rate of their
+//! zip code, served over HTTP, gRPC, GraphQL, WebSocket or a NATS queue.
+//! `run` starts it, for the binary, the integration tests or other crates,
+//! which may also build its `AppState` with `init` and serve it themselves.
+
+#[macro_use]
+extern crate lazy_static;
+
+mod activity;
+mod address;
+mod admin;
+mod as_of;
+mod audit;
+mod auth;
+mod balancer;
+mod batch;
+mod body;
+mod bulkhead;
+mod cache;
+mod chaos;
+mod circuit_breaker;
+mod codec;
+mod compression;
+mod conditional;
+pub mod config;
+mod cors;
+mod dead_letter;
+mod deadline;
+mod dedup;
+mod discounts;
+mod discovery;
+mod egress;
+mod error;
+mod events;
+mod exchange;
+mod exemptions;
+mod export;
+mod flags;
+mod graphql;
+mod grpc;
+mod grpc_rates;
+mod health;
+mod hedging;
+mod idempotency;
+mod inventory;
+mod jwt;
+mod lifecycle;
+mod load_shedding;
+pub mod logging;
+mod metrics;
+mod middleware;
+#[cfg(feature = "nats")]
+mod nats;
+mod openapi;
+mod payments;
+mod postal;
+mod priority;
+#[cfg(feature = "nats")]
+mod queue;
+mod rate_limit;
+mod rate_overrides;
+mod rates;
+mod redis;
+pub mod reload;
+mod reports;
+mod request_id;
+mod retry;
+mod revisions;
+mod router;
+mod routing;
+mod saga;
+mod scheduler;
+mod search;
+mod service;
+mod shared_cache;
+mod shipping;
+mod shutdown;
+mod singleflight;
+pub mod state;
+mod store;
+mod telemetry;
+mod tenants;
+#[cfg(feature = "tls")]
+mod tls;
+mod validation;
+mod warm_up;
+mod webhooks;
+mod websocket;
+
+use activity::EventsQuery;
+use anyhow::Error;
+use codec::Format;
+use config::{AppConfig, RunMode};
+use error::{AppError, IntoResponse};
+use export::ExportFormat;
+use flags::Flag;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::server::conn::AddrStream;
+use hyper::service::make_service_fn;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use lifecycle::OrderStatus;
+use router::Router;
+use saga::SagaList;
+use search::SearchResults;
+use serde::Deserialize;
+use shutdown::Shutdown;
+use state::AppState;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str;
+use std::time::{Duration, SystemTime};
+use store::{Cursor, OrderQuery, OrderSort, SortDirection};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+lazy_static! {
+    static ref SHUTDOWN: Shutdown = Shutdown::new(Duration::from_secs(
+        AppConfig::get().server.shutdown_drain_timeout_secs
+    ));
+    static ref MAX_BODY_BYTES: usize = AppConfig::get().server.max_body_bytes;
+    static ref ROUTES: Router = routes();
+    static ref ADMIN_ROUTES: Router = admin::routes();
+}
+
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Filters, sorting and pagination of `GET /orders`.
+#[derive(Deserialize)]
+#[serde(default)]
+struct OrdersQuery {
+    zip: Option<String>,
+    /// RFC 3339 times the orders were priced from, and before.
+    from: Option<String>,
+    to: Option<String>,
+    min_total: Option<String>,
+    max_total: Option<String>,
+    include_cancelled: bool,
+    sort: OrderSort,
+    order: SortDirection,
+    cursor: Option<String>,
+    offset: usize,
+    limit: usize,
+}
+
+impl Default for OrdersQuery {
+    fn default() -> Self {
+        Self {
+            zip: None,
+            from: None,
+            to: None,
+            min_total: None,
+            max_total: None,
+            include_cancelled: false,
+            sort: OrderSort::default(),
+            order: SortDirection::default(),
+            cursor: None,
+            offset: 0,
+            limit: 20,
+        }
+    }
+}
+
+impl OrdersQuery {
+    fn into_query(self) -> Result<OrderQuery, AppError> {
+        let invalid = |name: &str, value: &str| {
+            AppError::InvalidPayload(format!("invalid {}: {:?}", name, value))
+        };
+        let amount = |name: &str, value: Option<String>| match value {
+            Some(value) => value.parse().map(Some).map_err(|_| invalid(name, &value)),
+            None => Ok(None),
+        };
+        let cursor = match self.cursor {
+            Some(token) => Some(Cursor::decode(&token).ok_or_else(|| invalid("cursor", &token))?),
+            None => None,
+        };
+        Ok(OrderQuery {
+            zip: self.zip.filter(|zip| !zip.is_empty()),
+            priced_from: rfc3339("from", self.from)?,
+            priced_before: rfc3339("to", self.to)?,
+            min_total: amount("min_total", self.min_total)?,
+            max_total: amount("max_total", self.max_total)?,
+            include_cancelled: self.include_cancelled,
+            sort: self.sort,
+            direction: self.order,
+            cursor,
+            offset: self.offset,
+            limit: self.limit.clamp(1, MAX_PAGE_SIZE),
+        })
+    }
+}
+
+/// The optional body of `DELETE /orders/{id}` and `POST /orders/{id}/cancel`.
+#[derive(Default, Deserialize, ToSchema)]
+struct Cancellation {
+    /// Why the order is cancelled, up to 500 characters.
+    #[schema(example = "ordered twice")]
+    reason: Option<String>,
+}
+
+const MAX_REASON_CHARS: usize = 500;
+
+/// The RFC 3339 time of the query parameter `name`, if given.
+fn rfc3339(name: &str, value: Option<String>) -> Result<Option<SystemTime>, AppError> {
+    match value {
+        Some(value) => humantime::parse_rfc3339_weak(&value)
+            .map(Some)
+            .map_err(|_| AppError::InvalidPayload(format!("invalid {}: {:?}", name, value))),
+        None => Ok(None),
+    }
+}
+
+/// The query of `GET /orders/export`.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ExportQuery {
+    format: ExportFormat,
+    /// RFC 3339 times the orders were priced from, and before.
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// The query of `GET /reports/daily`.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ReportQuery {
+    /// `YYYY-MM-DD`; today, in UTC, by default.
+    date: Option<String>,
+}
+
+/// The query of `GET /orders/search`.
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    #[serde(default = "default_search_limit")]
+    limit: usize,
+}
+
+fn default_search_limit() -> usize {
+    20
+}
+
+/// The order of `GET /sagas`.
+#[derive(Deserialize)]
+struct SagasQuery {
+    order_id: i32,
+}
+
+/// This is our service handler, the innermost layer of `middleware::stack`.
+/// It receives a Request, routes on its path, and returns a Future of a
+/// Response.
+async fn handle_request(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let path = req.uri().path().to_owned();
+    // v1 is the only version so far, and unversioned API paths are served as v1.
+    let (_, path) = routing::split(&path);
+    route(state, req, path).await
+}
+
+/// Routes an authorized request.
+async fn route(
+    state: AppState,
+    req: Request<Body>,
+    path: &str,
+) -> Result<Response<Body>, AppError> {
+    if admin::on_admin_listener(&req) || path.starts_with("/admin/") {
+        // The admin endpoints are only served on the admin listener, unless
+        // it is disabled, and the admin listener serves nothing else.
+        let admin_here = admin::on_admin_listener(&req) || state.config.server.admin_port == 0;
+        if admin_here && path.starts_with("/admin/") {
+            return ADMIN_ROUTES.dispatch(state, req, path).await;
+        }
+        return Ok(not_found());
+    }
+    ROUTES.dispatch(state, req, path).await
+}
+
+/// The routes of the public listener, paths without version prefix.
+fn routes() -> Router {
+    Router::new()
+        // Serve some instructions at /
+        .route(Method::GET, "/", |_, _| async {
+            Ok(Response::new(Body::from(
+                "Try POSTing data to /v1/compute such as: `curl localhost:8002/v1/compute -XPOST -d '...'`",
+            )))
+        })
+        // Liveness and readiness probes
+        .route(Method::GET, "/healthz", |_, _| async { Ok(health::healthz()) })
+        .route(Method::GET, "/readyz", |state, _| async move {
+            Ok(health::readyz(&state.readiness).await)
+        })
+        // Prometheus metrics
+        .route(Method::GET, "/metrics", |state, _| async move {
+            Ok(state.metrics.render())
+        })
+        // API description and its Swagger UI
+        .route(Method::GET, "/openapi.json", |_, _| async {
+            Ok(openapi::spec())
+        })
+        .route(Method::GET, "/docs", |_, _| async { Ok(openapi::docs()) })
+        .route(Method::POST, "/compute", compute_handler)
+        // Live totals, without storing or publishing anything
+        .route(Method::POST, "/quote", quote)
+        .route(Method::POST, "/compute_batch", compute_batch)
+        // GraphQL API, and its schema
+        .route(Method::POST, "/graphql", |state, req| async {
+            let byte_stream = body::read(req).await?;
+            graphql::handle(state, &byte_stream).await
+        })
+        .route(Method::GET, "/graphql", |_, _| async { Ok(graphql::sdl()) })
+        // Priced orders
+        .route(Method::GET, "/orders", list_orders)
+        .route(Method::GET, "/orders/search", search_orders)
+        .route(Method::GET, "/orders/export", export_orders)
+        .route(Method::GET, "/orders/{id}", get_order)
+        .route(Method::PATCH, "/orders/{id}", reprice_order)
+        .route(Method::GET, "/orders/{id}/history", order_history)
+        .route(Method::DELETE, "/orders/{id}", cancel_order)
+        .route(Method::POST, "/orders/{id}/confirm", |state, req| async move {
+            let order_id = router::param(&req, "id")?;
+            change_status(&state, order_id, OrderStatus::Confirmed, None).await
+        })
+        .route(Method::POST, "/orders/{id}/cancel", cancel_order)
+        // Reports on the priced orders
+        .route(Method::GET, "/reports/daily", daily_report)
+        // Sagas pricing orders across services
+        .route(Method::GET, "/sagas", list_sagas)
+        .route(Method::GET, "/sagas/{id}", get_saga)
+        // Pricing over a WebSocket connection
+        .route(Method::GET, "/ws", |state, req| async {
+            websocket::accept(state, req)
+        })
+        // Live stream of priced orders, as Server-Sent Events
+        .route(Method::GET, "/events", |state, req| async move {
+            let query: EventsQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or(""))
+                .map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+            state.activity.subscribe(query)
+        })
+}
+
+fn not_found() -> Response<Body> {
+    AppError::NotFound.into_response()
+}
+
+async fn compute_handler(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let formats = [Format::Json, Format::MessagePack];
+    let request_format = codec::request_format(&req, &formats)?;
+    let response_format = codec::negotiate(&req, &formats)?;
+    let key = idempotency::key(&req);
+    let day = as_of::from_query(&req)?;
+    let byte_stream = request_format.to_json(body::read(req).await?)?;
+    let response = async {
+        match key {
+            Some(key) => {
+                let response = compute(&state, &byte_stream, response_format);
+                state.idempotency.serve(&key, &byte_stream, response).await
+            }
+            None => compute(&state, &byte_stream, response_format).await,
+        }
+    };
+    as_of::scope(day, state.compute_bulkhead.run(response)).await
+}
+
+async fn quote(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    body::require_json(&req)?;
+    let day = as_of::from_query(&req)?;
+    let byte_stream = body::read(req).await?;
+    let order = as_of::scope(day, service::quote(&state, &byte_stream)).await?;
+    let body = serde_json::to_string_pretty(&order).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+/// Answers `404` to the callers the `batch_endpoint` flag is off for.
+async fn compute_batch(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    if !state.flags.is_enabled(Flag::BatchEndpoint) {
+        return Err(AppError::NotFound);
+    }
+    body::require_json(&req)?;
+    let format = codec::negotiate(&req, &[Format::Json, Format::Csv])?;
+    let day = as_of::from_query(&req)?;
+    let byte_stream = body::read(req).await?;
+    let response = state
+        .compute_bulkhead
+        .run(batch::handle_batch(&state, &byte_stream, format));
+    as_of::scope(day, response).await
+}
+
+async fn list_orders(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let params = req.uri().query().unwrap_or("");
+    let query: OrdersQuery = serde_urlencoded::from_str(params)
+        .map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+    let mut page = state.orders.list(&query.into_query()?)?;
+    page.next = page
+        .next_cursor
+        .as_deref()
+        .map(|cursor| next_page(req.uri().path(), params, cursor));
+    let body = serde_json::to_string_pretty(&page).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+/// The link to the page after `cursor`: the query of the request, with the
+/// cursor instead of its own and without offset.
+fn next_page(path: &str, params: &str, cursor: &str) -> String {
+    let mut pairs: Vec<(String, String)> =
+        serde_urlencoded::from_str::<Vec<(String, String)>>(params)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(name, _)| name != "cursor" && name != "offset")
+            .collect();
+    pairs.push(("cursor".into(), cursor.into()));
+    let query = serde_urlencoded::to_string(&pairs).expect("query strings serialize");
+    format!("{}?{}", path, query)
+}
+
+/// `GET /orders/export`: every order priced between `from` and `to`, oldest
+/// first, cancelled ones included, streamed as CSV or JSON lines.
+async fn export_orders(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let params: ExportQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or(""))
+        .map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+    let query = OrderQuery {
+        priced_from: rfc3339("from", params.from)?,
+        priced_before: rfc3339("to", params.to)?,
+        direction: SortDirection::Asc,
+        ..OrderQuery::default()
+    };
+    Ok(export::respond(state, query, params.format))
+}
+
+/// `GET /orders/search?q=...`: the orders whose shipping address or zip code
+/// match the words of `q`, best match first.
+async fn search_orders(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let query: SearchQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or(""))
+        .map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+    if search::words(&query.q).is_empty() {
+        return Err(AppError::InvalidPayload(
+            "q needs a word to search for".into(),
+        ));
+    }
+    let hits = state
+        .orders
+        .search(&query.q, query.limit.clamp(1, MAX_PAGE_SIZE))?;
+    let results = SearchResults {
+        query: query.q,
+        hits,
+    };
+    let body = serde_json::to_string_pretty(&results).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+async fn get_order(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let order_id = router::param(&req, "id")?;
+    let record = state
+        .orders
+        .get(order_id)?
+        .ok_or(AppError::OrderNotFound(order_id))?;
+    let body = serde_json::to_string_pretty(&record).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+/// `GET /reports/daily?date=...`: the orders priced on a day, in total and
+/// by state.
+async fn daily_report(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let query: ReportQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or(""))
+        .map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+    let date = query
+        .date
+        .unwrap_or_else(|| reports::format_date(SystemTime::now()));
+    let report = reports::daily(state.orders.as_ref(), reports::parse_date(&date)?)?;
+    let body = serde_json::to_string_pretty(&report).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+/// `GET /sagas?order_id=...`: the sagas of an order, most recent first. None
+/// when orders aren't priced by a saga.
+async fn list_sagas(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let query: SagasQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or(""))
+        .map_err(|err| AppError::InvalidPayload(err.to_string()))?;
+    let sagas = match &state.sagas {
+        Some(sagas) => sagas.for_order(query.order_id)?,
+        None => Vec::new(),
+    };
+    let body = serde_json::to_string_pretty(&SagaList { sagas }).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+async fn get_saga(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let saga_id: String = router::param(&req, "id")?;
+    let record = match &state.sagas {
+        Some(sagas) => sagas.get(&saga_id)?,
+        None => None,
+    }
+    .ok_or(AppError::SagaNotFound(saga_id))?;
+    let body = serde_json::to_string_pretty(&record).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+/// `GET /orders/{id}/history`: the pricings of an order, with what changed
+/// from one to the next.
+async fn order_history(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let order_id = router::param(&req, "id")?;
+    let record = state
+        .orders
+        .get(order_id)?
+        .ok_or(AppError::OrderNotFound(order_id))?;
+    let body = serde_json::to_string_pretty(&revisions::history(&record)).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+/// `PATCH /orders/{id}`: prices a stored order again for the new shipping
+/// address of the body.
+async fn reprice_order(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let order_id = router::param(&req, "id")?;
+    body::require_json(&req)?;
+    let byte_stream = body::read(req).await?;
+    let change = serde_json::from_slice(&byte_stream)?;
+    let record = service::reprice(&state, order_id, change).await?;
+    let body = serde_json::to_string_pretty(&record).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+/// Cancels an order, with the `reason` of the body if there is one. The
+/// order stays stored, so `DELETE` leaves it to be looked up, but it's no
+/// longer listed by default.
+async fn cancel_order(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let order_id = router::param(&req, "id")?;
+    let byte_stream = body::read(req).await?;
+    let cancellation: Cancellation = if byte_stream.iter().all(u8::is_ascii_whitespace) {
+        Cancellation::default()
+    } else {
+        serde_json::from_slice(&byte_stream)?
+    };
+    let reason = cancellation
+        .reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+    if reason
+        .as_ref()
+        .is_some_and(|reason| reason.chars().count() > MAX_REASON_CHARS)
+    {
+        return Err(AppError::InvalidPayload(format!(
+            "reason is longer than {} characters",
+            MAX_REASON_CHARS
+        )));
+    }
+    change_status(&state, order_id, OrderStatus::Cancelled, reason).await
+}
+
+/// Moves a priced order to `status`, if its current status allows it.
+async fn change_status(
+    state: &AppState,
+    order_id: i32,
+    status: OrderStatus,
+    reason: Option<String>,
+) -> Result<Response<Body>, AppError> {
+    let record = service::change_status(state, order_id, status, reason).await?;
+    let body = serde_json::to_string_pretty(&record).map_err(Error::from)?;
+    Ok(response_build(&body))
+}
+
+async fn compute(
+    state: &AppState,
+    byte_stream: &[u8],
+    format: Format,
+) -> Result<Response<Body>, AppError> {
+    let order = service::price(state, byte_stream).await?;
+    format.respond(&order)
+}
+
+fn response_build(body: &str) -> Response<Body> {
+    response_build_with_status(StatusCode::OK, body)
+}
+
+fn response_build_with_status(status: StatusCode, body: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(body.to_owned()))
+        .unwrap()
+}
+
+/// A response whose body is in `format`.
+fn response_build_as(status: StatusCode, format: Format, body: Vec<u8>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, format.content_type())
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// The HTTPS server, not started yet.
+#[cfg(feature = "tls")]
+async fn serve_tls(
+    state: AppState,
+    addr: SocketAddr,
+) -> anyhow::Result<BoxFuture<'static, hyper::Result<()>>> {
+    let config = tls::server_config(&state.config.tls, state.config.server.http2)?;
+    let make_svc = make_service_fn(move |conn: &tls::TlsStream| {
+        let service = middleware::stack(state.clone(), conn.remote_addr());
+        async move { Ok::<_, Infallible>(service) }
+    });
+    let incoming = tls::bind(addr, config).await?;
+    Ok(http_options(Server::builder(incoming))
+        .serve(make_svc)
+        .with_graceful_shutdown(SHUTDOWN.triggered())
+        .boxed())
+}
+
+#[cfg(not(feature = "tls"))]
+async fn serve_tls(
+    _state: AppState,
+    _addr: SocketAddr,
+) -> anyhow::Result<BoxFuture<'static, hyper::Result<()>>> {
+    anyhow::bail!("order_total was built without the `tls` feature")
+}
+
+/// The protocol options of the HTTP API: HTTP/2 is accepted next to HTTP/1.1
+/// unless `server.http2` is off, with prior knowledge or negotiated by TLS.
+fn http_options<I>(builder: hyper::server::Builder<I>) -> hyper::server::Builder<I> {
+    let config = &AppConfig::get().server;
+    let builder = builder.http1_keepalive(config.http1_keep_alive);
+    if config.http2 {
+        http2_options(builder)
+    } else {
+        builder.http1_only(true)
+    }
+}
+
+/// The HTTP/2 options of the HTTP and gRPC APIs.
+fn http2_options<I>(builder: hyper::server::Builder<I>) -> hyper::server::Builder<I> {
+    let config = &AppConfig::get().server;
+    let interval = Duration::from_secs(config.http2_keep_alive_interval_secs);
+    let max_streams = Some(config.http2_max_concurrent_streams).filter(|max| *max != 0);
+    builder
+        .http2_max_concurrent_streams(max_streams)
+        .http2_keep_alive_interval(Some(interval).filter(|interval| !interval.is_zero()))
+        .http2_keep_alive_timeout(Duration::from_secs(config.http2_keep_alive_timeout_secs))
+}
+
+/// `server.request_timeout_ms` of the live configuration.
+fn request_timeout() -> Duration {
+    Duration::from_millis(AppConfig::current().server.request_timeout_ms)
+}
+
+/// Runs the service with `config` until shutdown, see `init` and `serve`.
+pub async fn run(config: AppConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let state = init(config)?;
+    serve(state).await
+}
+
+/// Installs `config` and builds the dependencies of the service, then starts
+/// the telemetry exporter, the admin listener, the rate cache warm-up, the
+/// scheduled jobs and, on unix, the signal listener. The configuration is
+/// installed for the whole process, so the service is initialized once per
+/// process. Must be called within the runtime. Logging is left to the
+/// caller, see `logging::init`.
+pub fn init(config: AppConfig) -> anyhow::Result<AppState> {
+    check_run_mode(config.mode)?;
+    AppConfig::install(config);
+    let state = AppState::from_config(AppConfig::get())?;
+    telemetry::start_exporter(state.http_client.clone());
+    let admin_port = state.config.server.admin_port;
+    if admin_port != 0 {
+        admin::start(state.clone(), admin_port)?;
+    }
+    warm_up::start(&state)?;
+    state.scheduler.start(&state);
+    #[cfg(unix)]
+    tokio::spawn(shutdown::listen_for_signals(&SHUTDOWN));
+    Ok(state)
+}
+
+/// Runs the service with `state` until shutdown: the HTTP and gRPC APIs, or
+/// the queue consumer in the `queue` run mode.
+pub async fn serve(state: AppState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let result = match state.config.mode {
+        RunMode::Http => serve_http(state.clone()).await,
+        RunMode::Queue => consume(state.clone()).await,
+    };
+    if let Err(err) = warm_up::save_snapshot(&state) {
+        warn!(
+            error = format!("{:#}", err),
+            "rate cache snapshot not saved"
+        );
+    }
+    result
+}
+
+/// The HTTP API as a service, for a connection from `remote_addr`, to serve
+/// it from another server than `serve`'s.
+pub fn http_service(state: AppState, remote_addr: SocketAddr) -> middleware::Next<Infallible> {
+    middleware::stack(state, remote_addr)
+}
+
+/// Serves the HTTP API, and the gRPC API unless its port is 0, until shutdown.
+async fn serve_http(state: AppState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let port = state.config.server.port;
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let grpc_port = state.config.server.grpc_port;
+    let grpc_state = state.clone();
+    let server = if state.config.tls.enabled {
+        serve_tls(state, addr).await?
+    } else {
+        let make_svc = make_service_fn(move |conn: &AddrStream| {
+            let service = middleware::stack(state.clone(), conn.remote_addr());
+            async move { Ok::<_, Infallible>(service) }
+        });
+        http_options(Server::bind(&addr))
+            .serve(make_svc)
+            .with_graceful_shutdown(SHUTDOWN.triggered())
+            .boxed()
+    };
+    info!(port, "server started");
+    let grpc_server = async {
+        if grpc_port == 0 {
+            return Ok(());
+        }
+        grpc::run(grpc_state, grpc_port).await
+    };
+
+    // In-flight requests get the drain timeout to finish once shutdown starts.
+    let drain_deadline = async {
+        SHUTDOWN.triggered().await;
+        tokio::time::sleep(SHUTDOWN.drain_timeout).await;
+    };
+    tokio::select! {
+        (result, grpc_result) = futures::future::join(server, grpc_server) => {
+            if let Err(e) = result {
+                tracing::error!(error = %e, "server error");
+            }
+            if let Err(e) = grpc_result {
+                tracing::error!(error = %e, "gRPC server error");
+            }
+        }
+        _ = drain_deadline => warn!("drain timeout exceeded, dropping in-flight requests"),
+    }
+    Ok(())
+}
+
+/// Prices orders consumed from NATS until shutdown.
+#[cfg(feature = "nats")]
+async fn consume(state: AppState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = &state.config.queue;
+    queue::run(state.clone(), config).await.map_err(Into::into)
+}
+
+#[cfg(not(feature = "nats"))]
+async fn consume(_state: AppState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    Err(anyhow::anyhow!("order_total was built without the `nats` feature").into())
+}
+
+/// Fails for a run mode the build doesn't support.
+#[cfg(feature = "nats")]
+fn check_run_mode(_mode: RunMode) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(feature = "nats"))]
+fn check_run_mode(mode: RunMode) -> anyhow::Result<()> {
+    if mode == RunMode::Queue {
+        anyhow::bail!("order_total was built without the `nats` feature");
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Where an order stands. Orders move forward through
+/// received → priced → confirmed, and may be cancelled until then.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    /// The order has been accepted but not priced yet.
+    Received,
+    /// The order has been priced and may be priced again, e.g. with a new
+    /// promo code, until it is confirmed. Records stored before orders had a
+    /// status were all priced.
+    #[default]
+    Priced,
+    /// The customer accepted the price; the order can no longer change.
+    Confirmed,
+    /// The order was withdrawn. This is final.
+    Cancelled,
+}
+
+impl OrderStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::Received => "received",
+            OrderStatus::Priced => "priced",
+            OrderStatus::Confirmed => "confirmed",
+            OrderStatus::Cancelled => "cancelled",
+        }
+    }
+
+    /// Whether an order in this status may move to `next`.
+    pub fn can_become(self, next: OrderStatus) -> bool {
+        use OrderStatus::*;
+        matches!(
+            (self, next),
+            (Received, Priced)
+                | (Priced, Priced)
+                | (Priced, Confirmed)
+                | (Received, Cancelled)
+                | (Priced, Cancelled)
+                | (Confirmed, Cancelled)
+        )
+    }
+
+    /// The status after moving to `next`, or the rejected transition.
+    pub fn transition(self, next: OrderStatus) -> Result<OrderStatus, Transition> {
+        if self.can_become(next) {
+            Ok(next)
+        } else {
+            Err(Transition {
+                from: self,
+                to: next,
+            })
+        }
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A move between two statuses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: OrderStatus,
+    pub to: OrderStatus,
+}
+
+impl Transition {
+    /// Why the move isn't allowed, for error messages.
+    pub fn reason(&self) -> &'static str {
+        match (self.from, self.to) {
+            (OrderStatus::Cancelled, _) => "cancelled orders are final",
+            (OrderStatus::Confirmed, _) => "confirmed orders can only be cancelled",
+            (OrderStatus::Received, OrderStatus::Confirmed) => {
+                "orders must be priced before they are confirmed"
+            }
+            _ => "this transition is not allowed",
+        }
+    }
+}
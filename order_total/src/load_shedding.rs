@@ -0,0 +1,113 @@
+//! Adaptive load shedding: an AIMD limit on the API requests in flight. After
+//! every `load_shedding.window` requests the p99 latency of those requests is
+//! compared to `load_shedding.latency_target_ms`: over it, the service is
+//! saturated and the limit is cut by `backoff_ratio`; under it, the limit
+//! grows by one. Requests beyond the limit are shed with `503 OVERLOADED`
+//! rather than queued, so a saturated service answers the others in time.
+
+use prometheus::{IntCounter, IntGauge};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::LoadSheddingConfig;
+use crate::error::AppError;
+use crate::metrics::Metrics;
+
+pub struct LoadShedder {
+    latency_target: Duration,
+    min_limit: f64,
+    max_limit: f64,
+    backoff_ratio: f64,
+    window: usize,
+    state: Mutex<State>,
+    limit_gauge: IntGauge,
+    shed: IntCounter,
+}
+
+struct State {
+    limit: f64,
+    in_flight: usize,
+    /// Latencies of the requests finished in the current window.
+    latencies: Vec<Duration>,
+}
+
+/// A request let in; leaving makes room for another one.
+struct Admission<'a>(&'a LoadShedder);
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+    }
+}
+
+impl LoadShedder {
+    /// The load shedder of `config`, reporting its limit in `metrics`, or
+    /// none when load shedding is off.
+    pub fn from_config(config: &LoadSheddingConfig, metrics: &Metrics) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let min_limit = config.min_limit.max(1) as f64;
+        let max_limit = (config.max_limit as f64).max(min_limit);
+        let limit = (config.initial_limit as f64).clamp(min_limit, max_limit);
+        metrics.concurrency_limit.set(limit as i64);
+        Some(Self {
+            latency_target: Duration::from_millis(config.latency_target_ms),
+            min_limit,
+            max_limit,
+            backoff_ratio: config.backoff_ratio.clamp(0.1, 1.0),
+            window: config.window.max(1),
+            state: Mutex::new(State {
+                limit,
+                in_flight: 0,
+                latencies: Vec::with_capacity(config.window),
+            }),
+            limit_gauge: metrics.concurrency_limit.clone(),
+            shed: metrics.shed_requests.clone(),
+        })
+    }
+
+    /// Runs `request` unless the limit is reached, and learns from its
+    /// latency.
+    pub async fn run<T>(
+        &self,
+        request: impl Future<Output = Result<T, AppError>>,
+    ) -> Result<T, AppError> {
+        let _admission = self.admit()?;
+        let start = Instant::now();
+        let result = request.await;
+        self.record(start.elapsed());
+        result
+    }
+
+    fn admit(&self) -> Result<Admission<'_>, AppError> {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight as f64 >= state.limit.floor() {
+            self.shed.inc();
+            return Err(AppError::Overloaded("load_shedding".into()));
+        }
+        state.in_flight += 1;
+        Ok(Admission(self))
+    }
+
+    fn record(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.latencies.push(latency);
+        if state.latencies.len() < self.window {
+            return;
+        }
+        state.latencies.sort();
+        // The nearest rank: the smallest latency of at least 99% of the
+        // requests.
+        let rank = (0.99 * state.latencies.len() as f64).ceil() as usize;
+        let p99 = state.latencies[rank.clamp(1, state.latencies.len()) - 1];
+        state.limit = if p99 > self.latency_target {
+            (state.limit * self.backoff_ratio).max(self.min_limit)
+        } else {
+            (state.limit + 1.0).min(self.max_limit)
+        };
+        state.latencies.clear();
+        self.limit_gauge.set(state.limit as i64);
+    }
+}
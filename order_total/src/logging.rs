@@ -0,0 +1,44 @@
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// Swaps the filter of the installed subscriber.
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+/// Logs JSON lines filtered by the configured log level. The filter can be
+/// changed later with `set_level`.
+pub fn init(level: &str) {
+    let filter = EnvFilter::try_new(level).unwrap_or_else(|_| EnvFilter::new("info"));
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(false),
+        )
+        .init();
+    let _ = FILTER.set(handle);
+}
+
+/// The active filter, in `RUST_LOG` syntax.
+pub fn level() -> String {
+    FILTER
+        .get()
+        .and_then(|handle| handle.with_current(|filter| filter.to_string()).ok())
+        .unwrap_or_default()
+}
+
+/// Replaces the filter, e.g. with `info,order_total=debug`, without a restart.
+pub fn set_level(level: &str) -> anyhow::Result<()> {
+    let filter =
+        EnvFilter::try_new(level).with_context(|| format!("invalid log filter {:?}", level))?;
+    FILTER
+        .get()
+        .context("logging isn't initialized")?
+        .reload(filter)?;
+    Ok(())
+}
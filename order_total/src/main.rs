@@ -1,150 +1,30 @@
-#[macro_use]
-extern crate lazy_static;
+use clap::Parser;
+use order_total::config::{AppConfig, Cli};
+use order_total::{logging, reload};
 
-use anyhow::Error;
-use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Method, Request, Response, Server, StatusCode};
-use serde::{Deserialize, Serialize};
-use std::convert::Infallible;
-use std::net::SocketAddr;
-use std::str;
-
-lazy_static! {
-    static ref SALES_TAX_RATE_SERVICE: String = {
-        if let Ok(url) = std::env::var("SALES_TAX_RATE_SERVICE") {
-            url
-        } else {
-            "http://localhost:8001/find_rate".into()
+// WASI has no threads, so tokio_wasi only offers the current-thread runtime.
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cli = Cli::parse();
+    let config = match AppConfig::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("invalid configuration: {}", err);
+            std::process::exit(2);
         }
     };
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct Order {
-    order_id: i32,
-    product_id: i32,
-    quantity: i32,
-    subtotal: f32,
-    shipping_address: String,
-    shipping_zip: String,
-    total: f32,
-}
-
-/*
-impl Order {
-    fn new(
-        order_id: i32,
-        product_id: i32,
-        quantity: i32,
-        subtotal: f32,
-        shipping_address: String,
-        shipping_zip: String,
-        total: f32,
-    ) -> Self {
-        Self {
-            order_id,
-            product_id,
-            quantity,
-            subtotal,
-            shipping_address,
-            shipping_zip,
-            total,
-        }
-    }
-}
-*/
-
-/// This is our service handler. It receives a Request, routes on its
-/// path, and returns a Future of a Response.
-async fn handle_request(req: Request<Body>) -> Result<Response<Body>, anyhow::Error> {
-    match (req.method(), req.uri().path()) {
-        // CORS OPTIONS
-        (&Method::OPTIONS, "/compute") => Ok(response_build(&String::from(""))),
-
-        // Serve some instructions at /
-        (&Method::GET, "/") => Ok(Response::new(Body::from(
-            "Try POSTing data to /compute such as: `curl localhost:8002/compute -XPOST -d '...'`",
-        ))),
-
-        (&Method::POST, "/compute") => {
-            let byte_stream = hyper::body::to_bytes(req).await?;
-            let maybe_order = serde_json::from_slice(&byte_stream);
-            match maybe_order {
-                Ok(mut order) => handle_order(&mut order).await?,
-                Err(err) => {
-                    // only way to convert missing field error to other message is to check the string?
-                    let mut err_message = err.to_string();
-                    if err_message.contains("missing field") {
-                        err_message = err_message
-                            .to_lowercase()
-                            .replace("`", "")
-                            .replace("_", " ");
-                        match err_message.find("at") {
-                            Some(i) => err_message.truncate(i-1),
-                            _ => (), // do nothing
-                        }
-                    }
-                    let json_message =
-                        format!("{{\"status\":\"error\", \"message\":\"{}\"}}", err_message);
-                    Ok(response_build(json_message.as_str()))
-                }
-            }
-        }
-
-        // Return the 404 Not Found for other routes.
-        _ => {
-            let mut not_found = Response::default();
-            *not_found.status_mut() = StatusCode::NOT_FOUND;
-            Ok(not_found)
+    logging::init(&config.log_level);
+    // The configuration is valid by now, so anything else going wrong is
+    // logged like the rest of the service's failures.
+    let state = match order_total::init(config) {
+        Ok(state) => state,
+        Err(err) => {
+            tracing::error!(error = format!("{:#}", err), "startup failed");
+            std::process::exit(1);
         }
-    }
-}
-
-async fn handle_order(order: &mut Order) -> Result<Result<Response<Body>, Error>, Error> {
-    let client = reqwest::Client::new();
-    let result = client
-        .post(&*SALES_TAX_RATE_SERVICE)
-        .body(order.shipping_zip.clone())
-        .send()
-        .await;
-    let mapped_result = result.as_ref().map(|response| response.status().as_u16());
-    Ok(match mapped_result {
-        Ok(200) => {
-            let rate = result.unwrap().text().await?.parse::<f32>()?;
-
-            order.total = order.subtotal * (1.0 + rate);
-            Ok(response_build(&serde_json::to_string_pretty(&order)?))
-        }
-        _ => {
-            let err_message = format!("{{\"status\":\"error\", \"message\":\"The zip code ({}) in the order does not have a corresponding sales tax rate.\"}}", order.shipping_zip.clone());
-            Ok(response_build(err_message.as_str()))
-        }
-    })
-}
-
-// CORS headers
-fn response_build(body: &str) -> Response<Body> {
-    Response::builder()
-        .header("Access-Control-Allow-Origin", "*")
-        .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
-        .header(
-            "Access-Control-Allow-Headers",
-            "api,Keep-Alive,User-Agent,Content-Type",
-        )
-        .body(Body::from(body.to_owned()))
-        .unwrap()
-}
-
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8002));
-    let make_svc = make_service_fn(|_| async move {
-        Ok::<_, Infallible>(service_fn(move |req| handle_request(req)))
-    });
-    let server = Server::bind(&addr).serve(make_svc);
-    dbg!("Server started on port 8002");
-    if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
-    }
-    Ok(())
+    };
+    #[cfg(unix)]
+    tokio::spawn(reload::reload_on_sighup(cli.clone(), state.clone()));
+    tokio::spawn(reload::watch(cli, state.clone()));
+    order_total::serve(state).await
 }
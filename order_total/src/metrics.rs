@@ -0,0 +1,427 @@
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Response};
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::{ADMIN_ROUTES, ROUTES};
+
+/// Every metric the service exports, registered in one registry that is
+/// shared by the request handler, the upstream client and the rate cache.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub grpc_requests: IntCounterVec,
+    pub upstream_requests: IntCounterVec,
+    pub upstream_request_duration: HistogramVec,
+    pub upstream_instances: IntGauge,
+    pub upstream_ejections: IntCounter,
+    pub hedged_requests: IntCounterVec,
+    pub cache_hits: IntCounter,
+    pub cache_misses: IntCounter,
+    pub cache_stale_hits: IntCounter,
+    pub coalesced_lookups: IntCounter,
+    pub shared_cache_lookups: IntCounterVec,
+    pub deduplicated_orders: IntCounter,
+    pub rate_fallbacks: IntCounter,
+    pub rate_overrides: IntCounter,
+    pub bulkhead_rejections: IntCounterVec,
+    pub bulkhead_waiting: IntGaugeVec,
+    pub priority_requests: IntCounterVec,
+    pub concurrency_limit: IntGauge,
+    pub shed_requests: IntCounter,
+    pub inventory_releases: IntCounterVec,
+    pub sagas: IntCounterVec,
+    pub webhook_deliveries: IntCounterVec,
+    pub websocket_messages: IntCounterVec,
+    pub config_reloads: IntCounterVec,
+    pub flag_refreshes: IntCounterVec,
+    pub job_runs: IntCounterVec,
+    http_request_rate: Gauge,
+    cache_hit_ratio: Gauge,
+    last_rollup: Mutex<Rollup>,
+}
+
+/// The counters behind the rolled-up metrics, as of the last rollup.
+struct Rollup {
+    at: Instant,
+    http_requests: f64,
+    cache_hits: u64,
+    cache_lookups: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "HTTP requests by route, method, status and tenant",
+            ),
+            &["route", "method", "status", "tenant"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent serving HTTP requests by route and tenant",
+            ),
+            &["route", "tenant"],
+        )
+        .unwrap();
+        let grpc_requests = IntCounterVec::new(
+            Opts::new(
+                "grpc_requests_total",
+                "gRPC calls by method and status code",
+            ),
+            &["method", "code"],
+        )
+        .unwrap();
+        let upstream_requests = IntCounterVec::new(
+            Opts::new(
+                "upstream_requests_total",
+                "Calls to the sales tax rate service by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let upstream_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "Latency of calls to the sales tax rate service by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let hedged_requests = IntCounterVec::new(
+            Opts::new(
+                "upstream_hedged_requests_total",
+                "Calls to the sales tax rate service hedged with a second call, by the call answering first",
+            ),
+            &["winner"],
+        )
+        .unwrap();
+        let cache_hits = IntCounter::new(
+            "rate_cache_hits_total",
+            "Rate lookups served from the cache",
+        )
+        .unwrap();
+        let cache_misses = IntCounter::new(
+            "rate_cache_misses_total",
+            "Rate lookups that had to go to the sales tax rate service",
+        )
+        .unwrap();
+        let cache_stale_hits = IntCounter::new(
+            "rate_cache_stale_hits_total",
+            "Rate lookups served from a stale cache entry while it was refreshed",
+        )
+        .unwrap();
+        let coalesced_lookups = IntCounter::new(
+            "rate_lookups_coalesced_total",
+            "Rate lookups that waited for an identical lookup already in flight",
+        )
+        .unwrap();
+        let shared_cache_lookups = IntCounterVec::new(
+            Opts::new(
+                "shared_cache_lookups_total",
+                "Lookups in the cache shared by the replicas by kind and outcome",
+            ),
+            &["kind", "outcome"],
+        )
+        .unwrap();
+        let deduplicated_orders = IntCounter::new(
+            "orders_deduplicated_total",
+            "Orders answered with the result of an identical order within the deduplication window",
+        )
+        .unwrap();
+        let rate_fallbacks = IntCounter::new(
+            "rate_fallbacks_total",
+            "Rates taken from the fallback table while the sales tax rate service was unavailable",
+        )
+        .unwrap();
+        let rate_overrides = IntCounter::new(
+            "rate_overrides_total",
+            "Rates taken from a rate override rather than the rate provider",
+        )
+        .unwrap();
+        let bulkhead_rejections = IntCounterVec::new(
+            Opts::new(
+                "bulkhead_rejections_total",
+                "Calls turned away by a full bulkhead, by bulkhead and priority class",
+            ),
+            &["bulkhead", "class"],
+        )
+        .unwrap();
+        let bulkhead_waiting = IntGaugeVec::new(
+            Opts::new(
+                "bulkhead_waiting",
+                "Calls waiting for a slot of a bulkhead, by bulkhead and priority class",
+            ),
+            &["bulkhead", "class"],
+        )
+        .unwrap();
+        let priority_requests = IntCounterVec::new(
+            Opts::new(
+                "priority_requests_total",
+                "HTTP and gRPC requests by priority class",
+            ),
+            &["class"],
+        )
+        .unwrap();
+        let upstream_instances = IntGauge::new(
+            "upstream_instances",
+            "Endpoints of the sales tax rate service in rotation",
+        )
+        .unwrap();
+        let upstream_ejections = IntCounter::new(
+            "upstream_ejections_total",
+            "Endpoints of the sales tax rate service ejected after failing calls in a row",
+        )
+        .unwrap();
+        let concurrency_limit = IntGauge::new(
+            "concurrency_limit",
+            "API requests let in at once by adaptive load shedding",
+        )
+        .unwrap();
+        let shed_requests = IntCounter::new(
+            "shed_requests_total",
+            "API requests shed by adaptive load shedding",
+        )
+        .unwrap();
+        let inventory_releases = IntCounterVec::new(
+            Opts::new(
+                "inventory_releases_total",
+                "Stock reservations released after their order failed to be priced, by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let sagas = IntCounterVec::new(
+            Opts::new("sagas_total", "Order sagas finished, by final status"),
+            &["status"],
+        )
+        .unwrap();
+        let webhook_deliveries = IntCounterVec::new(
+            Opts::new(
+                "webhook_deliveries_total",
+                "Webhook notifications by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let websocket_messages = IntCounterVec::new(
+            Opts::new(
+                "websocket_messages_total",
+                "Orders received over WebSocket by result code",
+            ),
+            &["code"],
+        )
+        .unwrap();
+        let config_reloads = IntCounterVec::new(
+            Opts::new(
+                "config_reloads_total",
+                "Reloads of the configuration by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let flag_refreshes = IntCounterVec::new(
+            Opts::new(
+                "flag_refreshes_total",
+                "Refreshes of the feature flag rules by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let job_runs = IntCounterVec::new(
+            Opts::new(
+                "job_runs_total",
+                "Runs of the scheduled jobs by job and outcome",
+            ),
+            &["job", "outcome"],
+        )
+        .unwrap();
+        let http_request_rate = Gauge::new(
+            "http_requests_per_second",
+            "HTTP requests per second between the last two metrics rollups",
+        )
+        .unwrap();
+        let cache_hit_ratio = Gauge::new(
+            "rate_cache_hit_ratio",
+            "Share of the rate lookups served from the cache between the last two metrics rollups",
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(grpc_requests.clone())).unwrap();
+        registry
+            .register(Box::new(upstream_requests.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_instances.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_ejections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(hedged_requests.clone()))
+            .unwrap();
+        registry.register(Box::new(cache_hits.clone())).unwrap();
+        registry.register(Box::new(cache_misses.clone())).unwrap();
+        registry
+            .register(Box::new(cache_stale_hits.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(coalesced_lookups.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(shared_cache_lookups.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(deduplicated_orders.clone()))
+            .unwrap();
+        registry.register(Box::new(rate_fallbacks.clone())).unwrap();
+        registry.register(Box::new(rate_overrides.clone())).unwrap();
+        registry
+            .register(Box::new(bulkhead_rejections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(bulkhead_waiting.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(priority_requests.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(concurrency_limit.clone()))
+            .unwrap();
+        registry.register(Box::new(shed_requests.clone())).unwrap();
+        registry
+            .register(Box::new(inventory_releases.clone()))
+            .unwrap();
+        registry.register(Box::new(sagas.clone())).unwrap();
+        registry
+            .register(Box::new(webhook_deliveries.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(websocket_messages.clone()))
+            .unwrap();
+        registry.register(Box::new(config_reloads.clone())).unwrap();
+        registry.register(Box::new(flag_refreshes.clone())).unwrap();
+        registry.register(Box::new(job_runs.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_rate.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(cache_hit_ratio.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            grpc_requests,
+            upstream_requests,
+            upstream_request_duration,
+            upstream_instances,
+            upstream_ejections,
+            hedged_requests,
+            cache_hits,
+            cache_misses,
+            cache_stale_hits,
+            coalesced_lookups,
+            shared_cache_lookups,
+            deduplicated_orders,
+            rate_fallbacks,
+            rate_overrides,
+            bulkhead_rejections,
+            bulkhead_waiting,
+            priority_requests,
+            concurrency_limit,
+            shed_requests,
+            inventory_releases,
+            sagas,
+            webhook_deliveries,
+            websocket_messages,
+            config_reloads,
+            flag_refreshes,
+            job_runs,
+            http_request_rate,
+            cache_hit_ratio,
+            last_rollup: Mutex::new(Rollup {
+                at: Instant::now(),
+                http_requests: 0.0,
+                cache_hits: 0,
+                cache_lookups: 0,
+            }),
+        }
+    }
+
+    /// Updates the metrics computed from others over the time since the last
+    /// rollup: `http_requests_per_second` and `rate_cache_hit_ratio`, which
+    /// is left as it was when no rate was looked up.
+    pub fn roll_up(&self) {
+        let cache_hits = self.cache_hits.get() + self.cache_stale_hits.get();
+        let now = Rollup {
+            at: Instant::now(),
+            http_requests: total(&self.http_requests),
+            cache_hits,
+            cache_lookups: cache_hits + self.cache_misses.get(),
+        };
+        let mut last = self.last_rollup.lock().unwrap();
+        let secs = now.at.duration_since(last.at).as_secs_f64();
+        if secs > 0.0 {
+            self.http_request_rate
+                .set((now.http_requests - last.http_requests) / secs);
+        }
+        let lookups = now.cache_lookups - last.cache_lookups;
+        if lookups > 0 {
+            let hits = now.cache_hits - last.cache_hits;
+            self.cache_hit_ratio.set(hits as f64 / lookups as f64);
+        }
+        *last = now;
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> Response<Body> {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        encoder
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        Response::builder()
+            .header(CONTENT_TYPE, encoder.format_type())
+            .body(Body::from(buffer))
+            .unwrap()
+    }
+}
+
+/// The sum of `counter` over all its labels.
+fn total(counter: &IntCounterVec) -> f64 {
+    counter
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .map(|metric| metric.get_counter().get_value())
+        .sum()
+}
+
+/// The route label for a request path, without version prefix: the pattern
+/// of its route, such as `/orders/{id}`. Unknown paths share one label so
+/// scanners can't blow up the number of time series.
+pub fn route_label(path: &str) -> &'static str {
+    ROUTES
+        .pattern(path)
+        .or_else(|| ADMIN_ROUTES.pattern(path))
+        .unwrap_or("unmatched")
+}
@@ -0,0 +1,410 @@
+//! The HTTP API as a stack of tower layers around the router,
+//! `handle_request`. Each concern that applies to every request (tenants,
+//! metrics, request ids, tracing, CORS, compression, conditional requests,
+//! errors, load shedding, timeouts, fault injection, rate limiting,
+//! authentication) is a layer of its own, written as an async fn taking the
+//! request and the rest of the stack, `next`, and the `AppState` when it
+//! needs one of its dependencies.
+//!
+//! Layers above `handle_errors` see every request answered, errors included;
+//! those below it fail with `AppError`, which `handle_errors` turns into the
+//! error response.
+
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{IF_NONE_MATCH, ORIGIN};
+use hyper::{Body, Request, Response};
+use std::convert::Infallible;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tower::util::BoxCloneService;
+use tower::{Layer, Service, ServiceBuilder, ServiceExt};
+use tracing::{info, warn, Instrument};
+
+use crate::chaos::Fault;
+use crate::config::AppConfig;
+use crate::error::{AppError, IntoResponse};
+use crate::priority::{self, Priority};
+use crate::routing::{self, Access};
+use crate::state::AppState;
+use crate::telemetry::{self, Span, SpanContext, SpanKind};
+use crate::{
+    auth, compression, conditional, deadline, handle_request, metrics, request_id, request_timeout,
+    tenants, SHUTDOWN,
+};
+
+/// The rest of the stack, below a layer.
+pub type Next<E> = BoxCloneService<Request<Body>, Response<Body>, E>;
+
+/// The HTTP API, for a connection from `remote_addr`, which identifies the
+/// client for rate limiting. Outermost layer first.
+pub fn stack(state: AppState, remote_addr: SocketAddr) -> Next<Infallible> {
+    let service = ServiceBuilder::new()
+        .map_request(move |mut req: Request<Body>| {
+            req.extensions_mut().insert(remote_addr);
+            req
+        })
+        .layer(from_fn_with_state(state.clone(), label_tenant))
+        .layer(from_fn_with_state(state.clone(), record_metrics))
+        .layer(from_fn(tag_request))
+        .layer(from_fn(trace))
+        .layer(from_fn(deprecate))
+        .layer(from_fn_with_state(state.clone(), cors))
+        .layer(from_fn(compress))
+        .layer(from_fn(revalidate))
+        .layer(from_fn(handle_errors))
+        .layer(from_fn(reject_when_draining))
+        .layer(from_fn_with_state(state.clone(), shed_load))
+        .layer(from_fn(time_out))
+        .layer(from_fn_with_state(state.clone(), inject_faults))
+        .layer(from_fn_with_state(state.clone(), rate_limit))
+        .layer(from_fn_with_state(state.clone(), authenticate))
+        .layer(from_fn_with_state(state.clone(), prioritize))
+        .service_fn(move |req| handle_request(state.clone(), req));
+    BoxCloneService::new(service)
+}
+
+/// A layer running `f` with each request and the layers below it.
+pub fn from_fn<F, Fut, E, E2>(f: F) -> FromFn<F, E>
+where
+    F: Fn(Request<Body>, Next<E>) -> Fut + Clone,
+    Fut: Future<Output = Result<Response<Body>, E2>> + Send + 'static,
+{
+    FromFn {
+        f,
+        error: PhantomData,
+    }
+}
+
+/// A layer running `f` with `state`, each request and the layers below it.
+pub fn from_fn_with_state<F, Fut, E, E2>(
+    state: AppState,
+    f: F,
+) -> FromFn<impl Fn(Request<Body>, Next<E>) -> Fut + Clone, E>
+where
+    F: Fn(AppState, Request<Body>, Next<E>) -> Fut + Clone,
+    Fut: Future<Output = Result<Response<Body>, E2>> + Send + 'static,
+{
+    from_fn(move |req, next| f(state.clone(), req, next))
+}
+
+pub struct FromFn<F, E> {
+    f: F,
+    error: PhantomData<fn() -> E>,
+}
+
+impl<S, F, E> Layer<S> for FromFn<F, E>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = E> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    F: Clone,
+{
+    type Service = Middleware<F, E>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            f: self.f.clone(),
+            next: BoxCloneService::new(inner),
+        }
+    }
+}
+
+/// The service of a `FromFn` layer.
+pub struct Middleware<F, E> {
+    f: F,
+    next: Next<E>,
+}
+
+impl<F: Clone, E> Clone for Middleware<F, E> {
+    fn clone(&self) -> Self {
+        Self {
+            f: self.f.clone(),
+            next: self.next.clone(),
+        }
+    }
+}
+
+impl<F, Fut, E, E2> Service<Request<Body>> for Middleware<F, E>
+where
+    F: Fn(Request<Body>, Next<E>) -> Fut,
+    Fut: Future<Output = Result<Response<Body>, E2>> + Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = E2;
+    type Future = BoxFuture<'static, Result<Response<Body>, E2>>;
+
+    // `next` is only made ready when called, by `oneshot`.
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), E2>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        (self.f)(req, self.next.clone()).boxed()
+    }
+}
+
+/// Serves the request as its tenant, for the metrics and logs of the layers
+/// below. A request naming a tenant it can't act for is served as no tenant's,
+/// and refused by `authenticate`.
+async fn label_tenant(
+    state: AppState,
+    req: Request<Body>,
+    next: Next<Infallible>,
+) -> Result<Response<Body>, Infallible> {
+    let tenant = state.tenants.identify(req.headers()).ok().flatten();
+    tenants::scope(tenant.map(|tenant| tenant.id.clone()), next.oneshot(req)).await
+}
+
+/// Counts requests and their latency by route, method, status and tenant.
+async fn record_metrics(
+    state: AppState,
+    req: Request<Body>,
+    next: Next<Infallible>,
+) -> Result<Response<Body>, Infallible> {
+    let start = Instant::now();
+    let route = metrics::route_label(routing::split(req.uri().path()).1);
+    let method = req.method().to_string();
+    let tenant = tenants::current_tenant().unwrap_or_default();
+    let response = next.oneshot(req).await?;
+    state
+        .metrics
+        .http_requests
+        .with_label_values(&[route, &method, response.status().as_str(), &tenant])
+        .inc();
+    state
+        .metrics
+        .http_request_duration
+        .with_label_values(&[route, &tenant])
+        .observe(start.elapsed().as_secs_f64());
+    Ok(response)
+}
+
+/// Tags the request with an id, taken from the client or generated, that
+/// every log line and upstream call carry, and that the response returns.
+async fn tag_request(
+    req: Request<Body>,
+    next: Next<Infallible>,
+) -> Result<Response<Body>, Infallible> {
+    let request_id = request_id::from_request(&req);
+    let mut response = request_id::scope(request_id.clone(), next.oneshot(req)).await?;
+    if let Ok(value) = request_id.parse() {
+        response
+            .headers_mut()
+            .insert(request_id::REQUEST_ID_HEADER, value);
+    }
+    Ok(response)
+}
+
+/// Serves the request in a server span, continuing the client's trace, and
+/// a tracing span its log lines are tagged with.
+async fn trace(req: Request<Body>, next: Next<Infallible>) -> Result<Response<Body>, Infallible> {
+    let start = Instant::now();
+    let route = metrics::route_label(routing::split(req.uri().path()).1);
+    let method = req.method().to_string();
+    let request_id = request_id::current().unwrap_or_default();
+    let parent = req
+        .headers()
+        .get(telemetry::TRACEPARENT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(SpanContext::from_traceparent);
+    let mut server_span = Span::start(
+        format!("{} {}", method, route),
+        SpanKind::Server,
+        parent.as_ref(),
+    );
+    server_span.set_attribute("http.method", method.as_str());
+    server_span.set_attribute("http.route", route);
+    server_span.set_attribute("request_id", request_id.as_str());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        tenant = %tenants::current_tenant().unwrap_or_default(),
+        trace_id = %server_span.context().trace_id,
+        %method,
+        path = %req.uri().path()
+    );
+
+    let request = async {
+        let response = next.oneshot(req).await?;
+        info!(
+            status = response.status().as_u16(),
+            latency_ms = start.elapsed().as_millis() as u64,
+            "request completed"
+        );
+        Ok::<_, Infallible>(response)
+    }
+    .instrument(span);
+    let response = telemetry::in_span(server_span.context().clone(), request).await?;
+
+    server_span.set_attribute("http.status_code", response.status().as_u16());
+    if response.status().is_server_error() {
+        server_span.set_error();
+    }
+    server_span.end();
+    Ok(response)
+}
+
+/// Points the responses of unversioned paths to their versioned successor.
+async fn deprecate(
+    req: Request<Body>,
+    next: Next<Infallible>,
+) -> Result<Response<Body>, Infallible> {
+    let successor = routing::successor(req.uri().path());
+    let mut response = next.oneshot(req).await?;
+    if let Some(successor) = successor {
+        routing::deprecate(&mut response, &successor);
+    }
+    Ok(response)
+}
+
+/// Answers CORS preflight requests, and allows the request's origin to read
+/// the response.
+async fn cors(
+    state: AppState,
+    req: Request<Body>,
+    next: Next<Infallible>,
+) -> Result<Response<Body>, Infallible> {
+    let origin = req.headers().get(ORIGIN).cloned();
+    let mut response = match state.cors.preflight(&req) {
+        Some(response) => response,
+        None => next.oneshot(req).await?,
+    };
+    state.cors.apply(origin.as_ref(), &mut response);
+    Ok(response)
+}
+
+/// Compresses the response in an encoding the client accepts.
+async fn compress(
+    req: Request<Body>,
+    next: Next<Infallible>,
+) -> Result<Response<Body>, Infallible> {
+    let encoding = compression::accepted(req.headers());
+    let response = next.oneshot(req).await?;
+    Ok(compression::compress(response, encoding).await)
+}
+
+/// Tags successful `GET` responses with an ETag and their route's
+/// `Cache-Control`, and answers `304 Not Modified` to requests that have the
+/// ETag already. Below `compress`, so it tags the response as produced.
+async fn revalidate(
+    req: Request<Body>,
+    next: Next<Infallible>,
+) -> Result<Response<Body>, Infallible> {
+    let route = metrics::route_label(routing::split(req.uri().path()).1);
+    let method = req.method().clone();
+    let if_none_match = req.headers().get(IF_NONE_MATCH).cloned();
+    let response = next.oneshot(req).await?;
+    let config = &AppConfig::get().http_cache;
+    Ok(conditional::apply(config, &method, route, if_none_match, response).await)
+}
+
+/// Turns errors into their JSON error response.
+async fn handle_errors(
+    req: Request<Body>,
+    next: Next<AppError>,
+) -> Result<Response<Body>, Infallible> {
+    Ok(next.oneshot(req).await.unwrap_or_else(|err| {
+        warn!(code = err.code(), error = %err, "request failed");
+        err.into_response()
+    }))
+}
+
+/// Turns requests away once shutdown has started.
+async fn reject_when_draining(
+    req: Request<Body>,
+    next: Next<AppError>,
+) -> Result<Response<Body>, AppError> {
+    if SHUTDOWN.is_draining() {
+        return Err(AppError::ShuttingDown);
+    }
+    next.oneshot(req).await
+}
+
+/// Fails requests taking longer than `server.request_timeout_ms`, or than
+/// the budget their caller gives them, the deadline of the calls made to
+/// serve them.
+async fn time_out(req: Request<Body>, next: Next<AppError>) -> Result<Response<Body>, AppError> {
+    let budget = deadline::budget(req.headers());
+    deadline::within_budget(request_timeout(), budget, next.oneshot(req)).await
+}
+
+/// Sheds API requests beyond the adaptive concurrency limit, when
+/// `load_shedding.enabled`. Probes, metrics and the admin endpoints always
+/// get through. Above `time_out`, so timed out requests count with their
+/// latency.
+async fn shed_load(
+    state: AppState,
+    req: Request<Body>,
+    next: Next<AppError>,
+) -> Result<Response<Body>, AppError> {
+    match &state.load_shedder {
+        Some(shedder) if access(&req) == Access::Client => shedder.run(next.oneshot(req)).await,
+        _ => next.oneshot(req).await,
+    }
+}
+
+/// Delays or fails API requests at random, when `chaos.enabled`. Probes,
+/// metrics and the admin endpoints are left alone.
+async fn inject_faults(
+    state: AppState,
+    req: Request<Body>,
+    next: Next<AppError>,
+) -> Result<Response<Body>, AppError> {
+    if access(&req) == Access::Client {
+        match state.faults.as_ref().and_then(|faults| faults.inbound()) {
+            Some(Fault::Fail) => return Err(AppError::InjectedFault),
+            Some(Fault::Delay(delay)) => tokio::time::sleep(delay).await,
+            None => {}
+        }
+    }
+    next.oneshot(req).await
+}
+
+/// Takes a token of the client's bucket for every request but public ones.
+async fn rate_limit(
+    state: AppState,
+    req: Request<Body>,
+    next: Next<AppError>,
+) -> Result<Response<Body>, AppError> {
+    if access(&req) != Access::Public {
+        state.rate_limiter.check(&req)?;
+    }
+    next.oneshot(req).await
+}
+
+/// Checks the credentials the route needs, and the tenant of API requests,
+/// taking a token of its rate limit; then serves the request as the caller
+/// the credentials identify.
+async fn authenticate(
+    state: AppState,
+    req: Request<Body>,
+    next: Next<AppError>,
+) -> Result<Response<Body>, AppError> {
+    let access = access(&req);
+    let caller = state.auth.authorize(req.headers(), access).await?;
+    if access == Access::Client {
+        state.tenants.admit(req.headers())?;
+    }
+    auth::scope(caller, next.oneshot(req)).await
+}
+
+/// Serves the request in its priority class, for the bulkheads below.
+async fn prioritize(
+    state: AppState,
+    req: Request<Body>,
+    next: Next<AppError>,
+) -> Result<Response<Body>, AppError> {
+    let class = Priority::of(&req);
+    state
+        .metrics
+        .priority_requests
+        .with_label_values(&[class.as_str()])
+        .inc();
+    priority::scope(class, next.oneshot(req)).await
+}
+
+fn access(req: &Request<Body>) -> Access {
+    routing::access(req.method(), routing::split(req.uri().path()).1)
+}
@@ -0,0 +1,180 @@
+//! A minimal NATS client speaking the text protocol over a plain TCP
+//! connection: the async-nats client depends on a networking stack that
+//! doesn't run on WasmEdge. It publishes, and subscribes in queue groups,
+//! reading the headers of the messages sent with some.
+
+use anyhow::Context;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{info, warn};
+
+const CONNECT: &str =
+    "CONNECT {\"verbose\":false,\"pedantic\":false,\"headers\":true,\"name\":\"order_total\",\"lang\":\"rust\"}\r\n";
+
+/// Messages of subscriptions read but not yet taken by the receiver.
+const MAX_PENDING_MESSAGES: usize = 256;
+
+/// A message delivered to one of our subscriptions.
+#[derive(Debug)]
+pub struct Message {
+    pub subject: String,
+    /// Where the sender expects the reply, if anywhere.
+    pub reply_to: Option<String>,
+    /// The headers of a message sent with some, such as `Nats-Msg-Id`.
+    pub headers: HashMap<String, String>,
+    pub payload: Vec<u8>,
+}
+
+/// The sending side of a connection. Clones share the connection.
+#[derive(Clone)]
+pub struct Client {
+    writer: Arc<Mutex<WriteHalf<TcpStream>>>,
+}
+
+impl Client {
+    /// Connects to `url` (`nats://host:port` or `host:port`) and starts a task
+    /// reading what the server sends: it answers pings, logs errors, and hands
+    /// messages of our subscriptions to the returned receiver, which closes
+    /// when the connection is lost.
+    pub async fn connect(url: &str) -> anyhow::Result<(Client, mpsc::Receiver<Message>)> {
+        let address = url.strip_prefix("nats://").unwrap_or(url);
+        let stream = TcpStream::connect(address)
+            .await
+            .with_context(|| format!("cannot connect to NATS at {}", address))?;
+        let (reader, writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+        let mut greeting = String::new();
+        reader.read_line(&mut greeting).await?;
+        anyhow::ensure!(
+            greeting.starts_with("INFO"),
+            "unexpected NATS greeting {:?}",
+            greeting.trim_end()
+        );
+        let client = Client {
+            writer: Arc::new(Mutex::new(writer)),
+        };
+        client
+            .write(CONNECT.as_bytes())
+            .await
+            .context("cannot send CONNECT")?;
+        info!(address, "connected to NATS");
+
+        let (messages, receiver) = mpsc::channel(MAX_PENDING_MESSAGES);
+        tokio::spawn(read_server(reader, client.clone(), messages));
+        Ok((client, receiver))
+    }
+
+    pub async fn publish(&self, subject: &str, payload: &[u8]) -> io::Result<()> {
+        let mut frame = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(b"\r\n");
+        self.write(&frame).await
+    }
+
+    /// Subscribes to `subject` as a member of `queue_group`; the server hands
+    /// each message to one member of the group only.
+    pub async fn subscribe(&self, subject: &str, queue_group: &str, sid: u64) -> io::Result<()> {
+        self.write(format!("SUB {} {} {}\r\n", subject, queue_group, sid).as_bytes())
+            .await
+    }
+
+    pub async fn unsubscribe(&self, sid: u64) -> io::Result<()> {
+        self.write(format!("UNSUB {}\r\n", sid).as_bytes()).await
+    }
+
+    async fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(bytes).await?;
+        writer.flush().await
+    }
+}
+
+async fn read_server(
+    mut reader: BufReader<ReadHalf<TcpStream>>,
+    client: Client,
+    messages: mpsc::Sender<Message>,
+) {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line).await {
+            Ok(0) => {
+                warn!("NATS connection closed by the server");
+                return;
+            }
+            Ok(_) => (),
+            Err(err) => {
+                warn!(error = %err, "NATS connection lost");
+                return;
+            }
+        }
+        if line.starts_with("PING") {
+            if client.write(b"PONG\r\n").await.is_err() {
+                return;
+            }
+        } else if line.starts_with("-ERR") {
+            warn!(error = line.trim_end(), "NATS server error");
+        } else if line.starts_with("MSG ") || line.starts_with("HMSG ") {
+            match read_message(&mut reader, &line).await {
+                // Publishers that don't subscribe drop the receiver.
+                Ok(message) => {
+                    let _ = messages.send(message).await;
+                }
+                Err(err) => {
+                    warn!(error = %err, "invalid NATS message");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Reads the payload announced by `MSG <subject> <sid> [reply-to] <#bytes>`,
+/// or the headers and payload announced by `HMSG <subject> <sid> [reply-to]
+/// <#header bytes> <#total bytes>`.
+async fn read_message(
+    reader: &mut BufReader<ReadHalf<TcpStream>>,
+    header: &str,
+) -> io::Result<Message> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, header.trim_end().to_string());
+    let fields: Vec<&str> = header.split_whitespace().collect();
+    let (subject, reply_to, header_size, size) = match fields.as_slice() {
+        ["MSG", subject, _sid, size] => (subject, None, "0", size),
+        ["MSG", subject, _sid, reply_to, size] => (subject, Some(reply_to.to_string()), "0", size),
+        ["HMSG", subject, _sid, header_size, size] => (subject, None, *header_size, size),
+        ["HMSG", subject, _sid, reply_to, header_size, size] => {
+            (subject, Some(reply_to.to_string()), *header_size, size)
+        }
+        _ => return Err(invalid()),
+    };
+    let header_size: usize = header_size.parse().map_err(|_| invalid())?;
+    let size: usize = size.parse().map_err(|_| invalid())?;
+    if header_size > size {
+        return Err(invalid());
+    }
+    let mut payload = vec![0; size + 2];
+    reader.read_exact(&mut payload).await?;
+    payload.truncate(size);
+    let headers = parse_headers(&payload[..header_size]);
+    payload.drain(..header_size);
+    Ok(Message {
+        subject: subject.to_string(),
+        reply_to,
+        headers,
+        payload,
+    })
+}
+
+/// The headers of a `NATS/1.0` header block, after its status line.
+fn parse_headers(block: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(block)
+        .split("\r\n")
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
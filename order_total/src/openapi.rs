@@ -0,0 +1,769 @@
+//! The OpenAPI description of the HTTP API, served at `/openapi.json`, and
+//! the Swagger UI page at `/docs`. Routes are declared in `routes` and
+//! `admin::routes`; the functions below only carry their documentation.
+#![allow(dead_code)]
+
+use domain::{
+    Conversion, ErrorEnvelope, ErrorResponse, JurisdictionLevel, LineItem, NormalizedAddress,
+    Order, RateSource, Rounding, TaxComponent,
+};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Response};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::admin::{BuildInfo, LogLevel};
+use crate::batch::{BatchEntry, BatchResponse};
+use crate::cache::{CacheEntryInfo, CacheInfo};
+use crate::circuit_breaker::{CircuitState, CircuitStatus};
+use crate::dead_letter::DeadLetter;
+use crate::lifecycle::OrderStatus;
+use crate::rate_overrides::{RateOverride, RateOverrideEntry};
+use crate::reports::{DailyReport, StateTotals, Totals};
+use crate::revisions::{FieldChange, History, HistoryEntry, Revision};
+use crate::saga::{SagaList, SagaRecord, SagaStatus, Step, StepRecord, StepStatus};
+use crate::scheduler::JobStatus;
+use crate::search::{Highlights, SearchHit, SearchResults};
+use crate::service::AddressChange;
+use crate::store::{OrderRecord, Page};
+use crate::webhooks::FailedDelivery;
+use crate::Cancellation;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "order_total",
+        description = "Computes order totals including shipping, discounts and sales tax."
+    ),
+    paths(
+        compute,
+        compute_batch,
+        quote,
+        websocket,
+        list_orders,
+        search_orders,
+        export_orders,
+        get_order,
+        reprice_order,
+        order_history,
+        confirm_order,
+        cancel_order,
+        delete_order,
+        daily_report,
+        list_sagas,
+        get_saga,
+        events,
+        healthz,
+        readyz,
+        metrics,
+        cache_info,
+        flush_cache,
+        shutdown,
+        config,
+        log_level,
+        set_log_level,
+        circuit_breaker,
+        build_info,
+        jobs,
+        failed_webhooks,
+        replay_webhook,
+        dead_letters,
+        redrive_dead_letter,
+        rate_overrides,
+        add_rate_override,
+        remove_rate_override
+    ),
+    components(schemas(
+        Order,
+        LineItem,
+        RateSource,
+        Rounding,
+        Conversion,
+        TaxComponent,
+        JurisdictionLevel,
+        NormalizedAddress,
+        ErrorResponse,
+        ErrorEnvelope,
+        BatchEntry,
+        BatchResponse,
+        OrderRecord,
+        OrderStatus,
+        Revision,
+        History,
+        HistoryEntry,
+        FieldChange,
+        AddressChange,
+        Cancellation,
+        Page,
+        SearchResults,
+        SearchHit,
+        Highlights,
+        DailyReport,
+        Totals,
+        StateTotals,
+        SagaList,
+        SagaRecord,
+        SagaStatus,
+        StepRecord,
+        Step,
+        StepStatus,
+        CacheInfo,
+        CacheEntryInfo,
+        Status,
+        Flushed,
+        LogLevel,
+        CircuitStatus,
+        CircuitState,
+        BuildInfo,
+        JobStatus,
+        FailedDelivery,
+        DeadLetter,
+        RateOverride,
+        RateOverrideEntry
+    )),
+    tags(
+        (name = "pricing", description = "Pricing orders"),
+        (name = "orders", description = "Priced orders and their status"),
+        (name = "operations", description = "Probes and metrics"),
+        (name = "admin", description = "Administration, served on the admin port (`server.admin_port`, 9002 by default)")
+    )
+)]
+struct ApiDoc;
+
+/// The body of the probes and of `/admin/shutdown`.
+#[derive(ToSchema)]
+struct Status {
+    #[schema(example = "ok")]
+    status: String,
+    /// Why the service isn't ready.
+    reason: Option<String>,
+}
+
+#[derive(ToSchema)]
+struct Flushed {
+    /// Rates removed from the cache.
+    flushed: usize,
+}
+
+lazy_static! {
+    static ref SPEC: String = ApiDoc::openapi()
+        .to_pretty_json()
+        .expect("the OpenAPI description serializes");
+}
+
+const SWAGGER_UI: &str = include_str!("swagger_ui.html");
+
+/// `GET /openapi.json`
+pub fn spec() -> Response<Body> {
+    let mut response = crate::response_build(&SPEC);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// `GET /docs`: Swagger UI, with its assets loaded from a CDN.
+pub fn docs() -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(SWAGGER_UI))
+        .unwrap()
+}
+
+/// Price an order
+///
+/// Adds shipping, takes off the promo code's discount and applies the sales
+/// tax rate of the shipping zip code.
+///
+/// The order may also be sent as MessagePack (`Content-Type:
+/// application/msgpack`), and the priced order asked for as MessagePack with
+/// `Accept: application/msgpack`. Errors are always JSON.
+#[utoipa::path(
+    post,
+    path = "/v1/compute",
+    tag = "pricing",
+    request_body = Order,
+    params(
+        ("Idempotency-Key" = Option<String>, Header,
+            description = "Retries with the same key and body get the first response back"),
+        ("as_of" = Option<String>, Query,
+            description = "`YYYY-MM-DD` day to price at the rates in effect on, e.g. to reprocess past orders; today by default")
+    ),
+    responses(
+        (status = 200, description = "The priced order", content(
+            ("application/json" = Order),
+            ("application/msgpack" = Order)
+        )),
+        (status = 400, description = "The body is not a valid order", body = ErrorResponse),
+        (status = 402, description = "The payment service declined the order's total", body = ErrorResponse),
+        (status = 406, description = "`Accept` allows neither JSON nor MessagePack", body = ErrorResponse),
+        (status = 409, description = "The order's status doesn't allow pricing, its items are out of stock, or a request with this idempotency key is in progress", body = ErrorResponse),
+        (status = 413, description = "The body is larger than `server.max_body_bytes`", body = ErrorResponse),
+        (status = 415, description = "The body is neither JSON nor MessagePack, or compressed with an unsupported encoding", body = ErrorResponse),
+        (status = 422, description = "The order breaks a validation rule, its zip code has no rate, its currency no exchange rate or its tax exemption isn't valid", body = ErrorResponse),
+        (status = 502, description = "The sales tax rate service, the tax exemption registry, the inventory service or the payment service failed", body = ErrorResponse),
+        (status = 503, description = "The circuit breaker is open or the service is shutting down", body = ErrorResponse),
+        (status = 504, description = "A timeout was exceeded", body = ErrorResponse)
+    )
+)]
+fn compute() {}
+
+/// Price several orders
+///
+/// Each order gets its own result, so one bad order doesn't fail the batch.
+///
+/// With `Accept: text/csv`, the results are a CSV summary instead, one row
+/// per order: `index`, `status`, `order_id`, `shipping_zip`, `currency`,
+/// `subtotal`, `discount`, `shipping`, `tax` and `total` of priced orders,
+/// `error_code` and `error_message` of failed ones.
+#[utoipa::path(
+    post,
+    path = "/v1/compute_batch",
+    tag = "pricing",
+    request_body = Vec<Order>,
+    params(
+        ("as_of" = Option<String>, Query,
+            description = "`YYYY-MM-DD` day to price at the rates in effect on, e.g. to reprocess past orders; today by default")
+    ),
+    responses(
+        (status = 200, description = "One result per order, in order", content(
+            ("application/json" = BatchResponse),
+            ("text/csv" = String)
+        )),
+        (status = 400, description = "The body is not an array", body = ErrorResponse),
+        (status = 404, description = "The `batch_endpoint` feature flag is off for the caller", body = ErrorResponse),
+        (status = 406, description = "`Accept` allows neither JSON nor CSV", body = ErrorResponse),
+        (status = 413, description = "The body is larger than `server.max_body_bytes`", body = ErrorResponse),
+        (status = 415, description = "The body isn't JSON, or is compressed with an unsupported encoding", body = ErrorResponse)
+    )
+)]
+fn compute_batch() {}
+
+/// Quote an order
+///
+/// Prices an order exactly like `/v1/compute`, without side effects: the
+/// order isn't stored, published or audited, and its status isn't checked.
+/// Meant for live totals while the order is being filled in.
+#[utoipa::path(
+    post,
+    path = "/v1/quote",
+    tag = "pricing",
+    request_body = Order,
+    params(
+        ("as_of" = Option<String>, Query,
+            description = "`YYYY-MM-DD` day to price at the rates in effect on, e.g. to reprocess past orders; today by default")
+    ),
+    responses(
+        (status = 200, description = "The priced order", body = Order),
+        (status = 400, description = "The body is not a valid order", body = ErrorResponse),
+        (status = 413, description = "The body is larger than `server.max_body_bytes`", body = ErrorResponse),
+        (status = 415, description = "The body isn't JSON", body = ErrorResponse),
+        (status = 422, description = "The order breaks a validation rule, its zip code has no rate, its currency no exchange rate or its tax exemption isn't valid", body = ErrorResponse),
+        (status = 502, description = "The sales tax rate service or the tax exemption registry failed", body = ErrorResponse),
+        (status = 503, description = "The circuit breaker is open or the service is shutting down", body = ErrorResponse),
+        (status = 504, description = "A timeout was exceeded", body = ErrorResponse)
+    )
+)]
+fn quote() {}
+
+/// Price orders over a WebSocket connection
+///
+/// After the handshake, each text message `{"id": ..., "order": {...}}` is
+/// answered with `{"id": ..., "status": "ok", "order": {...}}` or
+/// `{"id": ..., "status": "error", "error": {...}}`. Orders of one connection
+/// are priced concurrently, up to `websocket.max_in_flight` at a time, and
+/// answered as they complete, so clients match answers by `id`.
+#[utoipa::path(
+    get,
+    path = "/v1/ws",
+    tag = "pricing",
+    responses(
+        (status = 101, description = "The connection now speaks WebSocket"),
+        (status = 426, description = "Not a WebSocket handshake", body = ErrorResponse)
+    )
+)]
+fn websocket() {}
+
+/// List priced orders
+///
+/// Most recently priced first, unless sorted otherwise, a page at a time.
+#[utoipa::path(
+    get,
+    path = "/v1/orders",
+    tag = "orders",
+    params(
+        ("zip" = Option<String>, Query, description = "Shipping zip codes starting with it"),
+        ("from" = Option<String>, Query, description = "Orders priced at or after this RFC 3339 time"),
+        ("to" = Option<String>, Query, description = "Orders priced before this RFC 3339 time"),
+        ("min_total" = Option<String>, Query, description = "Orders totaling at least this"),
+        ("max_total" = Option<String>, Query, description = "Orders totaling at most this"),
+        ("sort" = Option<String>, Query, description = "`created_at` (by default) or `total`"),
+        ("order" = Option<String>, Query, description = "`desc` (by default) or `asc`"),
+        ("include_cancelled" = Option<bool>, Query, description = "Lists cancelled orders too, `false` by default"),
+        ("cursor" = Option<String>, Query, description = "The `next_cursor` of the previous page"),
+        ("offset" = Option<usize>, Query, description = "Orders to skip, 0 by default"),
+        ("limit" = Option<usize>, Query, description = "Page size, 20 by default and at most 100")
+    ),
+    responses(
+        (status = 200, description = "A page of orders", body = Page),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 400, description = "Invalid filters or pagination", body = ErrorResponse)
+    )
+)]
+fn list_orders() {}
+
+/// Search priced orders
+///
+/// By the words of their shipping address and zip code: each word of `q`
+/// matches the words it equals or starts with. Best match first, the matched
+/// parts highlighted in `<em>`.
+#[utoipa::path(
+    get,
+    path = "/v1/orders/search",
+    tag = "orders",
+    params(
+        ("q" = String, Query, description = "Words to search for, e.g. `main 787`"),
+        ("limit" = Option<usize>, Query, description = "Results, 20 by default and at most 100")
+    ),
+    responses(
+        (status = 200, description = "The matching orders", body = SearchResults),
+        (status = 400, description = "No word to search for", body = ErrorResponse)
+    )
+)]
+fn search_orders() {}
+
+/// Export orders
+///
+/// Every order priced between `from` and `to`, oldest first, cancelled ones
+/// included: as CSV, one row of totals per order, or as JSON lines, one
+/// whole record per line. The response is streamed, a chunk at a time.
+#[utoipa::path(
+    get,
+    path = "/v1/orders/export",
+    tag = "orders",
+    params(
+        ("format" = Option<String>, Query, description = "`csv` (by default) or `jsonl`"),
+        ("from" = Option<String>, Query, description = "Orders priced at or after this RFC 3339 time"),
+        ("to" = Option<String>, Query, description = "Orders priced before this RFC 3339 time")
+    ),
+    responses(
+        (status = 200, description = "The orders, as an attachment", body = String,
+            content_type = ["text/csv", "application/x-ndjson"]),
+        (status = 400, description = "Invalid format or times", body = ErrorResponse)
+    )
+)]
+fn export_orders() {}
+
+/// Get a priced order
+#[utoipa::path(
+    get,
+    path = "/v1/orders/{id}",
+    tag = "orders",
+    params(
+        ("id" = i32, Path, description = "The order id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of the order as last read")
+    ),
+    responses(
+        (status = 200, description = "The order, with its `ETag`", body = OrderRecord),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 404, description = "No such order", body = ErrorResponse)
+    )
+)]
+fn get_order() {}
+
+/// Change the shipping address of an order
+///
+/// The order is priced again for its new destination: the address is
+/// validated, and the shipping, tax exemption and sales tax rate looked up
+/// again. The record keeps every pricing in `revisions`, with the rate
+/// applied, and an `OrderPriced` event is published.
+#[utoipa::path(
+    patch,
+    path = "/v1/orders/{id}",
+    tag = "orders",
+    params(("id" = i32, Path, description = "The order id")),
+    request_body = AddressChange,
+    responses(
+        (status = 200, description = "The order priced again", body = OrderRecord),
+        (status = 400, description = "Invalid body, or nothing to change", body = ErrorResponse),
+        (status = 404, description = "No such order", body = ErrorResponse),
+        (status = 409, description = "The order is confirmed or cancelled", body = ErrorResponse),
+        (status = 422, description = "The new address is invalid, or its zip code has no rate", body = ErrorResponse)
+    )
+)]
+fn reprice_order() {}
+
+/// Get the pricing history of an order
+///
+/// Every pricing of the order, oldest first, with the rate applied and the
+/// fields that changed since the one before: its address, zip code, rate,
+/// tax and total. An order never priced again has a single revision.
+#[utoipa::path(
+    get,
+    path = "/v1/orders/{id}/history",
+    tag = "orders",
+    params(("id" = i32, Path, description = "The order id")),
+    responses(
+        (status = 200, description = "The revisions of the order", body = History),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 404, description = "No such order", body = ErrorResponse)
+    )
+)]
+fn order_history() {}
+
+/// Confirm a priced order
+///
+/// With a payment service (`payments.enabled`), the order's total is
+/// authorized first.
+#[utoipa::path(
+    post,
+    path = "/v1/orders/{id}/confirm",
+    tag = "orders",
+    params(("id" = i32, Path, description = "The order id")),
+    responses(
+        (status = 200, description = "The confirmed order", body = OrderRecord),
+        (status = 402, description = "The payment was declined", body = ErrorResponse),
+        (status = 404, description = "No such order", body = ErrorResponse),
+        (status = 409, description = "The order isn't priced", body = ErrorResponse),
+        (status = 502, description = "The payment service failed", body = ErrorResponse)
+    )
+)]
+fn confirm_order() {}
+
+/// Cancel an order
+///
+/// The body is optional. An `OrderCancelled` event is published.
+#[utoipa::path(
+    post,
+    path = "/v1/orders/{id}/cancel",
+    tag = "orders",
+    params(("id" = i32, Path, description = "The order id")),
+    request_body = Option<Cancellation>,
+    responses(
+        (status = 200, description = "The cancelled order", body = OrderRecord),
+        (status = 400, description = "Invalid body", body = ErrorResponse),
+        (status = 404, description = "No such order", body = ErrorResponse),
+        (status = 409, description = "The order is already cancelled", body = ErrorResponse)
+    )
+)]
+fn cancel_order() {}
+
+/// Delete an order
+///
+/// Cancels it, as `POST /v1/orders/{id}/cancel` does. The order is kept, with
+/// its `cancelled` status and `cancellation_reason`, and can still be looked
+/// up, but `GET /v1/orders` leaves it out unless `include_cancelled=true`.
+#[utoipa::path(
+    delete,
+    path = "/v1/orders/{id}",
+    tag = "orders",
+    params(("id" = i32, Path, description = "The order id")),
+    request_body = Option<Cancellation>,
+    responses(
+        (status = 200, description = "The cancelled order", body = OrderRecord),
+        (status = 400, description = "Invalid body", body = ErrorResponse),
+        (status = 404, description = "No such order", body = ErrorResponse),
+        (status = 409, description = "The order is already cancelled", body = ErrorResponse)
+    )
+)]
+fn delete_order() {}
+
+/// Daily sales and tax report
+///
+/// The number of orders priced on a day, their subtotal and the sales tax
+/// collected, in total and by the state of their shipping zip code, from its
+/// first three digits. Cancelled orders aren't counted; amounts are in the
+/// base currency.
+#[utoipa::path(
+    get,
+    path = "/v1/reports/daily",
+    tag = "orders",
+    params(("date" = Option<String>, Query, description = "`YYYY-MM-DD`, in UTC; today by default")),
+    responses(
+        (status = 200, description = "The report of the day", body = DailyReport),
+        (status = 400, description = "Invalid date", body = ErrorResponse)
+    )
+)]
+fn daily_report() {}
+
+/// List the sagas of an order
+///
+/// Most recently started first; none unless orders are priced by a saga
+/// (`saga.enabled`).
+#[utoipa::path(
+    get,
+    path = "/v1/sagas",
+    tag = "orders",
+    params(("order_id" = i32, Query, description = "The order id")),
+    responses(
+        (status = 200, description = "The sagas of the order", body = SagaList),
+        (status = 400, description = "Missing or invalid order id", body = ErrorResponse)
+    )
+)]
+fn list_sagas() {}
+
+/// Get a saga
+///
+/// How far each step of the saga pricing an order got, and which were undone.
+#[utoipa::path(
+    get,
+    path = "/v1/sagas/{id}",
+    tag = "orders",
+    params(("id" = String, Path, description = "The saga id")),
+    responses(
+        (status = 200, description = "The saga", body = SagaRecord),
+        (status = 404, description = "No such saga", body = ErrorResponse)
+    )
+)]
+fn get_saga() {}
+
+/// Follow priced orders live
+///
+/// A Server-Sent Events stream: an `order_priced` event, with the
+/// `OrderPriced` event as data, for every order priced from now on, and a
+/// `lagged` event, `{"skipped": n}`, when the client fell too far behind and
+/// missed `n` orders. A comment is sent on idle streams every
+/// `stream.heartbeat_secs`.
+#[utoipa::path(
+    get,
+    path = "/v1/events",
+    tag = "orders",
+    params(
+        ("zip_prefix" = Option<String>, Query,
+            description = "Only orders shipped to ZIP codes starting with these 1 to 5 digits")
+    ),
+    responses(
+        (status = 200, description = "The event stream", body = String,
+            content_type = "text/event-stream"),
+        (status = 400, description = "Invalid zip_prefix", body = ErrorResponse)
+    )
+)]
+fn events() {}
+
+/// Liveness probe
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    responses((status = 200, description = "The process is up", body = Status))
+)]
+fn healthz() {}
+
+/// Readiness probe
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "The sales tax rate service is reachable", body = Status),
+        (status = 503, description = "The sales tax rate service is unreachable", body = Status)
+    )
+)]
+fn readyz() {}
+
+/// Prometheus metrics
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "Metrics in the Prometheus text format", body = String,
+        content_type = "text/plain"))
+)]
+fn metrics() {}
+
+/// Inspect the rate cache
+#[utoipa::path(
+    get,
+    path = "/admin/cache",
+    tag = "admin",
+    responses((status = 200, description = "The cached rates", body = CacheInfo))
+)]
+fn cache_info() {}
+
+/// Flush the rate cache
+#[utoipa::path(
+    delete,
+    path = "/admin/cache",
+    tag = "admin",
+    responses((status = 200, description = "The number of flushed rates", body = Flushed))
+)]
+fn flush_cache() {}
+
+/// Start a graceful shutdown
+///
+/// For runtimes that don't deliver signals to the service.
+#[utoipa::path(
+    post,
+    path = "/admin/shutdown",
+    tag = "admin",
+    responses((status = 202, description = "Shutdown started", body = Status))
+)]
+fn shutdown() {}
+
+/// Dump the configuration
+///
+/// Secrets, such as API keys, and the credentials of URLs are redacted.
+#[utoipa::path(
+    get,
+    path = "/admin/config",
+    tag = "admin",
+    responses((status = 200, description = "The configuration in effect, as last reloaded", body = Object))
+)]
+fn config() {}
+
+/// Get the log level
+#[utoipa::path(
+    get,
+    path = "/admin/log_level",
+    tag = "admin",
+    responses((status = 200, description = "The active log filter", body = LogLevel))
+)]
+fn log_level() {}
+
+/// Change the log level
+///
+/// Takes effect immediately, until the next restart.
+#[utoipa::path(
+    put,
+    path = "/admin/log_level",
+    tag = "admin",
+    request_body = LogLevel,
+    responses(
+        (status = 200, description = "The new log filter", body = LogLevel),
+        (status = 400, description = "Invalid log filter", body = ErrorResponse)
+    )
+)]
+fn set_log_level() {}
+
+/// Inspect the circuit breaker of the rate provider
+#[utoipa::path(
+    get,
+    path = "/admin/circuit_breaker",
+    tag = "admin",
+    responses((status = 200, description = "The state of the circuit breaker", body = CircuitStatus))
+)]
+fn circuit_breaker() {}
+
+/// What is running
+#[utoipa::path(
+    get,
+    path = "/admin/build_info",
+    tag = "admin",
+    responses((status = 200, description = "Version and build of the service", body = BuildInfo))
+)]
+fn build_info() {}
+
+/// List the scheduled jobs
+///
+/// Every job of `[scheduler]`, whether enabled, and its last run.
+#[utoipa::path(
+    get,
+    path = "/admin/jobs",
+    tag = "admin",
+    responses((status = 200, description = "The jobs and their last runs", body = [JobStatus]))
+)]
+fn jobs() {}
+
+/// List the failed webhook deliveries
+///
+/// Deliveries that failed every attempt, oldest first, as kept in the
+/// dead-letter log (`webhooks.dead_letter_path`).
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/failed",
+    tag = "admin",
+    responses((status = 200, description = "The failed deliveries", body = [FailedDelivery]))
+)]
+fn failed_webhooks() {}
+
+/// Replay a failed webhook delivery
+///
+/// Takes the delivery out of the dead-letter log and sends it again, with the
+/// same `X-Webhook-Id`. It returns to the log if it fails again.
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/failed/{id}/replay",
+    tag = "admin",
+    params(("id" = String, Path, description = "Id of the failed delivery")),
+    responses(
+        (status = 202, description = "The delivery, queued again", body = FailedDelivery),
+        (status = 404, description = "No failed delivery has this id", body = ErrorResponse)
+    )
+)]
+fn replay_webhook() {}
+
+/// List the dead letters of the queue consumer
+///
+/// Orders of the `queue` run mode that failed every attempt with a retryable
+/// status, oldest first, as kept in the dead-letter log
+/// (`queue.dead_letter_path`). Empty in the `http` run mode.
+#[utoipa::path(
+    get,
+    path = "/admin/queue/dead_letters",
+    tag = "admin",
+    responses((status = 200, description = "The dead letters", body = [DeadLetter]))
+)]
+fn dead_letters() {}
+
+/// Re-drive a dead letter
+///
+/// Takes the order out of the dead-letter log and hands it to the queue
+/// consumer, which prices it again and publishes the result to its reply
+/// subject. Failing every attempt again makes it a new dead letter.
+#[utoipa::path(
+    post,
+    path = "/admin/queue/dead_letters/{id}/redrive",
+    tag = "admin",
+    params(("id" = String, Path, description = "Id of the dead letter")),
+    responses(
+        (status = 202, description = "The dead letter, handed to the consumer", body = DeadLetter),
+        (status = 404, description = "No dead letter has this id", body = ErrorResponse),
+        (status = 503, description = "Too many dead letters are waiting to be priced again", body = ErrorResponse)
+    )
+)]
+fn redrive_dead_letter() {}
+
+/// List the rate overrides
+///
+/// Those added with the admin API, newest last, then those of
+/// `rates.overrides`, whether they apply today or not.
+#[utoipa::path(
+    get,
+    path = "/admin/rate_overrides",
+    tag = "admin",
+    responses((status = 200, description = "The rate overrides", body = [RateOverrideEntry]))
+)]
+fn rate_overrides() {}
+
+/// Add a rate override
+///
+/// Orders shipped to the zip code are priced at the override's rate, rather
+/// than the rate provider's, while it applies; they carry `"rate_source":
+/// "override"`. Lasts until the next restart.
+#[utoipa::path(
+    post,
+    path = "/admin/rate_overrides",
+    tag = "admin",
+    request_body = RateOverride,
+    responses(
+        (status = 201, description = "The override, with its id", body = RateOverrideEntry),
+        (status = 400, description = "The body is not a rate override", body = ErrorResponse),
+        (status = 422, description = "The zip code, rate or dates of the override aren't valid", body = ErrorResponse)
+    )
+)]
+fn add_rate_override() {}
+
+/// Remove a rate override
+///
+/// Only overrides added with the admin API can be removed; those of
+/// `rates.overrides` are removed from the configuration.
+#[utoipa::path(
+    delete,
+    path = "/admin/rate_overrides/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Id of the rate override")),
+    responses(
+        (status = 200, description = "The removed override", body = RateOverride),
+        (status = 404, description = "No rate override has this id", body = ErrorResponse)
+    )
+)]
+fn remove_rate_override() {}
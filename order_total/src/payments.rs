@@ -0,0 +1,121 @@
+//! Payment authorization, when an order is confirmed or as the last step of
+//! the order saga: the total of a priced order is authorized at a payment
+//! service, and the authorization is voided again when the order is
+//! cancelled or a later step of the saga fails.
+
+use domain::money::{self, Decimal};
+use domain::{ErrorResponse, Order};
+use reqwest::header::ACCEPT;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::config::PaymentsConfig;
+use crate::error::AppError;
+use crate::request_id;
+
+/// A payment service, asked with `POST <url> {"order_id": ..., "amount":
+/// ..., "currency": ...}`. It answers an authorization with `201
+/// {"authorization_id": ...}`, or `402` with an error body when it declines
+/// the payment, and voids authorization `id` on `DELETE <url>/<id>`.
+pub struct Payments {
+    url: String,
+    timeout: Duration,
+    client: reqwest::Client,
+}
+
+/// The body of an authorization.
+#[derive(Serialize)]
+struct AuthorizationRequest<'a> {
+    order_id: i32,
+    #[serde(with = "money::json_number")]
+    amount: Decimal,
+    currency: &'a str,
+}
+
+/// The service's answer for an authorization.
+#[derive(Deserialize)]
+struct AuthorizationResponse {
+    authorization_id: String,
+}
+
+impl Payments {
+    /// The payment service of `config`, called with `client`, or none when
+    /// payments aren't authorized.
+    pub fn from_config(config: &PaymentsConfig, client: reqwest::Client) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        Some(Self {
+            url: config.url.trim_end_matches('/').to_string(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            client,
+        })
+    }
+
+    /// Authorizes the total of `order`, in `currency`, and returns the
+    /// authorization id. Fails with `PaymentDeclined` when the service
+    /// declines it.
+    pub async fn authorize(&self, order: &Order, currency: &str) -> Result<String, AppError> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(ACCEPT, "application/json")
+            .timeout(self.timeout)
+            .json(&AuthorizationRequest {
+                order_id: order.order_id,
+                amount: order.total,
+                currency,
+            });
+        if let Some(request_id) = request_id::current() {
+            request = request.header(request_id::REQUEST_ID_HEADER, request_id);
+        }
+        let response = request.send().await.map_err(|err| self.unavailable(err))?;
+        match response.status().as_u16() {
+            200 | 201 => response
+                .json::<AuthorizationResponse>()
+                .await
+                .map(|body| body.authorization_id)
+                .map_err(|err| {
+                    AppError::PaymentServiceUnavailable(format!("invalid response: {}", err))
+                }),
+            402 => Err(AppError::PaymentDeclined(
+                match response.json::<ErrorResponse>().await {
+                    Ok(body) => body.error.message,
+                    Err(_) => "declined by the payment service".into(),
+                },
+            )),
+            status => Err(AppError::PaymentServiceUnavailable(format!(
+                "unexpected status {}",
+                status
+            ))),
+        }
+    }
+
+    /// Voids authorization `id`. An authorization the service doesn't know
+    /// is as good as voided.
+    pub async fn void(&self, id: &str) -> Result<(), AppError> {
+        let mut request = self
+            .client
+            .delete(format!("{}/{}", self.url, id))
+            .timeout(self.timeout);
+        if let Some(request_id) = request_id::current() {
+            request = request.header(request_id::REQUEST_ID_HEADER, request_id);
+        }
+        let response = request.send().await.map_err(|err| self.unavailable(err))?;
+        match response.status().as_u16() {
+            200..=299 | 404 => Ok(()),
+            status => Err(AppError::PaymentServiceUnavailable(format!(
+                "unexpected status {}",
+                status
+            ))),
+        }
+    }
+
+    fn unavailable(&self, err: reqwest::Error) -> AppError {
+        AppError::PaymentServiceUnavailable(if err.is_timeout() {
+            format!("no answer within {} ms", self.timeout.as_millis())
+        } else {
+            err.to_string()
+        })
+    }
+}
@@ -0,0 +1,180 @@
+//! Postal codes of shipping addresses. Only US addresses are taxed, by the
+//! 5-digit part of their zip code, so ZIP+4 codes share the rate, the cache
+//! entry and the lookup of their 5-digit code. Postal codes of other
+//! countries are recognised by their shape and fail with
+//! `UNSUPPORTED_JURISDICTION`, rather than as a zip code without a rate.
+
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostalCode<'a> {
+    /// A US zip code, `12345` or `12345-6789`, by its 5-digit part.
+    Us(&'a str),
+    /// The postal code of another country, with its ISO 3166 code when the
+    /// shape tells it.
+    Foreign(Option<&'static str>),
+    /// Neither, e.g. a mistyped zip code.
+    Invalid,
+}
+
+/// Which kind of postal code `code` is.
+pub fn parse(code: &str) -> PostalCode<'_> {
+    let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    match code.split_once('-') {
+        Some((zip5, plus4))
+            if zip5.len() == 5 && plus4.len() == 4 && all_digits(zip5) && all_digits(plus4) =>
+        {
+            return PostalCode::Us(zip5)
+        }
+        None if code.len() == 5 && all_digits(code) => return PostalCode::Us(code),
+        _ => {}
+    }
+    let code = code.to_ascii_uppercase();
+    if is_canadian(&code) {
+        PostalCode::Foreign(Some("CA"))
+    } else if is_british(&code) {
+        PostalCode::Foreign(Some("GB"))
+    } else if is_alphanumeric_postal_code(&code) {
+        PostalCode::Foreign(None)
+    } else {
+        PostalCode::Invalid
+    }
+}
+
+/// The zip code to look the rate of `code` up by: the 5-digit part of a US
+/// zip code, and anything else as it is, which won't have a rate. Fails for
+/// the postal codes of other countries.
+pub fn rate_zip(code: &str) -> Result<&str, AppError> {
+    match parse(code) {
+        PostalCode::Us(zip5) => Ok(zip5),
+        PostalCode::Foreign(country) => {
+            Err(AppError::UnsupportedJurisdiction(code.to_string(), country))
+        }
+        PostalCode::Invalid => Ok(code),
+    }
+}
+
+/// The first and last 3-digit prefixes of the zip codes of each state, as
+/// the USPS assigns them. Military and territory prefixes aren't listed.
+const STATE_ZIP3: &[(u16, u16, &str)] = &[
+    (5, 5, "NY"),
+    (10, 27, "MA"),
+    (28, 29, "RI"),
+    (30, 38, "NH"),
+    (39, 49, "ME"),
+    (50, 54, "VT"),
+    (55, 55, "MA"),
+    (56, 59, "VT"),
+    (60, 69, "CT"),
+    (70, 89, "NJ"),
+    (100, 149, "NY"),
+    (150, 196, "PA"),
+    (197, 199, "DE"),
+    (200, 205, "DC"),
+    (206, 219, "MD"),
+    (220, 246, "VA"),
+    (247, 268, "WV"),
+    (270, 289, "NC"),
+    (290, 299, "SC"),
+    (300, 319, "GA"),
+    (320, 339, "FL"),
+    (341, 349, "FL"),
+    (350, 369, "AL"),
+    (370, 385, "TN"),
+    (386, 397, "MS"),
+    (398, 399, "GA"),
+    (400, 427, "KY"),
+    (430, 459, "OH"),
+    (460, 479, "IN"),
+    (480, 499, "MI"),
+    (500, 528, "IA"),
+    (530, 549, "WI"),
+    (550, 567, "MN"),
+    (570, 577, "SD"),
+    (580, 588, "ND"),
+    (590, 599, "MT"),
+    (600, 629, "IL"),
+    (630, 658, "MO"),
+    (660, 679, "KS"),
+    (680, 693, "NE"),
+    (700, 715, "LA"),
+    (716, 729, "AR"),
+    (730, 749, "OK"),
+    (750, 799, "TX"),
+    (800, 816, "CO"),
+    (820, 831, "WY"),
+    (832, 838, "ID"),
+    (840, 847, "UT"),
+    (850, 865, "AZ"),
+    (870, 884, "NM"),
+    (885, 885, "TX"),
+    (889, 898, "NV"),
+    (900, 961, "CA"),
+    (967, 968, "HI"),
+    (970, 979, "OR"),
+    (980, 994, "WA"),
+    (995, 999, "AK"),
+];
+
+/// The two-letter code of the state of a US zip code, by its first three
+/// digits; none for other postal codes and unassigned prefixes.
+pub fn state(code: &str) -> Option<&'static str> {
+    let zip3: u16 = match parse(code) {
+        PostalCode::Us(zip5) => zip5[..3].parse().ok()?,
+        _ => return None,
+    };
+    STATE_ZIP3
+        .iter()
+        .find(|(first, last, _)| (*first..=*last).contains(&zip3))
+        .map(|(_, _, state)| *state)
+}
+
+/// `A1A 1A1`, with or without the space.
+fn is_canadian(code: &str) -> bool {
+    let code: Vec<u8> = code.bytes().filter(|byte| *byte != b' ').collect();
+    code.len() == 6
+        && code.iter().enumerate().all(|(index, byte)| {
+            if index % 2 == 0 {
+                byte.is_ascii_uppercase()
+            } else {
+                byte.is_ascii_digit()
+            }
+        })
+}
+
+/// `SW1A 1AA`: an outward code of a letter, a letter or digit and up to two
+/// more, with a digit among them, then a digit and two letters.
+fn is_british(code: &str) -> bool {
+    let (outward, inward) = match code.split_once(' ') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let outward = outward.as_bytes();
+    let inward = inward.as_bytes();
+    (2..=4).contains(&outward.len())
+        && outward[0].is_ascii_uppercase()
+        && outward.iter().all(u8::is_ascii_alphanumeric)
+        && outward.iter().any(u8::is_ascii_digit)
+        && inward.len() == 3
+        && inward[0].is_ascii_digit()
+        && inward[1..].iter().all(u8::is_ascii_uppercase)
+}
+
+/// Letters and digits, both, with at most one space or hyphen, as in
+/// `1012 AB` or `D02 X285`. US zip codes have no letters, so these aren't
+/// mistyped ones.
+fn is_alphanumeric_postal_code(code: &str) -> bool {
+    let separators = code
+        .bytes()
+        .filter(|byte| matches!(byte, b' ' | b'-'))
+        .count();
+    (4..=10).contains(&code.len())
+        && separators <= 1
+        && !code.starts_with([' ', '-'])
+        && !code.ends_with([' ', '-'])
+        && code
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b' ' | b'-'))
+        && code.bytes().any(|byte| byte.is_ascii_uppercase())
+        && code.bytes().any(|byte| byte.is_ascii_digit())
+}
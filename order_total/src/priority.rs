@@ -0,0 +1,72 @@
+//! Priority classes: a request is `interactive`, someone waiting on a quote,
+//! or `batch`, bulk work that can wait. The class is named by the
+//! `X-Priority` header, and is `batch` for `/compute_batch` and `interactive`
+//! otherwise. The bulkheads hand their freed slots to the waiting calls of
+//! each class by `priority.<class>.weight`, so a large batch job can't starve
+//! interactive requests, nor the other way round.
+
+use hyper::header::HeaderMap;
+use hyper::{Method, Request};
+use std::future::Future;
+
+use crate::routing;
+
+/// The header naming the class of a request, `interactive` or `batch`.
+pub const PRIORITY_HEADER: &str = "x-priority";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Interactive,
+    Batch,
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+impl Priority {
+    pub const ALL: [Priority; 2] = [Priority::Interactive, Priority::Batch];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Batch => "batch",
+        }
+    }
+
+    /// The position of the class in `ALL`.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The class named by the `X-Priority` header, if valid.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        match headers.get(PRIORITY_HEADER)?.to_str().ok()?.trim() {
+            value if value.eq_ignore_ascii_case("interactive") => Some(Priority::Interactive),
+            value if value.eq_ignore_ascii_case("batch") => Some(Priority::Batch),
+            _ => None,
+        }
+    }
+
+    /// The class of `req`: the one it names, or else that of its endpoint.
+    pub fn of<B>(req: &Request<B>) -> Self {
+        Self::from_headers(req.headers()).unwrap_or_else(|| {
+            match (req.method(), routing::split(req.uri().path()).1) {
+                (&Method::POST, "/compute_batch") => Priority::Batch,
+                _ => Priority::Interactive,
+            }
+        })
+    }
+}
+
+/// Runs `future` in the class `priority`, available through `current`.
+pub async fn scope<F: Future>(priority: Priority, future: F) -> F::Output {
+    PRIORITY.scope(priority, future).await
+}
+
+/// The class of the request being served; `interactive` outside of one.
+pub fn current() -> Priority {
+    PRIORITY
+        .try_with(|priority| *priority)
+        .unwrap_or(Priority::Interactive)
+}
@@ -0,0 +1,341 @@
+//! The `queue` run mode: orders are consumed from a NATS subject instead of
+//! coming in as HTTP requests, and the result for each is published to the
+//! message's reply subject. With manual acknowledgement, the reply subject is
+//! rather where the order is acknowledged once its result is published to
+//! `queue.reply_subject`. Messages with an id, their `Nats-Msg-Id` or their
+//! JetStream sequence, are handled once within the idempotency TTL: a
+//! redelivered order gets the result of the first delivery instead of being
+//! priced, stored and published again. Orders failing with a retryable status are priced
+//! again with backoff; those failing every attempt become dead letters,
+//! published to the dead-letter subject and kept in the dead-letter log, from
+//! which `/admin/queue/dead_letters` re-drives them.
+
+use domain::{ErrorEnvelope, Order};
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use tracing::{info, warn, Instrument};
+use uuid::Uuid;
+
+use crate::config::{AckMode, QueueConfig};
+use crate::dead_letter::DeadLetter;
+use crate::error::AppError;
+use crate::idempotency::REPLAYED_HEADER;
+use crate::nats::{Client, Message};
+use crate::retry::RetryPolicy;
+use crate::service::price;
+use crate::state::AppState;
+use crate::{deadline, request_id, request_timeout, SHUTDOWN};
+
+/// Our only subscription.
+const SID: u64 = 1;
+
+/// The id a publisher gives a message, the same on every redelivery.
+const MESSAGE_ID_HEADER: &str = "Nats-Msg-Id";
+
+/// The result published for each consumed order.
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum Reply {
+    Ok { order: Box<Order> },
+    Error { error: ErrorEnvelope },
+}
+
+/// Where the result of a consumed order goes, and how it is acknowledged.
+struct Delivery {
+    reply_to: String,
+    /// The subject acknowledging the message, with manual acknowledgement.
+    ack: Option<String>,
+    /// The id telling a redelivery of the message from a new order.
+    message_id: Option<String>,
+}
+
+impl Delivery {
+    fn new(message: &Message, config: &QueueConfig) -> Self {
+        let message_id = message.headers.get(MESSAGE_ID_HEADER).cloned();
+        match (config.ack, &message.reply_to) {
+            (AckMode::Manual, Some(ack)) => Self {
+                reply_to: config.reply_subject.clone(),
+                message_id: message_id.or_else(|| stream_sequence(ack)),
+                ack: Some(ack.clone()),
+            },
+            (_, reply_to) => Self {
+                reply_to: reply_to
+                    .clone()
+                    .unwrap_or_else(|| config.reply_subject.clone()),
+                ack: None,
+                message_id,
+            },
+        }
+    }
+}
+
+/// `<stream>:<sequence>` of a JetStream message, from its acknowledgement
+/// subject: `$JS.ACK.[<domain>.<account hash>.]<stream>.<consumer>.
+/// <delivered>.<stream sequence>.<consumer sequence>.<time>.<pending>[.<token>]`.
+fn stream_sequence(ack: &str) -> Option<String> {
+    let tokens: Vec<&str> = ack.strip_prefix("$JS.ACK.")?.split('.').collect();
+    let (stream, sequence) = match tokens.len() {
+        7 => (tokens[0], tokens[3]),
+        9 | 10 => (tokens[2], tokens[5]),
+        _ => return None,
+    };
+    Some(format!("{}:{}", stream, sequence))
+}
+
+/// Prices the orders of `config.subject` until shutdown, sharing them with the
+/// other instances of the queue group. Losing the connection ends the run, for
+/// the supervisor to restart the service.
+pub async fn run(state: AppState, config: &QueueConfig) -> anyhow::Result<()> {
+    let (client, mut messages) = Client::connect(&config.nats_url).await?;
+    client
+        .subscribe(&config.subject, &config.queue_group, SID)
+        .await?;
+    info!(
+        subject = %config.subject,
+        queue_group = %config.queue_group,
+        "consuming orders"
+    );
+
+    let mut redriven = state
+        .dead_letters
+        .as_ref()
+        .and_then(|dead_letters| dead_letters.redriven());
+
+    // Every order being priced holds a sender; the receiver sees the channel
+    // close once all of them are done.
+    let (in_flight, mut drained) = mpsc::channel::<()>(1);
+    loop {
+        tokio::select! {
+            message = messages.recv() => {
+                let message = match message {
+                    Some(message) => message,
+                    None => anyhow::bail!("NATS connection lost"),
+                };
+                let delivery = Delivery::new(&message, config);
+                spawn(&state, &client, message, delivery, in_flight.clone());
+            }
+            Some(letter) = next_redriven(&mut redriven) => {
+                let delivery = Delivery {
+                    reply_to: letter.reply_to.clone(),
+                    ack: None,
+                    message_id: None,
+                };
+                let message = Message {
+                    subject: letter.subject,
+                    reply_to: Some(letter.reply_to),
+                    headers: Default::default(),
+                    payload: serde_json::to_vec(&letter.payload)?,
+                };
+                spawn(&state, &client, message, delivery, in_flight.clone());
+            }
+            _ = SHUTDOWN.triggered() => break,
+        }
+    }
+
+    // Take no more orders and give the ones in flight the drain timeout.
+    client.unsubscribe(SID).await?;
+    drop(in_flight);
+    if tokio::time::timeout(SHUTDOWN.drain_timeout, drained.recv())
+        .await
+        .is_err()
+    {
+        warn!("drain timeout exceeded, dropping in-flight orders");
+    }
+    Ok(())
+}
+
+/// The next dead letter re-driven from the admin endpoints; never, without a
+/// dead-letter log.
+async fn next_redriven(redriven: &mut Option<mpsc::Receiver<DeadLetter>>) -> Option<DeadLetter> {
+    match redriven {
+        Some(redriven) => redriven.recv().await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Prices the order of `message` in its own task, publishes the result and
+/// acknowledges the message if it must be: `-NAK`, for the broker to redeliver
+/// it, if the result couldn't be published.
+fn spawn(
+    state: &AppState,
+    client: &Client,
+    message: Message,
+    delivery: Delivery,
+    in_flight: mpsc::Sender<()>,
+) {
+    let client = client.clone();
+    let state = state.clone();
+    tokio::spawn(async move {
+        let _in_flight = in_flight;
+        let reply_to = &delivery.reply_to;
+        let reply = match &delivery.message_id {
+            Some(message_id) => {
+                match handle_once(&state, &client, message, reply_to, message_id).await {
+                    Some(reply) => reply,
+                    // Another delivery of the message is being handled.
+                    None => return,
+                }
+            }
+            None => handle(&state, &client, message, reply_to).await.1,
+        };
+        let published = client.publish(reply_to, &reply).await;
+        if let Err(err) = &published {
+            warn!(error = %err, %reply_to, "cannot publish result");
+        }
+        if let Some(ack) = &delivery.ack {
+            let ack_payload: &[u8] = if published.is_ok() { b"+ACK" } else { b"-NAK" };
+            if let Err(err) = client.publish(ack, ack_payload).await {
+                warn!(error = %err, %ack, "cannot acknowledge order");
+            }
+        }
+    });
+}
+
+/// Handles a message as `handle` does, unless a message with the same id was
+/// handled within the idempotency TTL, whose result it gets. `None` while
+/// another delivery of the message is being handled, for the broker to
+/// redeliver it later.
+async fn handle_once(
+    state: &AppState,
+    client: &Client,
+    message: Message,
+    reply_to: &str,
+    message_id: &str,
+) -> Option<Vec<u8>> {
+    let key = format!("queue:{}", message_id);
+    let payload = message.payload.clone();
+    let handled = state
+        .idempotency
+        .serve(&key, &payload, async {
+            let (status, reply) = handle(state, client, message, reply_to).await;
+            let mut response = Response::new(Body::from(reply));
+            *response.status_mut() = status;
+            Ok(response)
+        })
+        .await;
+    match handled {
+        Ok(response) => {
+            if response.headers().contains_key(REPLAYED_HEADER) {
+                info!(message_id, "redelivered order, result replayed");
+            }
+            let reply = hyper::body::to_bytes(response.into_body()).await.ok()?;
+            Some(reply.to_vec())
+        }
+        Err(AppError::IdempotencyKeyInUse(_)) => {
+            info!(message_id, "order redelivered while being priced");
+            None
+        }
+        Err(err) => {
+            warn!(code = err.code(), error = %err, message_id, "order failed");
+            let reply = Reply::Error {
+                error: err.envelope(),
+            };
+            Some(serde_json::to_vec(&reply).unwrap())
+        }
+    }
+}
+
+/// Prices the order of one message, each attempt within the request timeout,
+/// dead-letters it if every attempt failed with a retryable status, and
+/// returns the status of the outcome and the serialized reply.
+async fn handle(
+    state: &AppState,
+    client: &Client,
+    message: Message,
+    reply_to: &str,
+) -> (StatusCode, Vec<u8>) {
+    let retry = RetryPolicy::new(&state.config.queue.retry);
+    let retryable = |priced: &Result<Order, AppError>| match priced {
+        Ok(_) => false,
+        Err(err) => retry.retryable_statuses.contains(&err.status().as_u16()),
+    };
+    let request_id = Uuid::new_v4().to_string();
+    let span = tracing::info_span!(
+        "message",
+        request_id = %request_id,
+        subject = %message.subject
+    );
+    let (priced, attempts) = request_id::scope(request_id, async {
+        let mut attempt = 1;
+        loop {
+            let priced = deadline::within(request_timeout(), price(state, &message.payload)).await;
+            match &priced {
+                Ok(order) => info!(order_id = order.order_id, attempt, "order priced"),
+                Err(err) => warn!(code = err.code(), error = %err, attempt, "order failed"),
+            }
+            if !retryable(&priced) || attempt >= retry.max_attempts {
+                return (priced, attempt);
+            }
+            tokio::time::sleep(retry.delay_for(attempt)).await;
+            attempt += 1;
+        }
+    })
+    .instrument(span)
+    .await;
+    if let Err(err) = &priced {
+        if retryable(&priced) {
+            dead_letter(state, client, message, reply_to, attempts, err).await;
+        }
+    }
+    let (status, reply) = match priced {
+        Ok(order) => (
+            StatusCode::OK,
+            Reply::Ok {
+                order: Box::new(order),
+            },
+        ),
+        Err(err) => (
+            err.status(),
+            Reply::Error {
+                error: err.envelope(),
+            },
+        ),
+    };
+    // Serializing an order or an error envelope cannot fail.
+    (status, serde_json::to_vec(&reply).unwrap())
+}
+
+/// Keeps the message that failed every attempt in the dead-letter log and
+/// publishes it to the dead-letter subject, with why and when.
+async fn dead_letter(
+    state: &AppState,
+    client: &Client,
+    message: Message,
+    reply_to: &str,
+    attempts: u32,
+    err: &AppError,
+) {
+    let config = &state.config.queue;
+    let letter = DeadLetter {
+        id: Uuid::new_v4().to_string(),
+        payload: serde_json::from_slice(&message.payload).unwrap_or_else(|_| {
+            Value::String(String::from_utf8_lossy(&message.payload).into_owned())
+        }),
+        subject: message.subject,
+        reply_to: reply_to.to_string(),
+        attempts,
+        error: err.envelope(),
+        failed_at: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+    };
+    warn!(
+        dead_letter_id = %letter.id,
+        attempts,
+        subject = %config.dead_letter_subject,
+        "order dead-lettered"
+    );
+    // Serializing a dead letter cannot fail.
+    let payload = serde_json::to_vec(&letter).unwrap();
+    if let Some(dead_letters) = &state.dead_letters {
+        dead_letters.push(letter);
+    }
+    if let Err(err) = client.publish(&config.dead_letter_subject, &payload).await {
+        warn!(
+            error = %err,
+            subject = %config.dead_letter_subject,
+            "cannot publish dead letter"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use hyper::{Body, Request};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::auth;
+use crate::config::RateLimitConfig;
+use crate::error::AppError;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// The limits of the configuration.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// Tokens added per second; 0 turns rate limiting off.
+    rate: f64,
+    burst: f64,
+    max_clients: usize,
+    trust_forwarded_for: bool,
+}
+
+impl Limits {
+    fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            rate: config.requests_per_second.max(0.0),
+            burst: f64::from(config.burst.max(1)),
+            max_clients: config.max_clients.max(1),
+            trust_forwarded_for: config.trust_forwarded_for,
+        }
+    }
+}
+
+/// A token bucket per client, keyed by API key or token when the request
+/// carries one and by client IP otherwise, so one client sending too many
+/// requests can't starve the others or the sales tax rate service.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RwLock<Limits>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            limits: RwLock::new(Limits::new(config)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Applies the limits of `config` from the next request on. Clients keep
+    /// their tokens, up to the new burst.
+    pub fn reconfigure(&self, config: &RateLimitConfig) {
+        *self.limits.write().unwrap() = Limits::new(config);
+    }
+
+    /// Takes a token for the client of `req`, or fails with how long it should
+    /// wait before its next request.
+    pub fn check(&self, req: &Request<Body>) -> Result<(), AppError> {
+        let limits = *self.limits.read().unwrap();
+        if limits.rate == 0.0 {
+            return Ok(());
+        }
+        self.acquire(&limits, &self.client(&limits, req), Instant::now())
+            .map_err(AppError::RateLimited)
+    }
+
+    /// Takes a token for `key`, such as a tenant, rather than for the client
+    /// of a request.
+    pub fn check_key(&self, key: &str) -> Result<(), AppError> {
+        let limits = *self.limits.read().unwrap();
+        if limits.rate == 0.0 {
+            return Ok(());
+        }
+        self.acquire(&limits, key, Instant::now())
+            .map_err(AppError::RateLimited)
+    }
+
+    fn acquire(&self, limits: &Limits, client: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(client) && buckets.len() >= limits.max_clients {
+            self.evict(limits, &mut buckets, now);
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: limits.burst,
+            updated_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * limits.rate).min(limits.burst);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / limits.rate))
+        }
+    }
+
+    /// Forgets the clients whose bucket has refilled, as a new bucket would be
+    /// the same, or else the least recently seen one.
+    fn evict(&self, limits: &Limits, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        let refill = Duration::from_secs_f64(limits.burst / limits.rate);
+        buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated_at) < refill);
+        if buckets.len() >= limits.max_clients {
+            if let Some(oldest) = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.updated_at)
+                .map(|(client, _)| client.clone())
+            {
+                buckets.remove(&oldest);
+            }
+        }
+    }
+
+    /// The credential of the request, or else the client IP: the first
+    /// `X-Forwarded-For` address when the service runs behind a trusted proxy,
+    /// the peer address otherwise.
+    fn client(&self, limits: &Limits, req: &Request<Body>) -> String {
+        if let Some(credential) = auth::credential(req.headers()) {
+            return format!("key:{}", credential);
+        }
+        let forwarded = req
+            .headers()
+            .get("x-forwarded-for")
+            .filter(|_| limits.trust_forwarded_for)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(|ip| ip.trim().to_string());
+        let ip = forwarded.or_else(|| {
+            req.extensions()
+                .get::<SocketAddr>()
+                .map(|addr| addr.ip().to_string())
+        });
+        format!("ip:{}", ip.unwrap_or_default())
+    }
+}
@@ -0,0 +1,208 @@
+//! Rate overrides: rates set by operators for zip codes, in `rates.overrides`
+//! or with the admin API, charged instead of the rate provider's, e.g. to
+//! correct a wrong rate until the provider is fixed, or to try a rate out. An
+//! override applies from and until the dates it names, both inclusive and in
+//! UTC, or always; orders priced as of a past day get the overrides of that
+//! day. Orders priced at an override carry `"rate_source": "override"`.
+//!
+//! Overrides added with the admin API last until the next restart and take
+//! precedence over those of the configuration; among either, the last one
+//! added for a zip code wins.
+
+use anyhow::bail;
+use domain::{money, Decimal};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::postal::{self, PostalCode};
+use crate::reports;
+use crate::validation::FieldError;
+
+/// A rate charged for a zip code instead of the rate provider's.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RateOverride {
+    /// 5-digit zip code; ZIP+4 codes share the override of their 5-digit
+    /// part.
+    pub zip: String,
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub rate: Decimal,
+    /// First day the override applies, `YYYY-MM-DD`; any day before `until`
+    /// when unset.
+    #[serde(default)]
+    pub from: Option<String>,
+    /// Last day the override applies, `YYYY-MM-DD`; any day after `from`
+    /// when unset.
+    #[serde(default)]
+    pub until: Option<String>,
+    /// Why the rate is overridden, e.g. a ticket number.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// An override, as listed by `GET /admin/rate_overrides`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RateOverrideEntry {
+    /// Id of an override added with the admin API, to delete it by; unset
+    /// for those of `rates.overrides`.
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub rate_override: RateOverride,
+    /// Whether it applies today.
+    pub active: bool,
+}
+
+/// The overrides of the configuration and those added since.
+pub struct RateOverrides {
+    configured: Mutex<Vec<RateOverride>>,
+    /// With their ids, oldest first.
+    added: Mutex<Vec<(String, RateOverride)>>,
+}
+
+impl RateOverrides {
+    /// The overrides of `rates.overrides`. Fails on an invalid one.
+    pub fn from_config(overrides: &[RateOverride]) -> anyhow::Result<Self> {
+        check_all(overrides)?;
+        Ok(Self {
+            configured: Mutex::new(overrides.to_vec()),
+            added: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Replaces the overrides of the configuration, on reload, keeping those
+    /// added with the admin API. Fails on an invalid one, keeping the
+    /// previous ones.
+    pub fn reconfigure(&self, overrides: &[RateOverride]) -> anyhow::Result<()> {
+        check_all(overrides)?;
+        *self.configured.lock().unwrap() = overrides.to_vec();
+        Ok(())
+    }
+
+    /// The rate of `zip`, a 5-digit zip code, when an override applies to it
+    /// on `day`, `YYYY-MM-DD`.
+    pub fn get(&self, zip: &str, day: &str) -> Option<Decimal> {
+        let applies = |rate_override: &&RateOverride| {
+            rate_override.zip == zip && is_active(rate_override, day)
+        };
+        let added = self.added.lock().unwrap();
+        if let Some((_, rate_override)) = added.iter().rev().find(|(_, o)| applies(&o)) {
+            return Some(rate_override.rate);
+        }
+        let configured = self.configured.lock().unwrap();
+        configured.iter().rev().find(applies).map(|o| o.rate)
+    }
+
+    /// Every override, whether it applies today or not, those added with the
+    /// admin API first.
+    pub fn list(&self) -> Vec<RateOverrideEntry> {
+        let today = today();
+        let added = self.added.lock().unwrap();
+        let configured = self.configured.lock().unwrap();
+        added
+            .iter()
+            .map(|(id, rate_override)| (Some(id.clone()), rate_override))
+            .chain(configured.iter().map(|rate_override| (None, rate_override)))
+            .map(|(id, rate_override)| RateOverrideEntry {
+                id,
+                active: is_active(rate_override, &today),
+                rate_override: rate_override.clone(),
+            })
+            .collect()
+    }
+
+    /// Adds `rate_override` until the next restart.
+    pub fn add(&self, rate_override: RateOverride) -> Result<RateOverrideEntry, AppError> {
+        let errors = check(&rate_override);
+        if !errors.is_empty() {
+            return Err(AppError::Validation(errors));
+        }
+        let id = Uuid::new_v4().to_string();
+        self.added
+            .lock()
+            .unwrap()
+            .push((id.clone(), rate_override.clone()));
+        Ok(RateOverrideEntry {
+            id: Some(id),
+            active: is_active(&rate_override, &today()),
+            rate_override,
+        })
+    }
+
+    /// Removes the override added with the id `id`.
+    pub fn remove(&self, id: &str) -> Result<RateOverride, AppError> {
+        let mut added = self.added.lock().unwrap();
+        let position = added
+            .iter()
+            .position(|(added_id, _)| added_id == id)
+            .ok_or_else(|| AppError::RateOverrideNotFound(id.to_string()))?;
+        Ok(added.remove(position).1)
+    }
+}
+
+fn check_all(overrides: &[RateOverride]) -> anyhow::Result<()> {
+    for (index, rate_override) in overrides.iter().enumerate() {
+        if let Some(error) = check(rate_override).first() {
+            bail!(
+                "rates.overrides[{}] ({}): {} {}",
+                index,
+                rate_override.zip,
+                error.field,
+                error.message
+            );
+        }
+    }
+    Ok(())
+}
+
+fn check(rate_override: &RateOverride) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let mut error = |field: &str, message| {
+        errors.push(FieldError {
+            field: field.to_string(),
+            message,
+        })
+    };
+    if !matches!(postal::parse(&rate_override.zip), PostalCode::Us(zip) if zip == rate_override.zip)
+    {
+        error("zip", "must be a 5-digit zip code");
+    }
+    if rate_override.rate < Decimal::ZERO || rate_override.rate >= Decimal::ONE {
+        error("rate", "must be at least 0 and less than 1");
+    }
+    for (field, date) in [
+        ("from", &rate_override.from),
+        ("until", &rate_override.until),
+    ] {
+        if matches!(date, Some(date) if !is_date(date)) {
+            error(field, "must be a date, YYYY-MM-DD");
+        }
+    }
+    if let (Some(from), Some(until)) = (&rate_override.from, &rate_override.until) {
+        if is_date(from) && is_date(until) && until < from {
+            error("until", "must not be before from");
+        }
+    }
+    errors
+}
+
+/// Whether `date` is a `YYYY-MM-DD` date, which compare as text.
+fn is_date(date: &str) -> bool {
+    reports::parse_date(date).is_ok()
+}
+
+fn today() -> String {
+    reports::format_date(SystemTime::now())
+}
+
+/// Whether `rate_override` applies on `day`, `YYYY-MM-DD`.
+fn is_active(rate_override: &RateOverride, day: &str) -> bool {
+    rate_override.from.as_deref().is_none_or(|from| from <= day)
+        && rate_override
+            .until
+            .as_deref()
+            .is_none_or(|until| day <= until)
+}
@@ -0,0 +1,438 @@
+//! Where sales tax rates come from: the sales tax rate service, a rate table
+//! file, or a fixed table in the configuration.
+
+use anyhow::{bail, Context};
+use domain::{Decimal, ErrorResponse, RateComponent, RateQuote, RateRequest};
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::header::ACCEPT;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::as_of;
+use crate::balancer::Balancer;
+use crate::chaos::{self, Fault, FaultInjector};
+use crate::config::{FallbackConfig, RateProviderKind, RatesConfig, RetryConfig, UpstreamConfig};
+use crate::deadline;
+use crate::discovery::Discovery;
+use crate::error::AppError;
+use crate::grpc_rates::{GrpcProvider, GRPC_SCHEME};
+use crate::hedging::Hedging;
+use crate::metrics::Metrics;
+use crate::request_id;
+use crate::retry::RetryPolicy;
+use crate::telemetry::{self, Span, SpanKind};
+
+/// A sales tax rate, and its components by jurisdiction when the provider
+/// breaks it down.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub rate: Decimal,
+    /// Empty, or adding up to `rate`.
+    pub components: Vec<RateComponent>,
+}
+
+impl Quote {
+    /// A rate without a breakdown.
+    pub fn new(rate: Decimal) -> Self {
+        Self {
+            rate,
+            components: Vec::new(),
+        }
+    }
+
+    /// A rate with its breakdown, which is dropped unless it adds up to the
+    /// rate, so the tax of every component can be reconciled with the total.
+    pub fn with_components(rate: Decimal, components: Vec<RateComponent>) -> Self {
+        let sum: Decimal = components.iter().map(|component| component.rate).sum();
+        if !components.is_empty() && sum != rate {
+            warn!(rate = %rate, sum = %sum, "rate components don't add up to the rate, ignored");
+            return Self::new(rate);
+        }
+        Self { rate, components }
+    }
+}
+
+/// Looks up the sales tax rate of a zip code. Caching and the circuit breaker
+/// are left to the caller.
+pub trait TaxRateProvider: Send + Sync {
+    /// The rate of `zip`, or `RateNotFound`.
+    fn rate<'a>(&'a self, zip: &'a str) -> BoxFuture<'a, Result<Quote, AppError>>;
+
+    /// Whether rates can be looked up at all, for `/readyz`.
+    fn is_ready(&self, timeout: Duration) -> BoxFuture<'_, bool>;
+
+    /// Applies the settings of a reloaded `upstream` section that take
+    /// effect without a restart.
+    fn reconfigure(&self, _upstream: &UpstreamConfig) {}
+}
+
+/// The provider selected by the configuration. The sales tax rate service,
+/// over gRPC for `grpc://` URLs and over HTTP with `client` otherwise, is
+/// discovered and balanced over as configured, retried by `retry` and hedged
+/// by `hedging`, its calls counted in `metrics` and faulted by `faults`, when
+/// set.
+pub fn from_config(
+    config: &RatesConfig,
+    upstream: &UpstreamConfig,
+    retry: &RetryConfig,
+    hedging: Option<Hedging>,
+    client: reqwest::Client,
+    metrics: Arc<Metrics>,
+    faults: Option<Arc<FaultInjector>>,
+) -> anyhow::Result<Box<dyn TaxRateProvider>> {
+    Ok(match config.provider {
+        RateProviderKind::Http => {
+            let balancer = Arc::new(Balancer::new(&upstream.load_balancer, &metrics));
+            let discovery = Discovery::from_config(upstream, client.clone(), balancer.clone())?;
+            if discovery.is_none() {
+                balancer.set_endpoints(upstream.urls());
+            }
+            let calls = UpstreamCalls {
+                balancer,
+                discovery,
+                retry: RetryPolicy::new(retry),
+                hedging,
+                metrics,
+                faults,
+            };
+            if upstream.url.trim_start().starts_with(GRPC_SCHEME) {
+                if let Some(url) = upstream
+                    .urls()
+                    .into_iter()
+                    .find(|url| !url.starts_with(GRPC_SCHEME))
+                {
+                    bail!("upstream.url mixes grpc:// URLs with {}", url);
+                }
+                return Ok(Box::new(GrpcProvider::new(upstream, calls)));
+            }
+            Box::new(HttpProvider::new(upstream, calls, client))
+        }
+        RateProviderKind::File => Box::new(TableProvider::load(Path::new(&config.path))?),
+        RateProviderKind::Memory => Box::new(TableProvider::new(config.table.clone())),
+    })
+}
+
+/// The table rates fall back to while the rate provider is unavailable, when
+/// enabled: the file of `rates.fallback.path`, or else the embedded table.
+pub fn fallback(config: &FallbackConfig) -> anyhow::Result<Option<TableProvider>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    Ok(Some(match &config.path {
+        Some(path) => TableProvider::load(Path::new(path))?,
+        None => TableProvider::embedded(),
+    }))
+}
+
+/// How the sales tax rate service is called: at the endpoint the balancer
+/// picks, among the discovered instances when discovery is on, retried and
+/// hedged, counted in the metrics and faulted when chaos is on.
+pub struct UpstreamCalls {
+    pub balancer: Arc<Balancer>,
+    pub discovery: Option<Arc<Discovery>>,
+    pub retry: RetryPolicy,
+    pub hedging: Option<Hedging>,
+    pub metrics: Arc<Metrics>,
+    pub faults: Option<Arc<FaultInjector>>,
+}
+
+/// The sales tax rate service, at the URLs of `upstream.url` or at its
+/// discovered instances.
+pub struct HttpProvider {
+    url: String,
+    balancer: Arc<Balancer>,
+    discovery: Option<Arc<Discovery>>,
+    timeout_ms: AtomicU64,
+    retry: RetryPolicy,
+    hedging: Option<Hedging>,
+    client: reqwest::Client,
+    metrics: Arc<Metrics>,
+    faults: Option<Arc<FaultInjector>>,
+}
+
+impl HttpProvider {
+    pub fn new(config: &UpstreamConfig, calls: UpstreamCalls, client: reqwest::Client) -> Self {
+        Self {
+            url: config.url.clone(),
+            balancer: calls.balancer,
+            discovery: calls.discovery,
+            timeout_ms: AtomicU64::new(config.timeout_ms),
+            retry: calls.retry,
+            hedging: calls.hedging,
+            client,
+            metrics: calls.metrics,
+            faults: calls.faults,
+        }
+    }
+
+    /// Time allowed for each call.
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.load(Ordering::Relaxed))
+    }
+
+    async fn call(&self, zip: &str) -> Result<Quote, AppError> {
+        deadline::check()?;
+        if let Some(discovery) = &self.discovery {
+            discovery.ready().await?;
+        }
+        let response = self
+            .retry
+            .run(|| async {
+                match &self.hedging {
+                    Some(hedging) => hedging.run(&self.metrics, || self.attempt(zip)).await,
+                    None => self.attempt(zip).await,
+                }
+            })
+            .await
+            .map_err(|err| {
+                if err.is_timeout() {
+                    // Cut short by the deadline rather than the upstream
+                    // timeout, the call tells nothing of the service.
+                    deadline::check()
+                        .err()
+                        .unwrap_or(AppError::UpstreamTimeout(self.timeout()))
+                } else {
+                    AppError::UpstreamUnavailable(err.to_string())
+                }
+            })?;
+        match response.status().as_u16() {
+            200 => {
+                let text = response
+                    .text()
+                    .await
+                    .map_err(|err| AppError::UpstreamUnavailable(err.to_string()))?;
+                serde_json::from_str::<RateQuote>(&text)
+                    .map(|quote| Quote::with_components(quote.rate, quote.components))
+                    .map_err(|_| {
+                        AppError::UpstreamUnavailable(format!(
+                            "invalid rate in response: {:?}",
+                            text
+                        ))
+                    })
+            }
+            // A 404 for anything but the zip code, e.g. a wrong `upstream.url`,
+            // is a misconfigured service rather than a missing rate.
+            404 => match response.json::<ErrorResponse>().await {
+                Ok(body) if body.error.code != "RATE_NOT_FOUND" => {
+                    Err(AppError::UpstreamUnavailable(format!(
+                        "{}: {}",
+                        body.error.code, body.error.message
+                    )))
+                }
+                _ => Err(AppError::RateNotFound(zip.to_string())),
+            },
+            status => Err(AppError::UpstreamUnavailable(format!(
+                "unexpected status {}",
+                status
+            ))),
+        }
+    }
+
+    /// One call, to the endpoint picked by the load balancer, traced and
+    /// counted.
+    async fn attempt(&self, zip: &str) -> reqwest::Result<reqwest::Response> {
+        let start = Instant::now();
+        let lease = self.balancer.pick();
+        let url = lease
+            .as_ref()
+            .map_or(self.url.as_str(), |lease| lease.url());
+        let mut span = Span::start_child("POST find_rate", SpanKind::Client);
+        span.set_attribute("http.method", "POST");
+        span.set_attribute("http.url", url);
+        let fault = self.faults.as_ref().and_then(|faults| faults.upstream());
+        if let Some(Fault::Delay(delay)) = fault {
+            tokio::time::sleep(delay).await;
+        }
+        let mut request = self
+            .client
+            .post(url)
+            .header(
+                telemetry::TRACEPARENT_HEADER,
+                span.context().to_traceparent(),
+            )
+            .header(ACCEPT, "application/json")
+            .json(&RateRequest {
+                zip: zip.to_string(),
+                as_of: as_of::current(),
+            });
+        // An injected delay counts against the timeout, and the service is
+        // told when the caller stops waiting. The deadline is read last, so
+        // a call it cuts short ends past it.
+        let timeout = self.timeout().saturating_sub(start.elapsed());
+        let timeout = deadline::remaining().map_or(timeout, |left| left.min(timeout));
+        request = request.timeout(timeout).header(
+            deadline::REQUEST_DEADLINE_HEADER,
+            deadline::request_deadline(timeout),
+        );
+        if let Some(request_id) = request_id::current() {
+            request = request.header(request_id::REQUEST_ID_HEADER, request_id);
+        }
+        let result = match fault {
+            Some(Fault::Fail) => Ok(chaos::failed_upstream_response()),
+            _ => request.send().await,
+        };
+        match &result {
+            Ok(response) => {
+                span.set_attribute("http.status_code", response.status().as_u16());
+                if response.status().is_server_error() {
+                    span.set_error();
+                }
+            }
+            Err(_) => span.set_error(),
+        }
+        span.end();
+        let outcome = match &result {
+            Ok(response) if response.status().is_server_error() => {
+                warn!(
+                    status = response.status().as_u16(),
+                    "sales tax rate service error"
+                );
+                "failure"
+            }
+            Ok(_) => "success",
+            Err(err) => {
+                warn!(error = %err, "sales tax rate service unreachable");
+                "failure"
+            }
+        };
+        if let Some(lease) = &lease {
+            self.balancer.record(lease, outcome == "success");
+        }
+        self.metrics
+            .upstream_requests
+            .with_label_values(&[outcome])
+            .inc();
+        self.metrics
+            .upstream_request_duration
+            .with_label_values(&[outcome])
+            .observe(start.elapsed().as_secs_f64());
+        if let (Some(hedging), "success") = (&self.hedging, outcome) {
+            hedging.record(start.elapsed());
+        }
+        result
+    }
+
+    /// Any answer that isn't a server error shows the service is up; a GET on
+    /// the lookup route itself is expected to come back as 404. With several
+    /// endpoints, one answering is enough.
+    async fn reachable(&self, timeout: Duration) -> bool {
+        if let Some(discovery) = &self.discovery {
+            if discovery.ready().await.is_err() {
+                return false;
+            }
+        }
+        for url in self.balancer.urls() {
+            match self.client.get(&url).timeout(timeout).send().await {
+                Ok(response) if !response.status().is_server_error() => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
+impl TaxRateProvider for HttpProvider {
+    fn rate<'a>(&'a self, zip: &'a str) -> BoxFuture<'a, Result<Quote, AppError>> {
+        self.call(zip).boxed()
+    }
+
+    fn is_ready(&self, timeout: Duration) -> BoxFuture<'_, bool> {
+        self.reachable(timeout).boxed()
+    }
+
+    /// The timeout, and the endpoints unless they are discovered.
+    fn reconfigure(&self, upstream: &UpstreamConfig) {
+        self.timeout_ms
+            .store(upstream.timeout_ms, Ordering::Relaxed);
+        if self.discovery.is_none() {
+            self.balancer.set_endpoints(upstream.urls());
+        }
+    }
+}
+
+/// A fixed table of rates by zip code, read from a file or given in the
+/// configuration, so orders can be priced without the sales tax rate service,
+/// e.g. offline or in tests. ZIP+4 codes use the rate of their first part.
+#[derive(Debug, Default)]
+pub struct TableProvider {
+    rates: HashMap<String, Decimal>,
+}
+
+/// A copy of `sales_tax_rate/src/rates_by_zipcode.csv`.
+const EMBEDDED_RATES: &str = include_str!("fallback_rates.csv");
+
+/// A row of a CSV rate table; further columns, such as the jurisdiction of
+/// the sales tax rate service's table, are ignored.
+#[derive(Debug, Deserialize)]
+struct RateRow {
+    zip: String,
+    rate: Decimal,
+}
+
+impl TableProvider {
+    pub fn new(rates: HashMap<String, Decimal>) -> Self {
+        Self {
+            rates: rates
+                .into_iter()
+                .map(|(zip, rate)| (zip.trim().to_string(), rate))
+                .collect(),
+        }
+    }
+
+    /// Reads a CSV table with `zip` and `rate` columns, or a JSON object of
+    /// rates by zip code, e.g. `{"78701": 0.0825}`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let context = || format!("cannot read the rate table {}", path.display());
+        let file = File::open(path).with_context(context)?;
+        let rates = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => read_csv(file).with_context(context)?,
+            Some("json") => serde_json::from_reader(BufReader::new(file)).with_context(context)?,
+            _ => bail!(
+                "the rate table {} is neither a .csv nor a .json file",
+                path.display()
+            ),
+        };
+        Ok(Self::new(rates))
+    }
+
+    /// The table compiled into the service.
+    pub fn embedded() -> Self {
+        Self::new(read_csv(EMBEDDED_RATES.as_bytes()).expect("the embedded rate table is valid"))
+    }
+
+    pub fn get(&self, zip: &str) -> Option<Decimal> {
+        let zip = zip.trim();
+        self.rates
+            .get(zip)
+            .or_else(|| self.rates.get(zip.split('-').next().unwrap_or_default()))
+            .copied()
+    }
+}
+
+fn read_csv(reader: impl Read) -> csv::Result<HashMap<String, Decimal>> {
+    csv::Reader::from_reader(reader)
+        .deserialize::<RateRow>()
+        .map(|row| row.map(|row| (row.zip, row.rate)))
+        .collect()
+}
+
+impl TaxRateProvider for TableProvider {
+    fn rate<'a>(&'a self, zip: &'a str) -> BoxFuture<'a, Result<Quote, AppError>> {
+        let rate = self
+            .get(zip)
+            .map(Quote::new)
+            .ok_or_else(|| AppError::RateNotFound(zip.to_string()));
+        futures::future::ready(rate).boxed()
+    }
+
+    fn is_ready(&self, _timeout: Duration) -> BoxFuture<'_, bool> {
+        futures::future::ready(true).boxed()
+    }
+}
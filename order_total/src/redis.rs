@@ -0,0 +1,170 @@
+//! A minimal Redis client speaking RESP over plain TCP connections, for the
+//! same reason as the NATS client: the redis crate's networking doesn't run
+//! on WasmEdge. It sends one command at a time on a connection taken from a
+//! small pool, and knows the few replies the shared cache needs.
+
+use anyhow::{anyhow, bail, Context};
+use reqwest::Url;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::info;
+
+/// A reply of the server; errors are returned as `Err`.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Status(String),
+    Integer(i64),
+    /// `None` for the nil reply, e.g. of `GET` on a missing key.
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+struct Connection {
+    stream: BufReader<TcpStream>,
+}
+
+/// Connections to one Redis server, kept open between commands.
+pub struct Pool {
+    address: String,
+    username: Option<String>,
+    password: Option<String>,
+    db: u32,
+    timeout: Duration,
+    /// Idle connections kept; more are opened as needed and closed after use.
+    max_idle: usize,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl Pool {
+    /// A pool for `url`, `redis://[[user]:password@]host[:port][/db]`. No
+    /// connection is opened until the first command.
+    pub fn new(url: &str, max_idle: usize, timeout: Duration) -> anyhow::Result<Self> {
+        let parsed = Url::parse(url).with_context(|| format!("invalid Redis URL {}", url))?;
+        anyhow::ensure!(parsed.scheme() == "redis", "Redis URLs start with redis://");
+        let host = parsed.host_str().context("the Redis URL has no host")?;
+        let db = match parsed.path().trim_start_matches('/') {
+            "" => 0,
+            db => db
+                .parse()
+                .context("the database of the Redis URL isn't a number")?,
+        };
+        Ok(Self {
+            address: format!("{}:{}", host, parsed.port().unwrap_or(6379)),
+            username: Some(parsed.username().to_string()).filter(|user| !user.is_empty()),
+            password: parsed.password().map(str::to_string),
+            db,
+            timeout,
+            max_idle,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Sends `args` as one command and reads its reply, within the timeout.
+    /// A connection that fails is closed rather than reused.
+    pub async fn command(&self, args: &[&[u8]]) -> anyhow::Result<Reply> {
+        let call = async {
+            let idle = self.idle.lock().unwrap().pop();
+            let mut connection = match idle {
+                Some(connection) => connection,
+                None => self.connect().await?,
+            };
+            let reply = connection.call(args).await;
+            // A server error leaves the connection usable.
+            if reply.is_ok() || connection.is_in_sync() {
+                let mut idle = self.idle.lock().unwrap();
+                if idle.len() < self.max_idle {
+                    idle.push(connection);
+                }
+            }
+            reply
+        };
+        tokio::time::timeout(self.timeout, call)
+            .await
+            .map_err(|_| anyhow!("Redis didn't answer within {:?}", self.timeout))?
+    }
+
+    async fn connect(&self) -> anyhow::Result<Connection> {
+        let stream = TcpStream::connect(&self.address)
+            .await
+            .with_context(|| format!("cannot connect to Redis at {}", self.address))?;
+        let mut connection = Connection {
+            stream: BufReader::new(stream),
+        };
+        if let Some(password) = &self.password {
+            let mut auth: Vec<&[u8]> = vec![b"AUTH"];
+            if let Some(username) = &self.username {
+                auth.push(username.as_bytes());
+            }
+            auth.push(password.as_bytes());
+            connection.call(&auth).await.context("Redis AUTH failed")?;
+        }
+        if self.db != 0 {
+            let db = self.db.to_string();
+            connection
+                .call(&[b"SELECT", db.as_bytes()])
+                .await
+                .context("Redis SELECT failed")?;
+        }
+        info!(address = %self.address, "connected to Redis");
+        Ok(connection)
+    }
+}
+
+impl Connection {
+    async fn call(&mut self, args: &[&[u8]]) -> anyhow::Result<Reply> {
+        let mut frame = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            frame.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            frame.extend_from_slice(arg);
+            frame.extend_from_slice(b"\r\n");
+        }
+        let stream = self.stream.get_mut();
+        stream.write_all(&frame).await?;
+        stream.flush().await?;
+        read_reply(&mut self.stream).await
+    }
+
+    /// Whether nothing is left unread, so the next reply is the next
+    /// command's.
+    fn is_in_sync(&self) -> bool {
+        self.stream.buffer().is_empty()
+    }
+}
+
+async fn read_reply(stream: &mut BufReader<TcpStream>) -> anyhow::Result<Reply> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        bail!("Redis closed the connection");
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, rest) = line.split_at(line.len().min(1));
+    match kind {
+        "+" => Ok(Reply::Status(rest.to_string())),
+        "-" => Err(anyhow!("Redis error: {}", rest)),
+        ":" => Ok(Reply::Integer(rest.parse()?)),
+        "$" => {
+            let size: i64 = rest.parse()?;
+            if size < 0 {
+                return Ok(Reply::Bulk(None));
+            }
+            let mut data = vec![0; size as usize + 2];
+            stream.read_exact(&mut data).await?;
+            data.truncate(size as usize);
+            Ok(Reply::Bulk(Some(data)))
+        }
+        "*" => {
+            let count: i64 = rest.parse()?;
+            if count < 0 {
+                return Ok(Reply::Array(None));
+            }
+            let mut items = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                items.push(Box::pin(read_reply(stream)).await?);
+            }
+            Ok(Reply::Array(Some(items)))
+        }
+        _ => bail!("unexpected Redis reply {:?}", line),
+    }
+}
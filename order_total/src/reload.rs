@@ -0,0 +1,201 @@
+//! Reloading the configuration while the service runs: when its file changes,
+//! with `reload.watch`, and on SIGHUP. The configuration is read again from
+//! all its layers, its settings of `RELOADABLE` applied to the dependencies
+//! keeping them and swapped into the live configuration; other changes are
+//! logged as waiting for a restart. Every reload is counted, and audited when
+//! the audit log is on.
+//!
+//! File watching crates rely on inotify and the like, which WasmEdge doesn't
+//! offer, hence the file's modification time is polled.
+
+use serde_json::Value;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::audit::ConfigReloadRecord;
+use crate::config::{AppConfig, Cli, DiscoveryMode};
+use crate::logging;
+use crate::state::AppState;
+
+/// The settings, or sections, that take effect without a restart; the
+/// others wait for one. `upstream.url` only while its URLs are called as they
+/// are, not discovered. Kept in line with `reloaded`.
+pub const RELOADABLE: &[&str] = &[
+    "log_level",
+    "server.request_timeout_ms",
+    "upstream.url",
+    "upstream.timeout_ms",
+    "cache.ttl_secs",
+    "cache.stale_secs",
+    "rates.overrides",
+    "rate_limit",
+    "reload",
+];
+
+/// Reloads the configuration of `cli` into `state` whenever its file
+/// changes, while `reload.watch` is on.
+pub async fn watch(cli: Cli, state: AppState) {
+    let file = match cli.config_file() {
+        Some(file) => file,
+        None => return,
+    };
+    let mut seen = version(&file);
+    loop {
+        let interval = AppConfig::current().reload.poll_interval_ms.max(10);
+        tokio::time::sleep(Duration::from_millis(interval)).await;
+        if !AppConfig::current().reload.watch {
+            continue;
+        }
+        // A file being replaced may be missing for a moment, and the
+        // defaults aren't what was meant.
+        let current = version(&file);
+        if current.is_some() && current != seen {
+            seen = current;
+            reload(&cli, &state, "file");
+        }
+    }
+}
+
+/// Reloads the configuration of `cli` into `state` on every SIGHUP.
+///
+/// WasmEdge doesn't forward signals to the guest, so under Wasm the file is
+/// watched instead, or the log level changed with `PUT /admin/log_level`.
+#[cfg(unix)]
+pub async fn reload_on_sighup(cli: Cli, state: AppState) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(_) => return,
+    };
+    while hangup.recv().await.is_some() {
+        reload(&cli, &state, "sighup");
+    }
+}
+
+/// The modification time and size of `file`, which change with its content.
+fn version(file: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(file).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Reads the configuration of `cli` again and applies what changed.
+fn reload(cli: &Cli, state: &AppState, trigger: &'static str) {
+    let live = AppConfig::current();
+    let loaded = match AppConfig::load(cli) {
+        Ok(loaded) => loaded,
+        Err(err) => return failed(state, trigger, err),
+    };
+    let changed = changed_keys(&live, &loaded);
+    if changed.is_empty() {
+        return;
+    }
+    let next = reloaded(&live, &loaded);
+    if let Err(err) = state.rate_overrides.reconfigure(&next.rates.overrides) {
+        return failed(state, trigger, err);
+    }
+    if next.log_level != live.log_level {
+        if let Err(err) = logging::set_level(&next.log_level) {
+            return failed(state, trigger, err);
+        }
+    }
+    state.rates.reconfigure(&next.upstream);
+    state.rate_cache.set_ttl(
+        Duration::from_secs(next.cache.ttl_secs),
+        Duration::from_secs(next.cache.stale_secs),
+    );
+    state.rate_limiter.reconfigure(&next.rate_limit);
+    let restart_required: Vec<String> = changed
+        .iter()
+        .filter(|key| !is_reloadable(key, &live))
+        .cloned()
+        .collect();
+    AppConfig::replace(next);
+
+    state
+        .metrics
+        .config_reloads
+        .with_label_values(&["success"])
+        .inc();
+    if let Some(audit) = &state.audit {
+        if let Err(err) = audit.record(&ConfigReloadRecord::new(&changed, &restart_required)) {
+            warn!(error = %err, "cannot write the audit log");
+        }
+    }
+    info!(trigger, changed = ?changed, "configuration reloaded");
+    if !restart_required.is_empty() {
+        warn!(keys = ?restart_required, "changed settings take effect on restart");
+    }
+}
+
+/// Counts and logs a reload that failed, leaving the configuration as it was.
+fn failed(state: &AppState, trigger: &'static str, err: anyhow::Error) {
+    state
+        .metrics
+        .config_reloads
+        .with_label_values(&["failure"])
+        .inc();
+    warn!(
+        trigger,
+        error = format!("{:#}", err),
+        "cannot reload the configuration"
+    );
+}
+
+/// `live` with the settings of `RELOADABLE` taken from `loaded`.
+fn reloaded(live: &AppConfig, loaded: &AppConfig) -> AppConfig {
+    let mut next = live.clone();
+    next.log_level = loaded.log_level.clone();
+    next.server.request_timeout_ms = loaded.server.request_timeout_ms;
+    if live.upstream.discovery.mode == DiscoveryMode::Static {
+        next.upstream.url = loaded.upstream.url.clone();
+    }
+    next.upstream.timeout_ms = loaded.upstream.timeout_ms;
+    next.cache.ttl_secs = loaded.cache.ttl_secs;
+    next.cache.stale_secs = loaded.cache.stale_secs;
+    next.rates.overrides = loaded.rates.overrides.clone();
+    next.rate_limit = loaded.rate_limit.clone();
+    next.reload = loaded.reload.clone();
+    next
+}
+
+fn is_reloadable(key: &str, live: &AppConfig) -> bool {
+    if key == "upstream.url" {
+        return live.upstream.discovery.mode == DiscoveryMode::Static;
+    }
+    RELOADABLE
+        .iter()
+        .any(|reloadable| key == *reloadable || key.starts_with(&format!("{}.", reloadable)))
+}
+
+/// The keys of the settings that differ between `old` and `new`, such as
+/// `cache.ttl_secs`.
+fn changed_keys(old: &AppConfig, new: &AppConfig) -> Vec<String> {
+    let old = serde_json::to_value(old).expect("the configuration serializes");
+    let new = serde_json::to_value(new).expect("the configuration serializes");
+    let mut keys = Vec::new();
+    diff("", &old, &new, &mut keys);
+    keys
+}
+
+fn diff(key: &str, old: &Value, new: &Value, keys: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old_fields), Value::Object(new_fields)) => {
+            let mut names: Vec<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+            names.sort();
+            names.dedup();
+            for name in names {
+                let field = match key {
+                    "" => name.clone(),
+                    _ => format!("{}.{}", key, name),
+                };
+                let old = old_fields.get(name).unwrap_or(&Value::Null);
+                let new = new_fields.get(name).unwrap_or(&Value::Null);
+                diff(&field, old, new, keys);
+            }
+        }
+        _ if old != new => keys.push(key.to_string()),
+        _ => (),
+    }
+}
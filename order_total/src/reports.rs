@@ -0,0 +1,112 @@
+//! Sales reports computed from the order store, whichever its backend: the
+//! file store of a deployment, or the memory store of the demo setup. The
+//! daily report at `GET /reports/daily` sums up the orders priced on a day,
+//! in total and by the state they ship to.
+
+use domain::money::{self, Decimal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+use utoipa::ToSchema;
+
+use crate::error::AppError;
+use crate::postal;
+use crate::store::{Cursor, OrderQuery, OrderRecord, OrderStore, SortDirection};
+
+/// Orders read from the store at a time.
+const PAGE_SIZE: usize = 500;
+
+/// The state of orders shipped outside the known US zip codes.
+const UNKNOWN_STATE: &str = "unknown";
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The orders priced on a day, cancelled ones aside.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DailyReport {
+    /// `YYYY-MM-DD`, in UTC.
+    #[schema(example = "2026-10-15")]
+    pub date: String,
+    #[serde(flatten)]
+    pub totals: Totals,
+    /// By the state of the shipping zip code, in alphabetical order.
+    pub by_state: Vec<StateTotals>,
+}
+
+/// Sums of the orders, in the base currency.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct Totals {
+    pub orders: usize,
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub subtotal: Decimal,
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub tax: Decimal,
+}
+
+impl Totals {
+    fn add(&mut self, record: &OrderRecord) {
+        self.orders += 1;
+        self.subtotal += record.order.subtotal.unwrap_or_default();
+        self.tax += record.order.tax;
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StateTotals {
+    /// Two-letter code, derived from the zip code, or `unknown`.
+    #[schema(example = "TX")]
+    pub state: &'static str,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+/// The start of the UTC day `date`, given as `YYYY-MM-DD`.
+pub fn parse_date(date: &str) -> Result<SystemTime, AppError> {
+    let invalid = || AppError::InvalidPayload(format!("invalid date: {:?}, not YYYY-MM-DD", date));
+    if date.len() != 10 {
+        return Err(invalid());
+    }
+    humantime::parse_rfc3339(&format!("{}T00:00:00Z", date)).map_err(|_| invalid())
+}
+
+/// `day` as `YYYY-MM-DD`.
+pub fn format_date(day: SystemTime) -> String {
+    humantime::format_rfc3339(day).to_string()[..10].to_string()
+}
+
+/// The report of the UTC day starting at `day`, read from `orders` a page at
+/// a time.
+pub fn daily(orders: &dyn OrderStore, day: SystemTime) -> anyhow::Result<DailyReport> {
+    let mut query = OrderQuery {
+        priced_from: Some(day),
+        priced_before: Some(day + DAY),
+        include_cancelled: false,
+        direction: SortDirection::Asc,
+        limit: PAGE_SIZE,
+        ..OrderQuery::default()
+    };
+    let mut totals = Totals::default();
+    let mut by_state: BTreeMap<&'static str, Totals> = BTreeMap::new();
+    loop {
+        let page = orders.list(&query)?;
+        for record in &page.orders {
+            totals.add(record);
+            let state = postal::state(&record.order.shipping_zip).unwrap_or(UNKNOWN_STATE);
+            by_state.entry(state).or_default().add(record);
+        }
+        match page.next_cursor.as_deref().and_then(Cursor::decode) {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    Ok(DailyReport {
+        date: format_date(day),
+        totals,
+        by_state: by_state
+            .into_iter()
+            .map(|(state, totals)| StateTotals { state, totals })
+            .collect(),
+    })
+}
@@ -0,0 +1,83 @@
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::config::RetryConfig;
+use crate::deadline;
+
+/// How failed outbound calls, to the sales tax rate service or to webhooks, are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for every following retry.
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_delay: Duration,
+    /// Fraction of the delay (0.0 - 1.0) added as random jitter.
+    pub jitter: f64,
+    /// Upstream status codes worth another attempt.
+    pub retryable_statuses: Vec<u16>,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &RetryConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts.max(1),
+            initial_delay: Duration::from_millis(config.initial_delay_ms),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+            jitter: config.jitter.clamp(0.0, 1.0),
+            retryable_statuses: config.retryable_statuses.clone(),
+        }
+    }
+
+    /// The delay to wait after the given (1-based) failed attempt.
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let delay = self
+            .initial_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let jitter = delay.mul_f64(self.jitter * rand::thread_rng().gen::<f64>());
+        delay + jitter
+    }
+
+    /// Runs `call` until it returns a non-retryable outcome or attempts run out,
+    /// sleeping with exponential backoff in between. Transport errors are always
+    /// retried; responses only when their status is listed as retryable. No
+    /// attempt is made past the deadline of the request being served.
+    pub async fn run<F, Fut, T>(&self, mut call: F) -> T
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = T>,
+        T: Retryable,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = call().await;
+            let retryable = result.is_retryable(&self.retryable_statuses);
+            let delay = self.delay_for(attempt);
+            let in_time = deadline::remaining().is_none_or(|left| delay < left);
+            if !retryable || attempt >= self.max_attempts || !in_time {
+                return result;
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// The outcome of an attempt, as far as retrying it goes.
+pub trait Retryable {
+    /// Worth another attempt, given the statuses worth one.
+    fn is_retryable(&self, retryable_statuses: &[u16]) -> bool;
+}
+
+impl Retryable for reqwest::Result<reqwest::Response> {
+    fn is_retryable(&self, retryable_statuses: &[u16]) -> bool {
+        match self {
+            Ok(response) => retryable_statuses.contains(&response.status().as_u16()),
+            Err(_) => true,
+        }
+    }
+}
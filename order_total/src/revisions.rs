@@ -0,0 +1,115 @@
+//! Revisions of a priced order. Each time its shipping address is changed
+//! with `PATCH /orders/{id}` the order is priced again, and the totals of
+//! every pricing are kept with the rate applied, so the record shows how its
+//! price moved. `GET /orders/{id}/history` lists them with what changed from
+//! one to the next.
+
+use domain::money::{self, Decimal};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::store::OrderRecord;
+
+/// One pricing of an order.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Revision {
+    /// 1 for the first pricing, counting up.
+    pub revision: u32,
+    /// RFC 3339 time of the pricing.
+    pub priced_at: String,
+    pub shipping_address: String,
+    pub shipping_zip: String,
+    /// The sales tax rate applied.
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub rate: Decimal,
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub tax: Decimal,
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub total: Decimal,
+}
+
+impl Revision {
+    /// The pricing `record` holds now, as revision `revision`.
+    pub fn new(revision: u32, record: &OrderRecord) -> Self {
+        Self {
+            revision,
+            priced_at: record.priced_at.clone(),
+            shipping_address: record.order.shipping_address.clone(),
+            shipping_zip: record.order.shipping_zip.clone(),
+            rate: record.rate,
+            tax: record.order.tax,
+            total: record.order.total,
+        }
+    }
+}
+
+/// The fields of a revision compared with the one before.
+const COMPARED_FIELDS: [&str; 5] = ["shipping_address", "shipping_zip", "rate", "tax", "total"];
+
+/// The revisions of an order, oldest first.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct History {
+    pub order_id: i32,
+    pub revisions: Vec<HistoryEntry>,
+}
+
+/// A revision and what changed since the one before.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    pub revision: Revision,
+    /// None for the first revision.
+    pub changes: Vec<FieldChange>,
+}
+
+/// A field whose value differs from the revision before.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldChange {
+    #[schema(example = "shipping_zip")]
+    pub field: String,
+    #[schema(value_type = Object)]
+    pub old: Value,
+    #[schema(value_type = Object)]
+    pub new: Value,
+}
+
+/// The history of `record`. An order never priced again has a single
+/// revision, its pricing.
+pub fn history(record: &OrderRecord) -> History {
+    let revisions = match record.revisions.is_empty() {
+        true => vec![Revision::new(1, record)],
+        false => record.revisions.clone(),
+    };
+    let mut entries: Vec<HistoryEntry> = Vec::with_capacity(revisions.len());
+    for revision in revisions {
+        let changes = match entries.last() {
+            Some(previous) => changes(&previous.revision, &revision),
+            None => Vec::new(),
+        };
+        entries.push(HistoryEntry { revision, changes });
+    }
+    History {
+        order_id: record.order.order_id,
+        revisions: entries,
+    }
+}
+
+/// The compared fields that differ between `old` and `new`, with their
+/// values as serialized.
+fn changes(old: &Revision, new: &Revision) -> Vec<FieldChange> {
+    let old = serde_json::to_value(old).expect("revisions serialize");
+    let new = serde_json::to_value(new).expect("revisions serialize");
+    COMPARED_FIELDS
+        .iter()
+        .filter(|field| old[**field] != new[**field])
+        .map(|field| FieldChange {
+            field: field.to_string(),
+            old: old[*field].clone(),
+            new: new[*field].clone(),
+        })
+        .collect()
+}
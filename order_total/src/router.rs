@@ -0,0 +1,159 @@
+//! Matching requests to their handler, by method and path pattern, such as
+//! `GET /orders/{id}`. `{name}` segments match any one path segment, which
+//! handlers read with `param`. Paths are matched without a trailing slash, so
+//! `/orders/` is `/orders`. Handlers are given the `AppState` along with the
+//! request.
+//!
+//! A path matching some route, but not with the request's method, answers
+//! `405 METHOD_NOT_ALLOWED` with the methods it has in `Allow`, and `OPTIONS`
+//! on it answers these methods too.
+
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderValue, ALLOW};
+use hyper::{Body, Method, Request, Response};
+use std::future::Future;
+use std::str::FromStr;
+
+use crate::error::AppError;
+use crate::state::AppState;
+
+type Handler = Box<
+    dyn Fn(AppState, Request<Body>) -> BoxFuture<'static, Result<Response<Body>, AppError>>
+        + Send
+        + Sync,
+>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    Literal(&'static str),
+    Param(&'static str),
+}
+
+struct Route {
+    method: Method,
+    pattern: &'static str,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+/// The path parameters of the route a request matched.
+#[derive(Debug, Clone, Default)]
+struct Params(Vec<(&'static str, String)>);
+
+/// Routes, matched in the order they were added.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves `method` requests to paths matching `pattern` with `handler`.
+    pub fn route<F, Fut>(mut self, method: Method, pattern: &'static str, handler: F) -> Self
+    where
+        F: Fn(AppState, Request<Body>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response<Body>, AppError>> + Send + 'static,
+    {
+        let segments = segments(pattern)
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|name| name.strip_suffix('}'))
+                {
+                    Some(name) => Segment::Param(name),
+                    None => Segment::Literal(segment),
+                }
+            })
+            .collect();
+        self.routes.push(Route {
+            method,
+            pattern,
+            segments,
+            handler: Box::new(move |state, req| handler(state, req).boxed()),
+        });
+        self
+    }
+
+    /// The pattern of the routes `path` matches, if any, e.g. `/orders/{id}`.
+    pub fn pattern(&self, path: &str) -> Option<&'static str> {
+        self.routes
+            .iter()
+            .find(|route| route.matches(path).is_some())
+            .map(|route| route.pattern)
+    }
+
+    /// Serves `req`, whose path is `path`, without version prefix, with
+    /// `state`. Fails with `NotFound` when no route matches the path, or
+    /// `MethodNotAllowed` when none matches its method.
+    pub async fn dispatch(
+        &self,
+        state: AppState,
+        mut req: Request<Body>,
+        path: &str,
+    ) -> Result<Response<Body>, AppError> {
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            let params = match route.matches(path) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.method == req.method() {
+                req.extensions_mut().insert(params);
+                return (route.handler)(state, req).await;
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method.clone());
+            }
+        }
+        if allowed.is_empty() {
+            return Err(AppError::NotFound);
+        }
+        if req.method() == Method::OPTIONS {
+            let mut response = Response::new(Body::empty());
+            response.headers_mut().insert(ALLOW, allow(&allowed));
+            return Ok(response);
+        }
+        Err(AppError::MethodNotAllowed(allowed))
+    }
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut params = Params::default();
+        let mut segments = segments(path);
+        for expected in &self.segments {
+            let segment = segments.next()?;
+            match expected {
+                Segment::Literal(literal) if *literal == segment => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => params.0.push((name, segment.to_string())),
+            }
+        }
+        segments.next().is_none().then_some(params)
+    }
+}
+
+/// The segments of a path, ignoring a trailing slash.
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// The value of an `Allow` header listing `methods`.
+pub fn allow(methods: &[Method]) -> HeaderValue {
+    let methods: Vec<&str> = methods.iter().map(Method::as_str).collect();
+    // Method names are tokens, so always valid in a header.
+    HeaderValue::from_str(&methods.join(", ")).unwrap()
+}
+
+/// The path parameter `name` of the route `req` matched, parsed. Fails with
+/// `NotFound` when it doesn't parse, as no resource has such an id.
+pub fn param<T: FromStr>(req: &Request<Body>, name: &str) -> Result<T, AppError> {
+    req.extensions()
+        .get::<Params>()
+        .and_then(|params| params.0.iter().find(|(param, _)| *param == name))
+        .and_then(|(_, value)| value.parse().ok())
+        .ok_or(AppError::NotFound)
+}
@@ -0,0 +1,109 @@
+//! API versions. The pricing API is served under a version prefix, such as
+//! `/v1/compute`, so that incompatible changes can ship under a new prefix
+//! without breaking existing clients. The unversioned paths it was first
+//! served at still work as aliases of `/v1`, but their responses carry a
+//! `Deprecation` header. Probes, metrics, docs and admin endpoints aren't
+//! versioned.
+
+use hyper::header::{HeaderValue, LINK};
+use hyper::{Body, Method, Response};
+
+/// Set to `true` on responses to deprecated paths.
+pub const DEPRECATION_HEADER: &str = "deprecation";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    /// The version unversioned paths are served as.
+    pub const DEFAULT: ApiVersion = ApiVersion::V1;
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "/v1",
+        }
+    }
+}
+
+/// Whether `path`, without version prefix, belongs to the versioned API.
+fn is_api(path: &str) -> bool {
+    let path = match path.strip_suffix('/') {
+        Some(trimmed) if !trimmed.is_empty() => trimmed,
+        _ => path,
+    };
+    matches!(
+        path,
+        "/compute"
+            | "/compute_batch"
+            | "/quote"
+            | "/graphql"
+            | "/orders"
+            | "/sagas"
+            | "/events"
+            | "/ws"
+    ) || path.starts_with("/orders/")
+        || path.starts_with("/sagas/")
+        || path.starts_with("/reports/")
+}
+
+/// The API version a request path asks for and the path without its version
+/// prefix. Unversioned API paths get the default version; other paths are
+/// returned as they are, without a version.
+pub fn split(path: &str) -> (Option<ApiVersion>, &str) {
+    for version in [ApiVersion::V1] {
+        if let Some(rest) = path.strip_prefix(version.prefix()) {
+            if is_api(rest) {
+                return (Some(version), rest);
+            }
+        }
+    }
+    if is_api(path) {
+        (Some(ApiVersion::DEFAULT), path)
+    } else {
+        (None, path)
+    }
+}
+
+/// For an unversioned API path, which is deprecated, the versioned path
+/// replacing it.
+pub fn successor(path: &str) -> Option<String> {
+    is_api(path).then(|| format!("{}{}", ApiVersion::DEFAULT.prefix(), path))
+}
+
+/// Marks the response to a request on a deprecated path, pointing at the path
+/// to use instead.
+pub fn deprecate(response: &mut Response<Body>, successor: &str) {
+    let headers = response.headers_mut();
+    headers.insert(DEPRECATION_HEADER, HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor))
+    {
+        headers.insert(LINK, link);
+    }
+}
+
+/// Who may call an endpoint, when authentication is configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Anyone: probes, metrics, docs and CORS preflight requests.
+    Public,
+    /// Holders of an API key or token.
+    Client,
+    /// Holders of an admin API key or of a token with the admin scope.
+    Admin,
+}
+
+/// The access rule of a route, given its path without version prefix. Routes
+/// opt in to authentication here.
+pub fn access(method: &Method, path: &str) -> Access {
+    if *method == Method::OPTIONS {
+        Access::Public
+    } else if path.starts_with("/admin/") {
+        Access::Admin
+    } else if is_api(path) {
+        Access::Client
+    } else {
+        Access::Public
+    }
+}
@@ -0,0 +1,466 @@
+//! The saga pricing an order across services, when enabled. The stock of the
+//! order is reserved at the inventory service, its rate looked up, its total
+//! authorized at the payment service and the priced order recorded; when a
+//! step fails, the steps done before it are undone by their compensating
+//! actions, in reverse order. The saga runs in a task of its own, so a client
+//! giving up doesn't leave it halfway, and its progress is kept in a saga
+//! store, for `GET /sagas/{id}`.
+
+use anyhow::{anyhow, Context};
+use domain::{ErrorEnvelope, Order};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::config::{PersistenceBackend, SagaConfig};
+use crate::error::AppError;
+use crate::inventory::Reservation;
+use crate::service;
+use crate::state::AppState;
+use crate::{as_of, auth, request_id, tenants};
+
+/// Where a saga stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaStatus {
+    /// Its steps are being run.
+    Running,
+    /// Every step succeeded: the order is priced.
+    Completed,
+    /// A step failed and the steps before it are being undone.
+    Compensating,
+    /// A step failed and the steps before it were undone.
+    Compensated,
+    /// A step failed and undoing a step before it failed too, e.g. a
+    /// reservation is still held and must be released by hand.
+    CompensationFailed,
+}
+
+impl SagaStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SagaStatus::Running => "running",
+            SagaStatus::Completed => "completed",
+            SagaStatus::Compensating => "compensating",
+            SagaStatus::Compensated => "compensated",
+            SagaStatus::CompensationFailed => "compensation_failed",
+        }
+    }
+}
+
+/// The steps of a saga, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Reserves the line items at the inventory service; released when undone.
+    ReserveInventory,
+    /// Looks the sales tax rate up and applies it; nothing to undo.
+    LookUpRate,
+    /// Authorizes the total at the payment service; voided when undone.
+    AuthorizePayment,
+    /// Stores and publishes the priced order, unless it was confirmed or
+    /// cancelled in the meantime.
+    RecordOrder,
+}
+
+/// How far a step got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    /// Not part of the saga, its service being disabled.
+    Skipped,
+    Done,
+    Failed,
+    /// Done, then undone.
+    Compensated,
+    /// Done, and undoing it failed.
+    CompensationFailed,
+}
+
+/// A step of a saga.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct StepRecord {
+    pub step: Step,
+    pub status: StepStatus,
+    /// What the step produced, e.g. the reservation id, or why it or its
+    /// compensation failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// RFC 3339 time of the last change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+}
+
+/// A saga as kept by the saga store.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SagaRecord {
+    pub saga_id: String,
+    pub order_id: i32,
+    pub status: SagaStatus,
+    pub steps: Vec<StepRecord>,
+    /// The error the failed step answered the order with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorEnvelope>,
+    /// RFC 3339 times.
+    pub started_at: String,
+    pub updated_at: String,
+}
+
+impl SagaRecord {
+    fn new(order_id: i32, state: &AppState) -> Self {
+        let step = |step, enabled: bool| StepRecord {
+            step,
+            status: if enabled {
+                StepStatus::Pending
+            } else {
+                StepStatus::Skipped
+            },
+            detail: None,
+            updated_at: None,
+        };
+        let now = now();
+        Self {
+            saga_id: Uuid::new_v4().to_string(),
+            order_id,
+            status: SagaStatus::Running,
+            steps: vec![
+                step(Step::ReserveInventory, state.inventory.is_some()),
+                step(Step::LookUpRate, true),
+                step(Step::AuthorizePayment, state.payments.is_some()),
+                step(Step::RecordOrder, true),
+            ],
+            error: None,
+            started_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    fn set_step(&mut self, step: Step, status: StepStatus, detail: Option<String>) {
+        let now = now();
+        if let Some(record) = self.steps.iter_mut().find(|record| record.step == step) {
+            record.status = status;
+            record.detail = detail;
+            record.updated_at = Some(now.clone());
+        }
+        self.updated_at = now;
+    }
+}
+
+fn now() -> String {
+    humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+}
+
+/// The sagas of an order, most recently started first.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SagaList {
+    pub sagas: Vec<SagaRecord>,
+}
+
+/// Where sagas are kept.
+pub trait SagaStore: Send + Sync {
+    /// Keeps `record`, replacing the earlier state of the same saga.
+    fn save(&self, record: &SagaRecord) -> anyhow::Result<()>;
+    fn get(&self, saga_id: &str) -> anyhow::Result<Option<SagaRecord>>;
+    /// The sagas of order `order_id`, most recently started first.
+    fn for_order(&self, order_id: i32) -> anyhow::Result<Vec<SagaRecord>>;
+}
+
+/// The saga store selected by the configuration, or none when orders aren't
+/// priced by the saga.
+pub fn from_config(config: &SagaConfig) -> anyhow::Result<Option<Box<dyn SagaStore>>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    Ok(Some(match config.backend {
+        PersistenceBackend::Memory => Box::new(MemorySagaStore::new(config.max_sagas)),
+        PersistenceBackend::File => Box::new(
+            FileSagaStore::open(Path::new(&config.path), config.max_sagas)
+                .with_context(|| format!("cannot open saga store {}", config.path))?,
+        ),
+    }))
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    records: HashMap<String, SagaRecord>,
+    /// Saga ids, oldest first.
+    started: VecDeque<String>,
+}
+
+/// Keeps sagas in memory; the oldest make room when `max_sagas` is reached.
+#[derive(Debug)]
+pub struct MemorySagaStore {
+    /// 0 keeps every saga.
+    max_sagas: usize,
+    inner: Mutex<Inner>,
+}
+
+impl MemorySagaStore {
+    pub fn new(max_sagas: usize) -> Self {
+        Self {
+            max_sagas,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+}
+
+impl SagaStore for MemorySagaStore {
+    fn save(&self, record: &SagaRecord) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.records.contains_key(&record.saga_id) {
+            if self.max_sagas > 0 && inner.records.len() >= self.max_sagas {
+                if let Some(oldest) = inner.started.pop_front() {
+                    inner.records.remove(&oldest);
+                }
+            }
+            inner.started.push_back(record.saga_id.clone());
+        }
+        inner.records.insert(record.saga_id.clone(), record.clone());
+        Ok(())
+    }
+
+    fn get(&self, saga_id: &str) -> anyhow::Result<Option<SagaRecord>> {
+        Ok(self.inner.lock().unwrap().records.get(saga_id).cloned())
+    }
+
+    fn for_order(&self, order_id: i32) -> anyhow::Result<Vec<SagaRecord>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .started
+            .iter()
+            .rev()
+            .map(|saga_id| &inner.records[saga_id])
+            .filter(|record| record.order_id == order_id)
+            .cloned()
+            .collect())
+    }
+}
+
+/// Appends every change of a saga to a JSON lines file and replays it at
+/// startup, with the sagas served from memory. Sagas that were still running
+/// when the service stopped stay `running` or `compensating`: what they did
+/// must be checked by hand.
+#[derive(Debug)]
+pub struct FileSagaStore {
+    memory: MemorySagaStore,
+    file: Mutex<File>,
+}
+
+impl FileSagaStore {
+    pub fn open(path: &Path, max_sagas: usize) -> anyhow::Result<Self> {
+        let memory = MemorySagaStore::new(max_sagas);
+        if path.exists() {
+            let reader = BufReader::new(File::open(path)?);
+            for (number, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record: SagaRecord =
+                    serde_json::from_str(&line).with_context(|| format!("line {}", number + 1))?;
+                memory.save(&record)?;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            memory,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl SagaStore for FileSagaStore {
+    fn save(&self, record: &SagaRecord) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file.lock().unwrap().write_all(line.as_bytes())?;
+        self.memory.save(record)
+    }
+
+    fn get(&self, saga_id: &str) -> anyhow::Result<Option<SagaRecord>> {
+        self.memory.get(saga_id)
+    }
+
+    fn for_order(&self, order_id: i32) -> anyhow::Result<Vec<SagaRecord>> {
+        self.memory.for_order(order_id)
+    }
+}
+
+/// Prices `order`, validated, converted and prepared, by a saga kept in
+/// `store`. `received` is the order as received, for the audit log, and
+/// `start` when it was.
+pub async fn run(
+    state: &AppState,
+    store: Arc<dyn SagaStore>,
+    received: Order,
+    order: Order,
+    start: Instant,
+) -> Result<Order, AppError> {
+    let saga = Saga {
+        record: SagaRecord::new(order.order_id, state),
+        state: state.clone(),
+        store,
+    };
+    // The task carries on the request's id, caller, tenant and day it prices
+    // as of, for the calls, logs, audit records and webhooks of the saga.
+    let task = auth::scope(auth::current_caller(), saga.run(received, order, start));
+    let task = tenants::scope(tenants::current_tenant(), task);
+    let task = as_of::scope(as_of::current(), task);
+    let task = match request_id::current() {
+        Some(request_id) => tokio::spawn(request_id::scope(request_id, task)),
+        None => tokio::spawn(task),
+    };
+    task.await
+        .map_err(|err| AppError::from(anyhow!("the saga task failed: {}", err)))?
+}
+
+/// A saga being run.
+struct Saga {
+    record: SagaRecord,
+    state: AppState,
+    store: Arc<dyn SagaStore>,
+}
+
+/// What the steps done so far undo.
+#[derive(Default)]
+struct Compensations {
+    reservation: Option<Reservation>,
+    authorization: Option<String>,
+}
+
+impl Saga {
+    async fn run(
+        mut self,
+        received: Order,
+        mut order: Order,
+        start: Instant,
+    ) -> Result<Order, AppError> {
+        order.saga_id = Some(self.record.saga_id.clone());
+        order.reservation_id = None;
+        self.save();
+        let mut done = Compensations::default();
+
+        if let Some(inventory) = self.state.inventory.clone() {
+            match inventory.reserve(&order).await {
+                Ok(reservation) => {
+                    order.reservation_id = Some(reservation.id().to_string());
+                    self.done(Step::ReserveInventory, reservation.id());
+                    done.reservation = Some(reservation);
+                }
+                Err(err) => return Err(self.fail(Step::ReserveInventory, err, done).await),
+            }
+        }
+
+        let rate = match service::order_rate(&self.state, &order).await {
+            Ok(rate) => rate,
+            Err(err) => return Err(self.fail(Step::LookUpRate, err, done).await),
+        };
+        service::apply_rate(&mut order, &rate);
+        self.done(Step::LookUpRate, &rate.value.to_string());
+
+        if let Some(payments) = self.state.payments.clone() {
+            let currency = &self.state.config.currency.base;
+            match payments.authorize(&order, currency).await {
+                Ok(authorization) => {
+                    self.done(Step::AuthorizePayment, &authorization);
+                    done.authorization = Some(authorization);
+                }
+                Err(err) => return Err(self.fail(Step::AuthorizePayment, err, done).await),
+            }
+        }
+
+        // The order may have been confirmed or cancelled in the meantime.
+        if let Err(err) = service::check_priceable(&self.state, order.order_id) {
+            return Err(self.fail(Step::RecordOrder, err, done).await);
+        }
+        service::record_order(&self.state, &order, rate.value, done.authorization);
+        service::audit(&self.state, &received, &order, &rate, start.elapsed());
+        if let Some(reservation) = done.reservation {
+            reservation.keep();
+        }
+        self.record
+            .set_step(Step::RecordOrder, StepStatus::Done, None);
+        self.finish(SagaStatus::Completed);
+        Ok(order)
+    }
+
+    fn done(&mut self, step: Step, detail: &str) {
+        self.record
+            .set_step(step, StepStatus::Done, Some(detail.to_string()));
+        self.save();
+    }
+
+    /// Records the failure of `step`, undoes the steps done before it, and
+    /// returns its error.
+    async fn fail(&mut self, step: Step, err: AppError, done: Compensations) -> AppError {
+        self.record
+            .set_step(step, StepStatus::Failed, Some(err.to_string()));
+        self.record.error = Some(err.envelope());
+        self.record.status = SagaStatus::Compensating;
+        self.save();
+
+        let mut compensated = true;
+        if let (Some(authorization), Some(payments)) =
+            (done.authorization, self.state.payments.clone())
+        {
+            let result = payments.void(&authorization).await;
+            compensated &= self.compensated(Step::AuthorizePayment, result);
+        }
+        if let Some(reservation) = done.reservation {
+            let result = reservation.release().await;
+            compensated &= self.compensated(Step::ReserveInventory, result);
+        }
+        self.finish(if compensated {
+            SagaStatus::Compensated
+        } else {
+            SagaStatus::CompensationFailed
+        });
+        err
+    }
+
+    /// Records the outcome of undoing `step`, and whether it was undone.
+    fn compensated(&mut self, step: Step, result: Result<(), AppError>) -> bool {
+        let undone = result.is_ok();
+        match result {
+            Ok(()) => self.record.set_step(step, StepStatus::Compensated, None),
+            Err(err) => {
+                warn!(error = %err, saga_id = %self.record.saga_id, ?step, "saga compensation failed");
+                self.record
+                    .set_step(step, StepStatus::CompensationFailed, Some(err.to_string()));
+            }
+        }
+        self.save();
+        undone
+    }
+
+    fn finish(&mut self, status: SagaStatus) {
+        self.record.status = status;
+        self.save();
+        self.state
+            .metrics
+            .sagas
+            .with_label_values(&[status.as_str()])
+            .inc();
+        info!(
+            saga_id = %self.record.saga_id,
+            order_id = self.record.order_id,
+            status = status.as_str(),
+            "saga finished"
+        );
+    }
+
+    /// Keeps the saga's progress. Failing to do so doesn't fail the saga.
+    fn save(&self) {
+        if let Err(err) = self.store.save(&self.record) {
+            warn!(error = %err, saga_id = %self.record.saga_id, "failed to store saga");
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! The periodic jobs: refreshing and saving the cached rates, removing stale
+//! orders, relaying the outbox and rolling up metrics. Each job runs in a
+//! task of its own on the schedule of its `scheduler.<job>` section, one run
+//! at a time, and its last run is reported at `GET /admin/jobs`.
+
+use anyhow::{bail, ensure, Context};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::config::{JobConfig, SchedulerConfig};
+use crate::state::AppState;
+use crate::{events, service, warm_up};
+
+/// Minutes looked ahead for the next time of a cron schedule, four years, so
+/// that `0 0 29 2 *` fires.
+const CRON_HORIZON_MINUTES: u64 = 4 * 366 * 24 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobKind {
+    CacheRefresh,
+    CacheSnapshot,
+    OrderCleanup,
+    OutboxRelay,
+    MetricsRollup,
+}
+
+impl JobKind {
+    fn name(self) -> &'static str {
+        match self {
+            JobKind::CacheRefresh => "cache_refresh",
+            JobKind::CacheSnapshot => "cache_snapshot",
+            JobKind::OrderCleanup => "order_cleanup",
+            JobKind::OutboxRelay => "outbox_relay",
+            JobKind::MetricsRollup => "metrics_rollup",
+        }
+    }
+}
+
+/// The last run of a job, as reported by the admin endpoint.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobStatus {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub schedule: String,
+    pub runs: u64,
+    pub failures: u64,
+    /// RFC 3339 time the last run started.
+    pub last_run_at: Option<String>,
+    pub last_duration_ms: Option<u64>,
+    /// Why the last run failed; unset when it succeeded.
+    pub last_error: Option<String>,
+    /// RFC 3339 time of the next run; unset for a disabled job.
+    pub next_run_at: Option<String>,
+}
+
+struct Job {
+    kind: JobKind,
+    schedule: Schedule,
+    status: Mutex<JobStatus>,
+}
+
+/// The jobs of the configuration, with their last runs.
+pub struct Scheduler {
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    /// Parses the schedules of `config`, so a broken one fails here. The jobs
+    /// start with `start`.
+    pub fn from_config(config: &SchedulerConfig) -> anyhow::Result<Self> {
+        let jobs = [
+            (JobKind::CacheRefresh, &config.cache_refresh),
+            (JobKind::CacheSnapshot, &config.cache_snapshot),
+            (JobKind::OrderCleanup, &config.order_cleanup),
+            (JobKind::OutboxRelay, &config.outbox_relay),
+            (JobKind::MetricsRollup, &config.metrics_rollup),
+        ];
+        let jobs = jobs
+            .into_iter()
+            .map(|(kind, config)| Job::new(kind, config))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { jobs })
+    }
+
+    /// Runs every enabled job on its schedule in the background. Must be
+    /// called within the runtime.
+    pub fn start(&self, state: &AppState) {
+        for (index, job) in self.jobs.iter().enumerate() {
+            if job.status.lock().unwrap().enabled {
+                tokio::spawn(run(state.clone(), index));
+            }
+        }
+    }
+
+    /// The jobs and their last runs.
+    pub fn status(&self) -> Vec<JobStatus> {
+        self.jobs
+            .iter()
+            .map(|job| job.status.lock().unwrap().clone())
+            .collect()
+    }
+}
+
+impl Job {
+    fn new(kind: JobKind, config: &JobConfig) -> anyhow::Result<Self> {
+        let schedule = Schedule::parse(&config.schedule)
+            .with_context(|| format!("invalid scheduler.{}.schedule", kind.name()))?;
+        let next_run_at = schedule
+            .next_after(SystemTime::now())
+            .filter(|_| config.enabled);
+        Ok(Self {
+            kind,
+            schedule,
+            status: Mutex::new(JobStatus {
+                name: kind.name(),
+                enabled: config.enabled,
+                schedule: config.schedule.clone(),
+                runs: 0,
+                failures: 0,
+                last_run_at: None,
+                last_duration_ms: None,
+                last_error: None,
+                next_run_at: next_run_at.map(rfc3339),
+            }),
+        })
+    }
+}
+
+/// Runs the job `index` of the scheduler of `state` at every time of its
+/// schedule. A run that outlasts the next time skips it.
+async fn run(state: AppState, index: usize) {
+    let job = &state.scheduler.jobs[index];
+    loop {
+        let next = match job.schedule.next_after(SystemTime::now()) {
+            Some(next) => next,
+            None => return,
+        };
+        job.status.lock().unwrap().next_run_at = Some(rfc3339(next));
+        let wait = next.duration_since(SystemTime::now()).unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        let started_at = SystemTime::now();
+        let started = Instant::now();
+        let result = run_job(&state, job.kind).await;
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        state
+            .metrics
+            .job_runs
+            .with_label_values(&[job.kind.name(), outcome])
+            .inc();
+        let mut status = job.status.lock().unwrap();
+        status.runs += 1;
+        status.last_run_at = Some(rfc3339(started_at));
+        status.last_duration_ms = Some(started.elapsed().as_millis() as u64);
+        status.last_error = match result {
+            Ok(()) => None,
+            Err(err) => {
+                let error = format!("{:#}", err);
+                warn!(error = %error, job = job.kind.name(), "scheduled job failed");
+                status.failures += 1;
+                Some(error)
+            }
+        };
+    }
+}
+
+async fn run_job(state: &AppState, kind: JobKind) -> anyhow::Result<()> {
+    let config = &state.config.scheduler;
+    match kind {
+        JobKind::CacheRefresh => {
+            let refreshed = service::refresh_hot_rates(state, config.hot_zips).await;
+            debug!(refreshed, "cached rates refreshed");
+        }
+        JobKind::CacheSnapshot => warm_up::save_snapshot(state)?,
+        JobKind::OrderCleanup => {
+            let max_age = Duration::from_secs(config.order_max_age_secs);
+            let before = SystemTime::now().checked_sub(max_age).unwrap_or(UNIX_EPOCH);
+            let removed = state.orders.purge(before)?;
+            debug!(removed, "stale orders removed");
+        }
+        JobKind::OutboxRelay => {
+            if state.config.events.outbox {
+                let batch = state.config.events.relay_batch.max(1);
+                let published =
+                    events::relay(state.orders.as_ref(), state.events.as_ref(), batch).await?;
+                debug!(published, "outbox relayed");
+            }
+        }
+        JobKind::MetricsRollup => state.metrics.roll_up(),
+    }
+    Ok(())
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// When a job runs: every so often, or at the times of a cron expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Schedule {
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    fn parse(spec: &str) -> anyhow::Result<Self> {
+        let spec = spec.trim();
+        if let Some(every) = spec.strip_prefix("@every") {
+            let every = humantime::parse_duration(every.trim())?;
+            ensure!(!every.is_zero(), "a job can't run every 0s");
+            return Ok(Schedule::Every(every));
+        }
+        let cron = Cron::parse(spec)?;
+        ensure!(
+            cron.next_after(SystemTime::now()).is_some(),
+            "{:?} never fires",
+            spec
+        );
+        Ok(Schedule::Cron(cron))
+    }
+
+    /// The first time of the schedule after `time`.
+    fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        match self {
+            Schedule::Every(every) => Some(time + *every),
+            Schedule::Cron(cron) => cron.next_after(time),
+        }
+    }
+}
+
+/// A cron expression, `[seconds] minutes hours day-of-month month
+/// day-of-week`, in UTC. Fields take `*`, numbers, ranges such as `1-5`,
+/// steps such as `*/15` or `0-30/10`, and lists of those. Sunday is 0 or 7.
+/// As with cron, a day matches either day field when both are restricted.
+#[derive(Debug, Clone, PartialEq)]
+struct Cron {
+    /// One bit per allowed value.
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    fn parse(spec: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let (seconds, rest) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            _ => bail!(
+                "{:?} is neither `@every <duration>` nor a cron expression of 5 or 6 fields",
+                spec
+            ),
+        };
+        let mut weekdays = field(rest[4], 0, 7).context("day of week")?;
+        // Sunday is both 0 and 7.
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            seconds: field(seconds, 0, 59).context("seconds")?,
+            minutes: field(rest[0], 0, 59).context("minutes")?,
+            hours: field(rest[1], 0, 23).context("hours")?,
+            days: field(rest[2], 1, 31).context("day of month")?,
+            months: field(rest[3], 1, 12).context("month")?,
+            weekdays,
+            any_day: rest[2] == "*",
+            any_weekday: rest[4] == "*",
+        })
+    }
+
+    /// The first time of the expression after `time`, looking a few years
+    /// ahead.
+    fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let start = time.duration_since(UNIX_EPOCH).ok()?.as_secs() + 1;
+        let mut minute = start / 60;
+        let end = minute + CRON_HORIZON_MINUTES;
+        while minute < end {
+            let days = minute / (24 * 60);
+            if !self.matches_day(days) {
+                minute = (days + 1) * 24 * 60;
+                continue;
+            }
+            let hour = minute / 60 % 24;
+            if self.hours & (1 << hour) == 0 {
+                minute = (minute / 60 + 1) * 60;
+                continue;
+            }
+            if self.minutes & (1 << (minute % 60)) != 0 {
+                let from = if minute == start / 60 { start % 60 } else { 0 };
+                if let Some(second) = (from..60).find(|second| self.seconds & (1 << second) != 0) {
+                    return Some(UNIX_EPOCH + Duration::from_secs(minute * 60 + second));
+                }
+            }
+            minute += 1;
+        }
+        None
+    }
+
+    /// Whether the day `days` after the Unix epoch matches the day fields.
+    fn matches_day(&self, days: u64) -> bool {
+        let (month, day) = month_and_day(days);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        // 1970-01-01 was a Thursday.
+        let weekday = (days + 4) % 7;
+        let day_matches = self.days & (1 << day) != 0;
+        let weekday_matches = self.weekdays & (1 << weekday) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        }
+    }
+}
+
+/// The allowed values of a cron field, one bit each.
+fn field(spec: &str, min: u64, max: u64) -> anyhow::Result<u64> {
+    let mut bits = 0;
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>()?),
+            None => (part, 1),
+        };
+        ensure!(step > 0, "a step of 0 in {:?}", spec);
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((first, last)) => (first.parse()?, last.parse()?),
+                // A step from a single value runs to the end of the field.
+                None if part.contains('/') => (range.parse()?, max),
+                None => (range.parse()?, range.parse()?),
+            },
+        };
+        ensure!(
+            min <= first && first <= last && last <= max,
+            "{:?} is out of {}-{}",
+            part,
+            min,
+            max
+        );
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// The month, 1 to 12, and the day of the month of the day `days` after the
+/// Unix epoch, in the proleptic Gregorian calendar.
+fn month_and_day(days: u64) -> (u64, u64) {
+    // Howard Hinnant's `civil_from_days`, for days on or after the epoch.
+    let z = days + 719_468;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (month, day)
+}
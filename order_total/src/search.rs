@@ -0,0 +1,162 @@
+//! Free text search of the stored orders, over their shipping address and
+//! zip code: an inverted index from the lowercased words of those fields to
+//! the orders having them, kept by the order store as orders are saved and
+//! removed. A word of the query matches the words it equals or starts with,
+//! so `787` finds the orders shipped to `78701`.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use utoipa::ToSchema;
+
+use crate::store::OrderRecord;
+use domain::Order;
+
+/// Points of a query word equal to a word of the order, and of one it only
+/// starts.
+const EXACT_MATCH: u32 = 2;
+const PREFIX_MATCH: u32 = 1;
+
+/// An order matching a query, with its fields with the matched words
+/// highlighted in `<em>`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchHit {
+    pub record: OrderRecord,
+    /// Higher for better matches.
+    pub score: u32,
+    pub highlights: Highlights,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Highlights {
+    pub shipping_address: String,
+    pub shipping_zip: String,
+}
+
+/// The orders matching a query, best first.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchResults {
+    pub query: String,
+    pub hits: Vec<SearchHit>,
+}
+
+/// Order ids by word.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    postings: BTreeMap<String, HashSet<i32>>,
+}
+
+impl SearchIndex {
+    pub fn add(&mut self, order: &Order) {
+        for word in order_words(order) {
+            self.postings
+                .entry(word)
+                .or_default()
+                .insert(order.order_id);
+        }
+    }
+
+    pub fn remove(&mut self, order: &Order) {
+        for word in order_words(order) {
+            if let Some(ids) = self.postings.get_mut(&word) {
+                ids.remove(&order.order_id);
+                if ids.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// The ids of the orders matching any word of `query`, with their score:
+    /// the sum, over the words of the query, of their best match.
+    pub fn search(&self, query: &str) -> HashMap<i32, u32> {
+        let mut scores: HashMap<i32, u32> = HashMap::new();
+        for term in words(query) {
+            let mut best: HashMap<i32, u32> = HashMap::new();
+            for (word, ids) in self.postings.range(term.clone()..) {
+                if !word.starts_with(&term) {
+                    break;
+                }
+                let points = if *word == term {
+                    EXACT_MATCH
+                } else {
+                    PREFIX_MATCH
+                };
+                for id in ids {
+                    let score = best.entry(*id).or_default();
+                    *score = (*score).max(points);
+                }
+            }
+            for (id, points) in best {
+                *scores.entry(id).or_default() += points;
+            }
+        }
+        scores
+    }
+}
+
+/// The lowercased words of `text`: its runs of letters and digits.
+pub fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn order_words(order: &Order) -> HashSet<String> {
+    words(&order.shipping_address)
+        .into_iter()
+        .chain(words(&order.shipping_zip))
+        .collect()
+}
+
+/// `text` with the part of each word that a word of `query` matches wrapped
+/// in `<em>`.
+pub fn highlight(text: &str, query: &str) -> String {
+    let terms = words(query);
+    let mut highlighted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(char::is_alphanumeric) {
+        highlighted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(rest.len());
+        let (word, after) = rest.split_at(end);
+        let lowercase = word.to_lowercase();
+        let matched = terms
+            .iter()
+            .filter(|term| lowercase.starts_with(term.as_str()))
+            .map(|term| term.chars().count())
+            .max();
+        match matched {
+            Some(chars) => {
+                let split = word
+                    .char_indices()
+                    .nth(chars)
+                    .map_or(word.len(), |(index, _)| index);
+                highlighted.push_str("<em>");
+                highlighted.push_str(&word[..split]);
+                highlighted.push_str("</em>");
+                highlighted.push_str(&word[split..]);
+            }
+            None => highlighted.push_str(word),
+        }
+        rest = after;
+    }
+    highlighted.push_str(rest);
+    highlighted
+}
+
+impl SearchHit {
+    pub fn new(record: OrderRecord, score: u32, query: &str) -> Self {
+        let highlights = Highlights {
+            shipping_address: highlight(&record.order.shipping_address, query),
+            shipping_zip: highlight(&record.order.shipping_zip, query),
+        };
+        Self {
+            record,
+            score,
+            highlights,
+        }
+    }
+}
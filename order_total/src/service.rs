@@ -0,0 +1,656 @@
+//! The pricing service shared by the HTTP, gRPC, GraphQL and queue front ends:
+//! validating, converting and pricing orders, checking tax exemptions, looking
+//! up rates and moving orders through their lifecycle. Its dependencies come
+//! from the `AppState` each function is given.
+
+use domain::{Decimal, Order, RateComponent, RateSource};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::audit::AuditRecord;
+use crate::cache::Cached;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::error::AppError;
+use crate::events::EventPublisher;
+use crate::flags::Flag;
+use crate::inventory::Reservation;
+use crate::lifecycle::OrderStatus;
+use crate::rates::{Quote, TaxRateProvider};
+use crate::shared_cache::SharedRate;
+use crate::state::AppState;
+use crate::store::OrderRecord;
+use crate::tenants::{self, Tenant};
+use crate::{as_of, deadline, events, postal, saga, shipping, validation};
+
+/// Parses, validates and prices one order.
+pub async fn price(state: &AppState, byte_stream: &[u8]) -> Result<Order, AppError> {
+    price_order(state, serde_json::from_slice(byte_stream)?).await
+}
+
+/// Validates and prices one order, unless it duplicates one priced within
+/// the deduplication window, whose result it gets.
+pub async fn price_order(state: &AppState, order: Order) -> Result<Order, AppError> {
+    let priced = |order| {
+        let state = state.clone();
+        async move { price_new_order(&state, order).await }
+    };
+    state.dedup.run(order, priced).await
+}
+
+async fn price_new_order(state: &AppState, mut order: Order) -> Result<Order, AppError> {
+    let start = Instant::now();
+    normalize_address(state, &mut order).await?;
+    validation::validate(&order)?;
+    let received = order.clone();
+    set_rounding(state, &mut order);
+    convert_currency(state, &mut order).await?;
+    check_exemption(state, &mut order).await?;
+    prepare_order(state, &mut order)?;
+    if let Some(sagas) = &state.sagas {
+        return saga::run(state, sagas.clone(), received, order, start).await;
+    }
+    let reservation = reserve_stock(state, &mut order).await?;
+    let rate = order_rate(state, &order).await?;
+    apply_rate(&mut order, &rate);
+    record_order(state, &order, rate.value, None);
+    audit(state, &received, &order, &rate, start.elapsed());
+    if let Some(reservation) = reservation {
+        reservation.keep();
+    }
+    Ok(order)
+}
+
+/// Parses, validates and prices one order exactly like `price`, but without
+/// side effects: the order isn't stored, published or audited, and its
+/// status isn't checked, so frontends can show live totals while it is being
+/// filled in.
+pub async fn quote(state: &AppState, byte_stream: &[u8]) -> Result<Order, AppError> {
+    let mut order: Order = serde_json::from_slice(byte_stream)?;
+    normalize_address(state, &mut order).await?;
+    validation::validate(&order)?;
+    set_rounding(state, &mut order);
+    convert_currency(state, &mut order).await?;
+    check_exemption(state, &mut order).await?;
+    apply_charges(state, &mut order)?;
+    let rate = order_rate(state, &order).await?;
+    apply_rate(&mut order, &rate);
+    Ok(order)
+}
+
+/// Appends the pricing of `received` to the audit log, when enabled. Failing
+/// to do so doesn't fail the request.
+pub fn audit(state: &AppState, received: &Order, priced: &Order, rate: &Rate, latency: Duration) {
+    if let Some(log) = &state.audit {
+        if let Err(err) = log.record(&AuditRecord::new(received, priced, rate, latency)) {
+            warn!(error = %err, order_id = priced.order_id, "failed to write the audit log");
+        }
+    }
+}
+
+/// Keeps the priced order for `GET /orders` and publishes an `OrderPriced`
+/// event, to the event publisher, the webhooks and `GET /events`. Failing
+/// to do so doesn't fail the request, the order has been priced all the
+/// same. `authorization` is the payment authorization of a saga.
+pub fn record_order(state: &AppState, order: &Order, rate: Decimal, authorization: Option<String>) {
+    // The order may have been confirmed or cancelled while it was being priced.
+    if let Err(err) = check_priceable(state, order.order_id) {
+        warn!(error = %err, order_id = order.order_id, "priced order not stored");
+        return;
+    }
+    let mut record = OrderRecord::new(order, rate);
+    record.payment_authorization_id = authorization;
+    if let Err(err) = save_priced(state, record) {
+        warn!(error = %err, order_id = order.order_id, "failed to store priced order");
+    }
+}
+
+/// Publishes the `OrderPriced` event of `record` and stores it.
+fn save_priced(state: &AppState, record: OrderRecord) -> anyhow::Result<()> {
+    let event = events::order_priced(&record);
+    state.webhooks.publish(&event);
+    state.activity.publish(&event);
+    // With the outbox, the relay publishes the event once the order is stored.
+    if state.config.events.outbox {
+        state.orders.save_with_event(record)
+    } else {
+        state.events.publish(&event);
+        state.orders.save(record)
+    }
+}
+
+/// The new shipping address of a priced order, the body of
+/// `PATCH /orders/{id}`. Fields left out keep their value, except that with
+/// address validation a new address without a zip code gets the zip code of
+/// that address.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct AddressChange {
+    #[schema(example = "1 Congress Ave, Austin TX")]
+    pub shipping_address: Option<String>,
+    #[schema(example = "78701")]
+    pub shipping_zip: Option<String>,
+}
+
+/// Prices a stored order again for a new shipping address: the address is
+/// validated, and the shipping, tax exemption and rate of the new destination
+/// looked up. The order keeps its items, its currency conversion and its
+/// reservation; the record keeps its earlier pricing in its revisions, and
+/// an `OrderPriced` event is published. Confirmed and cancelled orders can't
+/// be changed.
+pub async fn reprice(
+    state: &AppState,
+    order_id: i32,
+    change: AddressChange,
+) -> Result<OrderRecord, AppError> {
+    let start = Instant::now();
+    if change.shipping_address.is_none() && change.shipping_zip.is_none() {
+        return Err(AppError::InvalidPayload(
+            "nothing to change: give a shipping_address or a shipping_zip".into(),
+        ));
+    }
+    let mut record = state
+        .orders
+        .get(order_id)?
+        .ok_or(AppError::OrderNotFound(order_id))?;
+    let mut order = record.order.clone();
+    if let Some(address) = change.shipping_address {
+        if change.shipping_zip.is_none() && state.address_validator.is_some() {
+            order.shipping_zip.clear();
+        }
+        order.shipping_address = address;
+    }
+    if let Some(zip) = change.shipping_zip {
+        order.shipping_zip = zip;
+    }
+    normalize_address(state, &mut order).await?;
+    validation::validate(&order)?;
+    let received = order.clone();
+    set_rounding(state, &mut order);
+    check_exemption(state, &mut order).await?;
+    prepare_order(state, &mut order)?;
+    let rate = order_rate(state, &order).await?;
+    apply_rate(&mut order, &rate);
+    record.revise(order.clone(), rate.value);
+    save_priced(state, record.clone())?;
+    audit(state, &received, &order, &rate, start.elapsed());
+    info!(
+        order_id,
+        revision = record.revisions.len(),
+        total = %order.total,
+        "order priced again for a new address"
+    );
+    Ok(record)
+}
+
+/// Normalizes the shipping address of an order with the address validator,
+/// when enabled, and ships orders without a `shipping_zip` to the zip code
+/// of their address. Fails with `InvalidAddress` for addresses the validator
+/// rejects; empty addresses are left to the validation.
+pub async fn normalize_address(state: &AppState, order: &mut Order) -> Result<(), AppError> {
+    // Only the validator sets the normalized address.
+    order.normalized_address = None;
+    let validator = match &state.address_validator {
+        Some(validator) => validator,
+        None => return Ok(()),
+    };
+    if order.shipping_address.trim().is_empty() {
+        return Ok(());
+    }
+    let mut normalized = validator
+        .normalize(&order.shipping_address, &order.shipping_zip)
+        .await?;
+    if order.shipping_zip.trim().is_empty() {
+        order.shipping_zip = normalized.zip.clone();
+        normalized.zip_derived = true;
+    }
+    order.normalized_address = Some(normalized);
+    Ok(())
+}
+
+/// Reserves the line items of an order at the inventory service, when
+/// enabled. Unless kept once the order is priced, the reservation is released
+/// when dropped. Fails with `InsufficientStock` when some item is out of
+/// stock.
+pub async fn reserve_stock(
+    state: &AppState,
+    order: &mut Order,
+) -> Result<Option<Reservation>, AppError> {
+    // Only the inventory service sets the reservation.
+    order.reservation_id = None;
+    let inventory = match &state.inventory {
+        Some(inventory) => inventory,
+        None => return Ok(None),
+    };
+    let reservation = inventory.reserve(order).await?;
+    order.reservation_id = Some(reservation.id().to_string());
+    Ok(Some(reservation))
+}
+
+/// Rounds the amounts of an order to cents by its tenant's `rounding`, or
+/// else `currency.rounding`, whatever the order says. Call before converting
+/// and pricing it, so its line items, shipping, discount, tax and converted
+/// totals are all rounded alike.
+pub fn set_rounding(state: &AppState, order: &mut Order) {
+    let tenant = state.tenants.current().and_then(|tenant| tenant.rounding);
+    order.rounding = Some(tenant.unwrap_or(state.config.currency.rounding));
+}
+
+/// Converts the prices of an order sent in another currency than the base
+/// currency, so shipping, discounts and tax all apply to base currency
+/// amounts. Fails with `UnsupportedCurrency` without an exchange rate.
+pub async fn convert_currency(state: &AppState, order: &mut Order) -> Result<(), AppError> {
+    // Totals in another currency are only reported for orders converted here.
+    order.converted = None;
+    let base = &state.config.currency.base;
+    let currency = match &order.currency {
+        Some(currency) if currency != base => currency.clone(),
+        _ => return Ok(()),
+    };
+    let exchange_rate = state.exchange.rate(&currency).await?;
+    order.convert(base, exchange_rate);
+    Ok(())
+}
+
+/// Checks the `tax_exempt_id` of an order against the exemption registry and
+/// keeps the registry's reference, so the order is priced without tax. Fails
+/// with `InvalidTaxExemption` for exemptions the registry doesn't accept.
+pub async fn check_exemption(state: &AppState, order: &mut Order) -> Result<(), AppError> {
+    // Only the registry sets the reference.
+    order.exemption_reference = None;
+    let id = match &order.tax_exempt_id {
+        Some(id) => id.trim(),
+        None => return Ok(()),
+    };
+    let reference = state.exemptions.verify(id, &order.shipping_zip).await?;
+    info!(order_id = order.order_id, reference = %reference, "tax exemption accepted");
+    order.exemption_reference = Some(reference);
+    Ok(())
+}
+
+/// Adds shipping and takes off the promo code's discount, leaving only the
+/// sales tax to apply. Confirmed and cancelled orders can't be priced again.
+pub fn prepare_order(state: &AppState, order: &mut Order) -> Result<(), AppError> {
+    check_priceable(state, order.order_id)?;
+    apply_charges(state, order)
+}
+
+/// Adds shipping and takes off the promo code's discount.
+fn apply_charges(state: &AppState, order: &mut Order) -> Result<(), AppError> {
+    shipping::apply(&state.shipping, order);
+    state.discounts.apply(order)
+}
+
+/// Whether the order may be priced, judging by its stored status. Orders that
+/// aren't stored yet have just been received.
+pub fn check_priceable(state: &AppState, order_id: i32) -> Result<(), AppError> {
+    let status = state
+        .orders
+        .get(order_id)?
+        .map_or(OrderStatus::Received, |record| record.status);
+    status
+        .transition(OrderStatus::Priced)
+        .map_err(|transition| AppError::InvalidTransition(order_id, transition))?;
+    Ok(())
+}
+
+/// A sales tax rate, its components by jurisdiction when known, and where it
+/// came from when that wasn't the rate provider.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub value: Decimal,
+    pub components: Vec<RateComponent>,
+    pub source: Option<RateSource>,
+    /// Served from the rate cache rather than asked of the rate provider.
+    pub cached: bool,
+}
+
+impl Rate {
+    /// The rate of tax exempt orders, which isn't looked up.
+    pub fn exempt() -> Self {
+        Self {
+            value: Decimal::ZERO,
+            components: Vec::new(),
+            source: Some(RateSource::Exempt),
+            cached: false,
+        }
+    }
+}
+
+/// The rate to tax the order at: none for tax exempt orders, otherwise the
+/// rate of its zip code.
+pub async fn order_rate(state: &AppState, order: &Order) -> Result<Rate, AppError> {
+    match order.exemption_reference {
+        Some(_) => Ok(Rate::exempt()),
+        None => fetch_rate(state, &order.shipping_zip).await,
+    }
+}
+
+/// Applies the rate to the order, splits the tax by jurisdiction when the
+/// rate is broken down, and marks fallback, exempt and overridden rates.
+pub fn apply_rate(order: &mut Order, rate: &Rate) {
+    order.apply_rate(rate.value);
+    order.apply_breakdown(&rate.components);
+    order.rate_source = rate.source;
+}
+
+/// Looks up the rate of the given zip code, by its 5-digit part, unless a
+/// rate override applies to it, falling back to the fallback rate table,
+/// when enabled, while the rate provider is unavailable.
+pub async fn fetch_rate(state: &AppState, zip: &str) -> Result<Rate, AppError> {
+    let rate_zip = postal::rate_zip(zip)?;
+    if let Some(value) = state.rate_overrides.get(rate_zip, &as_of::day()) {
+        state.metrics.rate_overrides.inc();
+        return Ok(Rate {
+            value,
+            components: Vec::new(),
+            source: Some(RateSource::Override),
+            cached: false,
+        });
+    }
+    let err = match lookup_rate(state, rate_zip).await {
+        Ok((quote, cached)) => {
+            return Ok(Rate {
+                value: quote.rate,
+                components: quote.components,
+                source: None,
+                cached,
+            })
+        }
+        // Named after the zip code asked for, rather than its 5-digit part.
+        Err(AppError::RateNotFound(_)) => AppError::RateNotFound(zip.to_string()),
+        Err(err) => err,
+    };
+    let unavailable = matches!(
+        err,
+        AppError::UpstreamUnavailable(_)
+            | AppError::UpstreamTimeout(_)
+            | AppError::CircuitOpen(_)
+            | AppError::Overloaded(_)
+    );
+    let fallback = state
+        .fallback_rates
+        .as_ref()
+        .filter(|_| unavailable && state.flags.is_enabled(Flag::FallbackRates));
+    match fallback {
+        Some(fallback) => {
+            let value = fallback.get(zip).ok_or(err)?;
+            warn!(zip, "rate provider unavailable, using the fallback rate");
+            state.metrics.rate_fallbacks.inc();
+            Ok(Rate {
+                value,
+                components: Vec::new(),
+                source: Some(RateSource::Fallback),
+                cached: false,
+            })
+        }
+        None => Err(err),
+    }
+}
+
+/// Where the rates of the request being served come from: the rate provider
+/// of its tenant, when it has one, or else the service's, with the circuit
+/// breaker guarding it and the prefix of the cache keys of its rates.
+#[derive(Clone)]
+struct RateBackend {
+    rates: Arc<dyn TaxRateProvider>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    key_prefix: String,
+    /// The past day rates are looked up as of, if any.
+    as_of: Option<String>,
+}
+
+impl RateBackend {
+    fn current(state: &AppState) -> Self {
+        let tenant = state.tenants.current();
+        match tenant.as_deref() {
+            Some(Tenant {
+                id,
+                rates: Some(rates),
+                ..
+            }) => Self {
+                rates: rates.provider.clone(),
+                circuit_breaker: rates.circuit_breaker.clone(),
+                key_prefix: format!("{}/", id),
+                as_of: as_of::current(),
+            },
+            _ => Self {
+                rates: state.rates.clone(),
+                circuit_breaker: state.circuit_breaker.clone(),
+                key_prefix: String::new(),
+                as_of: as_of::current(),
+            },
+        }
+    }
+
+    /// The key of the rate of `zip` in the cache, and of its lookups, e.g.
+    /// `acme/78701@2019-06-30` for a tenant's rate as of a past day.
+    fn key(&self, zip: &str) -> String {
+        match &self.as_of {
+            Some(day) => format!("{}{}@{}", self.key_prefix, zip, day),
+            None => format!("{}{}", self.key_prefix, zip),
+        }
+    }
+}
+
+/// Looks up the rate of the given zip code, from the cache if possible, then
+/// from the cache shared by the replicas, when there is one, and otherwise
+/// from the rate provider of the request's tenant or the configured one. A
+/// stale cached rate is served at once while a background task refreshes
+/// it. Tells whether the rate came from the cache.
+async fn lookup_rate(state: &AppState, zip: &str) -> Result<(Quote, bool), AppError> {
+    let backend = RateBackend::current(state);
+    let key = backend.key(zip);
+    match state.rate_cache.get(&key) {
+        Some(Cached::Fresh(quote)) => {
+            state.metrics.cache_hits.inc();
+            return Ok((quote, true));
+        }
+        Some(Cached::Stale { quote, refresh }) => {
+            state.metrics.cache_stale_hits.inc();
+            if refresh {
+                tokio::spawn(refresh_rate(state.clone(), backend, zip.to_string()));
+            }
+            return Ok((quote, true));
+        }
+        None => state.metrics.cache_misses.inc(),
+    }
+    // Concurrent misses for the same zip code share one call, which runs
+    // until the latest of their deadlines; each waits for it until its own.
+    let call = {
+        let state = state.clone();
+        let zip = zip.to_string();
+        async move {
+            if let Some(quote) = shared_rate(&state, &backend.key(&zip)).await {
+                return Ok(quote);
+            }
+            call_provider(&state, &backend, &zip).await
+        }
+    };
+    let shared = state.rate_lookups.run(&key, call);
+    let (result, joined) = deadline::until_deadline(shared).await?;
+    if joined {
+        state.metrics.coalesced_lookups.inc();
+    }
+    result.map(|quote| (quote, false))
+}
+
+/// The rate of `key` in the cache shared by the replicas, when another one
+/// looked it up within its time to live; it is then cached locally too. The
+/// shared cache failing counts as a miss.
+async fn shared_rate(state: &AppState, key: &str) -> Option<Quote> {
+    let shared = state.shared_cache.as_ref()?;
+    let lookups = &state.metrics.shared_cache_lookups;
+    let value = match shared.get(&shared_rate_key(key)).await {
+        Ok(value) => value,
+        Err(err) => {
+            warn!(
+                error = format!("{:#}", err),
+                key, "shared cache lookup failed"
+            );
+            lookups.with_label_values(&["rate", "error"]).inc();
+            return None;
+        }
+    };
+    let rate = value.and_then(|value| serde_json::from_slice::<SharedRate>(&value).ok());
+    if let Some(rate) = rate {
+        let cached_at = rate.cached_at();
+        let quote = rate.into_quote();
+        if state
+            .rate_cache
+            .insert_cached_at(key, quote.clone(), cached_at)
+        {
+            lookups.with_label_values(&["rate", "hit"]).inc();
+            return Some(quote);
+        }
+    }
+    lookups.with_label_values(&["rate", "miss"]).inc();
+    None
+}
+
+/// Hands a rate just looked up to the other replicas, in the background.
+fn share_rate(state: &AppState, key: &str, quote: &Quote) {
+    if let Some(shared) = state.shared_cache.clone() {
+        let key = shared_rate_key(key);
+        let value = serde_json::to_vec(&SharedRate::new(quote)).expect("rates serialize");
+        let ttl = state.rate_cache.served_for();
+        tokio::spawn(async move {
+            if let Err(err) = shared.set(&key, &value, ttl).await {
+                warn!(error = format!("{:#}", err), key = %key, "failed to share a rate");
+            }
+        });
+    }
+}
+
+fn shared_rate_key(key: &str) -> String {
+    format!("rate:{}", key)
+}
+
+async fn refresh_rate(state: AppState, backend: RateBackend, zip: String) {
+    if let Err(err) = call_provider(&state, &backend, &zip).await {
+        warn!(error = %err, zip = %zip, "failed to refresh a stale rate");
+        state.rate_cache.refresh_failed(&backend.key(&zip));
+    }
+}
+
+/// Looks the `count` most read cached rates up again, so they don't expire
+/// while in use, and returns how many were refreshed. A rate that fails to
+/// refresh stays cached as it was.
+pub async fn refresh_hot_rates(state: &AppState, count: usize) -> usize {
+    let mut refreshed = 0;
+    for key in state.rate_cache.hot(count) {
+        // The keys of a tenant's rates start with its id, and those of past
+        // rates end with their day.
+        let (tenant, zip) = match key.split_once('/') {
+            Some((tenant, zip)) => (Some(tenant.to_string()), zip),
+            None => (None, key.as_str()),
+        };
+        let (zip, day) = match zip.split_once('@') {
+            Some((zip, day)) => (zip.to_string(), Some(day.to_string())),
+            None => (zip.to_string(), None),
+        };
+        let refresh = async {
+            let backend = RateBackend::current(state);
+            call_provider(state, &backend, &zip).await
+        };
+        match tenants::scope(tenant, as_of::scope(day, refresh)).await {
+            Ok(_) => refreshed += 1,
+            Err(err) => warn!(error = %err, key = %key, "failed to refresh a cached rate"),
+        }
+    }
+    refreshed
+}
+
+/// Asks the rate provider of `backend` and caches its answer, failing fast
+/// while its circuit breaker is open, or when the upstream bulkhead stays
+/// full.
+async fn call_provider(
+    state: &AppState,
+    backend: &RateBackend,
+    zip: &str,
+) -> Result<Quote, AppError> {
+    let breaker = &backend.circuit_breaker;
+    breaker.try_acquire().map_err(AppError::CircuitOpen)?;
+    // Refreshes run in tasks of their own, outside the request's scope.
+    let lookup = as_of::scope(backend.as_of.clone(), backend.rates.rate(zip));
+    let result = state.upstream_bulkhead.run(lookup).await;
+    match &result {
+        Ok(quote) => {
+            breaker.record_success();
+            let key = backend.key(zip);
+            state.rate_cache.insert(&key, quote.clone());
+            share_rate(state, &key, quote);
+        }
+        Err(AppError::UpstreamUnavailable(_)) | Err(AppError::UpstreamTimeout(_)) => {
+            breaker.record_failure()
+        }
+        // A lookup turned away, or cut short by the callers' deadline,
+        // tells nothing of the provider's health.
+        Err(AppError::Overloaded(_)) | Err(AppError::DeadlineExceeded) => {}
+        Err(_) => breaker.record_success(),
+    }
+    result
+}
+
+/// Moves a priced order to `status`, if its current status allows it. With
+/// a payment service, confirming an order authorizes its total, unless a
+/// saga already did, and fails with `PaymentDeclined` when the payment is
+/// declined; cancelling it voids the authorization, keeps `reason` with the
+/// order and publishes an `OrderCancelled` event.
+pub async fn change_status(
+    state: &AppState,
+    order_id: i32,
+    status: OrderStatus,
+    reason: Option<String>,
+) -> Result<OrderRecord, AppError> {
+    let mut record = state
+        .orders
+        .get(order_id)?
+        .ok_or(AppError::OrderNotFound(order_id))?;
+    record
+        .status
+        .transition(status)
+        .map_err(|transition| AppError::InvalidTransition(order_id, transition))?;
+    let mut authorized = None;
+    if let Some(payments) = &state.payments {
+        match status {
+            OrderStatus::Confirmed if record.payment_authorization_id.is_none() => {
+                let currency = &state.config.currency.base;
+                let authorization = payments.authorize(&record.order, currency).await?;
+                info!(order_id, authorization_id = %authorization, "payment authorized");
+                record.payment_authorization_id = Some(authorization.clone());
+                authorized = Some(authorization);
+            }
+            OrderStatus::Cancelled => {
+                if let Some(authorization) = record.payment_authorization_id.take() {
+                    // The order is cancelled all the same.
+                    if let Err(err) = payments.void(&authorization).await {
+                        warn!(error = %err, order_id, authorization_id = %authorization, "failed to void the payment authorization");
+                        record.payment_authorization_id = Some(authorization);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    record.set_status(status);
+    if status == OrderStatus::Cancelled {
+        record.cancellation_reason = reason;
+    }
+    if let Err(err) = state.orders.update(record.clone()) {
+        if let (Some(authorization), Some(payments)) = (authorized, &state.payments) {
+            if let Err(err) = payments.void(&authorization).await {
+                warn!(error = %err, order_id, authorization_id = %authorization, "failed to void the payment authorization");
+            }
+        }
+        return Err(err.into());
+    }
+    info!(order_id, status = %status, "order status changed");
+    if status == OrderStatus::Cancelled {
+        let event = events::order_cancelled(&record);
+        state.activity.publish_cancelled(&event);
+        state.events.publish_cancelled(&event);
+    }
+    Ok(record)
+}
@@ -0,0 +1,165 @@
+//! The cache shared by the replicas of the service, behind the local rate
+//! cache and idempotency store: a replica missing a rate or an idempotency
+//! key locally looks it up there, so replicas share what any of them looked
+//! up or answered.
+
+use anyhow::Context;
+use domain::money::{self, Decimal};
+use domain::RateComponent;
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::{CacheBackend, CacheConfig};
+use crate::rates::Quote;
+use crate::redis::{Pool, Reply};
+
+/// Values by key, each kept for its own time to live.
+pub trait SharedCache: Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>>;
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Sets `key` only if it isn't set yet, and tells whether it was set.
+    fn set_if_missing<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>>;
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// The shared cache of the configuration, if any.
+pub fn from_config(config: &CacheConfig) -> anyhow::Result<Option<Arc<dyn SharedCache>>> {
+    match config.backend {
+        CacheBackend::Local => Ok(None),
+        CacheBackend::Redis => Ok(Some(Arc::new(RedisCache::from_config(config)?))),
+    }
+}
+
+/// A rate as kept in the shared cache, with when it was looked up, so each
+/// replica serves it for what is left of its time to live.
+#[derive(Serialize, Deserialize)]
+pub struct SharedRate {
+    #[serde(with = "money::json_number")]
+    rate: Decimal,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    components: Vec<RateComponent>,
+    /// Unix milliseconds the rate was looked up at.
+    cached_at: u64,
+}
+
+impl SharedRate {
+    pub fn new(quote: &Quote) -> Self {
+        let cached_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        Self {
+            rate: quote.rate,
+            components: quote.components.clone(),
+            cached_at,
+        }
+    }
+
+    pub fn cached_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.cached_at)
+    }
+
+    pub fn into_quote(self) -> Quote {
+        Quote {
+            rate: self.rate,
+            components: self.components,
+        }
+    }
+}
+
+/// A Redis server, its keys starting with `cache.redis.key_prefix`.
+pub struct RedisCache {
+    pool: Pool,
+    key_prefix: String,
+}
+
+impl RedisCache {
+    pub fn from_config(config: &CacheConfig) -> anyhow::Result<Self> {
+        let redis = &config.redis;
+        let pool = Pool::new(
+            &redis.url,
+            redis.pool_size,
+            Duration::from_millis(redis.timeout_ms),
+        )
+        .context("invalid cache.redis settings")?;
+        Ok(Self {
+            pool,
+            key_prefix: redis.key_prefix.clone(),
+        })
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.key_prefix, key)
+    }
+}
+
+impl SharedCache for RedisCache {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>> {
+        async move {
+            let key = self.key(key);
+            match self.pool.command(&[b"GET", key.as_bytes()]).await? {
+                Reply::Bulk(value) => Ok(value),
+                reply => anyhow::bail!("unexpected reply to GET: {:?}", reply),
+            }
+        }
+        .boxed()
+    }
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let (key, ttl) = (self.key(key), millis(ttl));
+            let args: [&[u8]; 5] = [b"SET", key.as_bytes(), value, b"PX", ttl.as_bytes()];
+            self.pool.command(&args).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn set_if_missing<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let (key, ttl) = (self.key(key), millis(ttl));
+            let args: [&[u8]; 6] = [b"SET", key.as_bytes(), value, b"PX", ttl.as_bytes(), b"NX"];
+            // A key already set answers nil.
+            Ok(self.pool.command(&args).await? != Reply::Bulk(None))
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let key = self.key(key);
+            self.pool.command(&[b"DEL", key.as_bytes()]).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Redis rejects a time to live of 0.
+fn millis(ttl: Duration) -> String {
+    ttl.as_millis().max(1).to_string()
+}
@@ -0,0 +1,209 @@
+use anyhow::{anyhow, bail, Context};
+use domain::{Decimal, Order};
+use figment::providers::{Format, Toml, Yaml};
+use figment::Figment;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::config::ShippingConfig;
+
+/// The rate table file, see `shipping_rates.example.toml`.
+#[derive(Debug, Deserialize)]
+struct TableFile {
+    default_zone: u32,
+    #[serde(default)]
+    zones: Vec<ZoneFile>,
+    rates: Vec<RateFile>,
+    #[serde(default)]
+    states: Vec<StateFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZoneFile {
+    zone: u32,
+    zip3: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateFile {
+    zone: u32,
+    base: Decimal,
+    per_lb: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct StateFile {
+    state: String,
+    zip3: Vec<String>,
+    #[serde(default)]
+    tax_shipping: bool,
+}
+
+/// An inclusive range of 3-digit zip code prefixes.
+#[derive(Debug, Clone, Copy)]
+struct Zip3Range(u16, u16);
+
+impl Zip3Range {
+    /// Parses `"750-799"` or `"885"`.
+    fn parse(range: &str) -> anyhow::Result<Self> {
+        let parse_zip3 = |zip3: &str| {
+            let zip3 = zip3.trim();
+            if zip3.len() != 3 {
+                bail!("{:?} is not a 3-digit zip prefix", zip3);
+            }
+            zip3.parse::<u16>()
+                .with_context(|| format!("{:?} is not a 3-digit zip prefix", zip3))
+        };
+        let (from, to) = match range.split_once('-') {
+            Some((from, to)) => (parse_zip3(from)?, parse_zip3(to)?),
+            None => {
+                let zip3 = parse_zip3(range)?;
+                (zip3, zip3)
+            }
+        };
+        Ok(Zip3Range(from, to))
+    }
+
+    fn contains(&self, zip3: u16) -> bool {
+        self.0 <= zip3 && zip3 <= self.1
+    }
+}
+
+#[derive(Debug)]
+struct ZoneRate {
+    base: Decimal,
+    per_lb: Decimal,
+}
+
+/// What shipping an order costs, and whether the destination taxes it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quote {
+    pub cost: Decimal,
+    pub taxable: bool,
+}
+
+/// Shipping costs by zone and weight. Destinations are put in a zone by the
+/// first three digits of their zip code; a zone's cost is a base charge plus a
+/// charge per started pound.
+#[derive(Debug, Default)]
+pub struct ShippingTable {
+    default_zone: u32,
+    zones: Vec<(Zip3Range, u32)>,
+    rates: HashMap<u32, ZoneRate>,
+    taxed: Vec<Zip3Range>,
+}
+
+impl ShippingTable {
+    /// The configured rate table, or an empty one charging nothing for shipping.
+    pub fn from_config(config: &ShippingConfig) -> anyhow::Result<Self> {
+        match &config.rate_table {
+            Some(path) => Self::load(Path::new(path))
+                .with_context(|| format!("invalid shipping rate table {}", path)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Reads a TOML or YAML rate table.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            bail!("file not found");
+        }
+        let is_yaml = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("yaml") | Some("yml")
+        );
+        let figment = if is_yaml {
+            Figment::from(Yaml::file(path))
+        } else {
+            Figment::from(Toml::file(path))
+        };
+        let file: TableFile = figment.extract()?;
+
+        let mut rates = HashMap::new();
+        for rate in file.rates {
+            rates.insert(
+                rate.zone,
+                ZoneRate {
+                    base: rate.base,
+                    per_lb: rate.per_lb,
+                },
+            );
+        }
+        let mut zones = Vec::new();
+        for zone in &file.zones {
+            if !rates.contains_key(&zone.zone) {
+                bail!("zone {} has no rate", zone.zone);
+            }
+            for range in &zone.zip3 {
+                zones.push((Zip3Range::parse(range)?, zone.zone));
+            }
+        }
+        if !rates.contains_key(&file.default_zone) {
+            bail!("default zone {} has no rate", file.default_zone);
+        }
+        let mut taxed = Vec::new();
+        for state in file.states.iter().filter(|state| state.tax_shipping) {
+            for range in &state.zip3 {
+                taxed.push(
+                    Zip3Range::parse(range)
+                        .map_err(|err| anyhow!("state {}: {}", state.state, err))?,
+                );
+            }
+        }
+        Ok(Self {
+            default_zone: file.default_zone,
+            zones,
+            rates,
+            taxed,
+        })
+    }
+
+    /// Prices shipping the order to its zip code.
+    pub fn quote(&self, order: &Order) -> Quote {
+        let rate = match self.rates.get(&self.zone(&order.shipping_zip)) {
+            Some(rate) => rate,
+            // Only the empty table has no rates.
+            None => {
+                return Quote {
+                    cost: Decimal::ZERO,
+                    taxable: false,
+                }
+            }
+        };
+        let taxable = match zip3(&order.shipping_zip) {
+            Some(zip3) => self.taxed.iter().any(|range| range.contains(zip3)),
+            None => false,
+        };
+        Quote {
+            cost: order
+                .rounding()
+                .round_cents(rate.base + rate.per_lb * order.weight().ceil()),
+            taxable,
+        }
+    }
+
+    fn zone(&self, zip: &str) -> u32 {
+        zip3(zip)
+            .and_then(|zip3| {
+                self.zones
+                    .iter()
+                    .find(|(range, _)| range.contains(zip3))
+                    .map(|(_, zone)| *zone)
+            })
+            .unwrap_or(self.default_zone)
+    }
+}
+
+fn zip3(zip: &str) -> Option<u16> {
+    zip.get(..3)?.parse().ok()
+}
+
+/// Adds shipping to the order. Call before applying the sales tax rate, which
+/// taxes the shipping charge where the destination state does.
+pub fn apply(table: &ShippingTable, order: &mut Order) {
+    order.normalize();
+    let quote = table.quote(order);
+    order.shipping = quote.cost;
+    order.shipping_taxable = quote.taxable;
+}
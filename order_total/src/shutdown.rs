@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::info;
+
+/// Coordinates a graceful shutdown: once triggered, the server stops accepting
+/// connections, new requests are turned away with 503 and in-flight requests
+/// get up to the drain timeout to finish.
+#[derive(Debug)]
+pub struct Shutdown {
+    draining: AtomicBool,
+    notify: Notify,
+    pub drain_timeout: Duration,
+}
+
+impl Shutdown {
+    pub fn new(drain_timeout: Duration) -> Self {
+        Self {
+            draining: AtomicBool::new(false),
+            notify: Notify::new(),
+            drain_timeout,
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn trigger(&self, reason: &str) {
+        if !self.draining.swap(true, Ordering::SeqCst) {
+            info!(reason, "shutting down, draining in-flight requests");
+            self.notify.notify_waiters();
+        }
+    }
+
+    /// Completes once a shutdown has been triggered.
+    pub async fn triggered(&self) {
+        loop {
+            // Created before checking the flag so a concurrent trigger isn't missed.
+            let notified = self.notify.notified();
+            if self.is_draining() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Triggers `shutdown` on SIGTERM or SIGINT.
+///
+/// WasmEdge doesn't forward signals to the guest, so under Wasm the shutdown is
+/// triggered with `POST /admin/shutdown` instead (e.g. from a preStop hook).
+#[cfg(unix)]
+pub async fn listen_for_signals(shutdown: &Shutdown) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut term, mut int) = match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(term), Ok(int)) => (term, int),
+        _ => return,
+    };
+    tokio::select! {
+        _ = term.recv() => shutdown.trigger("SIGTERM"),
+        _ = int.recv() => shutdown.trigger("SIGINT"),
+    }
+}
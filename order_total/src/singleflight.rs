@@ -0,0 +1,74 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use crate::deadline::SharedDeadline;
+
+/// A call in flight.
+struct Call<T> {
+    /// Tells the call apart from a later call for the same key.
+    id: u64,
+    future: Shared<BoxFuture<'static, T>>,
+    deadline: SharedDeadline,
+}
+
+struct Inner<T> {
+    calls: HashMap<String, Call<T>>,
+    next_id: u64,
+}
+
+/// Coalesces identical concurrent calls: callers asking for a key while a call
+/// for it is in flight wait for that call and share its outcome instead of
+/// making their own. The call runs until the latest deadline of its callers.
+pub struct SingleFlight<T: Clone> {
+    inner: Mutex<Inner<T>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> SingleFlight<T> {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                calls: HashMap::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    /// The outcome of `call` for `key`, or of the call already in flight for
+    /// it, and whether it was the latter. A call whose callers all went away
+    /// is carried on by the next caller for the key.
+    pub async fn run<F>(&self, key: &str, call: F) -> (T, bool)
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        let (id, shared, joined) = {
+            let mut inner = self.inner.lock().unwrap();
+            match inner.calls.get(key) {
+                Some(in_flight) => {
+                    in_flight.deadline.join();
+                    (in_flight.id, in_flight.future.clone(), true)
+                }
+                None => {
+                    inner.next_id += 1;
+                    let id = inner.next_id;
+                    let deadline = SharedDeadline::current();
+                    let shared = deadline.clone().scope(call).boxed().shared();
+                    let in_flight = Call {
+                        id,
+                        future: shared.clone(),
+                        deadline,
+                    };
+                    inner.calls.insert(key.to_string(), in_flight);
+                    (id, shared, false)
+                }
+            }
+        };
+        let outcome = shared.await;
+        let mut inner = self.inner.lock().unwrap();
+        if matches!(inner.calls.get(key), Some(in_flight) if in_flight.id == id) {
+            inner.calls.remove(key);
+        }
+        (outcome, joined)
+    }
+}
@@ -0,0 +1,294 @@
+//! The dependencies of the handlers: the configuration, the HTTP clients, the
+//! rate providers, the caches, the order store, the publishers and the
+//! metrics. They are built once at startup and handed to every front end as
+//! an `AppState`, which handlers clone or borrow, so a handler can be run
+//! against other dependencies, such as a table rate provider or an in-memory
+//! order store, simply by building the state with them.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::activity::ActivityStream;
+use crate::address::{self, AddressValidator};
+use crate::audit::{self, AuditLog};
+use crate::auth::Authenticator;
+use crate::bulkhead::Bulkhead;
+use crate::cache::RateCache;
+use crate::chaos::FaultInjector;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config::{AppConfig, RunMode, UpstreamConfig};
+use crate::cors::Cors;
+use crate::dead_letter::QueueDeadLetters;
+use crate::dedup::Deduplicator;
+use crate::discounts::Discounts;
+use crate::egress::Egress;
+use crate::error::AppError;
+use crate::events::{self, EventPublisher};
+use crate::exchange::{self, ExchangeRateProvider};
+use crate::exemptions::{self, ExemptionRegistry};
+use crate::flags::Flags;
+use crate::health::ReadinessCheck;
+use crate::hedging::Hedging;
+use crate::idempotency::IdempotencyStore;
+use crate::inventory::Inventory;
+use crate::load_shedding::LoadShedder;
+use crate::metrics::Metrics;
+use crate::payments::Payments;
+use crate::rate_limit::RateLimiter;
+use crate::rate_overrides::RateOverrides;
+use crate::rates::{self, Quote, TableProvider, TaxRateProvider};
+use crate::saga::{self, SagaStore};
+use crate::scheduler::Scheduler;
+use crate::shared_cache::{self, SharedCache};
+use crate::shipping::ShippingTable;
+use crate::singleflight::SingleFlight;
+use crate::store::{self, OrderStore};
+use crate::tenants::Tenants;
+use crate::webhooks::Webhooks;
+
+/// Everything the handlers depend on. Cloning it is cheap: every dependency
+/// is shared.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: &'static AppConfig,
+    /// For calls to other services than the sales tax rate service.
+    pub http_client: reqwest::Client,
+    pub metrics: Arc<Metrics>,
+    pub rates: Arc<dyn TaxRateProvider>,
+    /// The rate table used while `rates` is unavailable, when enabled.
+    pub fallback_rates: Option<Arc<TableProvider>>,
+    /// Rates charged instead of those of `rates`, for some zip codes.
+    pub rate_overrides: Arc<RateOverrides>,
+    pub rate_cache: Arc<RateCache>,
+    /// Shares rates and idempotency keys with the other replicas, behind
+    /// `rate_cache` and `idempotency`, when enabled.
+    pub shared_cache: Option<Arc<dyn SharedCache>>,
+    pub rate_lookups: Arc<SingleFlight<Result<Quote, AppError>>>,
+    pub circuit_breaker: Arc<CircuitBreaker>,
+    /// Caps the rate lookups in flight.
+    pub upstream_bulkhead: Arc<Bulkhead>,
+    /// Caps the `/compute` and `/compute_batch` requests in flight.
+    pub compute_bulkhead: Arc<Bulkhead>,
+    pub readiness: Arc<ReadinessCheck>,
+    pub exchange: Arc<dyn ExchangeRateProvider>,
+    pub exemptions: Arc<dyn ExemptionRegistry>,
+    /// Normalizes shipping addresses, when enabled.
+    pub address_validator: Option<Arc<dyn AddressValidator>>,
+    /// Reserves stock for orders before they are priced, when enabled.
+    pub inventory: Option<Arc<Inventory>>,
+    /// Authorizes the totals of orders confirmed or priced by the saga, when
+    /// enabled.
+    pub payments: Option<Arc<Payments>>,
+    /// Keeps the sagas pricing orders, when orders are priced by a saga.
+    pub sagas: Option<Arc<dyn SagaStore>>,
+    pub discounts: Arc<Discounts>,
+    pub shipping: Arc<ShippingTable>,
+    pub orders: Arc<dyn OrderStore>,
+    pub audit: Option<Arc<AuditLog>>,
+    pub events: Arc<dyn EventPublisher>,
+    pub webhooks: Arc<Webhooks>,
+    /// The orders the queue consumer failed to price, in the `queue` run
+    /// mode.
+    pub dead_letters: Option<Arc<QueueDeadLetters>>,
+    pub activity: Arc<ActivityStream>,
+    pub idempotency: Arc<IdempotencyStore>,
+    pub dedup: Arc<Deduplicator>,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Sheds API requests while the service is saturated, when enabled.
+    pub load_shedder: Option<Arc<LoadShedder>>,
+    pub auth: Arc<Authenticator>,
+    pub cors: Arc<Cors>,
+    /// Faults injected into requests and rate lookups, when enabled.
+    pub faults: Option<Arc<FaultInjector>>,
+    /// Turns features on per caller.
+    pub flags: Arc<Flags>,
+    /// The tenants, and their own rate providers and limits.
+    pub tenants: Arc<Tenants>,
+    /// The periodic jobs, started by `init`.
+    pub scheduler: Arc<Scheduler>,
+}
+
+impl AppState {
+    /// The dependencies of the configuration. Reads the shipping, tax and
+    /// exchange rate tables, opens the order store, the audit log and the
+    /// dead-letter logs and starts the event publisher and the webhooks, so a
+    /// broken one fails here. Must be called within the runtime, which runs
+    /// the deliveries.
+    pub fn from_config(config: &'static AppConfig) -> anyhow::Result<Self> {
+        let metrics = Arc::new(Metrics::new());
+        let http_client = build_http_client(&config.upstream, Egress::shared(config)?)?;
+        let upstream_client = build_upstream_client(&config.upstream, Egress::upstream(config)?)?;
+        let faults = FaultInjector::from_config(&config.chaos)?.map(Arc::new);
+        let flags = Flags::from_config(&config.flags, http_client.clone(), metrics.clone())?;
+        let tenants =
+            Tenants::from_config(config, upstream_client.clone(), &flags, &metrics, &faults)?;
+        let rates: Arc<dyn TaxRateProvider> = Arc::from(rates::from_config(
+            &config.rates,
+            &config.upstream,
+            &config.retry,
+            Hedging::from_config(&config.hedging, flags.clone()),
+            upstream_client,
+            metrics.clone(),
+            faults.clone(),
+        )?);
+        let shared_cache = shared_cache::from_config(&config.cache)?;
+        let readiness = ReadinessCheck::new(
+            rates.clone(),
+            Duration::from_millis(config.readiness.timeout_ms),
+            Duration::from_millis(config.readiness.cache_ms),
+        );
+        Ok(Self {
+            config,
+            fallback_rates: rates::fallback(&config.rates.fallback)?.map(Arc::new),
+            rate_overrides: Arc::new(RateOverrides::from_config(&config.rates.overrides)?),
+            rate_cache: Arc::new(RateCache::new(
+                Duration::from_secs(config.cache.ttl_secs),
+                Duration::from_secs(config.cache.stale_secs),
+                config.cache.max_entries,
+            )),
+            rate_lookups: Arc::new(SingleFlight::new()),
+            circuit_breaker: Arc::new(CircuitBreaker::new(
+                config.circuit_breaker.failure_threshold,
+                Duration::from_millis(config.circuit_breaker.cooldown_ms),
+            )),
+            upstream_bulkhead: Arc::new(Bulkhead::new(
+                "upstream",
+                &config.bulkheads.upstream,
+                &config.priority,
+                &metrics,
+            )),
+            compute_bulkhead: Arc::new(Bulkhead::new(
+                "compute",
+                &config.bulkheads.compute,
+                &config.priority,
+                &metrics,
+            )),
+            readiness: Arc::new(readiness),
+            exchange: Arc::from(exchange::from_config(&config.currency)?),
+            exemptions: Arc::from(exemptions::from_config(
+                &config.exemptions,
+                http_client.clone(),
+            )?),
+            address_validator: address::from_config(&config.address, http_client.clone())
+                .map(Arc::from),
+            inventory: Inventory::from_config(&config.inventory, http_client.clone(), &metrics)
+                .map(Arc::new),
+            payments: Payments::from_config(&config.payments, http_client.clone()).map(Arc::new),
+            sagas: saga::from_config(&config.saga)?.map(Arc::from),
+            discounts: Arc::new(Discounts::new(&config.discounts)),
+            shipping: Arc::new(ShippingTable::from_config(&config.shipping)?),
+            orders: Arc::from(store::from_config(&config.persistence)?),
+            audit: audit::from_config(&config.audit)?.map(Arc::new),
+            events: Arc::from(events::from_config(&config.events)?),
+            webhooks: Arc::new(Webhooks::from_config(
+                &config.webhooks,
+                &config.tenants,
+                http_client.clone(),
+                metrics.clone(),
+            )?),
+            dead_letters: match config.mode {
+                RunMode::Queue => Some(Arc::new(QueueDeadLetters::from_config(&config.queue)?)),
+                RunMode::Http => None,
+            },
+            activity: Arc::new(ActivityStream::new(&config.stream)),
+            idempotency: Arc::new(IdempotencyStore::new(
+                Duration::from_secs(config.idempotency.ttl_secs),
+                config.idempotency.max_entries,
+                shared_cache.clone(),
+                metrics.clone(),
+            )),
+            dedup: Arc::new(Deduplicator::new(
+                &config.dedup,
+                &config.tenants,
+                metrics.clone(),
+            )),
+            rate_limiter: Arc::new(RateLimiter::new(&config.rate_limit)),
+            load_shedder: LoadShedder::from_config(&config.load_shedding, &metrics).map(Arc::new),
+            auth: Arc::new(Authenticator::new(
+                &config.auth,
+                &config.tenants,
+                http_client.clone(),
+            )),
+            cors: Arc::new(Cors::from_config(&config.cors)?),
+            shared_cache,
+            rates,
+            metrics,
+            http_client,
+            faults,
+            flags,
+            tenants: Arc::new(tenants),
+            scheduler: Arc::new(Scheduler::from_config(&config.scheduler)?),
+        })
+    }
+}
+
+/// One client, and so one connection pool, shared by every outbound call.
+fn build_http_client(config: &UpstreamConfig, egress: Egress) -> anyhow::Result<reqwest::Client> {
+    Ok(upstream_tls(client_builder(config, egress), config)?.build()?)
+}
+
+/// The client of the sales tax rate service, which may speak HTTP/2 to it:
+/// negotiated for an `https` URL, with prior knowledge for an `http` one
+/// called directly, as a proxy forwards HTTP/1.1. Other services get
+/// `http_client`, as they may not speak HTTP/2.
+fn build_upstream_client(
+    config: &UpstreamConfig,
+    egress: Egress,
+) -> anyhow::Result<reqwest::Client> {
+    let proxied = config
+        .urls()
+        .iter()
+        .filter_map(|url| reqwest::Url::parse(url).ok())
+        .any(|url| egress.proxy_for(&url).is_some());
+    let builder = client_builder(config, egress);
+    let builder = if !config.http2 {
+        builder.http1_only()
+    } else {
+        let interval = Duration::from_millis(config.http2_keep_alive_interval_ms);
+        let builder = builder
+            .http2_keep_alive_interval(Some(interval).filter(|interval| !interval.is_zero()))
+            .http2_keep_alive_timeout(Duration::from_millis(config.http2_keep_alive_timeout_ms))
+            .http2_adaptive_window(true);
+        if config.url.starts_with("http://") && !proxied {
+            builder.http2_prior_knowledge()
+        } else {
+            builder
+        }
+    };
+    Ok(upstream_tls(builder, config)?.build()?)
+}
+
+/// The connection pool settings shared by both clients, and the proxies of
+/// `egress`.
+fn client_builder(config: &UpstreamConfig, egress: Egress) -> reqwest::ClientBuilder {
+    let keepalive = Duration::from_millis(config.tcp_keepalive_ms);
+    reqwest::Client::builder()
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_millis(config.pool_idle_timeout_ms))
+        .tcp_keepalive(Some(keepalive).filter(|keepalive| !keepalive.is_zero()))
+        .proxy(egress.into_proxy())
+}
+
+/// Adds the CA and the client certificate configured for the sales tax rate
+/// service.
+#[cfg(feature = "tls")]
+fn upstream_tls(
+    builder: reqwest::ClientBuilder,
+    config: &UpstreamConfig,
+) -> anyhow::Result<reqwest::ClientBuilder> {
+    crate::tls::configure_client(builder, config)
+}
+
+#[cfg(not(feature = "tls"))]
+fn upstream_tls(
+    builder: reqwest::ClientBuilder,
+    config: &UpstreamConfig,
+) -> anyhow::Result<reqwest::ClientBuilder> {
+    let configured = config.ca_cert_path.is_some()
+        || config.client_cert_path.is_some()
+        || config.client_key_path.is_some();
+    if configured {
+        anyhow::bail!("order_total was built without the `tls` feature");
+    }
+    Ok(builder)
+}
@@ -0,0 +1,737 @@
+use anyhow::Context;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use domain::money::{self, Decimal};
+use domain::Order;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::config::{PersistenceBackend, PersistenceConfig};
+use crate::lifecycle::OrderStatus;
+use crate::revisions::Revision;
+use crate::search::{SearchHit, SearchIndex};
+
+/// A priced order as kept by the store.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OrderRecord {
+    pub order: Order,
+    /// The sales tax rate the order was priced at.
+    #[serde(with = "money::json_number")]
+    #[schema(value_type = f64)]
+    pub rate: Decimal,
+    /// RFC 3339 time of pricing.
+    pub priced_at: String,
+    #[serde(default)]
+    pub status: OrderStatus,
+    /// RFC 3339 time of the last status change after pricing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_changed_at: Option<String>,
+    /// The payment service's authorization of the total, once the order was
+    /// confirmed or priced by a saga.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_authorization_id: Option<String>,
+    /// Why the order was cancelled, when it was given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cancellation_reason: Option<String>,
+    /// Every pricing of the order, oldest first, once it was priced again
+    /// after a change of address.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revisions: Vec<Revision>,
+}
+
+impl OrderRecord {
+    pub fn new(order: &Order, rate: Decimal) -> Self {
+        Self {
+            order: order.clone(),
+            rate,
+            priced_at: now(),
+            status: OrderStatus::Priced,
+            status_changed_at: None,
+            payment_authorization_id: None,
+            cancellation_reason: None,
+            revisions: Vec::new(),
+        }
+    }
+
+    /// Replaces the order with `order`, priced again at `rate`, keeping the
+    /// earlier pricing in `revisions`.
+    pub fn revise(&mut self, order: Order, rate: Decimal) {
+        if self.revisions.is_empty() {
+            self.revisions.push(Revision::new(1, self));
+        }
+        self.order = order;
+        self.rate = rate;
+        self.priced_at = now();
+        let revision = self.revisions.len() as u32 + 1;
+        self.revisions.push(Revision::new(revision, self));
+    }
+
+    /// Moves the order to `status`, stamping the time of the change.
+    pub fn set_status(&mut self, status: OrderStatus) {
+        self.status = status;
+        self.status_changed_at = Some(now());
+    }
+}
+
+fn now() -> String {
+    humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+}
+
+/// One page of the orders matching a query, in its order.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page {
+    pub orders: Vec<OrderRecord>,
+    /// Orders matching the query, on every page.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    /// Where the next page starts, when there is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// The link to the next page, when there is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSort {
+    /// By when the order was last priced.
+    #[default]
+    CreatedAt,
+    Total,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Which orders to list, and how. Filters left unset match every order;
+/// ranges include their bounds, except `priced_before`.
+#[derive(Debug, Clone)]
+pub struct OrderQuery {
+    /// Shipping zip codes starting with it.
+    pub zip: Option<String>,
+    pub priced_from: Option<SystemTime>,
+    pub priced_before: Option<SystemTime>,
+    pub min_total: Option<Decimal>,
+    pub max_total: Option<Decimal>,
+    /// Whether cancelled orders are listed too.
+    pub include_cancelled: bool,
+    pub sort: OrderSort,
+    pub direction: SortDirection,
+    /// Lists the orders after the one the cursor was taken at.
+    pub cursor: Option<Cursor>,
+    /// Orders skipped, after the cursor.
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for OrderQuery {
+    /// Every order, most recently priced first.
+    fn default() -> Self {
+        Self {
+            zip: None,
+            priced_from: None,
+            priced_before: None,
+            min_total: None,
+            max_total: None,
+            include_cancelled: true,
+            sort: OrderSort::default(),
+            direction: SortDirection::default(),
+            cursor: None,
+            offset: 0,
+            limit: usize::MAX,
+        }
+    }
+}
+
+impl OrderQuery {
+    fn matches(&self, record: &OrderRecord) -> bool {
+        let order = &record.order;
+        let priced_at = || humantime::parse_rfc3339(&record.priced_at).ok();
+        (self.include_cancelled || record.status != OrderStatus::Cancelled)
+            && self
+                .zip
+                .as_ref()
+                .is_none_or(|zip| order.shipping_zip.starts_with(zip.as_str()))
+            && self.min_total.is_none_or(|min| order.total >= min)
+            && self.max_total.is_none_or(|max| order.total <= max)
+            && self
+                .priced_from
+                .is_none_or(|from| priced_at().is_some_and(|at| at >= from))
+            && self
+                .priced_before
+                .is_none_or(|before| priced_at().is_some_and(|at| at < before))
+    }
+
+    /// The position of `record`, saved as `seq`, in the sort order, ascending.
+    fn key(&self, seq: u64, record: &OrderRecord) -> Cursor {
+        Cursor {
+            total: match self.sort {
+                OrderSort::CreatedAt => Decimal::ZERO,
+                OrderSort::Total => record.order.total,
+            },
+            seq,
+        }
+    }
+}
+
+/// A position in a listing: the sort key of the last order of a page. Stays
+/// valid while orders are priced, as it doesn't count them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    total: Decimal,
+    seq: u64,
+}
+
+impl Cursor {
+    /// An opaque token for clients.
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.total, self.seq))
+    }
+
+    pub fn decode(token: &str) -> Option<Cursor> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(token).ok()?).ok()?;
+        let (total, seq) = decoded.split_once(':')?;
+        Some(Cursor {
+            total: total.parse().ok()?,
+            seq: seq.parse().ok()?,
+        })
+    }
+}
+
+/// A priced order whose `OrderPriced` event waits in the outbox.
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: u64,
+    pub record: OrderRecord,
+}
+
+/// Where priced orders are kept. Pricing an order again under the same
+/// `order_id` replaces the earlier record.
+///
+/// With the outbox, the event of a priced order is kept along with it, in the
+/// same write, until `mark_sent`, so it can't be lost between the two.
+pub trait OrderStore: Send + Sync {
+    fn save(&self, record: OrderRecord) -> anyhow::Result<()>;
+    /// Replaces a stored record without moving it in the listing, e.g. after a
+    /// status change.
+    fn update(&self, record: OrderRecord) -> anyhow::Result<()>;
+    fn get(&self, order_id: i32) -> anyhow::Result<Option<OrderRecord>>;
+    fn list(&self, query: &OrderQuery) -> anyhow::Result<Page>;
+    /// Up to `limit` of the orders whose shipping address or zip code match
+    /// the words of `query`, best match first.
+    fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<SearchHit>>;
+    /// Saves `record` and, in the same write, puts its event in the outbox.
+    fn save_with_event(&self, record: OrderRecord) -> anyhow::Result<()>;
+    /// Up to `limit` events waiting in the outbox, oldest first.
+    fn outbox(&self, limit: usize) -> anyhow::Result<Vec<OutboxEntry>>;
+    /// Takes the event `id` out of the outbox once published.
+    fn mark_sent(&self, id: u64) -> anyhow::Result<()>;
+    /// Removes the orders last priced or changed before `before`, and returns
+    /// how many there were. Their events stay in the outbox.
+    fn purge(&self, before: SystemTime) -> anyhow::Result<usize>;
+}
+
+/// The store selected by the configuration.
+pub fn from_config(config: &PersistenceConfig) -> anyhow::Result<Box<dyn OrderStore>> {
+    Ok(match config.backend {
+        PersistenceBackend::Memory => Box::new(MemoryStore::new(config.max_orders)),
+        PersistenceBackend::File => {
+            let path = Path::new(&config.path);
+            Box::new(
+                FileStore::open(path, config.max_orders, config.compact_after)
+                    .with_context(|| format!("cannot open order store {}", config.path))?,
+            )
+        }
+    })
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Records by order id, with the sequence number of their last save.
+    records: HashMap<i32, (u64, OrderRecord)>,
+    /// Order ids by sequence number, for listing in pricing order.
+    sequence: BTreeMap<u64, i32>,
+    next: u64,
+    /// Priced orders waiting for their event to be published, by outbox id.
+    outbox: BTreeMap<u64, OrderRecord>,
+    next_outbox_id: u64,
+    /// The stored records by the words of their shipping address and zip.
+    index: SearchIndex,
+}
+
+impl Inner {
+    fn save(&mut self, record: OrderRecord, max_orders: usize) {
+        let order_id = record.order.order_id;
+        self.remove(order_id);
+        if max_orders > 0 && self.records.len() >= max_orders {
+            if let Some(&oldest) = self.sequence.keys().next() {
+                let order_id = self.sequence[&oldest];
+                self.remove(order_id);
+            }
+        }
+        let seq = self.next;
+        self.next += 1;
+        self.sequence.insert(seq, order_id);
+        self.index.add(&record.order);
+        self.records.insert(order_id, (seq, record));
+    }
+
+    fn update(&mut self, record: OrderRecord) -> anyhow::Result<()> {
+        match self.records.get_mut(&record.order.order_id) {
+            Some((_, stored)) => {
+                self.index.remove(&stored.order);
+                self.index.add(&record.order);
+                *stored = record;
+            }
+            None => anyhow::bail!("order {} is not stored", record.order.order_id),
+        }
+        Ok(())
+    }
+
+    /// Up to `limit` of the records matching `query`, best first, and most
+    /// recently priced first among equals.
+    fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        let mut matching: Vec<(u32, u64, &OrderRecord)> = self
+            .index
+            .search(query)
+            .into_iter()
+            .filter_map(|(order_id, score)| {
+                let (seq, record) = self.records.get(&order_id)?;
+                Some((score, *seq, record))
+            })
+            .collect();
+        matching.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.1.cmp(&a.1)));
+        matching
+            .into_iter()
+            .take(limit)
+            .map(|(score, _, record)| SearchHit::new(record.clone(), score, query))
+            .collect()
+    }
+
+    /// The ids of the orders last priced or changed before `before`.
+    fn changed_before(&self, before: SystemTime) -> Vec<i32> {
+        self.records
+            .values()
+            .filter(|(_, record)| {
+                let changed = record
+                    .status_changed_at
+                    .as_deref()
+                    .unwrap_or(&record.priced_at);
+                humantime::parse_rfc3339(changed).is_ok_and(|changed| changed < before)
+            })
+            .map(|(_, record)| record.order.order_id)
+            .collect()
+    }
+
+    fn list(&self, query: &OrderQuery) -> Page {
+        let mut matching: Vec<(Cursor, &OrderRecord)> = self
+            .sequence
+            .iter()
+            .map(|(seq, order_id)| (*seq, &self.records[order_id].1))
+            .filter(|(_, record)| query.matches(record))
+            .map(|(seq, record)| (query.key(seq, record), record))
+            .collect();
+        matching.sort_by_key(|(key, _)| *key);
+        if query.direction == SortDirection::Desc {
+            matching.reverse();
+        }
+        let total = matching.len();
+        let after_cursor = |key: &Cursor| match (query.cursor, query.direction) {
+            (None, _) => true,
+            (Some(cursor), SortDirection::Asc) => *key > cursor,
+            (Some(cursor), SortDirection::Desc) => *key < cursor,
+        };
+        let mut page = matching
+            .into_iter()
+            .skip_while(|(key, _)| !after_cursor(key))
+            .skip(query.offset);
+        let orders: Vec<(Cursor, &OrderRecord)> = page.by_ref().take(query.limit).collect();
+        let next_cursor = match (orders.last(), page.next()) {
+            (Some((key, _)), Some(_)) => Some(key.encode()),
+            _ => None,
+        };
+        Page {
+            orders: orders
+                .into_iter()
+                .map(|(_, record)| record.clone())
+                .collect(),
+            total,
+            offset: query.offset,
+            limit: query.limit,
+            next_cursor,
+            next: None,
+        }
+    }
+
+    fn remove(&mut self, order_id: i32) {
+        if let Some((seq, record)) = self.records.remove(&order_id) {
+            self.sequence.remove(&seq);
+            self.index.remove(&record.order);
+        }
+    }
+
+    /// Puts `record` back under the sequence number it was saved with.
+    fn restore_at(&mut self, seq: u64, record: OrderRecord) {
+        let order_id = record.order.order_id;
+        self.remove(order_id);
+        self.next = self.next.max(seq + 1);
+        self.sequence.insert(seq, order_id);
+        self.index.add(&record.order);
+        self.records.insert(order_id, (seq, record));
+    }
+
+    /// Puts the event of `record` in the outbox under `id`.
+    fn add_to_outbox(&mut self, id: u64, record: OrderRecord) {
+        self.next_outbox_id = self.next_outbox_id.max(id + 1);
+        self.outbox.insert(id, record);
+    }
+}
+
+/// Keeps orders in memory; the oldest make room when `max_orders` is reached.
+#[derive(Debug)]
+pub struct MemoryStore {
+    /// 0 keeps every order.
+    max_orders: usize,
+    inner: Mutex<Inner>,
+}
+
+impl MemoryStore {
+    pub fn new(max_orders: usize) -> Self {
+        Self {
+            max_orders,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Replays a record of the file store: one with the pricing time of the
+    /// stored record is a status change, anything else a new pricing.
+    fn restore(&self, record: OrderRecord) -> anyhow::Result<()> {
+        let repriced = match self.get(record.order.order_id)? {
+            Some(stored) => stored.priced_at != record.priced_at,
+            None => true,
+        };
+        if repriced {
+            self.save(record)
+        } else {
+            self.update(record)
+        }
+    }
+}
+
+impl OrderStore for MemoryStore {
+    fn save(&self, record: OrderRecord) -> anyhow::Result<()> {
+        self.inner.lock().unwrap().save(record, self.max_orders);
+        Ok(())
+    }
+
+    fn save_with_event(&self, record: OrderRecord) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_outbox_id;
+        inner.add_to_outbox(id, record.clone());
+        inner.save(record, self.max_orders);
+        Ok(())
+    }
+
+    fn outbox(&self, limit: usize) -> anyhow::Result<Vec<OutboxEntry>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .outbox
+            .iter()
+            .take(limit)
+            .map(|(id, record)| OutboxEntry {
+                id: *id,
+                record: record.clone(),
+            })
+            .collect())
+    }
+
+    fn mark_sent(&self, id: u64) -> anyhow::Result<()> {
+        self.inner.lock().unwrap().outbox.remove(&id);
+        Ok(())
+    }
+
+    fn purge(&self, before: SystemTime) -> anyhow::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        let stale = inner.changed_before(before);
+        for &order_id in &stale {
+            inner.remove(order_id);
+        }
+        Ok(stale.len())
+    }
+
+    fn update(&self, record: OrderRecord) -> anyhow::Result<()> {
+        self.inner.lock().unwrap().update(record)
+    }
+
+    fn get(&self, order_id: i32) -> anyhow::Result<Option<OrderRecord>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .records
+            .get(&order_id)
+            .map(|(_, record)| record.clone()))
+    }
+
+    fn list(&self, query: &OrderQuery) -> anyhow::Result<Page> {
+        Ok(self.inner.lock().unwrap().list(query))
+    }
+
+    fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<SearchHit>> {
+        Ok(self.inner.lock().unwrap().search(query, limit))
+    }
+}
+
+/// A line of the file store.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    /// A priced order with its event in the outbox.
+    Outboxed {
+        outbox_id: u64,
+        record: OrderRecord,
+    },
+    /// The event of `Outboxed { outbox_id }` was published.
+    Sent {
+        sent_outbox_id: u64,
+    },
+    /// The order was purged.
+    Removed {
+        removed_order_id: i32,
+    },
+    /// The first line of a compacted file: where numbering goes on.
+    Counters {
+        next_seq: u64,
+        next_outbox_id: u64,
+    },
+    /// An event in the outbox, in a compacted file.
+    Pending {
+        pending_outbox_id: u64,
+        event: OrderRecord,
+    },
+    /// A stored record and its place in the listing, in a compacted file.
+    Kept {
+        seq: u64,
+        record: OrderRecord,
+    },
+    Record(OrderRecord),
+}
+
+/// Appends every record to a JSON lines file and replays it at startup, with
+/// the orders served from memory. Under WasmEdge the file's directory must be
+/// mapped with `--dir`. An order and its outbox event are one line, and
+/// events still in the outbox at startup are published again. Purged orders
+/// are kept in the file, with a line removing them, and replaced records and
+/// published events too, until the file is compacted: at startup, once
+/// `compact_after` of its lines are superseded, it is rewritten with only what
+/// the store holds.
+#[derive(Debug)]
+pub struct FileStore {
+    memory: MemoryStore,
+    file: Mutex<File>,
+}
+
+impl FileStore {
+    /// Opens the file at `path`, compacting it once `compact_after` of its
+    /// lines are superseded; 0 never compacts it.
+    pub fn open(path: &Path, max_orders: usize, compact_after: usize) -> anyhow::Result<Self> {
+        let memory = MemoryStore::new(max_orders);
+        if path.exists() {
+            let contents = std::fs::read(path)?;
+            let mut lines = 0;
+            let mut end = 0;
+            for (number, line) in contents.split(|byte| *byte == b'\n').enumerate() {
+                let start = end;
+                end += line.len() + 1;
+                if line.trim_ascii().is_empty() {
+                    continue;
+                }
+                let line: Line = match serde_json::from_slice(line) {
+                    Ok(line) => line,
+                    // A crash in the middle of an append tears the last
+                    // record; it is cut off so the next one starts its own
+                    // line.
+                    Err(err) if end >= contents.len() => {
+                        warn!(
+                            path = %path.display(),
+                            line = number + 1,
+                            error = %err,
+                            "dropping the torn last record of the order store"
+                        );
+                        OpenOptions::new()
+                            .write(true)
+                            .open(path)?
+                            .set_len(start as u64)?;
+                        break;
+                    }
+                    Err(err) => return Err(err).with_context(|| format!("line {}", number + 1)),
+                };
+                if !matches!(line, Line::Counters { .. }) {
+                    lines += 1;
+                }
+                match line {
+                    Line::Outboxed { outbox_id, record } => {
+                        memory
+                            .inner
+                            .lock()
+                            .unwrap()
+                            .add_to_outbox(outbox_id, record.clone());
+                        memory.restore(record)?;
+                    }
+                    Line::Sent { sent_outbox_id } => memory.mark_sent(sent_outbox_id)?,
+                    Line::Removed { removed_order_id } => {
+                        memory.inner.lock().unwrap().remove(removed_order_id)
+                    }
+                    Line::Counters {
+                        next_seq,
+                        next_outbox_id,
+                    } => {
+                        let mut inner = memory.inner.lock().unwrap();
+                        inner.next = next_seq;
+                        inner.next_outbox_id = next_outbox_id;
+                    }
+                    Line::Pending {
+                        pending_outbox_id,
+                        event,
+                    } => memory
+                        .inner
+                        .lock()
+                        .unwrap()
+                        .add_to_outbox(pending_outbox_id, event),
+                    Line::Kept { seq, record } => {
+                        memory.inner.lock().unwrap().restore_at(seq, record)
+                    }
+                    Line::Record(record) => memory.restore(record)?,
+                }
+            }
+            let inner = memory.inner.lock().unwrap();
+            let superseded = lines.saturating_sub(inner.outbox.len() + inner.records.len());
+            if compact_after > 0 && superseded >= compact_after {
+                compact(path, &inner)
+                    .with_context(|| format!("cannot compact {}", path.display()))?;
+                info!(path = %path.display(), superseded, "compacted the order store");
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            memory,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn append(&self, line: &Line) -> anyhow::Result<()> {
+        write_line(&mut self.file.lock().unwrap(), line)
+    }
+}
+
+/// Rewrites the file at `path` with the lines of what `inner` holds: the
+/// counters, the outbox, then the records in listing order. The lines go to a
+/// temporary file renamed over it, so a crash leaves one file or the other.
+fn compact(path: &Path, inner: &Inner) -> anyhow::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".compacting");
+    let temporary = Path::new(&temporary);
+    let mut file = File::create(temporary)?;
+    let counters = Line::Counters {
+        next_seq: inner.next,
+        next_outbox_id: inner.next_outbox_id,
+    };
+    write_line(&mut file, &counters)?;
+    for (id, event) in &inner.outbox {
+        let line = Line::Pending {
+            pending_outbox_id: *id,
+            event: event.clone(),
+        };
+        write_line(&mut file, &line)?;
+    }
+    for (seq, order_id) in &inner.sequence {
+        let line = Line::Kept {
+            seq: *seq,
+            record: inner.records[order_id].1.clone(),
+        };
+        write_line(&mut file, &line)?;
+    }
+    file.sync_all()?;
+    std::fs::rename(temporary, path)?;
+    Ok(())
+}
+
+fn write_line(file: &mut File, line: &Line) -> anyhow::Result<()> {
+    let mut line = serde_json::to_string(line)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+impl OrderStore for FileStore {
+    fn save(&self, record: OrderRecord) -> anyhow::Result<()> {
+        self.append(&Line::Record(record.clone()))?;
+        self.memory.save(record)
+    }
+
+    fn save_with_event(&self, record: OrderRecord) -> anyhow::Result<()> {
+        // Ids are handed out under the file's lock, so lines follow their order.
+        let mut file = self.file.lock().unwrap();
+        let outbox_id = self.memory.inner.lock().unwrap().next_outbox_id;
+        let line = Line::Outboxed {
+            outbox_id,
+            record: record.clone(),
+        };
+        write_line(&mut file, &line)?;
+        let mut inner = self.memory.inner.lock().unwrap();
+        inner.add_to_outbox(outbox_id, record.clone());
+        inner.save(record, self.memory.max_orders);
+        Ok(())
+    }
+
+    fn outbox(&self, limit: usize) -> anyhow::Result<Vec<OutboxEntry>> {
+        self.memory.outbox(limit)
+    }
+
+    fn mark_sent(&self, id: u64) -> anyhow::Result<()> {
+        self.append(&Line::Sent { sent_outbox_id: id })?;
+        self.memory.mark_sent(id)
+    }
+
+    fn purge(&self, before: SystemTime) -> anyhow::Result<usize> {
+        let mut file = self.file.lock().unwrap();
+        let stale = self.memory.inner.lock().unwrap().changed_before(before);
+        for &order_id in &stale {
+            let line = Line::Removed {
+                removed_order_id: order_id,
+            };
+            write_line(&mut file, &line)?;
+            self.memory.inner.lock().unwrap().remove(order_id);
+        }
+        Ok(stale.len())
+    }
+
+    fn update(&self, record: OrderRecord) -> anyhow::Result<()> {
+        self.append(&Line::Record(record.clone()))?;
+        self.memory.update(record)
+    }
+
+    fn get(&self, order_id: i32) -> anyhow::Result<Option<OrderRecord>> {
+        self.memory.get(order_id)
+    }
+
+    fn list(&self, query: &OrderQuery) -> anyhow::Result<Page> {
+        self.memory.list(query)
+    }
+
+    fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<SearchHit>> {
+        self.memory.search(query, limit)
+    }
+}
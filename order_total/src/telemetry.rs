@@ -0,0 +1,263 @@
+//! Minimal OpenTelemetry tracing: W3C `traceparent` propagation and an
+//! OTLP/HTTP JSON exporter. The official exporters depend on a Tokio and
+//! networking stack that doesn't run on WasmEdge, hence this small one on
+//! top of reqwest.
+
+use rand::Rng;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::config::{AppConfig, TelemetryConfig};
+
+/// The W3C trace context header.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Spans kept in memory between two exports; later spans are dropped.
+const MAX_QUEUED_SPANS: usize = 2048;
+
+tokio::task_local! {
+    static CURRENT: SpanContext;
+}
+
+/// The identity of a span, as carried by a `traceparent` header.
+#[derive(Debug, Clone)]
+pub struct SpanContext {
+    pub trace_id: String,
+    pub span_id: String,
+    pub sampled: bool,
+}
+
+impl SpanContext {
+    /// Parses `00-<32 hex trace id>-<16 hex span id>-<2 hex flags>`.
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let parts: Vec<&str> = header.trim().split('-').collect();
+        match parts.as_slice() {
+            [version, trace_id, span_id, flags]
+                if version.len() == 2
+                    && *version != "ff"
+                    && is_hex_id(trace_id, 32)
+                    && is_hex_id(span_id, 16)
+                    && flags.len() == 2 =>
+            {
+                let flags = u8::from_str_radix(flags, 16).ok()?;
+                Some(Self {
+                    trace_id: trace_id.to_string(),
+                    span_id: span_id.to_string(),
+                    sampled: flags & 1 == 1,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            self.trace_id,
+            self.span_id,
+            u8::from(self.sampled)
+        )
+    }
+}
+
+fn is_hex_id(id: &str, len: usize) -> bool {
+    id.len() == len && id.chars().all(|c| c.is_ascii_hexdigit()) && id.chars().any(|c| c != '0')
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bytes)
+        .map(|_| format!("{:02x}", rng.gen::<u8>()))
+        .collect()
+}
+
+fn now_unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SpanKind {
+    Server,
+    Client,
+}
+
+/// A span being recorded. It is queued for export when `end` is called.
+#[derive(Debug)]
+pub struct Span {
+    context: SpanContext,
+    parent_span_id: Option<String>,
+    name: String,
+    kind: SpanKind,
+    start: u128,
+    attributes: Vec<(&'static str, Value)>,
+    error: bool,
+}
+
+impl Span {
+    /// Starts a span continuing `parent`, or a new trace without one.
+    pub fn start(name: impl Into<String>, kind: SpanKind, parent: Option<&SpanContext>) -> Self {
+        let context = SpanContext {
+            trace_id: parent.map_or_else(|| random_hex(16), |p| p.trace_id.clone()),
+            span_id: random_hex(8),
+            sampled: parent.map(|p| p.sampled).unwrap_or(true),
+        };
+        Self {
+            context,
+            parent_span_id: parent.map(|p| p.span_id.clone()),
+            name: name.into(),
+            kind,
+            start: now_unix_nanos(),
+            attributes: Vec::new(),
+            error: false,
+        }
+    }
+
+    /// Starts a child of the span the current task runs in.
+    pub fn start_child(name: impl Into<String>, kind: SpanKind) -> Self {
+        let parent = CURRENT.try_with(|context| context.clone()).ok();
+        Self::start(name, kind, parent.as_ref())
+    }
+
+    pub fn context(&self) -> &SpanContext {
+        &self.context
+    }
+
+    pub fn set_attribute(&mut self, key: &'static str, value: impl Into<Value>) {
+        self.attributes.push((key, value.into()));
+    }
+
+    pub fn set_error(&mut self) {
+        self.error = true;
+    }
+
+    pub fn end(self) {
+        if self.context.sampled {
+            TRACER.export(self.into_otlp(now_unix_nanos()));
+        }
+    }
+
+    fn into_otlp(self, end: u128) -> Value {
+        let attributes: Vec<Value> = self
+            .attributes
+            .into_iter()
+            .map(|(key, value)| json!({ "key": key, "value": otlp_value(value) }))
+            .collect();
+        let mut span = json!({
+            "traceId": self.context.trace_id,
+            "spanId": self.context.span_id,
+            "name": self.name,
+            "kind": match self.kind { SpanKind::Server => 2, SpanKind::Client => 3 },
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": end.to_string(),
+            "attributes": attributes,
+            "status": { "code": if self.error { 2 } else { 1 } },
+        });
+        if let Some(parent) = self.parent_span_id {
+            span["parentSpanId"] = Value::from(parent);
+        }
+        span
+    }
+}
+
+fn otlp_value(value: Value) -> Value {
+    match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+/// Runs `future` as part of the given span, so `Span::start_child` nests under it.
+pub async fn in_span<F: Future>(context: SpanContext, future: F) -> F::Output {
+    CURRENT.scope(context, future).await
+}
+
+/// Collects finished spans and ships them to the OTLP collector.
+pub struct Tracer {
+    endpoint: Option<String>,
+    service_name: String,
+    interval: Duration,
+    queue: Mutex<Vec<Value>>,
+}
+
+lazy_static! {
+    static ref TRACER: Tracer = Tracer::new(&AppConfig::get().telemetry);
+}
+
+impl Tracer {
+    fn new(config: &TelemetryConfig) -> Self {
+        let endpoint = config
+            .otlp_endpoint
+            .as_deref()
+            .filter(|endpoint| !endpoint.is_empty())
+            .map(|endpoint| format!("{}/v1/traces", endpoint.trim_end_matches('/')));
+        Self {
+            endpoint,
+            service_name: config.service_name.clone(),
+            interval: Duration::from_millis(config.export_interval_ms),
+            queue: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn export(&self, span: Value) {
+        if self.endpoint.is_none() {
+            return;
+        }
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() < MAX_QUEUED_SPANS {
+            queue.push(span);
+        }
+    }
+
+    async fn flush(&self, client: &reqwest::Client, endpoint: &str) {
+        let spans = std::mem::take(&mut *self.queue.lock().unwrap());
+        if spans.is_empty() {
+            return;
+        }
+        let payload = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{
+                        "key": "service.name",
+                        "value": { "stringValue": self.service_name },
+                    }],
+                },
+                "scopeSpans": [{ "scope": { "name": "order_total" }, "spans": spans }],
+            }],
+        });
+        let result = client
+            .post(endpoint)
+            .header("content-type", "application/json")
+            .body(payload.to_string())
+            .send()
+            .await;
+        match result {
+            Ok(response) if !response.status().is_success() => {
+                warn!(status = response.status().as_u16(), "OTLP export rejected")
+            }
+            Err(err) => warn!(error = %err, "OTLP export failed"),
+            Ok(_) => (),
+        }
+    }
+}
+
+/// Starts the background task exporting spans with `client`, if an endpoint
+/// is configured.
+pub fn start_exporter(client: reqwest::Client) {
+    if let Some(endpoint) = TRACER.endpoint.as_deref() {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(TRACER.interval).await;
+                TRACER.flush(&client, endpoint).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,197 @@
+//! Tenants sharing the service, each with its own rate provider, rate limit
+//! and webhook endpoints when configured, and the service-wide ones
+//! otherwise. The tenant of a request is the one its API key belongs to, or
+//! else the one named by the `tenancy.header` header; the HTTP metrics and
+//! the log lines of the request are labelled with it.
+//!
+//! A key belonging to a tenant can't act for another one, whereas a
+//! service-wide key may name any tenant. Without authentication, the header
+//! is taken at its word.
+
+use anyhow::{bail, ensure};
+use domain::Rounding;
+use hyper::header::HeaderMap;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auth;
+use crate::chaos::FaultInjector;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config::{AppConfig, DiscoveryConfig, RateProviderKind, RatesConfig, TenantConfig};
+use crate::error::AppError;
+use crate::flags::Flags;
+use crate::hedging::Hedging;
+use crate::metrics::Metrics;
+use crate::rate_limit::RateLimiter;
+use crate::rates::{self, TaxRateProvider};
+
+tokio::task_local! {
+    static TENANT: Option<String>;
+}
+
+/// A tenant with its own settings.
+pub struct Tenant {
+    pub id: String,
+    /// The tenant's own rate provider, when it has one.
+    pub rates: Option<TenantRates>,
+    /// The tenant's own rounding, when it has one.
+    pub rounding: Option<Rounding>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+/// A tenant's rate provider, with a circuit breaker of its own, so a failing
+/// provider of one tenant doesn't turn the others away.
+pub struct TenantRates {
+    pub provider: Arc<dyn TaxRateProvider>,
+    pub circuit_breaker: Arc<CircuitBreaker>,
+}
+
+/// The tenants of the configuration.
+pub struct Tenants {
+    header: String,
+    required: bool,
+    tenants: HashMap<String, Arc<Tenant>>,
+    /// Tenant ids by API key.
+    keys: HashMap<String, String>,
+}
+
+impl Tenants {
+    /// The tenants of `config`, whose rate services are called with
+    /// `client`, hedged as `flags` allow, counted in `metrics` and faulted
+    /// by `faults`, as the service's. Must be called within the runtime.
+    pub fn from_config(
+        config: &AppConfig,
+        client: reqwest::Client,
+        flags: &Arc<Flags>,
+        metrics: &Arc<Metrics>,
+        faults: &Option<Arc<FaultInjector>>,
+    ) -> anyhow::Result<Self> {
+        let mut tenants = HashMap::new();
+        let mut keys = HashMap::new();
+        for (id, tenant) in &config.tenants {
+            ensure!(
+                !id.is_empty()
+                    && id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+                "the tenant id {:?} isn't made of letters, digits, - and _",
+                id
+            );
+            for key in &tenant.api_keys {
+                if let Some(other) = keys.insert(key.clone(), id.clone()) {
+                    bail!("tenants {} and {} share an API key", other, id);
+                }
+            }
+            let rates = tenant_rates(id, tenant, config, client.clone(), flags, metrics, faults)?;
+            let tenant = Tenant {
+                id: id.clone(),
+                rates,
+                rounding: tenant.rounding,
+                rate_limiter: tenant.rate_limit.as_ref().map(RateLimiter::new),
+            };
+            tenants.insert(id.clone(), Arc::new(tenant));
+        }
+        Ok(Self {
+            header: config.tenancy.header.clone(),
+            required: config.tenancy.required,
+            tenants,
+            keys,
+        })
+    }
+
+    /// The tenant `headers` name, by API key or tenant header. Fails with
+    /// `Forbidden` when the key belongs to another tenant than the header
+    /// names, `UnknownTenant` when the header names none of the configured
+    /// ones, and `TenantRequired` without tenant while one is required.
+    pub fn identify(&self, headers: &HeaderMap) -> Result<Option<Arc<Tenant>>, AppError> {
+        let named = headers
+            .get(self.header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|tenant| !tenant.is_empty());
+        let owner = auth::credential(headers).and_then(|key| self.keys.get(key));
+        let id = match (owner, named) {
+            (Some(owner), Some(named)) if owner != named => return Err(AppError::Forbidden),
+            (Some(owner), _) => owner.as_str(),
+            (None, Some(named)) => named,
+            (None, None) if self.required => return Err(AppError::TenantRequired),
+            (None, None) => return Ok(None),
+        };
+        match self.tenants.get(id) {
+            Some(tenant) => Ok(Some(tenant.clone())),
+            None => Err(AppError::UnknownTenant(id.to_string())),
+        }
+    }
+
+    /// The tenant of `headers`, as `identify` finds it, once a token of its
+    /// rate limit is taken.
+    pub fn admit(&self, headers: &HeaderMap) -> Result<Option<Arc<Tenant>>, AppError> {
+        let tenant = self.identify(headers)?;
+        if let Some(tenant) = &tenant {
+            if let Some(limiter) = &tenant.rate_limiter {
+                limiter.check_key(&tenant.id)?;
+            }
+        }
+        Ok(tenant)
+    }
+
+    /// The tenant of the request being served, if any.
+    pub fn current(&self) -> Option<Arc<Tenant>> {
+        current_tenant().and_then(|id| self.tenants.get(&id).cloned())
+    }
+}
+
+/// The provider of the rates of `tenant`: its rate service or its table.
+fn tenant_rates(
+    id: &str,
+    tenant: &TenantConfig,
+    config: &AppConfig,
+    client: reqwest::Client,
+    flags: &Arc<Flags>,
+    metrics: &Arc<Metrics>,
+    faults: &Option<Arc<FaultInjector>>,
+) -> anyhow::Result<Option<TenantRates>> {
+    let mut upstream = config.upstream.clone();
+    let rates = match (&tenant.upstream_url, tenant.rates.is_empty()) {
+        (Some(_), false) => bail!("tenants.{} sets both upstream_url and rates", id),
+        (Some(url), true) => {
+            upstream.url = url.clone();
+            upstream.discovery = DiscoveryConfig::default();
+            RatesConfig::default()
+        }
+        (None, false) => RatesConfig {
+            provider: RateProviderKind::Memory,
+            table: tenant.rates.clone(),
+            ..RatesConfig::default()
+        },
+        (None, true) => return Ok(None),
+    };
+    let provider = rates::from_config(
+        &rates,
+        &upstream,
+        &config.retry,
+        Hedging::from_config(&config.hedging, flags.clone()),
+        client,
+        metrics.clone(),
+        faults.clone(),
+    )?;
+    Ok(Some(TenantRates {
+        provider: Arc::from(provider),
+        circuit_breaker: Arc::new(CircuitBreaker::new(
+            config.circuit_breaker.failure_threshold,
+            Duration::from_millis(config.circuit_breaker.cooldown_ms),
+        )),
+    }))
+}
+
+/// Runs `future` with `tenant` available through `current_tenant`.
+pub async fn scope<F: Future>(tenant: Option<String>, future: F) -> F::Output {
+    TENANT.scope(tenant, future).await
+}
+
+/// The id of the tenant of the request being served, if any.
+pub fn current_tenant() -> Option<String> {
+    TENANT.try_with(|tenant| tenant.clone()).ok().flatten()
+}
@@ -0,0 +1,75 @@
+//! TLS termination of the HTTP server, with rustls over the `tls_stream`
+//! crate, and the TLS settings of the calls to the sales tax rate service.
+
+use anyhow::{bail, Context as _};
+use std::sync::Arc;
+use tls_stream::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tls_stream::rustls::ServerConfig;
+use tls_stream::{load_certs, load_key};
+use tracing::warn;
+
+use crate::config::{TlsConfig, UpstreamConfig};
+
+pub use tls_stream::{bind, TlsStream};
+
+/// The rustls configuration of the server: the configured certificate and
+/// key, or a self-signed certificate for `localhost` when neither is set.
+/// HTTP/2 is offered to clients when `http2` is set.
+pub fn server_config(config: &TlsConfig, http2: bool) -> anyhow::Result<Arc<ServerConfig>> {
+    let (certs, key) = match (&config.cert_path, &config.key_path) {
+        (Some(cert_path), Some(key_path)) => (load_certs(cert_path)?, load_key(key_path)?),
+        (None, None) => self_signed()?,
+        _ => bail!("tls.cert_path and tls.key_path must be set together"),
+    };
+    let mut server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("invalid TLS certificate or key")?;
+    server_config.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    Ok(Arc::new(server_config))
+}
+
+/// A certificate for local development, which clients have to be told to
+/// trust (e.g. `curl -k`).
+fn self_signed() -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into(), "127.0.0.1".into()])?;
+    warn!("serving HTTPS with a self-signed certificate");
+    Ok((
+        vec![CertificateDer::from(cert.serialize_der()?)],
+        PrivatePkcs8KeyDer::from(cert.serialize_private_key_der()).into(),
+    ))
+}
+
+/// Trusts `upstream.ca_cert_path` and presents the client certificate of
+/// `upstream.client_cert_path`, for mutual TLS, when they are set.
+pub fn configure_client(
+    mut builder: reqwest::ClientBuilder,
+    config: &UpstreamConfig,
+) -> anyhow::Result<reqwest::ClientBuilder> {
+    if let Some(path) = &config.ca_cert_path {
+        let pem = std::fs::read(path).with_context(|| format!("cannot read {}", path))?;
+        let ca = reqwest::Certificate::from_pem(&pem)
+            .with_context(|| format!("invalid CA certificate in {}", path))?;
+        builder = builder.add_root_certificate(ca);
+    }
+    match (&config.client_cert_path, &config.client_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let mut pem =
+                std::fs::read(cert_path).with_context(|| format!("cannot read {}", cert_path))?;
+            pem.push(b'\n');
+            pem.extend(
+                std::fs::read(key_path).with_context(|| format!("cannot read {}", key_path))?,
+            );
+            let identity = reqwest::Identity::from_pem(&pem)
+                .with_context(|| format!("invalid client certificate or key in {}", cert_path))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => (),
+        _ => bail!("upstream.client_cert_path and upstream.client_key_path must be set together"),
+    }
+    Ok(builder)
+}
@@ -0,0 +1,104 @@
+use domain::{currency, Decimal, Order};
+use serde::Serialize;
+
+use crate::error::AppError;
+use crate::postal::{self, PostalCode};
+
+/// One rule an order breaks, reported in the details of a 422 response.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: &'static str,
+}
+
+impl FieldError {
+    fn new(field: impl Into<String>, message: &'static str) -> Self {
+        Self {
+            field: field.into(),
+            message,
+        }
+    }
+}
+
+/// Checks the order is worth pricing, collecting every broken rule rather than
+/// stopping at the first. A well-formed order shipped outside the US fails
+/// with `UnsupportedJurisdiction` instead.
+pub fn validate(order: &Order) -> Result<(), AppError> {
+    let mut errors = Vec::new();
+    if order.line_items.is_empty() {
+        // A single-product order.
+        if order.product_id.is_none() {
+            errors.push(FieldError::new(
+                "product_id",
+                "is required without line_items",
+            ));
+        }
+        match order.quantity {
+            None => errors.push(FieldError::new(
+                "quantity",
+                "is required without line_items",
+            )),
+            Some(quantity) if quantity <= 0 => {
+                errors.push(FieldError::new("quantity", "must be greater than 0"))
+            }
+            Some(_) => (),
+        }
+        match order.subtotal {
+            None => errors.push(FieldError::new(
+                "subtotal",
+                "is required without line_items",
+            )),
+            Some(subtotal) if subtotal < Decimal::ZERO => {
+                errors.push(FieldError::new("subtotal", "must not be negative"))
+            }
+            Some(_) => (),
+        }
+    }
+    for (index, item) in order.line_items.iter().enumerate() {
+        if item.quantity <= 0 {
+            errors.push(FieldError::new(
+                format!("line_items[{}].quantity", index),
+                "must be greater than 0",
+            ));
+        }
+        if item.unit_price < Decimal::ZERO {
+            errors.push(FieldError::new(
+                format!("line_items[{}].unit_price", index),
+                "must not be negative",
+            ));
+        }
+    }
+    if order.shipping_address.trim().is_empty() {
+        errors.push(FieldError::new("shipping_address", "must not be empty"));
+    }
+    let postal_code = postal::parse(&order.shipping_zip);
+    if postal_code == PostalCode::Invalid {
+        errors.push(FieldError::new(
+            "shipping_zip",
+            "must be a 5-digit or ZIP+4 zip code",
+        ));
+    }
+    if let Some(code) = &order.currency {
+        if !currency::is_valid(code) {
+            errors.push(FieldError::new(
+                "currency",
+                "must be an ISO 4217 currency code, e.g. USD",
+            ));
+        }
+    }
+    if let Some(id) = &order.tax_exempt_id {
+        if id.trim().is_empty() {
+            errors.push(FieldError::new("tax_exempt_id", "must not be empty"));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+    match postal_code {
+        PostalCode::Foreign(country) => Err(AppError::UnsupportedJurisdiction(
+            order.shipping_zip.clone(),
+            country,
+        )),
+        _ => Ok(()),
+    }
+}
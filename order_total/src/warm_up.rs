@@ -0,0 +1,128 @@
+//! Warming the rate cache up at startup: the rates of the snapshot the
+//! previous run saved are loaded, then the rates of the zip codes of
+//! `cache.warm_up.path` and of the most frequent ones of the stored orders
+//! are looked up, a few at a time, while `/readyz` reports not ready.
+
+use anyhow::Context;
+use futures::future;
+use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::config::WarmUpConfig;
+use crate::state::AppState;
+use crate::store::{OrderQuery, OrderStore};
+use crate::{postal, service};
+
+/// Loads the snapshot of the cache, then gathers the zip codes to warm the
+/// cache up with and looks their rates up in the background, keeping the
+/// service not ready meanwhile. Fails when the file of zip codes can't be
+/// read; a snapshot that can't be is skipped. Must be called within the
+/// runtime.
+pub fn start(state: &AppState) -> anyhow::Result<()> {
+    load_snapshot(state);
+    let config = &state.config.cache.warm_up;
+    let zips = zips(config, state.orders.as_ref())?;
+    if zips.is_empty() {
+        return Ok(());
+    }
+    state.readiness.warming_up();
+    let state = state.clone();
+    tokio::spawn(async move {
+        let concurrency = state.config.cache.warm_up.concurrency.max(1);
+        let total = zips.len();
+        let lookups = stream::iter(zips).map(|zip| {
+            let state = &state;
+            async move {
+                let result = service::fetch_rate(state, &zip).await;
+                if let Err(err) = &result {
+                    warn!(error = %err, zip = %zip, "failed to warm up a rate");
+                }
+                result.is_ok()
+            }
+        });
+        let warmed = lookups
+            .buffer_unordered(concurrency)
+            .filter(|warmed| future::ready(*warmed))
+            .count()
+            .await;
+        info!(total, warmed, "rate cache warmed up");
+        state.readiness.warmed_up();
+    });
+    Ok(())
+}
+
+fn load_snapshot(state: &AppState) {
+    let config = &state.config.cache;
+    let path = match &config.snapshot_path {
+        Some(path) => path,
+        None => return,
+    };
+    let max_age = Duration::from_secs(config.snapshot_max_age_secs);
+    match state.rate_cache.load_snapshot(Path::new(path), max_age) {
+        Ok((loaded, 0)) => info!(loaded, "rate cache snapshot loaded"),
+        Ok((loaded, skipped)) => {
+            warn!(
+                loaded,
+                skipped, "rate cache snapshot loaded, skipping unreadable lines"
+            )
+        }
+        Err(err) => warn!(error = %err, path = %path, "cannot load the rate cache snapshot"),
+    }
+}
+
+/// Saves the cached rates to `cache.snapshot_path`, when set.
+pub fn save_snapshot(state: &AppState) -> anyhow::Result<()> {
+    if let Some(path) = &state.config.cache.snapshot_path {
+        let saved = state
+            .rate_cache
+            .save_snapshot(Path::new(path))
+            .with_context(|| format!("cannot save the rate cache snapshot to {}", path))?;
+        debug!(saved, "rate cache snapshot saved");
+    }
+    Ok(())
+}
+
+/// The zip codes of the file, then the most frequent ones of `orders`, each
+/// once, by the part rates are looked up by. Foreign postal codes are left
+/// out.
+fn zips(config: &WarmUpConfig, orders: &dyn OrderStore) -> anyhow::Result<Vec<String>> {
+    let mut zips = Vec::new();
+    if let Some(path) = &config.path {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("cannot read the zip codes of {}", path))?;
+        zips.extend(
+            content
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|zip| !zip.is_empty())
+                .filter_map(rate_zip),
+        );
+    }
+    if config.from_orders > 0 {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for record in orders.list(&OrderQuery::default())?.orders {
+            if let Some(zip) = rate_zip(&record.order.shipping_zip) {
+                *counts.entry(zip).or_default() += 1;
+            }
+        }
+        let mut frequent: Vec<(String, usize)> = counts.into_iter().collect();
+        frequent.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        zips.extend(
+            frequent
+                .into_iter()
+                .take(config.from_orders)
+                .map(|(zip, _)| zip),
+        );
+    }
+    let mut seen = HashSet::new();
+    zips.retain(|zip| seen.insert(zip.clone()));
+    Ok(zips)
+}
+
+fn rate_zip(zip: &str) -> Option<String> {
+    postal::rate_zip(zip).ok().map(str::to_string)
+}
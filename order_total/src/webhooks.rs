@@ -0,0 +1,327 @@
+//! Webhook notifications: every `OrderPriced` event is POSTed to each
+//! configured endpoint, and to those of the tenant of the order, signed with
+//! the endpoint's secret. Failed deliveries
+//! are retried with backoff, then kept in a dead-letter log, a JSON lines
+//! file, from which `/admin/webhooks/failed` lists and replays them.
+//!
+//! A notification carries three headers:
+//! - `X-Webhook-Id`: the delivery id, the same on every attempt and replay,
+//!   so receivers can drop duplicates;
+//! - `X-Webhook-Timestamp`: Unix seconds of the attempt;
+//! - `X-Webhook-Signature`: `sha256=` and the hex HMAC-SHA256, keyed with the
+//!   secret, of the timestamp, a `.` and the body.
+
+use anyhow::{ensure, Context};
+use domain::OrderPriced;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::config::{TenantConfig, WebhookEndpoint, WebhooksConfig};
+use crate::dead_letter::{DeadLetterEntry, DeadLetterLog};
+use crate::error::AppError;
+use crate::events::EventPublisher;
+use crate::metrics::Metrics;
+use crate::retry::RetryPolicy;
+use crate::tenants;
+
+pub const DELIVERY_ID_HEADER: &str = "x-webhook-id";
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// Deliveries waiting to be sent; later ones go to the dead-letter log.
+const MAX_QUEUED_DELIVERIES: usize = 1024;
+
+/// One notification for one endpoint.
+#[derive(Debug, Clone)]
+struct Delivery {
+    id: String,
+    url: String,
+    order_id: i32,
+    payload: String,
+}
+
+/// A delivery that failed every attempt.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FailedDelivery {
+    pub id: String,
+    pub url: String,
+    pub order_id: i32,
+    /// The `OrderPriced` event that was sent.
+    #[schema(value_type = Object)]
+    pub event: Value,
+    pub attempts: u32,
+    /// Why the last attempt failed, e.g. `status 500`.
+    pub error: String,
+    /// RFC 3339 time of the last attempt.
+    pub failed_at: String,
+}
+
+impl DeadLetterEntry for FailedDelivery {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Queues a delivery of every event to every endpoint for a background task,
+/// which sends each one in its own task.
+pub struct Webhooks {
+    sender: Arc<Sender>,
+    queue: mpsc::Sender<Delivery>,
+    /// The tenant of each endpoint of a tenant, by URL.
+    owners: HashMap<String, String>,
+}
+
+impl Webhooks {
+    /// The webhooks of the configuration and of `tenants`, delivered with
+    /// `client`. Must be called within the runtime, which runs the
+    /// deliveries.
+    pub fn from_config(
+        config: &WebhooksConfig,
+        tenants: &HashMap<String, TenantConfig>,
+        client: reqwest::Client,
+        metrics: Arc<Metrics>,
+    ) -> anyhow::Result<Self> {
+        let mut owners = HashMap::new();
+        let mut endpoints: Vec<&WebhookEndpoint> = config.endpoints.iter().collect();
+        for (id, tenant) in tenants {
+            for endpoint in &tenant.webhooks {
+                // Endpoints are keyed by URL.
+                let taken = endpoints.iter().any(|other| other.url == endpoint.url);
+                ensure!(
+                    !taken,
+                    "the webhook {} is configured more than once",
+                    endpoint.url
+                );
+                owners.insert(endpoint.url.clone(), id.clone());
+                endpoints.push(endpoint);
+            }
+        }
+        for endpoint in &endpoints {
+            ensure!(
+                endpoint.url.starts_with("http://") || endpoint.url.starts_with("https://"),
+                "the webhook URL {:?} is neither http nor https",
+                endpoint.url
+            );
+            ensure!(
+                !endpoint.secret.is_empty(),
+                "the webhook {} has no secret",
+                endpoint.url
+            );
+        }
+        let path = Path::new(&config.dead_letter_path);
+        let dead_letters = DeadLetterLog::open(path).with_context(|| {
+            format!("cannot read the webhook dead-letter log {}", path.display())
+        })?;
+        let sender = Arc::new(Sender {
+            secrets: endpoints
+                .iter()
+                .map(|endpoint| (endpoint.url.clone(), endpoint.secret.clone()))
+                .collect(),
+            retry: RetryPolicy::new(&config.retry),
+            timeout: Duration::from_millis(config.timeout_ms),
+            dead_letters,
+            client,
+            metrics,
+        });
+        let (queue, deliveries) = mpsc::channel(MAX_QUEUED_DELIVERIES);
+        tokio::spawn(dispatch(sender.clone(), deliveries));
+        Ok(Self {
+            sender,
+            queue,
+            owners,
+        })
+    }
+
+    /// The deliveries that failed every attempt, oldest first.
+    pub fn failed(&self) -> Vec<FailedDelivery> {
+        self.sender.dead_letters.list()
+    }
+
+    /// Takes a failed delivery out of the dead-letter log and queues it again,
+    /// with the same id.
+    pub fn replay(&self, id: &str) -> Result<FailedDelivery, AppError> {
+        let failed = self
+            .sender
+            .dead_letters
+            .take(id)?
+            .ok_or_else(|| AppError::DeliveryNotFound(id.to_string()))?;
+        info!(delivery_id = id, url = %failed.url, "replaying webhook delivery");
+        self.enqueue(Delivery {
+            id: failed.id.clone(),
+            url: failed.url.clone(),
+            order_id: failed.order_id,
+            payload: failed.event.to_string(),
+        });
+        Ok(failed)
+    }
+
+    fn enqueue(&self, delivery: Delivery) {
+        if let Err(err) = self.queue.try_send(delivery) {
+            let delivery = match err {
+                mpsc::error::TrySendError::Full(delivery) => delivery,
+                mpsc::error::TrySendError::Closed(delivery) => delivery,
+            };
+            self.sender
+                .dead_letter(delivery, 0, "the delivery queue is full".into());
+        }
+    }
+}
+
+impl EventPublisher for Webhooks {
+    fn publish(&self, event: &OrderPriced) {
+        if self.sender.secrets.is_empty() {
+            return;
+        }
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!(error = %err, "cannot serialize webhook event");
+                return;
+            }
+        };
+        let tenant = tenants::current_tenant();
+        let urls = self.sender.secrets.keys().filter(|url| {
+            self.owners
+                .get(*url)
+                .is_none_or(|owner| Some(owner) == tenant.as_ref())
+        });
+        for url in urls {
+            self.enqueue(Delivery {
+                id: Uuid::new_v4().to_string(),
+                url: url.clone(),
+                order_id: event.order.order_id,
+                payload: payload.clone(),
+            });
+        }
+    }
+}
+
+async fn dispatch(sender: Arc<Sender>, mut deliveries: mpsc::Receiver<Delivery>) {
+    while let Some(delivery) = deliveries.recv().await {
+        tokio::spawn(sender.clone().deliver(delivery));
+    }
+}
+
+/// What every delivery task shares.
+struct Sender {
+    /// Secrets by endpoint URL.
+    secrets: HashMap<String, String>,
+    retry: RetryPolicy,
+    timeout: Duration,
+    dead_letters: DeadLetterLog<FailedDelivery>,
+    client: reqwest::Client,
+    metrics: Arc<Metrics>,
+}
+
+impl Sender {
+    /// Sends `delivery` until it succeeds or attempts run out, then records it
+    /// in the dead-letter log.
+    async fn deliver(self: Arc<Self>, delivery: Delivery) {
+        let secret = match self.secrets.get(&delivery.url) {
+            Some(secret) => secret,
+            None => {
+                let error = "the endpoint is no longer configured".into();
+                self.dead_letter(delivery, 0, error);
+                return;
+            }
+        };
+        let mut attempts = 0;
+        let result = self
+            .retry
+            .run(|| {
+                attempts += 1;
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                self.client
+                    .post(&delivery.url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .header(DELIVERY_ID_HEADER, &delivery.id)
+                    .header(TIMESTAMP_HEADER, timestamp)
+                    .header(SIGNATURE_HEADER, sign(secret, timestamp, &delivery.payload))
+                    .timeout(self.timeout)
+                    .body(delivery.payload.clone())
+                    .send()
+            })
+            .await;
+        let error = match result {
+            Ok(response) if response.status().is_success() => {
+                self.metrics
+                    .webhook_deliveries
+                    .with_label_values(&["delivered"])
+                    .inc();
+                info!(
+                    delivery_id = %delivery.id,
+                    url = %delivery.url,
+                    attempts,
+                    "webhook delivered"
+                );
+                return;
+            }
+            Ok(response) => format!("status {}", response.status().as_u16()),
+            Err(err) if err.is_timeout() => {
+                format!("no answer within {} ms", self.timeout.as_millis())
+            }
+            Err(err) => err.to_string(),
+        };
+        warn!(
+            delivery_id = %delivery.id,
+            url = %delivery.url,
+            attempts,
+            error = %error,
+            "webhook delivery failed"
+        );
+        self.dead_letter(delivery, attempts, error);
+    }
+
+    fn dead_letter(&self, delivery: Delivery, attempts: u32, error: String) {
+        self.metrics
+            .webhook_deliveries
+            .with_label_values(&["failed"])
+            .inc();
+        let failed = FailedDelivery {
+            event: serde_json::from_str(&delivery.payload)
+                .unwrap_or(Value::String(delivery.payload)),
+            id: delivery.id,
+            url: delivery.url,
+            order_id: delivery.order_id,
+            attempts,
+            error,
+            failed_at: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        };
+        if let Err(err) = self.dead_letters.push(failed) {
+            warn!(
+                error = format!("{:#}", err),
+                "cannot record failed webhook delivery"
+            );
+        }
+    }
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `{timestamp}.{payload}`.
+fn sign(secret: &str, timestamp: u64, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
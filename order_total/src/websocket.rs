@@ -0,0 +1,373 @@
+//! The WebSocket API at `/ws`, for clients that keep a connection open, such
+//! as point-of-sale frontends. tokio-tungstenite doesn't run on WasmEdge, so
+//! the handshake and the framing of RFC 6455 are done here on top of hyper's
+//! connection upgrades.
+//!
+//! Clients send text messages `{"id": ..., "order": {...}}` and get
+//! `{"id": ..., "status": "ok", "order": {...}}` or
+//! `{"id": ..., "status": "error", "error": {...}}` back, with the same error
+//! envelope as the HTTP API. Orders of one connection are priced concurrently,
+//! up to `websocket.max_in_flight` at a time, and answered as they complete:
+//! the `id`, any JSON string or number, ties each answer to its order.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use domain::{ErrorEnvelope, Order};
+use hyper::header::{
+    HeaderMap, HeaderName, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
+    SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, WriteHalf};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn, Instrument};
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::service::price;
+use crate::state::AppState;
+use crate::{auth, deadline, request_id, request_timeout, tenants, MAX_BODY_BYTES, SHUTDOWN};
+
+/// Appended to the client's key to prove the server speaks WebSocket.
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+const NORMAL_CLOSURE: u16 = 1000;
+const GOING_AWAY: u16 = 1001;
+const PROTOCOL_ERROR: u16 = 1002;
+const UNSUPPORTED_DATA: u16 = 1003;
+const INVALID_PAYLOAD: u16 = 1007;
+const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// A message sent by the client.
+#[derive(Deserialize)]
+struct PriceRequest {
+    id: Value,
+    order: Value,
+}
+
+/// The answer to a message, tagged with the message's id.
+#[derive(Serialize)]
+struct Reply {
+    id: Value,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum Outcome {
+    Ok { order: Box<Order> },
+    Error { error: ErrorEnvelope },
+}
+
+/// Answers the opening handshake and serves the connection in the background
+/// once hyper hands it over. Requests that aren't a WebSocket handshake get
+/// `426 UPGRADE_REQUIRED`.
+pub fn accept(state: AppState, req: Request<Body>) -> Result<Response<Body>, AppError> {
+    let headers = req.headers();
+    if !has_token(headers, &CONNECTION, "upgrade") || !has_token(headers, &UPGRADE, "websocket") {
+        return Err(AppError::UpgradeRequired(
+            "expected Connection: Upgrade and Upgrade: websocket".into(),
+        ));
+    }
+    if headers
+        .get(SEC_WEBSOCKET_VERSION)
+        .is_none_or(|version| version != "13")
+    {
+        return Err(AppError::UpgradeRequired(
+            "only WebSocket version 13 is supported".into(),
+        ));
+    }
+    let key = headers
+        .get(SEC_WEBSOCKET_KEY)
+        .ok_or_else(|| AppError::UpgradeRequired("missing Sec-WebSocket-Key".into()))?;
+    let accept = accept_key(key.as_bytes());
+    // The connection outlives the request and its task-local caller and
+    // tenant.
+    let caller = auth::current_caller();
+    let tenant = tenants::current_tenant();
+    tokio::spawn(async move {
+        match hyper::upgrade::on(req).await {
+            Ok(upgraded) => tenants::scope(tenant, serve(state, upgraded, caller)).await,
+            Err(err) => warn!(error = %err, "WebSocket upgrade failed"),
+        }
+    });
+    Ok(Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(CONNECTION, "upgrade")
+        .header(UPGRADE, "websocket")
+        .header(SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Body::empty())
+        .unwrap())
+}
+
+/// Whether the comma-separated header `name` lists `token`, ignoring case.
+fn has_token(headers: &HeaderMap, name: &HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+/// `Sec-WebSocket-Accept` for the client's `Sec-WebSocket-Key`.
+fn accept_key(key: &[u8]) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key);
+    sha1.update(HANDSHAKE_GUID.as_bytes());
+    STANDARD.encode(sha1.finalize())
+}
+
+/// Why a connection ends.
+enum Closing {
+    /// The connection is gone; no close frame can be sent.
+    Lost,
+    /// Send a close frame with this code and reason.
+    Close(u16, &'static str),
+}
+
+impl From<io::Error> for Closing {
+    fn from(_: io::Error) -> Self {
+        Closing::Lost
+    }
+}
+
+/// Reads messages and prices them until the client closes the connection, it
+/// breaks the protocol or the service shuts down. Orders in flight then get
+/// the drain timeout to be answered before the close frame.
+async fn serve(state: AppState, upgraded: Upgraded, caller: Option<String>) {
+    let max_in_flight = state.config.websocket.max_in_flight.max(1);
+    let (mut reader, writer) = tokio::io::split(upgraded);
+    let (frames, outgoing) = mpsc::channel(max_in_flight);
+    let writer = tokio::spawn(write_frames(writer, outgoing));
+    let in_flight = Arc::new(Semaphore::new(max_in_flight));
+    info!("WebSocket connection opened");
+
+    // The opcode and the payload so far of a fragmented message.
+    let mut message: Option<(u8, Vec<u8>)> = None;
+    let closing = loop {
+        let read = tokio::select! {
+            read = read_frame(&mut reader, *MAX_BODY_BYTES) => read,
+            _ = SHUTDOWN.triggered() => break Closing::Close(GOING_AWAY, "shutting down"),
+        };
+        let (fin, opcode, payload) = match read {
+            Ok(read) => read,
+            Err(closing) => break closing,
+        };
+        let (opcode, payload) = match (opcode, message.take()) {
+            (PING, pending) => {
+                message = pending;
+                let _ = frames.send(frame(PONG, &payload)).await;
+                continue;
+            }
+            (PONG, pending) => {
+                message = pending;
+                continue;
+            }
+            (CLOSE, _) => break Closing::Close(NORMAL_CLOSURE, ""),
+            (TEXT | BINARY, None) => (opcode, payload),
+            (CONTINUATION, Some((opcode, mut start))) => {
+                if start.len() + payload.len() > *MAX_BODY_BYTES {
+                    break Closing::Close(MESSAGE_TOO_BIG, "message too big");
+                }
+                start.extend_from_slice(&payload);
+                (opcode, start)
+            }
+            (TEXT | BINARY, Some(_)) | (CONTINUATION, None) => {
+                break Closing::Close(PROTOCOL_ERROR, "unexpected fragment")
+            }
+            _ => break Closing::Close(PROTOCOL_ERROR, "unknown opcode"),
+        };
+        if !fin {
+            message = Some((opcode, payload));
+            continue;
+        }
+        if opcode == BINARY {
+            break Closing::Close(UNSUPPORTED_DATA, "only text messages are accepted");
+        }
+        let text = match String::from_utf8(payload) {
+            Ok(text) => text,
+            Err(_) => break Closing::Close(INVALID_PAYLOAD, "text is not UTF-8"),
+        };
+        // Waiting for a slot stops reading, which pushes back on the client.
+        let permit = match in_flight.clone().acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => break Closing::Lost,
+        };
+        let task = handle(state.clone(), text, caller.clone(), frames.clone(), permit);
+        tokio::spawn(tenants::scope(tenants::current_tenant(), task));
+    };
+
+    let drained = in_flight.acquire_many(max_in_flight as u32);
+    if tokio::time::timeout(SHUTDOWN.drain_timeout, drained)
+        .await
+        .is_err()
+    {
+        warn!("drain timeout exceeded, dropping in-flight WebSocket orders");
+    }
+    if let Closing::Close(code, reason) = closing {
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        let _ = frames.send(frame(CLOSE, &payload)).await;
+    }
+    drop(frames);
+    let _ = writer.await;
+    info!("WebSocket connection closed");
+}
+
+/// Prices the order of one message, within the request timeout, and queues
+/// the answer.
+async fn handle(
+    state: AppState,
+    text: String,
+    caller: Option<String>,
+    frames: mpsc::Sender<Vec<u8>>,
+    _permit: OwnedSemaphorePermit,
+) {
+    let request_id = Uuid::new_v4().to_string();
+    let span = tracing::info_span!("websocket message", request_id = %request_id);
+    let reply = request_id::scope(request_id, auth::scope(caller, reply(&state, &text)))
+        .instrument(span)
+        .await;
+    // Serializing an order or an error envelope cannot fail.
+    let _ = frames
+        .send(frame(TEXT, &serde_json::to_vec(&reply).unwrap()))
+        .await;
+}
+
+async fn reply(state: &AppState, text: &str) -> Reply {
+    let request: PriceRequest = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(err) => {
+            let err = AppError::from(err);
+            warn!(code = err.code(), error = %err, "invalid WebSocket message");
+            state
+                .metrics
+                .websocket_messages
+                .with_label_values(&[err.code()])
+                .inc();
+            return Reply {
+                id: Value::Null,
+                outcome: Outcome::Error {
+                    error: err.envelope(),
+                },
+            };
+        }
+    };
+    // The order is parsed again by `price`, for its error messages.
+    let order = serde_json::to_vec(&request.order).unwrap();
+    let priced = deadline::within(request_timeout(), price(state, &order)).await;
+    let outcome = match priced {
+        Ok(order) => {
+            info!(order_id = order.order_id, "order priced");
+            state
+                .metrics
+                .websocket_messages
+                .with_label_values(&["OK"])
+                .inc();
+            Outcome::Ok {
+                order: Box::new(order),
+            }
+        }
+        Err(err) => {
+            warn!(code = err.code(), error = %err, "order failed");
+            state
+                .metrics
+                .websocket_messages
+                .with_label_values(&[err.code()])
+                .inc();
+            Outcome::Error {
+                error: err.envelope(),
+            }
+        }
+    };
+    Reply {
+        id: request.id,
+        outcome,
+    }
+}
+
+/// Reads one frame: whether it ends its message, its opcode and its unmasked
+/// payload.
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<(bool, u8, Vec<u8>), Closing> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0F;
+    if head[0] & 0x70 != 0 {
+        return Err(Closing::Close(PROTOCOL_ERROR, "reserved bits set"));
+    }
+    if head[1] & 0x80 == 0 {
+        return Err(Closing::Close(
+            PROTOCOL_ERROR,
+            "client frames must be masked",
+        ));
+    }
+    let len = match head[1] & 0x7F {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if opcode & 0x8 != 0 && (!fin || len > 125) {
+        return Err(Closing::Close(PROTOCOL_ERROR, "invalid control frame"));
+    }
+    if len > max_len as u64 {
+        return Err(Closing::Close(MESSAGE_TOO_BIG, "message too big"));
+    }
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).await?;
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((fin, opcode, payload))
+}
+
+/// An unfragmented, unmasked frame, as servers send them.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Writes the queued frames until the queue closes or a close frame is sent.
+async fn write_frames(mut writer: WriteHalf<Upgraded>, mut frames: mpsc::Receiver<Vec<u8>>) {
+    while let Some(frame) = frames.recv().await {
+        if writer.write_all(&frame).await.is_err() {
+            return;
+        }
+        if frame[0] & 0x0F == CLOSE {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
@@ -0,0 +1,57 @@
+//! Address normalization by the static validator: orders without a zip code
+//! are shipped to the one of their address, and addresses in another zip
+//! code than the order's are rejected.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use order_total::config::AddressValidatorKind;
+use serde_json::json;
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+
+#[tokio::test]
+async fn normalizes_shipping_addresses() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.address.validator = AddressValidatorKind::Static;
+    })
+    .await;
+
+    let mut without_zip = order("");
+    without_zip["shipping_address"] = "123 Main Street, Austin, TX 78701".into();
+    let (status, body) = service.compute(&without_zip).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["shipping_zip"], TAXED_ZIP);
+    assert_eq!(body["tax"], json!(1.65));
+    assert_eq!(
+        body["normalized_address"],
+        json!({
+            "address": "123 MAIN ST, AUSTIN, TX 78701",
+            "zip": TAXED_ZIP,
+            "zip_derived": true
+        })
+    );
+
+    // The address has no zip code of its own, so it gets the order's.
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["normalized_address"]["address"],
+        "123 MAIN ST, ANYTOWN 78701"
+    );
+    assert_eq!(body["normalized_address"]["zip_derived"], false);
+
+    let mut elsewhere = order("10001");
+    elsewhere["shipping_address"] = "123 Main St, Austin, TX 78701".into();
+    let (status, body) = service.compute(&elsewhere).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(error_code(&body), "INVALID_ADDRESS");
+    assert_eq!(rates.calls("10001"), 0);
+
+    let (status, body) = service.compute(&order("")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(error_code(&body), "INVALID_ADDRESS");
+}
@@ -0,0 +1,68 @@
+//! The rate cache saved to a snapshot file and loaded from it at startup.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TAXED_ZIP: &str = "78701";
+const OTHER_ZIP: &str = "10001";
+const OLD_ZIP: &str = "60601";
+
+#[tokio::test]
+async fn starts_with_the_rates_of_the_snapshot() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (OTHER_ZIP, Stub::Rate("0.08875")),
+        (OLD_ZIP, Stub::Rate("0.1025")),
+    ]))
+    .await;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let path =
+        std::env::temp_dir().join(format!("order_total-snapshot-{}.jsonl", std::process::id()));
+    let snapshot = format!(
+        "{{\"key\":\"{}\",\"rate\":0.0825,\"cached_at\":{}}}\n\
+         {{\"key\":\"{}\",\"rate\":0.1025,\"cached_at\":{}}}\n\
+         {{\"key\":\"{}\",\"ra",
+        TAXED_ZIP,
+        now - 10,
+        OLD_ZIP,
+        now - 120,
+        OTHER_ZIP,
+    );
+    std::fs::write(&path, snapshot).unwrap();
+    let snapshot_path = path.to_str().unwrap().to_string();
+    let service = TestService::start_with(&rates.url, |config| {
+        config.cache.snapshot_path = Some(snapshot_path);
+        config.cache.snapshot_max_age_secs = 60;
+        config.scheduler.cache_snapshot.schedule = "@every 20ms".into();
+    })
+    .await;
+
+    // The rate of the snapshot is served; the one too old was left out.
+    for zip in [TAXED_ZIP, OLD_ZIP, OTHER_ZIP] {
+        let (status, body) = service.compute(&order(zip)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    assert_eq!(rates.calls(TAXED_ZIP), 0);
+    assert_eq!(rates.calls(OLD_ZIP), 1);
+    assert_eq!(rates.calls(OTHER_ZIP), 1);
+
+    let mut saved = String::new();
+    for _ in 0..100 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        saved = std::fs::read_to_string(&path).unwrap_or_default();
+        if saved.lines().count() == 3 {
+            break;
+        }
+    }
+    let _ = std::fs::remove_file(&path);
+    for zip in [TAXED_ZIP, OLD_ZIP, OTHER_ZIP] {
+        assert!(saved.contains(&format!("\"key\":\"{}\"", zip)), "{}", saved);
+    }
+}
@@ -0,0 +1,73 @@
+//! Cancelling orders with `DELETE /orders/{id}`: they are kept, with their
+//! reason, but left out of `GET /orders` unless asked for.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+
+fn ids(page: &Value) -> Vec<i64> {
+    page["orders"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|record| record["order"]["order_id"].as_i64().unwrap())
+        .collect()
+}
+
+async fn list(service: &TestService, query: &str) -> Value {
+    let response = service.get(&format!("/v1/orders?{}", query)).await.unwrap();
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn cancels_orders_without_removing_them() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let service = TestService::start(&rates.url).await;
+    for order_id in [1, 2, 3] {
+        let mut order = order(TAXED_ZIP);
+        order["order_id"] = order_id.into();
+        let (status, body) = service.compute(&order).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    let reason = json!({"reason": "  ordered twice "});
+    let (status, body) = service.delete("/v1/orders/1", Some(&reason)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["status"], "cancelled");
+    assert_eq!(body["cancellation_reason"], "ordered twice");
+    // Without a body.
+    let (status, body) = service.delete("/v1/orders/2", None).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body.get("cancellation_reason").is_none());
+
+    let record: Value = service
+        .get("/v1/orders/1")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(record["status"], "cancelled");
+    assert_eq!(record["cancellation_reason"], "ordered twice");
+    let page = list(&service, "").await;
+    assert_eq!(ids(&page), [3]);
+    assert_eq!(page["total"], 1);
+    let page = list(&service, "include_cancelled=true").await;
+    assert_eq!(ids(&page), [3, 2, 1]);
+
+    let (status, body) = service.delete("/v1/orders/1", None).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    assert_eq!(error_code(&body), "INVALID_TRANSITION");
+    let (status, body) = service.delete("/v1/orders/42", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{}", body);
+    let long = json!({"reason": "x".repeat(501)});
+    let (status, body) = service.delete("/v1/orders/3", Some(&long)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    let (status, body) = service.post("/v1/orders/3/cancel").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
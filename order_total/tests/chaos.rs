@@ -0,0 +1,46 @@
+//! Fault injection: failed calls to the sales tax rate service are retried,
+//! then open the circuit breaker, and delayed API requests are slower.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const TAXED_ZIP: &str = "78701";
+const INBOUND_DELAY: Duration = Duration::from_millis(300);
+
+// Every API request is delayed and every call to the rate service failed, so
+// the outcome doesn't depend on the seed.
+#[tokio::test]
+async fn injected_faults_meet_retries_and_the_circuit_breaker() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.chaos.enabled = true;
+        config.chaos.seed = Some(7);
+        config.chaos.inbound.delay_percent = 100.0;
+        config.chaos.inbound.delay_ms = INBOUND_DELAY.as_millis() as u64;
+        config.chaos.upstream.failure_percent = 100.0;
+        config.circuit_breaker.failure_threshold = 2;
+    })
+    .await;
+
+    for _ in 0..2 {
+        let start = Instant::now();
+        let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+        assert!(start.elapsed() >= INBOUND_DELAY);
+        assert_eq!(status, StatusCode::BAD_GATEWAY, "{}", body);
+        assert_eq!(error_code(&body), "UPSTREAM_UNAVAILABLE");
+    }
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+    assert_eq!(error_code(&body), "CIRCUIT_OPEN");
+
+    // The failed calls never reached the rate service.
+    assert_eq!(rates.calls(TAXED_ZIP), 0);
+    // Probes aren't delayed.
+    let start = Instant::now();
+    assert!(service.get("/healthz").await.is_ok());
+    assert!(start.elapsed() < INBOUND_DELAY);
+}
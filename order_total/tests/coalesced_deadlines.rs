@@ -0,0 +1,54 @@
+//! Requests sharing a rate lookup keep their own deadlines: the lookup runs
+//! until the latest of theirs, and a request giving up on it neither fails
+//! the others nor counts against the circuit breaker.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+const TAXED_ZIP: &str = "78701";
+const SLOW_ZIP: &str = "78702";
+
+#[tokio::test]
+async fn coalesced_lookups_keep_each_callers_deadline() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (
+            SLOW_ZIP,
+            Stub::Delayed("0.0825", Duration::from_millis(150)),
+        ),
+    ]))
+    .await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.circuit_breaker.failure_threshold = 1;
+    })
+    .await;
+
+    // The first request starts the lookup with too short a budget; the
+    // second joins it with time to spare.
+    let later = SystemTime::now() + Duration::from_secs(5);
+    let later = humantime::format_rfc3339_millis(later).to_string();
+    let slow = order(SLOW_ZIP);
+    let hurried = service.post_with("/v1/compute", &[("grpc-timeout", "50m")], &slow);
+    let patient = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        service
+            .post_with("/v1/compute", &[("X-Request-Deadline", &later)], &slow)
+            .await
+    };
+    let ((status, body), (patient_status, patient_body)) = tokio::join!(hurried, patient);
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT, "{}", body);
+    assert_eq!(error_code(&body), "DEADLINE_EXCEEDED");
+    assert_eq!(patient_status, StatusCode::OK, "{}", patient_body);
+    assert_eq!(patient_body["tax"], 1.65);
+    // The first attempt ended with the first request's deadline, the retry
+    // had the second's.
+    assert_eq!(rates.calls(SLOW_ZIP), 2);
+
+    // The circuit stays closed.
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
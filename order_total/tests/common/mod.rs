@@ -0,0 +1,307 @@
+//! Runs order_total against a fake sales tax rate service, whose answer for
+//! each zip code is stubbed, and calls its HTTP API.
+
+// Each test binary uses its own part of the harness.
+#![allow(dead_code)]
+
+#[cfg(feature = "nats")]
+pub mod nats;
+
+use domain::{Decimal, ErrorEnvelope, ErrorResponse, RateQuote, RateRequest};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use order_total::config::AppConfig;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long order_total waits for the fake rate service.
+pub const UPSTREAM_TIMEOUT: Duration = Duration::from_millis(200);
+/// Attempts of every call to the fake rate service.
+pub const MAX_ATTEMPTS: u32 = 2;
+
+/// What the fake rate service answers for a zip code.
+#[derive(Debug, Clone)]
+pub enum Stub {
+    /// `200` with this rate.
+    Rate(&'static str),
+    /// `404 RATE_NOT_FOUND`, as for zip codes without a stub.
+    NotFound,
+    /// This status, without a body.
+    Status(u16),
+    /// `200` with this body.
+    Body(&'static str),
+    /// `Rate`, answered after this delay.
+    Delayed(&'static str, Duration),
+}
+
+/// A fake sales tax rate service answering `POST /find_rate` with the stub of
+/// the zip code asked for, and counting the calls for each zip code.
+pub struct FakeRateService {
+    pub url: String,
+    calls: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl FakeRateService {
+    /// Serves `stubs` on an ephemeral port, in the background.
+    pub async fn start(stubs: HashMap<&'static str, Stub>) -> Self {
+        let stubs = Arc::new(stubs);
+        let calls = Arc::new(Mutex::new(HashMap::new()));
+        let service_calls = calls.clone();
+        let make_svc = make_service_fn(move |_| {
+            let stubs = stubs.clone();
+            let calls = service_calls.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    answer(stubs.clone(), calls.clone(), req)
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let url = format!("http://{}/find_rate", server.local_addr());
+        tokio::spawn(server);
+        Self { url, calls }
+    }
+
+    /// How many times `zip` was looked up.
+    pub fn calls(&self, zip: &str) -> usize {
+        self.calls.lock().unwrap().get(zip).copied().unwrap_or(0)
+    }
+}
+
+async fn answer(
+    stubs: Arc<HashMap<&'static str, Stub>>,
+    calls: Arc<Mutex<HashMap<String, usize>>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let zip = match serde_json::from_slice::<RateRequest>(&body) {
+        Ok(request) => request.zip,
+        Err(_) => return Ok(status(StatusCode::BAD_REQUEST)),
+    };
+    *calls.lock().unwrap().entry(zip.clone()).or_default() += 1;
+    let response = match stubs.get(zip.as_str()).cloned().unwrap_or(Stub::NotFound) {
+        Stub::Rate(rate) => quote(&zip, rate),
+        Stub::NotFound => {
+            let body = ErrorResponse {
+                error: ErrorEnvelope {
+                    code: "RATE_NOT_FOUND".into(),
+                    message: format!("no rate for zip code {}", zip),
+                    details: None,
+                    request_id: None,
+                },
+            };
+            let mut response = json(&body);
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+        Stub::Status(code) => status(StatusCode::from_u16(code).unwrap()),
+        Stub::Body(body) => Response::new(Body::from(body)),
+        Stub::Delayed(rate, delay) => {
+            tokio::time::sleep(delay).await;
+            quote(&zip, rate)
+        }
+    };
+    Ok(response)
+}
+
+fn quote(zip: &str, rate: &str) -> Response<Body> {
+    json(&RateQuote {
+        zip: zip.to_string(),
+        rate: rate.parse::<Decimal>().unwrap(),
+        jurisdiction: "Testville, TX".into(),
+        components: Vec::new(),
+    })
+}
+
+fn json<T: serde::Serialize>(body: &T) -> Response<Body> {
+    Response::builder()
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap()))
+        .unwrap()
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// order_total, running in the background on an ephemeral port.
+pub struct TestService {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl TestService {
+    /// Starts order_total against the sales tax rate service at `rates_url`,
+    /// with short timeouts and retries and without the admin, gRPC or
+    /// circuit breaker getting in the way. Its configuration is installed for
+    /// the whole process, so it is started once per test binary.
+    pub async fn start(rates_url: &str) -> Self {
+        Self::start_with(rates_url, |_| {}).await
+    }
+
+    /// Starts order_total as `start` does, with the configuration changed by
+    /// `configure`.
+    pub async fn start_with(rates_url: &str, configure: impl FnOnce(&mut AppConfig)) -> Self {
+        let port = free_port().await;
+        let mut config = AppConfig::default();
+        config.server.port = port;
+        config.server.admin_port = 0;
+        config.server.grpc_port = 0;
+        config.upstream.url = rates_url.to_string();
+        config.upstream.timeout_ms = UPSTREAM_TIMEOUT.as_millis() as u64;
+        config.retry.max_attempts = MAX_ATTEMPTS;
+        config.retry.initial_delay_ms = 10;
+        config.retry.max_delay_ms = 10;
+        config.retry.jitter = 0.0;
+        config.circuit_breaker.failure_threshold = u32::MAX;
+        configure(&mut config);
+        tokio::spawn(async move {
+            if let Err(err) = order_total::run(config).await {
+                panic!("order_total failed: {}", err);
+            }
+        });
+        Self::connect(port).await
+    }
+
+    /// order_total started otherwise on `port`, once it answers, be it with
+    /// the public API or only with the admin endpoints.
+    pub async fn connect(port: u16) -> Self {
+        let service = Self {
+            base_url: format!("http://127.0.0.1:{}", port),
+            client: reqwest::Client::new(),
+        };
+        let probe = format!("{}/healthz", service.base_url);
+        for _ in 0..100 {
+            if service.client.get(&probe).send().await.is_ok() {
+                return service;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("order_total didn't start on port {}", port);
+    }
+
+    /// `POST /v1/compute` of `order`: the status and the JSON body.
+    pub async fn compute(&self, order: &Value) -> (StatusCode, Value) {
+        let response = self
+            .client
+            .post(format!("{}/v1/compute", self.base_url))
+            .json(order)
+            .send()
+            .await
+            .expect("order_total answers");
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        let body = response.json().await.expect("a JSON body");
+        (status, body)
+    }
+
+    /// `POST` of `path` without a body: the status and the JSON body.
+    pub async fn post(&self, path: &str) -> (StatusCode, Value) {
+        let response = self
+            .client
+            .post(format!("{}{}", self.base_url, path))
+            .send()
+            .await
+            .expect("order_total answers");
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        let body = response.json().await.expect("a JSON body");
+        (status, body)
+    }
+
+    /// `POST` of the JSON `body` to `path` with `headers`, such as an API
+    /// key: the status and the JSON body.
+    pub async fn post_with(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &Value,
+    ) -> (StatusCode, Value) {
+        let mut request = self.client.post(format!("{}{}", self.base_url, path));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = request
+            .json(body)
+            .send()
+            .await
+            .expect("order_total answers");
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        let body = response.json().await.expect("a JSON body");
+        (status, body)
+    }
+
+    /// `PATCH` of the JSON `body` to `path`: the status and the JSON body.
+    pub async fn patch(&self, path: &str, body: &Value) -> (StatusCode, Value) {
+        let response = self
+            .client
+            .patch(format!("{}{}", self.base_url, path))
+            .json(body)
+            .send()
+            .await
+            .expect("order_total answers");
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        let body = response.json().await.expect("a JSON body");
+        (status, body)
+    }
+
+    /// `DELETE` of `path`, with the JSON `body` if there is one: the status
+    /// and the JSON body.
+    pub async fn delete(&self, path: &str, body: Option<&Value>) -> (StatusCode, Value) {
+        let mut request = self.client.delete(format!("{}{}", self.base_url, path));
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let response = request.send().await.expect("order_total answers");
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        let body = response.json().await.expect("a JSON body");
+        (status, body)
+    }
+
+    /// `GET` of `path`, failing on an error status.
+    pub async fn get(&self, path: &str) -> reqwest::Result<reqwest::Response> {
+        self.client
+            .get(format!("{}{}", self.base_url, path))
+            .send()
+            .await?
+            .error_for_status()
+    }
+
+    /// `GET` of `path` with `headers`, whatever the status.
+    pub async fn get_with(&self, path: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+        let mut request = self.client.get(format!("{}{}", self.base_url, path));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.send().await.expect("order_total answers")
+    }
+}
+
+/// A port nothing listens on, for now.
+pub async fn free_port() -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// An order of 20.00, shipped to `zip`.
+pub fn order(zip: &str) -> Value {
+    serde_json::json!({
+        "order_id": 123,
+        "product_id": 321,
+        "quantity": 2,
+        "subtotal": 20.0,
+        "shipping_address": "123 Main St, Anytown USA",
+        "shipping_zip": zip,
+        "total": 0.0
+    })
+}
+
+/// The `error.code` of an error response.
+pub fn error_code(body: &Value) -> &str {
+    body["error"]["code"].as_str().unwrap_or_default()
+}
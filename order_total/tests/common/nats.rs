@@ -0,0 +1,130 @@
+//! A fake NATS server, for the `queue` run mode and the NATS event publisher.
+
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpListener;
+
+type Writer = Arc<tokio::sync::Mutex<OwnedWriteHalf>>;
+
+/// A NATS server recording what its clients publish and delivering messages
+/// to the last client that subscribed, as `orders.compute`.
+pub struct FakeNats {
+    pub url: String,
+    /// Subject and payload, as JSON when it is some.
+    published: Arc<Mutex<Vec<(String, Value)>>>,
+    subscriber: Arc<Mutex<Option<Writer>>>,
+}
+
+impl FakeNats {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = Arc::new(Mutex::new(None));
+        let (server_published, server_subscriber) = (published.clone(), subscriber.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (reader, mut writer) = stream.into_split();
+                writer.write_all(b"INFO {}\r\n").await.unwrap();
+                let writer = Arc::new(tokio::sync::Mutex::new(writer));
+                tokio::spawn(serve(
+                    BufReader::new(reader),
+                    writer,
+                    server_published.clone(),
+                    server_subscriber.clone(),
+                ));
+            }
+        });
+        Self {
+            url,
+            published,
+            subscriber,
+        }
+    }
+
+    /// Delivers `payload` to the subscriber, once there is one, with
+    /// `headers` if there are any.
+    pub async fn deliver(&self, reply_to: &str, headers: &[(&str, &str)], payload: &Value) {
+        let mut subscriber = None;
+        for _ in 0..100 {
+            subscriber = self.subscriber.lock().unwrap().clone();
+            if subscriber.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let payload = serde_json::to_vec(payload).unwrap();
+        let mut frame = if headers.is_empty() {
+            format!("MSG orders.compute 1 {} {}\r\n", reply_to, payload.len()).into_bytes()
+        } else {
+            let mut block = String::from("NATS/1.0\r\n");
+            for (name, value) in headers {
+                block.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            block.push_str("\r\n");
+            let mut frame = format!(
+                "HMSG orders.compute 1 {} {} {}\r\n",
+                reply_to,
+                block.len(),
+                block.len() + payload.len()
+            )
+            .into_bytes();
+            frame.extend_from_slice(block.as_bytes());
+            frame
+        };
+        frame.extend_from_slice(&payload);
+        frame.extend_from_slice(b"\r\n");
+        let subscriber = subscriber.expect("order_total subscribes");
+        subscriber.lock().await.write_all(&frame).await.unwrap();
+    }
+
+    /// What was published to `subject`, once there are `count` messages.
+    pub async fn published(&self, subject: &str, count: usize) -> Vec<Value> {
+        let mut messages = Vec::new();
+        for _ in 0..100 {
+            messages = self
+                .published
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(published, _)| published == subject)
+                .map(|(_, payload)| payload.clone())
+                .collect();
+            if messages.len() >= count {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        messages
+    }
+}
+
+async fn serve(
+    mut reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: Writer,
+    published: Arc<Mutex<Vec<(String, Value)>>>,
+    subscriber: Arc<Mutex<Option<Writer>>>,
+) {
+    let mut line = String::new();
+    while reader.read_line(&mut line).await.unwrap_or(0) > 0 {
+        let fields: Vec<String> = line.split_whitespace().map(String::from).collect();
+        match fields.first().map(String::as_str) {
+            Some("SUB") => *subscriber.lock().unwrap() = Some(writer.clone()),
+            Some("PUB") => {
+                let size: usize = fields.last().unwrap().parse().unwrap();
+                let mut payload = vec![0; size + 2];
+                reader.read_exact(&mut payload).await.unwrap();
+                payload.truncate(size);
+                let payload = serde_json::from_slice(&payload).unwrap_or_else(|_| {
+                    Value::String(String::from_utf8_lossy(&payload).into_owned())
+                });
+                published.lock().unwrap().push((fields[1].clone(), payload));
+            }
+            _ => (),
+        }
+        line.clear();
+    }
+}
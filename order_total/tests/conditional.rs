@@ -0,0 +1,63 @@
+//! ETags, `If-None-Match` and `Cache-Control` of `GET` responses.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+
+#[tokio::test]
+async fn answers_not_modified_while_the_etag_matches() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config
+            .http_cache
+            .cache_control
+            .insert("*".into(), "public, max-age=5".into());
+    })
+    .await;
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let response = service.get_with("/v1/orders/123", &[]).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["cache-control"], "private, no-cache");
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert!(etag.starts_with("W/\""), "{}", etag);
+
+    let response = service
+        .get_with("/v1/orders/123", &[("If-None-Match", &etag)])
+        .await;
+    assert_eq!(response.status(), 304);
+    assert_eq!(response.headers()["etag"], etag.as_str());
+    assert!(response.bytes().await.unwrap().is_empty());
+
+    // Among others, and compared weakly.
+    let strong = etag.trim_start_matches("W/");
+    let list = format!("\"other\", {}", strong);
+    let response = service
+        .get_with("/v1/orders/123", &[("If-None-Match", &list)])
+        .await;
+    assert_eq!(response.status(), 304);
+
+    // A changed order has another ETag.
+    let (status, body) = service.post("/v1/orders/123/confirm").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let response = service
+        .get_with("/v1/orders/123", &[("If-None-Match", &etag)])
+        .await;
+    assert_eq!(response.status(), 200);
+    assert_ne!(response.headers()["etag"], etag.as_str());
+
+    // Other routes get the default, and errors neither.
+    let response = service.get_with("/v1/orders", &[]).await;
+    assert_eq!(response.headers()["cache-control"], "private, no-cache");
+    let response = service.get_with("/openapi.json", &[]).await;
+    assert_eq!(response.headers()["cache-control"], "public, max-age=5");
+    let response = service.get_with("/v1/orders/999", &[]).await;
+    assert_eq!(response.status(), 404);
+    assert!(response.headers().get("etag").is_none());
+    assert!(response.headers().get("cache-control").is_none());
+}
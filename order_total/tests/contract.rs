@@ -0,0 +1,149 @@
+//! order_total against the contract it records of the sales tax rate service,
+//! `contracts/sales_tax_rate.json`: a fake of the service answers requests
+//! matching an interaction with its response, and fails the others. The
+//! sales_tax_rate tests check that the service gives these responses.
+
+mod common;
+
+use common::{error_code, order, TestService};
+use contract::{Contract, Interaction};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+const CONTRACT: &str = include_str!("../contracts/sales_tax_rate.json");
+
+/// The sales tax rate service, as the contract describes it.
+struct ContractProvider {
+    url: String,
+    record: Arc<Mutex<Record>>,
+}
+
+/// The requests a `ContractProvider` received.
+#[derive(Default)]
+struct Record {
+    /// The descriptions of the interactions requests matched.
+    exercised: BTreeSet<String>,
+    /// The requests matching no interaction.
+    unexpected: Vec<String>,
+}
+
+impl ContractProvider {
+    /// Serves the interactions of `contract` on an ephemeral port, in the
+    /// background.
+    async fn start(contract: Contract) -> Self {
+        let interactions = Arc::new(contract.interactions);
+        let record = Arc::new(Mutex::new(Record::default()));
+        let service_record = record.clone();
+        let make_svc = make_service_fn(move |_| {
+            let interactions = interactions.clone();
+            let record = service_record.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    answer(interactions.clone(), record.clone(), req)
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let url = format!("http://{}/find_rate", server.local_addr());
+        tokio::spawn(server);
+        Self { url, record }
+    }
+}
+
+async fn answer(
+    interactions: Arc<Vec<Interaction>>,
+    record: Arc<Mutex<Record>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap();
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let matched = interactions.iter().find(|interaction| {
+        interaction
+            .request
+            .mismatches(parts.method.as_str(), parts.uri.path(), header, &body)
+            .is_empty()
+    });
+    let mut record = record.lock().unwrap();
+    let response = match matched {
+        Some(interaction) => {
+            record.exercised.insert(interaction.description.clone());
+            let expected = &interaction.response;
+            let mut response = Response::builder().status(expected.status);
+            for (name, value) in &expected.headers {
+                response = response.header(name, value);
+            }
+            let body = match &expected.body {
+                Some(body) => {
+                    response = response.header("content-type", "application/json");
+                    Body::from(body.to_string())
+                }
+                None => Body::empty(),
+            };
+            response.body(body).unwrap()
+        }
+        None => {
+            record.unexpected.push(format!(
+                "{} {} {}",
+                parts.method,
+                parts.uri.path(),
+                String::from_utf8_lossy(&body)
+            ));
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap()
+        }
+    };
+    Ok(response)
+}
+
+// order_total installs its configuration for the whole process, so every
+// interaction is exercised by one test.
+#[tokio::test]
+async fn keeps_to_the_sales_tax_rate_contract() {
+    let contract = Contract::parse(CONTRACT).unwrap();
+    let interactions: BTreeSet<String> = contract
+        .interactions
+        .iter()
+        .map(|interaction| interaction.description.clone())
+        .collect();
+    let provider = ContractProvider::start(contract).await;
+    let service = TestService::start(&provider.url).await;
+
+    // ZIP+4 codes are looked up by their 5-digit part.
+    for zip in ["78701", "78701-1234"] {
+        let (status, body) = service.compute(&order(zip)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["total"], json!(21.65));
+    }
+    // Priced as of a past day, at the rate of that day.
+    let (status, body) = service
+        .post_with("/v1/compute?as_of=2019-06-30", &[], &order("78701"))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["total"], json!(21.6));
+    let (status, body) = service.compute(&order("00000")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(error_code(&body), "RATE_NOT_FOUND");
+    assert!(service.get("/readyz").await.is_ok());
+
+    let record = provider.record.lock().unwrap();
+    assert!(
+        record.unexpected.is_empty(),
+        "requests outside the contract: {:?}",
+        record.unexpected
+    );
+    assert_eq!(record.exercised, interactions);
+}
@@ -0,0 +1,66 @@
+//! Deadlines given by callers with `X-Request-Deadline` or `grpc-timeout`:
+//! an order is priced within what is left of it, and fails fast with `504
+//! DEADLINE_EXCEEDED` rather than wait on the sales tax rate service.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TAXED_ZIP: &str = "78701";
+const SLOW_ZIP: &str = "78702";
+
+#[tokio::test]
+async fn prices_orders_within_the_callers_deadline() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (
+            SLOW_ZIP,
+            Stub::Delayed("0.0825", Duration::from_millis(150)),
+        ),
+    ]))
+    .await;
+    let service = TestService::start(&rates.url).await;
+
+    // A deadline far enough away changes nothing.
+    let later = SystemTime::now() + Duration::from_secs(5);
+    let later = humantime::format_rfc3339_millis(later).to_string();
+    let (status, body) = service
+        .post_with(
+            "/v1/compute",
+            &[("X-Request-Deadline", &later)],
+            &order(TAXED_ZIP),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 1.65);
+
+    // A deadline already past fails at once, without a lookup.
+    let past = SystemTime::now() - Duration::from_secs(1);
+    let past = past
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+        .to_string();
+    let (status, body) = service
+        .post_with(
+            "/v1/compute",
+            &[("X-Request-Deadline", &past)],
+            &order(SLOW_ZIP),
+        )
+        .await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT, "{}", body);
+    assert_eq!(error_code(&body), "DEADLINE_EXCEEDED");
+    assert_eq!(rates.calls(SLOW_ZIP), 0);
+
+    // A budget shorter than the upstream timeout cuts the lookup short, and
+    // leaves no time to retry it.
+    let (status, body) = service
+        .post_with("/v1/compute", &[("grpc-timeout", "50m")], &order(SLOW_ZIP))
+        .await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT, "{}", body);
+    assert_eq!(error_code(&body), "DEADLINE_EXCEEDED");
+    assert_eq!(rates.calls(SLOW_ZIP), 1);
+}
@@ -0,0 +1,58 @@
+//! Identical orders within the deduplication window answered with the result
+//! of the first one instead of being priced again.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use order_total::config::TenantConfig;
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+
+#[tokio::test]
+async fn answers_identical_orders_with_the_first_result() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let service = TestService::start_with(&rates.url, |config| {
+        // Every pricing calls the rate service.
+        config.cache.ttl_secs = 0;
+        config.dedup.window_ms = 60_000;
+        let acme = TenantConfig {
+            dedup_window_ms: Some(0),
+            ..TenantConfig::default()
+        };
+        config.tenants.insert("acme".into(), acme);
+    })
+    .await;
+
+    let (status, first) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", first);
+    let (status, second) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", second);
+    assert_eq!(first, second);
+    assert_eq!(rates.calls(TAXED_ZIP), 1);
+
+    // Another content is another order.
+    let mut changed = order(TAXED_ZIP);
+    changed["quantity"] = 3.into();
+    let (status, body) = service.compute(&changed).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(rates.calls(TAXED_ZIP), 2);
+
+    // The tenant turned deduplication off for its orders.
+    for _ in 0..2 {
+        let headers = [("x-tenant-id", "acme")];
+        let (status, body) = service
+            .post_with("/v1/compute", &headers, &order(TAXED_ZIP))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    assert_eq!(rates.calls(TAXED_ZIP), 4);
+
+    let metrics = service.get("/metrics").await.unwrap().text().await.unwrap();
+    assert!(
+        metrics.contains("orders_deduplicated_total 1"),
+        "{}",
+        metrics
+    );
+}
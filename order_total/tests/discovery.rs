@@ -0,0 +1,111 @@
+//! Discovering the sales tax rate service through a DNS SRV record: lookups
+//! rotate over the healthy instances of the lowest priority.
+
+mod common;
+
+use common::{free_port, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use order_total::config::DiscoveryMode;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::time::Duration;
+use wasmedge_wasi_socket::UdpSocket;
+
+const SRV_NAME: &str = "_sales-tax-rate._tcp.example.internal";
+const ZIPS: [&str; 4] = ["78701", "78702", "78703", "78704"];
+
+fn stubs() -> HashMap<&'static str, Stub> {
+    ZIPS.iter()
+        .map(|zip| (*zip, Stub::Rate("0.0825")))
+        .collect()
+}
+
+fn port(url: &str) -> u16 {
+    let authority = url.trim_start_matches("http://").split('/').next().unwrap();
+    authority.rsplit(':').next().unwrap().parse().unwrap()
+}
+
+/// A DNS server answering every query with SRV records of 127.0.0.1 at
+/// `(priority, port)` of `targets`.
+async fn start_dns(targets: Vec<(u16, u16)>) -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.as_ref().set_nonblocking(true).unwrap();
+    let address = socket.as_ref().get_local().unwrap().to_string();
+    tokio::spawn(async move {
+        let mut query = [0; 512];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut query) {
+                Ok(received) => received,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    continue;
+                }
+                Err(err) => panic!("{}", err),
+            };
+            let answer = srv_answer(&query[..len], &targets);
+            socket.send_to(&answer, peer).unwrap();
+        }
+    });
+    address
+}
+
+fn srv_answer(query: &[u8], targets: &[(u16, u16)]) -> Vec<u8> {
+    let mut answer = Vec::new();
+    // The query's id, a response with recursion available, its question.
+    answer.extend_from_slice(&query[0..2]);
+    answer.extend_from_slice(&[0x81, 0x80, 0, 1]);
+    answer.extend_from_slice(&(targets.len() as u16).to_be_bytes());
+    answer.extend_from_slice(&[0, 0, 0, 0]);
+    answer.extend_from_slice(&query[12..]);
+    for (priority, port) in targets {
+        let mut data = Vec::new();
+        data.extend_from_slice(&priority.to_be_bytes());
+        data.extend_from_slice(&[0, 10]);
+        data.extend_from_slice(&port.to_be_bytes());
+        for label in ["127", "0", "0", "1"] {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+        data.push(0);
+        // The name points to the question's; type SRV, class IN, TTL 30s.
+        answer.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1, 0, 0, 0, 30]);
+        answer.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        answer.extend_from_slice(&data);
+    }
+    answer
+}
+
+#[tokio::test]
+async fn rotates_over_the_healthy_instances_of_an_srv_record() {
+    let first = FakeRateService::start(stubs()).await;
+    let second = FakeRateService::start(stubs()).await;
+    let backup = FakeRateService::start(stubs()).await;
+    let dead = free_port().await;
+    let nameserver = start_dns(vec![
+        (10, port(&first.url)),
+        (10, dead),
+        (10, port(&second.url)),
+        (20, port(&backup.url)),
+    ])
+    .await;
+    // upstream.url only gives the scheme and path.
+    let service = TestService::start_with("http://sales-tax-rate/find_rate", |config| {
+        config.upstream.discovery.mode = DiscoveryMode::DnsSrv;
+        config.upstream.discovery.srv_name = SRV_NAME.into();
+        config.upstream.discovery.nameserver = Some(nameserver);
+    })
+    .await;
+
+    for zip in ZIPS {
+        let (status, body) = service.compute(&order(zip)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["tax"], 1.65);
+    }
+    let calls = |rates: &FakeRateService| ZIPS.iter().map(|zip| rates.calls(zip)).sum::<usize>();
+    assert_eq!(calls(&first), 2);
+    assert_eq!(calls(&second), 2);
+    assert_eq!(calls(&backup), 0);
+
+    let metrics = service.get("/metrics").await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("upstream_instances 2"), "{}", metrics);
+}
@@ -0,0 +1,37 @@
+//! Calling the sales tax rate service through the proxy of its egress route,
+//! over the proxy configured for every other call.
+
+mod common;
+
+use common::{free_port, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use order_total::config::EgressRoute;
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+
+#[tokio::test]
+async fn calls_the_rate_service_through_its_route() {
+    // The fake rate service answers whatever URL it is asked for, so it
+    // stands in for a forward proxy to a host that doesn't resolve.
+    let proxy = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let proxy_url = proxy.url.trim_end_matches("/find_rate").to_string();
+    let dead_proxy = format!("http://127.0.0.1:{}", free_port().await);
+    let service = TestService::start_with("http://sales-tax-rate.invalid/find_rate", |config| {
+        config.egress.use_env = false;
+        config.egress.proxy = Some(dead_proxy);
+        config.egress.routes.insert(
+            "upstream".into(),
+            EgressRoute {
+                proxy: Some(proxy_url),
+                direct: false,
+            },
+        );
+    })
+    .await;
+
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 1.65);
+    assert_eq!(proxy.calls(TAXED_ZIP), 1);
+}
@@ -0,0 +1,72 @@
+//! Streamed exports of the stored orders at `GET /orders/export`.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+
+#[tokio::test]
+async fn exports_orders_as_csv_and_json_lines() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let service = TestService::start(&rates.url).await;
+    for order_id in [1, 2, 3] {
+        let mut order = order(TAXED_ZIP);
+        order["order_id"] = order_id.into();
+        let (status, body) = service.compute(&order).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    let (status, body) = service.post("/v1/orders/2/cancel").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let response = service.get("/v1/orders/export").await.unwrap();
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/csv; charset=utf-8"
+    );
+    assert_eq!(response.headers()["transfer-encoding"], "chunked");
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "order_id,status,priced_at,shipping_zip,currency,rate,subtotal,discount,shipping,tax,total,cancellation_reason"
+    );
+    assert_eq!(lines.len(), 4, "{}", csv);
+    assert!(lines[1].starts_with("1,priced,"), "{}", lines[1]);
+    assert!(lines[2].starts_with("2,cancelled,"), "{}", lines[2]);
+    let columns: Vec<&str> = lines[3].split(',').collect();
+    assert_eq!(columns[0], "3");
+    assert_eq!(columns[3], TAXED_ZIP);
+    assert_eq!(columns[5], "0.0825");
+    let amount = |index: usize| columns[index].parse::<f64>().unwrap();
+    assert_eq!((amount(9), amount(10)), (1.65, 21.65));
+    assert_eq!(columns[11], "");
+
+    let response = service.get("/v1/orders/export?format=jsonl").await.unwrap();
+    assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+    let jsonl = response.text().await.unwrap();
+    let records: Vec<Value> = jsonl
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let ids: Vec<i64> = records
+        .iter()
+        .map(|record| record["order"]["order_id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, [1, 2, 3]);
+
+    let response = service
+        .get("/v1/orders/export?format=jsonl&to=2000-01-01T00:00:00Z")
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "");
+
+    for query in ["format=xlsx", "from=yesterday"] {
+        let response = service.get(&format!("/v1/orders/export?{}", query)).await;
+        let status = response.unwrap_err().status().map(|status| status.as_u16());
+        assert_eq!(status, Some(400), "{}", query);
+    }
+}
@@ -0,0 +1,54 @@
+//! The file store across a crash in the middle of an append: the torn last
+//! record is dropped when the store is opened again.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use order_total::config::PersistenceBackend;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+
+#[tokio::test]
+async fn opens_a_store_ending_in_a_torn_record() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let path = std::env::temp_dir().join(format!("order_total-torn-{}.jsonl", std::process::id()));
+    let priced: domain::Order = serde_json::from_value(order(TAXED_ZIP)).unwrap();
+    let record = json!({"order": priced, "rate": 0.0825, "priced_at": "2026-10-01T00:00:00Z"});
+    std::fs::write(
+        &path,
+        format!("{}\n{{\"order\":{{\"order_id\":124,", record),
+    )
+    .unwrap();
+    let store = path.to_str().unwrap().to_string();
+    let service = TestService::start_with(&rates.url, |config| {
+        config.persistence.backend = PersistenceBackend::File;
+        config.persistence.path = store;
+    })
+    .await;
+
+    let body: Value = service
+        .get("/v1/orders/123")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["order"]["order_id"], 123, "{}", body);
+
+    // The next record starts its own line.
+    let mut next = order(TAXED_ZIP);
+    next["order_id"] = json!(124);
+    let (status, body) = service.compute(&next).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let lines: Vec<Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2, "{}", contents);
+    assert_eq!(lines[1]["order"]["order_id"], 124);
+}
@@ -0,0 +1,75 @@
+//! The file store compacted when it is opened: replaced records, published
+//! events and purged orders are dropped, and the rest keeps its place.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use order_total::config::PersistenceBackend;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+
+#[tokio::test]
+async fn compacts_a_store_with_superseded_lines() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let path = std::env::temp_dir().join(format!(
+        "order_total-compaction-{}.jsonl",
+        std::process::id()
+    ));
+    let record = |order_id: i32, priced_at: &str| {
+        let mut priced = order(TAXED_ZIP);
+        priced["order_id"] = json!(order_id);
+        let priced: domain::Order = serde_json::from_value(priced).unwrap();
+        json!({"order": priced, "rate": 0.0825, "priced_at": priced_at})
+    };
+    let lines = [
+        record(123, "2026-10-01T00:00:00Z"),
+        record(123, "2026-10-02T00:00:00Z"),
+        json!({"outbox_id": 0, "record": record(124, "2026-10-03T00:00:00Z")}),
+        json!({"sent_outbox_id": 0}),
+        json!({"outbox_id": 1, "record": record(125, "2026-10-04T00:00:00Z")}),
+        record(126, "2026-10-05T00:00:00Z"),
+        json!({"removed_order_id": 126}),
+    ];
+    let contents: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    std::fs::write(&path, contents).unwrap();
+    let store = path.to_str().unwrap().to_string();
+    let service = TestService::start_with(&rates.url, |config| {
+        config.persistence.backend = PersistenceBackend::File;
+        config.persistence.path = store;
+        config.persistence.compact_after = 3;
+    })
+    .await;
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let lines: Vec<Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 5, "{}", contents);
+    assert_eq!(lines[0], json!({"next_seq": 5, "next_outbox_id": 2}));
+    assert_eq!(lines[1]["pending_outbox_id"], 1);
+    let kept: Vec<(&Value, &Value)> = lines[2..]
+        .iter()
+        .map(|line| (&line["seq"], &line["record"]["order"]["order_id"]))
+        .collect();
+    assert_eq!(
+        kept,
+        [
+            (&json!(1), &json!(123)),
+            (&json!(2), &json!(124)),
+            (&json!(3), &json!(125))
+        ]
+    );
+
+    let body: Value = service
+        .get("/v1/orders/123")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["priced_at"], "2026-10-02T00:00:00Z", "{}", body);
+}
@@ -0,0 +1,78 @@
+//! Feature flags read from a file: the batch endpoint on for one API key
+//! only, then off for everyone once the file changes.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use order_total::config::FlagProviderKind;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const TAXED_ZIP: &str = "78701";
+const ALPHA_KEY: &str = "alpha-key";
+const BETA_KEY: &str = "beta-key";
+
+/// The caller an API key is known as, as `auth` names it.
+fn caller(api_key: &str) -> String {
+    let digest = Sha256::digest(api_key.as_bytes());
+    let hex: String = digest[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("key:{}", hex)
+}
+
+async fn batch(service: &TestService, api_key: &str) -> (StatusCode, Value) {
+    let orders = Value::Array(vec![order(TAXED_ZIP)]);
+    let headers = [("x-api-key", api_key)];
+    service
+        .post_with("/v1/compute_batch", &headers, &orders)
+        .await
+}
+
+#[tokio::test]
+async fn gates_the_batch_endpoint_per_api_key() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let path = std::env::temp_dir().join(format!("order_total-flags-{}.toml", std::process::id()));
+    let rule = format!(
+        "[batch_endpoint]\nrollout_percent = 0\ncallers = [\"{}\"]\n",
+        caller(ALPHA_KEY)
+    );
+    std::fs::write(&path, rule).unwrap();
+    let flags_path = path.to_str().unwrap().to_string();
+    let service = TestService::start_with(&rates.url, |config| {
+        config.auth.api_keys = vec![ALPHA_KEY.into(), BETA_KEY.into()];
+        config.flags.provider = FlagProviderKind::File;
+        config.flags.path = Some(flags_path);
+        config.flags.refresh_ms = 20;
+    })
+    .await;
+
+    let (status, body) = batch(&service, ALPHA_KEY).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["results"][0]["order"]["tax"], 1.65, "{}", body);
+    let (status, body) = batch(&service, BETA_KEY).await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{}", body);
+    assert_eq!(error_code(&body), "NOT_FOUND");
+
+    std::fs::write(&path, "[batch_endpoint]\nenabled = false\n").unwrap();
+    let mut switched_off = false;
+    for _ in 0..100 {
+        if batch(&service, ALPHA_KEY).await.0 == StatusCode::NOT_FOUND {
+            switched_off = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(switched_off, "the changed flags weren't applied");
+    let metrics = service.get("/metrics").await.unwrap().text().await.unwrap();
+    assert!(
+        metrics.contains(r#"flag_refreshes_total{outcome="success"} 1"#),
+        "{}",
+        metrics
+    );
+    let _ = std::fs::remove_file(path);
+}
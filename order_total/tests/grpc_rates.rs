@@ -0,0 +1,132 @@
+//! Rates looked up over gRPC, for a `grpc://` `upstream.url`, against a fake
+//! `sales_tax_rate.v1.SalesTaxRate` service: each call carries what is left
+//! of the deadline of the order being priced as its `grpc-timeout`.
+
+mod common;
+
+use common::{order, TestService};
+use hyper::header::HeaderValue;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Request, Response, Server, StatusCode};
+use prost::Message;
+use proto::{FindRateRequest, FindRateResponse, RateComponent, FIND_RATE_PATH};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TAXED_ZIP: &str = "78701";
+const INVALID_ZIP: &str = "00001";
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(150);
+
+/// The `grpc-timeout` of every call received.
+type Timeouts = Arc<Mutex<Vec<String>>>;
+
+/// Serves `FindRate` over HTTP/2 in the background: the rate of `TAXED_ZIP`,
+/// INVALID_ARGUMENT for `INVALID_ZIP` and NOT_FOUND for every other zip code.
+async fn start_rate_service() -> (String, Timeouts) {
+    let timeouts = Timeouts::default();
+    let service_timeouts = timeouts.clone();
+    let make_svc = make_service_fn(move |_| {
+        let timeouts = service_timeouts.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| find_rate(timeouts.clone(), req))) }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .http2_only(true)
+        .serve(make_svc);
+    let url = format!("grpc://{}", server.local_addr());
+    tokio::spawn(server);
+    (url, timeouts)
+}
+
+async fn find_rate(timeouts: Timeouts, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    assert_eq!(req.uri().path(), FIND_RATE_PATH);
+    assert_eq!(req.headers()["content-type"], "application/grpc");
+    if let Some(timeout) = req.headers().get("grpc-timeout") {
+        timeouts
+            .lock()
+            .unwrap()
+            .push(timeout.to_str().unwrap().to_string());
+    }
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let request = FindRateRequest::decode(&body[5..]).unwrap();
+    if request.zip != TAXED_ZIP {
+        let (status, message) = match request.zip.as_str() {
+            INVALID_ZIP => ("3", "invalid zip code"),
+            _ => ("5", "no rate for zip code"),
+        };
+        // A trailers-only response.
+        let response = Response::builder()
+            .header("content-type", "application/grpc")
+            .header("grpc-status", status)
+            .header("grpc-message", message)
+            .body(Body::empty())
+            .unwrap();
+        return Ok(response);
+    }
+    let message = FindRateResponse {
+        zip: request.zip,
+        rate: "0.0825".into(),
+        jurisdiction: "Austin, TX".into(),
+        components: vec![
+            RateComponent {
+                level: "state".into(),
+                name: "Texas".into(),
+                rate: "0.0625".into(),
+            },
+            RateComponent {
+                level: "city".into(),
+                name: "Austin".into(),
+                rate: "0.02".into(),
+            },
+        ],
+    }
+    .encode_to_vec();
+    let mut frame = vec![0];
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        sender.send_data(frame.into()).await.unwrap();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        sender.send_trailers(trailers).await.unwrap();
+    });
+    Ok(Response::builder()
+        .header("content-type", "application/grpc")
+        .body(body)
+        .unwrap())
+}
+
+#[tokio::test]
+async fn looks_rates_up_over_grpc_within_the_request_deadline() {
+    let (url, timeouts) = start_rate_service().await;
+    let service = TestService::start_with(&url, |config| {
+        config.server.request_timeout_ms = REQUEST_TIMEOUT.as_millis() as u64;
+    })
+    .await;
+
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 1.65);
+    assert_eq!(body["tax_breakdown"][0]["name"], "Texas", "{}", body);
+    assert_eq!(body["tax_breakdown"][1]["tax"], 0.4, "{}", body);
+
+    let (status, body) = service.compute(&order("00000")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(common::error_code(&body), "RATE_NOT_FOUND");
+
+    let (status, body) = service.compute(&order(INVALID_ZIP)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(common::error_code(&body), "VALIDATION_FAILED");
+
+    // The request timeout, shorter than the upstream timeout, bounds every
+    // call.
+    let timeouts = timeouts.lock().unwrap().clone();
+    assert_eq!(timeouts.len(), 3, "{:?}", timeouts);
+    for timeout in timeouts {
+        let millis: u128 = timeout.strip_suffix('m').unwrap().parse().unwrap();
+        assert!(millis <= REQUEST_TIMEOUT.as_millis(), "{}", timeout);
+        assert!(millis > 0, "{}", timeout);
+    }
+}
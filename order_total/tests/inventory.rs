@@ -0,0 +1,116 @@
+//! The inventory saga: stock is reserved before an order is priced, kept when
+//! it is priced and released when pricing fails.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TAXED_ZIP: &str = "78701";
+const UNKNOWN_ZIP: &str = "00000";
+/// A product the fake inventory service has no stock of.
+const OUT_OF_STOCK: i64 = 999;
+
+/// A fake inventory service handing out `RES-<n>` reservations and recording
+/// the ones reserved and released.
+#[derive(Default)]
+struct FakeInventory {
+    reserved: Vec<String>,
+    released: Vec<String>,
+}
+
+async fn start_inventory() -> (String, Arc<Mutex<FakeInventory>>) {
+    let inventory = Arc::new(Mutex::new(FakeInventory::default()));
+    let service_inventory = inventory.clone();
+    let make_svc = make_service_fn(move |_| {
+        let inventory = service_inventory.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| answer(inventory.clone(), req))) }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let url = format!("http://{}/reservations", server.local_addr());
+    tokio::spawn(server);
+    (url, inventory)
+}
+
+async fn answer(
+    inventory: Arc<Mutex<FakeInventory>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let mut inventory = inventory.lock().unwrap();
+    let (status, body) = if method == Method::DELETE {
+        let id = path.trim_start_matches("/reservations/").to_string();
+        inventory.released.push(id);
+        (StatusCode::NO_CONTENT, Value::Null)
+    } else {
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        let items = request["items"].as_array().unwrap();
+        if items.iter().any(|item| item["product_id"] == OUT_OF_STOCK) {
+            let error = json!({
+                "error": { "code": "OUT_OF_STOCK", "message": "product 999 is out of stock" }
+            });
+            (StatusCode::CONFLICT, error)
+        } else {
+            let id = format!("RES-{}", inventory.reserved.len() + 1);
+            inventory.reserved.push(id.clone());
+            (StatusCode::CREATED, json!({ "reservation_id": id }))
+        }
+    };
+    let body = match body {
+        Value::Null => Body::empty(),
+        body => Body::from(body.to_string()),
+    };
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body)
+        .unwrap())
+}
+
+#[tokio::test]
+async fn reserves_stock_and_releases_it_when_pricing_fails() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let (inventory_url, inventory) = start_inventory().await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.inventory.enabled = true;
+        config.inventory.url = inventory_url;
+    })
+    .await;
+
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["reservation_id"], "RES-1");
+
+    // The rate lookup fails after the stock was reserved.
+    let (status, body) = service.compute(&order(UNKNOWN_ZIP)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(error_code(&body), "RATE_NOT_FOUND");
+    // The reservation is released in the background.
+    for _ in 0..50 {
+        if !inventory.lock().unwrap().released.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(inventory.lock().unwrap().released, vec!["RES-2"]);
+
+    let mut out_of_stock = order(TAXED_ZIP);
+    out_of_stock["product_id"] = OUT_OF_STOCK.into();
+    let (status, body) = service.compute(&out_of_stock).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    assert_eq!(error_code(&body), "INSUFFICIENT_STOCK");
+    assert_eq!(rates.calls(TAXED_ZIP), 1);
+
+    let inventory = inventory.lock().unwrap();
+    assert_eq!(inventory.reserved, vec!["RES-1", "RES-2"]);
+    assert_eq!(inventory.released, vec!["RES-2"]);
+}
@@ -0,0 +1,49 @@
+//! Balancing lookups over a comma-separated list of sales tax rate service
+//! replicas: a replica failing calls in a row is ejected, and the lookups
+//! retried on the others.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use std::collections::HashMap;
+
+const ZIPS: [&str; 6] = ["78701", "78702", "78703", "78704", "78705", "78706"];
+
+fn stubs(stub: Stub) -> HashMap<&'static str, Stub> {
+    ZIPS.iter().map(|zip| (*zip, stub.clone())).collect()
+}
+
+fn calls(rates: &FakeRateService) -> usize {
+    ZIPS.iter().map(|zip| rates.calls(zip)).sum()
+}
+
+#[tokio::test]
+async fn ejects_a_failing_replica_from_the_rotation() {
+    let broken = FakeRateService::start(stubs(Stub::Status(503))).await;
+    let healthy = FakeRateService::start(stubs(Stub::Rate("0.0825"))).await;
+    let urls = format!("{}, {}", broken.url, healthy.url);
+    let service = TestService::start_with(&urls, |config| {
+        config.upstream.load_balancer.max_failures = 2;
+        config.upstream.load_balancer.ejection_ms = 60_000;
+    })
+    .await;
+
+    // The first two lookups go to the broken replica first and are retried on
+    // the healthy one; after its second failure, it is out of the rotation.
+    for zip in ZIPS {
+        let (status, body) = service.compute(&order(zip)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["tax"], 1.65);
+    }
+    assert_eq!(calls(&broken), 2);
+    assert_eq!(calls(&healthy), ZIPS.len());
+
+    let metrics = service.get("/metrics").await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("upstream_instances 1"), "{}", metrics);
+    assert!(
+        metrics.contains("upstream_ejections_total 1"),
+        "{}",
+        metrics
+    );
+}
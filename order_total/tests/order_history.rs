@@ -0,0 +1,59 @@
+//! The revisions of an order at `GET /orders/{id}/history`, with what changed
+//! from one to the next.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+const OTHER_ZIP: &str = "10001";
+
+async fn history(service: &TestService) -> Value {
+    let response = service.get("/v1/orders/123/history").await.unwrap();
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn lists_revisions_with_their_changes() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (OTHER_ZIP, Stub::Rate("0.1")),
+    ]))
+    .await;
+    let service = TestService::start(&rates.url).await;
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let body = history(&service).await;
+    assert_eq!(body["order_id"], 123);
+    let revisions = body["revisions"].as_array().unwrap();
+    assert_eq!(revisions.len(), 1);
+    assert_eq!(revisions[0]["rate"], 0.0825);
+    assert_eq!(revisions[0]["changes"], json!([]));
+
+    let (status, body) = service
+        .patch("/v1/orders/123", &json!({"shipping_zip": OTHER_ZIP}))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let body = history(&service).await;
+    let revisions = body["revisions"].as_array().unwrap();
+    assert_eq!(revisions.len(), 2);
+    assert_eq!(revisions[1]["revision"], 2);
+    assert_eq!(revisions[1]["rate"], 0.1);
+    assert_eq!(
+        revisions[1]["changes"],
+        json!([
+            {"field": "shipping_zip", "old": TAXED_ZIP, "new": OTHER_ZIP},
+            {"field": "rate", "old": 0.0825, "new": 0.1},
+            {"field": "tax", "old": 1.65, "new": 2.0},
+            {"field": "total", "old": 21.65, "new": 22.0},
+        ])
+    );
+
+    let response = service.get("/v1/orders/42/history").await;
+    let status = response.unwrap_err().status().map(|status| status.as_u16());
+    assert_eq!(status, Some(404));
+}
@@ -0,0 +1,87 @@
+//! Free text search of the stored orders, `GET /orders/search`.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+const OTHER_ZIP: &str = "10001";
+
+async fn search(service: &TestService, query: &str) -> Value {
+    let response = service
+        .get(&format!("/v1/orders/search?{}", query))
+        .await
+        .unwrap();
+    response.json().await.unwrap()
+}
+
+fn ids(results: &Value) -> Vec<i64> {
+    results["hits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["record"]["order"]["order_id"].as_i64().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn searches_orders_by_address_and_zip() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (OTHER_ZIP, Stub::Rate("0.1")),
+    ]))
+    .await;
+    let service = TestService::start(&rates.url).await;
+    // Priced in this order.
+    for (order_id, address, zip) in [
+        (1, "123 Main St, Anytown USA", TAXED_ZIP),
+        (2, "9 Mainland Rd, Springfield", OTHER_ZIP),
+        (3, "5 Oak Ave, Anytown USA", TAXED_ZIP),
+        (4, "77 Main St, Springfield", OTHER_ZIP),
+    ] {
+        let mut order = order(zip);
+        order["order_id"] = order_id.into();
+        order["shipping_address"] = address.into();
+        let (status, body) = service.compute(&order).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    // Whole words first, then the most recent.
+    let results = search(&service, "q=MAIN+787").await;
+    assert_eq!(results["query"], "MAIN 787");
+    assert_eq!(ids(&results), [1, 4, 3, 2]);
+    let best = &results["hits"][0];
+    assert_eq!(best["score"], 3);
+    assert_eq!(
+        best["highlights"]["shipping_address"],
+        "123 <em>Main</em> St, Anytown USA"
+    );
+    assert_eq!(best["highlights"]["shipping_zip"], "<em>787</em>01");
+    assert_eq!(
+        results["hits"][3]["highlights"]["shipping_address"],
+        "9 <em>Main</em>land Rd, Springfield"
+    );
+
+    let results = search(&service, "q=anytown&limit=1").await;
+    assert_eq!(ids(&results), [3]);
+    let results = search(&service, "q=nowhere").await;
+    assert_eq!(ids(&results), Vec::<i64>::new());
+
+    // Stored again with another address, an order is found by its new one.
+    let mut order = order(OTHER_ZIP);
+    order["order_id"] = 3.into();
+    order["shipping_address"] = "1 Elm St, Springfield".into();
+    let (status, body) = service.compute(&order).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(ids(&search(&service, "q=oak").await), Vec::<i64>::new());
+    assert_eq!(ids(&search(&service, "q=elm").await), [3]);
+
+    for query in ["q=", "q=+,+", "limit=5"] {
+        let response = service.get(&format!("/v1/orders/search?{}", query)).await;
+        let status = response.unwrap_err().status().map(|status| status.as_u16());
+        assert_eq!(status, Some(400), "{}", query);
+    }
+}
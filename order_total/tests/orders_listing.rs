@@ -0,0 +1,97 @@
+//! Filtering, sorting and cursor pagination of `GET /orders`.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+const OTHER_ZIP: &str = "10001";
+
+async fn list(service: &TestService, query: &str) -> Value {
+    let response = service.get(&format!("/v1/orders?{}", query)).await.unwrap();
+    response.json().await.unwrap()
+}
+
+fn ids(page: &Value) -> Vec<i64> {
+    page["orders"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|record| record["order"]["order_id"].as_i64().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn filters_sorts_and_pages_orders() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (OTHER_ZIP, Stub::Rate("0.1")),
+    ]))
+    .await;
+    let service = TestService::start(&rates.url).await;
+    // Priced in this order.
+    for (order_id, zip, subtotal) in [
+        (1, TAXED_ZIP, 30.0),
+        (2, OTHER_ZIP, 10.0),
+        (3, TAXED_ZIP, 10.0),
+        (4, TAXED_ZIP, 50.0),
+        (5, OTHER_ZIP, 40.0),
+    ] {
+        let mut order = order(zip);
+        order["order_id"] = order_id.into();
+        order["subtotal"] = subtotal.into();
+        let (status, body) = service.compute(&order).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    let page = list(&service, "").await;
+    assert_eq!(ids(&page), [5, 4, 3, 2, 1]);
+    assert_eq!(page["total"], 5);
+    assert!(page.get("next_cursor").is_none());
+
+    let page = list(&service, "zip=787&sort=total&order=asc").await;
+    assert_eq!(ids(&page), [3, 1, 4]);
+    assert_eq!(page["total"], 3);
+    let page = list(&service, "min_total=20&max_total=44").await;
+    assert_eq!(ids(&page), [5, 1]);
+    let page = list(
+        &service,
+        "from=2000-01-01T00:00:00Z&to=2000-01-02T00:00:00Z",
+    )
+    .await;
+    assert_eq!(page["total"], 0);
+
+    // Page by page, while another order is priced.
+    let page = list(&service, "sort=total&limit=2").await;
+    assert_eq!(ids(&page), [4, 5]);
+    let next = page["next"].as_str().unwrap().to_string();
+    assert!(
+        next.starts_with("/v1/orders?sort=total&limit=2&cursor="),
+        "{}",
+        next
+    );
+    let mut order = order(TAXED_ZIP);
+    order["order_id"] = 6.into();
+    order["subtotal"] = 100.0.into();
+    service.compute(&order).await;
+    let page: Value = service.get(&next).await.unwrap().json().await.unwrap();
+    assert_eq!(ids(&page), [1, 2]);
+    let next = page["next"].as_str().unwrap().to_string();
+    let page: Value = service.get(&next).await.unwrap().json().await.unwrap();
+    assert_eq!(ids(&page), [3]);
+    assert!(page.get("next").is_none());
+
+    for query in [
+        "cursor=nonsense",
+        "min_total=lots",
+        "sort=zip",
+        "from=yesterday",
+    ] {
+        let response = service.get(&format!("/v1/orders?{}", query)).await;
+        let status = response.unwrap_err().status().map(|status| status.as_u16());
+        assert_eq!(status, Some(400), "{}", query);
+    }
+}
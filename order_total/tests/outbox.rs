@@ -0,0 +1,49 @@
+//! `OrderPriced` events kept in the order store with their order until the
+//! outbox relay publishes them.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use order_total::config::PersistenceBackend;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const TAXED_ZIP: &str = "78701";
+
+#[tokio::test]
+async fn publishes_events_from_the_outbox() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let path =
+        std::env::temp_dir().join(format!("order_total-outbox-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let store = path.to_str().unwrap().to_string();
+    let service = TestService::start_with(&rates.url, |config| {
+        config.persistence.backend = PersistenceBackend::File;
+        config.persistence.path = store;
+        config.events.outbox = true;
+        config.scheduler.outbox_relay.schedule = "@every 10ms".into();
+    })
+    .await;
+
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    // The order and its event are one line, and the relay marks it sent.
+    let mut lines = Vec::new();
+    for _ in 0..100 {
+        lines = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect();
+        if lines.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert_eq!(lines[0]["record"]["order"]["total"], body["total"]);
+    assert_eq!(lines[1]["sent_outbox_id"], lines[0]["outbox_id"]);
+}
@@ -0,0 +1,121 @@
+//! Confirming an order authorizes its total at the payment service, and
+//! cancelling it voids the authorization.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+const TAXED_ZIP: &str = "78701";
+/// Payments above this amount are declined.
+const PAYMENT_LIMIT: f64 = 100.0;
+
+/// A fake payment service handing out `AUTH-<n>` authorizations and
+/// recording the ones voided.
+#[derive(Default)]
+struct FakePayments {
+    authorized: Vec<(i64, f64)>,
+    voided: Vec<String>,
+}
+
+async fn start_payments() -> (String, Arc<Mutex<FakePayments>>) {
+    let payments = Arc::new(Mutex::new(FakePayments::default()));
+    let service_payments = payments.clone();
+    let make_svc = make_service_fn(move |_| {
+        let payments = service_payments.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| answer(payments.clone(), req))) }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let url = format!("http://{}/authorizations", server.local_addr());
+    tokio::spawn(server);
+    (url, payments)
+}
+
+async fn answer(
+    payments: Arc<Mutex<FakePayments>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let mut payments = payments.lock().unwrap();
+    let (status, body) = if method == Method::DELETE {
+        let id = path.trim_start_matches("/authorizations/").to_string();
+        payments.voided.push(id);
+        (StatusCode::NO_CONTENT, Value::Null)
+    } else {
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        let amount = request["amount"].as_f64().unwrap();
+        if amount > PAYMENT_LIMIT {
+            let error = json!({
+                "error": { "code": "PAYMENT_DECLINED", "message": "216.5 USD is above the limit of 100." }
+            });
+            (StatusCode::PAYMENT_REQUIRED, error)
+        } else {
+            payments
+                .authorized
+                .push((request["order_id"].as_i64().unwrap(), amount));
+            let id = format!("AUTH-{}", payments.authorized.len());
+            (StatusCode::CREATED, json!({ "authorization_id": id }))
+        }
+    };
+    let body = match body {
+        Value::Null => Body::empty(),
+        body => Body::from(body.to_string()),
+    };
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body)
+        .unwrap())
+}
+
+#[tokio::test]
+async fn authorizes_payment_when_an_order_is_confirmed() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let (payments_url, payments) = start_payments().await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.payments.enabled = true;
+        config.payments.url = payments_url;
+    })
+    .await;
+
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(payments.lock().unwrap().authorized.is_empty());
+    let (status, body) = service.post("/v1/orders/123/confirm").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["status"], "confirmed");
+    assert_eq!(body["payment_authorization_id"], "AUTH-1");
+    assert_eq!(payments.lock().unwrap().authorized, vec![(123, 21.65)]);
+
+    let mut expensive = order(TAXED_ZIP);
+    expensive["order_id"] = json!(124);
+    expensive["subtotal"] = json!(200.0);
+    let (status, body) = service.compute(&expensive).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, body) = service.post("/v1/orders/124/confirm").await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED, "{}", body);
+    assert_eq!(error_code(&body), "PAYMENT_DECLINED");
+    let record: Value = service
+        .get("/v1/orders/124")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(record["status"], "priced");
+    assert!(record.get("payment_authorization_id").is_none());
+
+    let (status, body) = service.post("/v1/orders/123/cancel").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["status"], "cancelled");
+    assert!(body.get("payment_authorization_id").is_none());
+    assert_eq!(payments.lock().unwrap().voided, vec!["AUTH-1"]);
+}
@@ -0,0 +1,75 @@
+//! Priority classes: with one slot for rate lookups, an interactive order
+//! arriving behind the lookups of a batch gets the next free slot rather than
+//! wait for the whole batch.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const BATCH_ZIPS: [&str; 6] = ["78701", "78702", "78703", "78704", "78705", "78706"];
+const INTERACTIVE_ZIP: &str = "78710";
+const LOOKUP_TIME: Duration = Duration::from_millis(100);
+
+#[tokio::test]
+async fn serves_interactive_orders_ahead_of_batch_lookups() {
+    let stubs = BATCH_ZIPS
+        .iter()
+        .chain([&INTERACTIVE_ZIP])
+        .map(|zip| (*zip, Stub::Delayed("0.0825", LOOKUP_TIME)))
+        .collect::<HashMap<_, _>>();
+    let rates = FakeRateService::start(stubs).await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.bulkheads.upstream.max_concurrent = 1;
+        config.bulkheads.upstream.queue_timeout_ms = 5_000;
+    })
+    .await;
+
+    let orders = Value::Array(BATCH_ZIPS.iter().map(|zip| order(zip)).collect());
+    let start = Instant::now();
+    let batch = async {
+        let answer = service.post_with("/v1/compute_batch", &[], &orders).await;
+        (answer, start.elapsed())
+    };
+    let interactive = async {
+        // Once the batch's lookups are queued.
+        tokio::time::sleep(LOOKUP_TIME / 2).await;
+        let answer = service.compute(&order(INTERACTIVE_ZIP)).await;
+        (answer, start.elapsed())
+    };
+    let (((batch_status, batch_body), batch_done), ((status, body), done)) =
+        tokio::join!(batch, interactive);
+    assert_eq!(batch_status, StatusCode::OK, "{}", batch_body);
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(
+        done < batch_done,
+        "the interactive order took {:?}, the batch {:?}",
+        done,
+        batch_done
+    );
+
+    // A request can name its class.
+    let (status, body) = service
+        .post_with(
+            "/v1/compute",
+            &[("X-Priority", "batch")],
+            &order(BATCH_ZIPS[0]),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let metrics = service.get("/metrics").await.unwrap().text().await.unwrap();
+    assert!(
+        metrics.contains("priority_requests_total{class=\"batch\"} 2"),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains("bulkhead_waiting{bulkhead=\"upstream\",class=\"batch\"} 0"),
+        "{}",
+        metrics
+    );
+}
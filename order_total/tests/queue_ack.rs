@@ -0,0 +1,79 @@
+//! Manual acknowledgement in the `queue` run mode, against a fake NATS
+//! server: orders are acknowledged once their result is published, and a
+//! redelivered order gets the result of the first delivery without being
+//! priced and published again.
+
+#![cfg(feature = "nats")]
+
+mod common;
+
+use common::nats::FakeNats;
+use common::{order, FakeRateService, Stub, TestService};
+use order_total::config::{AckMode, EventPublisherKind, RunMode};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const TAXED_ZIP: &str = "78701";
+
+/// The acknowledgement subject of deliveries of the message at stream
+/// sequence `sequence`.
+fn ack_subject(delivered: u32, sequence: u32) -> String {
+    format!(
+        "$JS.ACK.ORDERS.order_total.{}.{}.{}.1760519523000000000.0",
+        delivered, sequence, delivered
+    )
+}
+
+#[tokio::test]
+async fn acknowledges_orders_and_answers_redeliveries_once() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let nats = FakeNats::start().await;
+    let nats_url = nats.url.clone();
+    let _service = TestService::start_with(&rates.url, |config| {
+        config.mode = RunMode::Queue;
+        config.server.admin_port = config.server.port;
+        config.queue.nats_url = nats_url.clone();
+        config.queue.ack = AckMode::Manual;
+        config.events.publisher = EventPublisherKind::Nats;
+        config.events.nats_url = nats_url;
+    })
+    .await;
+
+    // The result goes to the reply subject of the configuration, then the
+    // message is acknowledged.
+    let first = ack_subject(1, 7);
+    nats.deliver(&first, &[], &order(TAXED_ZIP)).await;
+    let results = nats.published("orders.computed", 1).await;
+    assert_eq!(results.len(), 1, "{:?}", results);
+    assert_eq!(results[0]["status"], "ok", "{:?}", results);
+    assert_eq!(nats.published(&first, 1).await, ["+ACK"]);
+    assert_eq!(nats.published("orders.priced", 1).await.len(), 1);
+
+    // Redelivered, by its stream sequence, the order is answered the same
+    // without a second event.
+    let second = ack_subject(2, 7);
+    nats.deliver(&second, &[], &order(TAXED_ZIP)).await;
+    assert_eq!(nats.published(&second, 1).await, ["+ACK"]);
+    let results = nats.published("orders.computed", 2).await;
+    assert_eq!(results.len(), 2, "{:?}", results);
+    assert_eq!(results[1], results[0]);
+
+    // The same goes for messages with a `Nats-Msg-Id`, whatever their
+    // sequence.
+    let headers = [("Nats-Msg-Id", "order-123-a")];
+    let third = ack_subject(1, 8);
+    let fourth = ack_subject(1, 9);
+    for ack in [&third, &fourth] {
+        let mut order = order(TAXED_ZIP);
+        order["order_id"] = 456.into();
+        nats.deliver(ack, &headers, &order).await;
+        assert_eq!(nats.published(ack, 1).await, ["+ACK"]);
+    }
+    let results = nats.published("orders.computed", 4).await;
+    assert_eq!(results.len(), 4, "{:?}", results);
+    assert_eq!(results[3]["order"]["order_id"], 456);
+    assert_eq!(results[3], results[2]);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(nats.published("orders.priced", 2).await.len(), 2);
+}
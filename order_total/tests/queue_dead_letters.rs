@@ -0,0 +1,97 @@
+//! The `queue` run mode against a fake NATS server: orders failing every
+//! attempt with a retryable status become dead letters, published to the
+//! dead-letter subject and listed and re-driven at
+//! `/admin/queue/dead_letters`.
+
+#![cfg(feature = "nats")]
+
+mod common;
+
+use common::nats::FakeNats;
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use order_total::config::RunMode;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const DOWN_ZIP: &str = "78701";
+const UNKNOWN_ZIP: &str = "99999";
+const DEAD_LETTER_SUBJECT: &str = "orders.compute.dead_letter";
+
+#[tokio::test]
+async fn dead_letters_and_redrives_orders_failing_every_attempt() {
+    let rates = FakeRateService::start(HashMap::from([(DOWN_ZIP, Stub::Status(503))])).await;
+    let nats = FakeNats::start().await;
+    let path = std::env::temp_dir().join(format!(
+        "order_total-queue-dead-letters-{}.jsonl",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let dead_letter_path = path.to_str().unwrap().to_string();
+    let nats_url = nats.url.clone();
+    let service = TestService::start_with(&rates.url, |config| {
+        config.mode = RunMode::Queue;
+        // The admin endpoints are all the queue mode serves over HTTP.
+        config.server.admin_port = config.server.port;
+        config.queue.nats_url = nats_url;
+        config.queue.dead_letter_path = dead_letter_path;
+        config.queue.retry.max_attempts = 3;
+        config.queue.retry.initial_delay_ms = 10;
+        config.queue.retry.max_delay_ms = 10;
+        config.queue.retry.jitter = 0.0;
+    })
+    .await;
+
+    // A zip code without a rate fails at once, without a dead letter.
+    nats.deliver("reply.1", &[], &order(UNKNOWN_ZIP)).await;
+    let replies = nats.published("reply.1", 1).await;
+    assert_eq!(replies[0]["status"], "error", "{:?}", replies);
+    assert_eq!(replies[0]["error"]["code"], "RATE_NOT_FOUND");
+    assert_eq!(rates.calls(UNKNOWN_ZIP), 1);
+
+    // An unavailable rate service fails every attempt.
+    nats.deliver("reply.2", &[], &order(DOWN_ZIP)).await;
+    let replies = nats.published("reply.2", 1).await;
+    assert_eq!(replies[0]["status"], "error", "{:?}", replies);
+    let letters = nats.published(DEAD_LETTER_SUBJECT, 1).await;
+    assert_eq!(letters.len(), 1, "{:?}", letters);
+    let letter = &letters[0];
+    assert_eq!(letter["subject"], "orders.compute");
+    assert_eq!(letter["reply_to"], "reply.2");
+    assert_eq!(letter["attempts"], 3);
+    assert_eq!(letter["payload"]["shipping_zip"], DOWN_ZIP);
+    assert_eq!(letter["error"]["code"], replies[0]["error"]["code"]);
+    assert!(letter["failed_at"].is_string(), "{}", letter);
+    assert_eq!(rates.calls(DOWN_ZIP), 3 * common::MAX_ATTEMPTS as usize);
+
+    let response = service.get("/admin/queue/dead_letters").await.unwrap();
+    let listed: Vec<Value> = response.json().await.unwrap();
+    assert_eq!(listed, letters);
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+    // Re-driven, the order is priced again and answered on its reply
+    // subject; failing again, it becomes a new dead letter.
+    let id = letter["id"].as_str().unwrap();
+    let (status, body) = service
+        .post(&format!("/admin/queue/dead_letters/{}/redrive", id))
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+    assert_eq!(body["id"], id);
+    let replies = nats.published("reply.2", 2).await;
+    assert_eq!(replies.len(), 2, "{:?}", replies);
+    let letters = nats.published(DEAD_LETTER_SUBJECT, 2).await;
+    assert_eq!(letters.len(), 2, "{:?}", letters);
+    assert_ne!(letters[1]["id"], id);
+
+    let response = service.get("/admin/queue/dead_letters").await.unwrap();
+    let listed: Vec<Value> = response.json().await.unwrap();
+    assert_eq!(listed, letters[1..]);
+
+    let (status, body) = service
+        .post(&format!("/admin/queue/dead_letters/{}/redrive", id))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{}", body);
+    assert_eq!(common::error_code(&body), "DEAD_LETTER_NOT_FOUND");
+
+    let _ = std::fs::remove_file(&path);
+}
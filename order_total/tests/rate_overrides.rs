@@ -0,0 +1,97 @@
+//! Rate overrides, from `rates.overrides` and `/admin/rate_overrides`: while
+//! one applies, orders to its zip code are priced at its rate rather than the
+//! sales tax rate service's, and marked as such.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::json;
+use std::collections::HashMap;
+
+const CONFIGURED_ZIP: &str = "78701";
+const EXPIRED_ZIP: &str = "78702";
+const ADDED_ZIP: &str = "78703";
+
+#[tokio::test]
+async fn prices_orders_at_the_rate_overrides_in_effect() {
+    let rates = FakeRateService::start(
+        [CONFIGURED_ZIP, EXPIRED_ZIP, ADDED_ZIP]
+            .into_iter()
+            .map(|zip| (zip, Stub::Rate("0.0825")))
+            .collect::<HashMap<_, _>>(),
+    )
+    .await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.rates.overrides = serde_json::from_value(json!([
+            { "zip": CONFIGURED_ZIP, "rate": 0.05, "reason": "correction" },
+            { "zip": EXPIRED_ZIP, "rate": 0.05, "until": "2020-12-31" },
+        ]))
+        .unwrap();
+    })
+    .await;
+
+    // ZIP+4 codes share the override of their 5-digit part.
+    let (status, body) = service
+        .compute(&order(&format!("{}-1234", CONFIGURED_ZIP)))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 1.0);
+    assert_eq!(body["rate_source"], "override");
+    assert_eq!(rates.calls(CONFIGURED_ZIP), 0);
+
+    // An override past its `until` date no longer applies.
+    let (status, body) = service.compute(&order(EXPIRED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 1.65);
+    assert!(body.get("rate_source").is_none(), "{}", body);
+
+    // Added with the admin API, until removed.
+    let (status, added) = service
+        .post_with(
+            "/admin/rate_overrides",
+            &[],
+            &json!({ "zip": ADDED_ZIP, "rate": 0.1, "from": "2020-01-01" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", added);
+    assert_eq!(added["active"], true);
+    let (status, body) = service.compute(&order(ADDED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 2.0);
+    assert_eq!(body["rate_source"], "override");
+
+    let listed: serde_json::Value = service
+        .get("/admin/rate_overrides")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(listed.as_array().unwrap().len(), 3, "{}", listed);
+    assert_eq!(listed[0]["id"], added["id"]);
+    assert_eq!(listed[2]["zip"], EXPIRED_ZIP);
+    assert_eq!(listed[2]["active"], false);
+
+    let path = format!("/admin/rate_overrides/{}", added["id"].as_str().unwrap());
+    let (status, body) = service.delete(&path, None).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, body) = service.compute(&order(ADDED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 1.65);
+    assert_eq!(rates.calls(ADDED_ZIP), 1);
+
+    let (status, body) = service.delete(&path, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{}", body);
+    assert_eq!(error_code(&body), "RATE_OVERRIDE_NOT_FOUND");
+
+    let (status, body) = service
+        .post_with(
+            "/admin/rate_overrides",
+            &[],
+            &json!({ "zip": "787", "rate": 1.5, "until": "tomorrow" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(error_code(&body), "VALIDATION_FAILED", "{}", body);
+}
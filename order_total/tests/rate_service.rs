@@ -0,0 +1,112 @@
+//! Pricing orders against the sales tax rate service, as it answers, fails,
+//! stalls or talks nonsense.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService, MAX_ATTEMPTS};
+use hyper::StatusCode;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const TAXED_ZIP: &str = "78701";
+const UNKNOWN_ZIP: &str = "00000";
+const FAILING_ZIP: &str = "50000";
+const UNAVAILABLE_ZIP: &str = "50300";
+const SLOW_ZIP: &str = "60000";
+const MALFORMED_ZIP: &str = "70000";
+
+// order_total installs its configuration for the whole process, so the
+// scenarios share one instance and each uses a zip code of its own.
+#[tokio::test]
+async fn prices_orders_with_the_rate_service() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (FAILING_ZIP, Stub::Status(500)),
+        (UNAVAILABLE_ZIP, Stub::Status(503)),
+        (SLOW_ZIP, Stub::Delayed("0.0825", Duration::from_secs(2))),
+        (MALFORMED_ZIP, Stub::Body("{\"rate\": \"a lot\"}")),
+    ]))
+    .await;
+    let service = TestService::start(&rates.url).await;
+
+    taxes_orders_at_the_rate_of_their_zip_code(&service, &rates).await;
+    looks_zip_plus_4_codes_up_by_their_first_part(&service, &rates).await;
+    rejects_zip_codes_without_a_rate(&service).await;
+    rejects_postal_codes_outside_the_us(&service).await;
+    fails_on_upstream_errors(&service, &rates).await;
+    times_out_slow_lookups(&service, &rates).await;
+    fails_on_malformed_rates(&service).await;
+}
+
+async fn taxes_orders_at_the_rate_of_their_zip_code(
+    service: &TestService,
+    rates: &FakeRateService,
+) {
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], json!(1.65));
+    assert_eq!(body["total"], json!(21.65));
+
+    // The rate is cached.
+    let (status, _) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(rates.calls(TAXED_ZIP), 1);
+}
+
+async fn looks_zip_plus_4_codes_up_by_their_first_part(
+    service: &TestService,
+    rates: &FakeRateService,
+) {
+    let zip = format!("{}-1234", TAXED_ZIP);
+    let (status, body) = service.compute(&order(&zip)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["shipping_zip"], json!(zip));
+    assert_eq!(body["total"], json!(21.65));
+    // From the cache entry of the 5-digit code.
+    assert_eq!(rates.calls(&zip), 0);
+    assert_eq!(rates.calls(TAXED_ZIP), 1);
+}
+
+async fn rejects_zip_codes_without_a_rate(service: &TestService) {
+    let (status, body) = service.compute(&order(UNKNOWN_ZIP)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(error_code(&body), "RATE_NOT_FOUND");
+}
+
+async fn rejects_postal_codes_outside_the_us(service: &TestService) {
+    let (status, body) = service.compute(&order("K1A 0B1")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(error_code(&body), "UNSUPPORTED_JURISDICTION");
+    assert_eq!(body["error"]["details"]["country"], json!("CA"));
+
+    let (status, body) = service.compute(&order("7870")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(error_code(&body), "VALIDATION_FAILED");
+}
+
+async fn fails_on_upstream_errors(service: &TestService, rates: &FakeRateService) {
+    let (status, body) = service.compute(&order(FAILING_ZIP)).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY, "{}", body);
+    assert_eq!(error_code(&body), "UPSTREAM_UNAVAILABLE");
+    // 500 isn't one of the retried statuses.
+    assert_eq!(rates.calls(FAILING_ZIP), 1);
+
+    let (status, body) = service.compute(&order(UNAVAILABLE_ZIP)).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY, "{}", body);
+    assert_eq!(error_code(&body), "UPSTREAM_UNAVAILABLE");
+    assert_eq!(rates.calls(UNAVAILABLE_ZIP), MAX_ATTEMPTS as usize);
+}
+
+async fn times_out_slow_lookups(service: &TestService, rates: &FakeRateService) {
+    let (status, body) = service.compute(&order(SLOW_ZIP)).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT, "{}", body);
+    assert_eq!(error_code(&body), "UPSTREAM_TIMEOUT");
+    assert_eq!(rates.calls(SLOW_ZIP), MAX_ATTEMPTS as usize);
+}
+
+async fn fails_on_malformed_rates(service: &TestService) {
+    let (status, body) = service.compute(&order(MALFORMED_ZIP)).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY, "{}", body);
+    assert_eq!(error_code(&body), "UPSTREAM_UNAVAILABLE");
+}
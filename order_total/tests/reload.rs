@@ -0,0 +1,89 @@
+//! Reloading the configuration when its file changes: the upstream URL and
+//! the cache TTL take effect at once, other settings wait for a restart.
+
+mod common;
+
+use common::{free_port, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use order_total::config::{AppConfig, Cli};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+const TAXED_ZIP: &str = "78701";
+
+fn write_config(path: &Path, port: u16, rates_url: &str, extra: &str) {
+    let config = format!(
+        r#"
+[server]
+port = {}
+admin_port = 0
+grpc_port = 0
+
+[upstream]
+url = "{}"
+
+[reload]
+watch = true
+poll_interval_ms = 20
+{}"#,
+        port, rates_url, extra
+    );
+    std::fs::write(path, config).unwrap();
+}
+
+#[tokio::test]
+async fn applies_a_changed_configuration_file() {
+    let stubs = || HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))]);
+    let first = FakeRateService::start(stubs()).await;
+    let second = FakeRateService::start(stubs()).await;
+    let port = free_port().await;
+    let path = std::env::temp_dir().join(format!("order_total-reload-{}.toml", port));
+    write_config(&path, port, &first.url, "");
+    let cli = Cli {
+        config: Some(path.clone()),
+        port: None,
+        sales_tax_rate_service: None,
+        log_level: None,
+        mode: None,
+    };
+    let state = order_total::init(AppConfig::load(&cli).unwrap()).unwrap();
+    tokio::spawn(order_total::reload::watch(cli, state.clone()));
+    tokio::spawn(async move {
+        if let Err(err) = order_total::serve(state).await {
+            panic!("order_total failed: {}", err);
+        }
+    });
+    let service = TestService::connect(port).await;
+
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(first.calls(TAXED_ZIP), 1);
+
+    let extra = "\n[cache]\nttl_secs = 0\n\n[websocket]\nmax_in_flight = 4\n";
+    write_config(&path, port, &second.url, extra);
+    let mut reloaded = false;
+    for _ in 0..100 {
+        let metrics = service.get("/metrics").await.unwrap().text().await.unwrap();
+        if metrics.contains(r#"config_reloads_total{outcome="success"} 1"#) {
+            reloaded = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(reloaded, "the configuration wasn't reloaded");
+
+    // The rate cached before the reload expired with the new TTL.
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(first.calls(TAXED_ZIP), 1);
+    assert_eq!(second.calls(TAXED_ZIP), 1);
+
+    let response = service.get("/admin/config").await.unwrap();
+    let config: Value = response.json().await.unwrap();
+    assert_eq!(config["upstream"]["url"], second.url.as_str());
+    assert_eq!(config["cache"]["ttl_secs"], 0);
+    assert_eq!(config["websocket"]["max_in_flight"], 16);
+    let _ = std::fs::remove_file(path);
+}
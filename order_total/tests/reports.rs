@@ -0,0 +1,73 @@
+//! The daily sales and tax report at `GET /reports/daily`.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+const TAXED_ZIP: &str = "78701";
+const OTHER_ZIP: &str = "10001";
+
+async fn report(service: &TestService, query: &str) -> Value {
+    let response = service
+        .get(&format!("/v1/reports/daily{}", query))
+        .await
+        .unwrap();
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn sums_up_the_orders_of_a_day_by_state() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (OTHER_ZIP, Stub::Rate("0.1")),
+    ]))
+    .await;
+    let service = TestService::start(&rates.url).await;
+    for (order_id, zip) in [
+        (1, TAXED_ZIP),
+        (2, TAXED_ZIP),
+        (3, OTHER_ZIP),
+        (4, OTHER_ZIP),
+    ] {
+        let mut order = order(zip);
+        order["order_id"] = order_id.into();
+        let (status, body) = service.compute(&order).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    let (status, body) = service.post("/v1/orders/4/cancel").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let today = humantime::format_rfc3339(SystemTime::now()).to_string()[..10].to_string();
+    let body = report(&service, "").await;
+    assert_eq!(body["date"], today);
+    assert_eq!(body["orders"], 3);
+    assert_eq!(body["subtotal"], 60.0);
+    assert_eq!(body["tax"], 5.3);
+    assert_eq!(
+        body["by_state"],
+        json!([
+            {"state": "NY", "orders": 1, "subtotal": 20.0, "tax": 2.0},
+            {"state": "TX", "orders": 2, "subtotal": 40.0, "tax": 3.3},
+        ])
+    );
+    assert_eq!(
+        report(&service, &format!("?date={}", today)).await["orders"],
+        3
+    );
+
+    let body = report(&service, "?date=2000-01-01").await;
+    assert_eq!(body["orders"], 0);
+    assert_eq!(body["by_state"], json!([]));
+
+    for date in ["yesterday", "2026-13-01", "2026-10-15T00:00:00Z"] {
+        let response = service
+            .get(&format!("/v1/reports/daily?date={}", date))
+            .await;
+        let status = response.unwrap_err().status().map(|status| status.as_u16());
+        assert_eq!(status, Some(400), "{}", date);
+    }
+}
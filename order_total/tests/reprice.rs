@@ -0,0 +1,87 @@
+//! Changing the shipping address of a priced order with `PATCH /orders/{id}`
+//! prices it again, keeping every pricing in its revisions.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+const OTHER_ZIP: &str = "10001";
+const UNKNOWN_ZIP: &str = "99999";
+
+#[tokio::test]
+async fn prices_an_order_again_for_a_new_address() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (OTHER_ZIP, Stub::Rate("0.1")),
+    ]))
+    .await;
+    let service = TestService::start(&rates.url).await;
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["total"], 21.65);
+
+    let change = json!({"shipping_address": "1 Elm St, Springfield", "shipping_zip": OTHER_ZIP});
+    let (status, body) = service.patch("/v1/orders/123", &change).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["order"]["shipping_zip"], OTHER_ZIP);
+    assert_eq!(body["order"]["tax"], 2.0);
+    assert_eq!(body["order"]["total"], 22.0);
+    assert_eq!(body["rate"], 0.1);
+    let revisions = body["revisions"].as_array().unwrap();
+    assert_eq!(revisions.len(), 2);
+    assert_eq!(revisions[0]["revision"], 1);
+    assert_eq!(revisions[0]["shipping_zip"], TAXED_ZIP);
+    assert_eq!(revisions[0]["rate"], 0.0825);
+    assert_eq!(revisions[0]["total"], 21.65);
+    assert_eq!(revisions[1]["revision"], 2);
+    assert_eq!(revisions[1]["shipping_address"], "1 Elm St, Springfield");
+    assert_eq!(revisions[1]["total"], 22.0);
+
+    let record: Value = service
+        .get("/v1/orders/123")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(record["order"]["total"], 22.0);
+    let results: Value = service
+        .get("/v1/orders/search?q=elm")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(results["hits"].as_array().unwrap().len(), 1);
+
+    // A zip code without a rate leaves the order as it was.
+    let (status, body) = service
+        .patch("/v1/orders/123", &json!({"shipping_zip": UNKNOWN_ZIP}))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(error_code(&body), "RATE_NOT_FOUND");
+    let (status, body) = service.patch("/v1/orders/123", &json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    let (status, body) = service
+        .patch("/v1/orders/42", &json!({"shipping_zip": OTHER_ZIP}))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{}", body);
+
+    let (status, body) = service
+        .patch("/v1/orders/123", &json!({"shipping_zip": TAXED_ZIP}))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["order"]["total"], 21.65);
+    assert_eq!(body["revisions"].as_array().unwrap().len(), 3);
+
+    service.post("/v1/orders/123/confirm").await;
+    let (status, body) = service
+        .patch("/v1/orders/123", &json!({"shipping_zip": OTHER_ZIP}))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    assert_eq!(error_code(&body), "INVALID_TRANSITION");
+}
@@ -0,0 +1,68 @@
+//! Rounding strategies: orders are rounded to cents by `currency.rounding`,
+//! or their tenant's own, on their line amounts and their tax alike, and say
+//! which one in the response.
+
+mod common;
+
+use common::{FakeRateService, Stub, TestService};
+use domain::Rounding;
+use hyper::StatusCode;
+use order_total::config::TenantConfig;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+
+/// Two units at 10.0025, 20.005 in all.
+fn order(rounding: Option<&str>) -> Value {
+    let mut order = json!({
+        "order_id": 123,
+        "line_items": [{ "product_id": 321, "quantity": 2, "unit_price": "10.0025" }],
+        "shipping_address": "123 Main St, Anytown USA",
+        "shipping_zip": TAXED_ZIP
+    });
+    if let Some(rounding) = rounding {
+        order["rounding"] = json!(rounding);
+    }
+    order
+}
+
+#[tokio::test]
+async fn rounds_orders_by_the_strategy_of_their_tenant() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.08125"))])).await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.currency.rounding = Rounding::HalfUp;
+        let acme = TenantConfig {
+            rounding: Some(Rounding::Truncate),
+            ..TenantConfig::default()
+        };
+        config.tenants.insert("acme".into(), acme);
+    })
+    .await;
+
+    // 8.125% of 20.01 is 1.6258125.
+    let (status, body) = service.post_with("/v1/compute", &[], &order(None)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["subtotal"], 20.01);
+    assert_eq!(body["tax"], 1.63);
+    assert_eq!(body["total"], 21.64);
+    assert_eq!(body["rounding"], "half_up");
+
+    // 8.125% of 20.00 is 1.625.
+    let (status, body) = service
+        .post_with("/v1/compute", &[("x-tenant-id", "acme")], &order(None))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["subtotal"], 20.0);
+    assert_eq!(body["tax"], 1.62);
+    assert_eq!(body["total"], 21.62);
+    assert_eq!(body["rounding"], "truncate");
+
+    // Orders don't choose their own.
+    let (status, body) = service
+        .post_with("/v1/quote", &[], &order(Some("truncate")))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 1.63);
+    assert_eq!(body["rounding"], "half_up");
+}
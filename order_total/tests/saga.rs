@@ -0,0 +1,156 @@
+//! The order saga: stock is reserved, the rate looked up and the total
+//! authorized, and when the payment is declined the reservation is released
+//! again. The saga's progress can be looked up by its id or its order.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+const TAXED_ZIP: &str = "78701";
+/// Payments above this amount are declined.
+const PAYMENT_LIMIT: f64 = 100.0;
+
+/// A fake inventory and payment service recording what it was asked.
+#[derive(Default)]
+struct FakeServices {
+    reserved: Vec<String>,
+    released: Vec<String>,
+    authorized: Vec<String>,
+}
+
+async fn start_services() -> (String, Arc<Mutex<FakeServices>>) {
+    let services = Arc::new(Mutex::new(FakeServices::default()));
+    let shared = services.clone();
+    let make_svc = make_service_fn(move |_| {
+        let services = shared.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| answer(services.clone(), req))) }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    (url, services)
+}
+
+async fn answer(
+    services: Arc<Mutex<FakeServices>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let mut services = services.lock().unwrap();
+    let (status, body) = match (method, path.as_str()) {
+        (Method::POST, "/reservations") => {
+            let id = format!("RES-{}", services.reserved.len() + 1);
+            services.reserved.push(id.clone());
+            (StatusCode::CREATED, json!({ "reservation_id": id }))
+        }
+        (Method::DELETE, path) if path.starts_with("/reservations/") => {
+            let id = path.trim_start_matches("/reservations/").to_string();
+            services.released.push(id);
+            (StatusCode::NO_CONTENT, Value::Null)
+        }
+        (Method::POST, "/authorizations") => {
+            let request: Value = serde_json::from_slice(&body).unwrap();
+            if request["amount"].as_f64().unwrap() > PAYMENT_LIMIT {
+                let error = json!({
+                    "error": { "code": "DECLINED", "message": "insufficient funds" }
+                });
+                (StatusCode::PAYMENT_REQUIRED, error)
+            } else {
+                let id = format!("AUTH-{}", services.authorized.len() + 1);
+                services.authorized.push(id.clone());
+                (StatusCode::CREATED, json!({ "authorization_id": id }))
+            }
+        }
+        _ => (StatusCode::NOT_FOUND, Value::Null),
+    };
+    let body = match body {
+        Value::Null => Body::empty(),
+        body => Body::from(body.to_string()),
+    };
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body)
+        .unwrap())
+}
+
+async fn get_json(service: &TestService, path: &str) -> Value {
+    let response = service.get(path).await.expect("a successful answer");
+    response.json().await.expect("a JSON body")
+}
+
+#[tokio::test]
+async fn compensates_the_steps_before_a_declined_payment() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let (services_url, services) = start_services().await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.saga.enabled = true;
+        config.inventory.enabled = true;
+        config.inventory.url = format!("{}/reservations", services_url);
+        config.payments.enabled = true;
+        config.payments.url = format!("{}/authorizations", services_url);
+    })
+    .await;
+
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["reservation_id"], "RES-1");
+    let saga_id = body["saga_id"].as_str().unwrap().to_string();
+    let saga = get_json(&service, &format!("/v1/sagas/{}", saga_id)).await;
+    assert_eq!(saga["status"], "completed");
+    let steps: Vec<(&str, &str)> = saga["steps"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|step| {
+            (
+                step["step"].as_str().unwrap(),
+                step["status"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        steps,
+        vec![
+            ("reserve_inventory", "done"),
+            ("look_up_rate", "done"),
+            ("authorize_payment", "done"),
+            ("record_order", "done"),
+        ]
+    );
+
+    let mut expensive = order(TAXED_ZIP);
+    expensive["subtotal"] = json!(200.0);
+    let (status, body) = service.compute(&expensive).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED, "{}", body);
+    assert_eq!(error_code(&body), "PAYMENT_DECLINED");
+    {
+        let services = services.lock().unwrap();
+        assert_eq!(services.reserved, vec!["RES-1", "RES-2"]);
+        assert_eq!(services.released, vec!["RES-2"]);
+        assert_eq!(services.authorized, vec!["AUTH-1"]);
+    }
+
+    // Both sagas of the order, the declined one first.
+    let list = get_json(&service, "/v1/sagas?order_id=123").await;
+    let sagas = list["sagas"].as_array().unwrap();
+    assert_eq!(sagas.len(), 2);
+    assert_eq!(sagas[0]["status"], "compensated");
+    assert_eq!(sagas[0]["error"]["code"], "PAYMENT_DECLINED");
+    assert_eq!(sagas[0]["steps"][0]["status"], "compensated");
+    assert_eq!(sagas[0]["steps"][2]["status"], "failed");
+    assert_eq!(sagas[0]["steps"][3]["status"], "pending");
+    assert_eq!(sagas[1]["saga_id"], saga_id.as_str());
+
+    let err = service.get("/v1/sagas/no-such-saga").await.unwrap_err();
+    assert_eq!(err.status().map(|status| status.as_u16()), Some(404));
+}
@@ -0,0 +1,64 @@
+//! The periodic jobs: refreshing the most read cached rates, removing stale
+//! orders and rolling up metrics, with their runs at `GET /admin/jobs`.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const TAXED_ZIP: &str = "78701";
+
+#[tokio::test]
+async fn runs_the_enabled_jobs_on_their_schedules() {
+    let rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.scheduler.cache_refresh.enabled = true;
+        config.scheduler.cache_refresh.schedule = "@every 50ms".into();
+        config.scheduler.order_cleanup.enabled = true;
+        config.scheduler.order_cleanup.schedule = "@every 50ms".into();
+        config.scheduler.order_max_age_secs = 0;
+        config.scheduler.metrics_rollup.schedule = "@every 50ms".into();
+        // Disabled jobs are listed all the same.
+        config.scheduler.outbox_relay.enabled = false;
+    })
+    .await;
+
+    // The second pricing reads the cached rate, which makes it a hot one.
+    for _ in 0..2 {
+        let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    assert_eq!(rates.calls(TAXED_ZIP), 1);
+
+    let mut jobs = Vec::new();
+    for _ in 0..100 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let response = service.get("/admin/jobs").await.unwrap();
+        jobs = response.json::<Vec<Value>>().await.unwrap();
+        let ran = jobs.iter().filter(|job| job["runs"].as_u64() > Some(0));
+        let removed = service.get("/v1/orders/123").await.is_err();
+        if ran.count() == 3 && removed && rates.calls(TAXED_ZIP) > 1 {
+            break;
+        }
+    }
+    let job = |name: &str| jobs.iter().find(|job| job["name"] == name).unwrap().clone();
+    for name in ["cache_refresh", "order_cleanup", "metrics_rollup"] {
+        let job = job(name);
+        assert!(job["runs"].as_u64() > Some(0), "{}", job);
+        assert_eq!(job["last_error"], Value::Null, "{}", job);
+        assert!(job["next_run_at"].is_string(), "{}", job);
+    }
+    let relay = job("outbox_relay");
+    assert_eq!(relay["enabled"], false);
+    assert_eq!(relay["runs"], 0);
+
+    assert!(rates.calls(TAXED_ZIP) > 1);
+    let stored = service.get("/v1/orders/123").await;
+    let status = stored.unwrap_err().status().map(|status| status.as_u16());
+    assert_eq!(status, Some(404));
+    let metrics = service.get("/metrics").await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("rate_cache_hit_ratio"), "{}", metrics);
+}
@@ -0,0 +1,178 @@
+//! Rates and idempotency keys shared between replicas through Redis, here a
+//! fake one keeping keys in memory, playing the other replicas' part.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+const TAXED_ZIP: &str = "78701";
+const SHARED_ZIP: &str = "10001";
+const PREFIX: &str = "test:";
+
+type Keys = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+/// Answers `GET`, `SET` (with `NX`) and `DEL`; times to live are ignored.
+async fn fake_redis() -> (String, Keys) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("redis://{}/0", listener.local_addr().unwrap());
+    let keys = Keys::default();
+    let served = keys.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve(stream, served.clone()));
+        }
+    });
+    (url, keys)
+}
+
+async fn serve(stream: TcpStream, keys: Keys) {
+    let mut stream = BufReader::new(stream);
+    while let Some(args) = read_command(&mut stream).await {
+        let reply = {
+            let mut keys = keys.lock().unwrap();
+            let key = String::from_utf8_lossy(&args[1]).to_string();
+            match args[0].to_ascii_uppercase().as_slice() {
+                b"GET" => match keys.get(&key) {
+                    Some(value) => bulk(value),
+                    None => b"$-1\r\n".to_vec(),
+                },
+                b"SET" => {
+                    let nx = args.iter().any(|arg| arg.eq_ignore_ascii_case(b"NX"));
+                    if nx && keys.contains_key(&key) {
+                        b"$-1\r\n".to_vec()
+                    } else {
+                        keys.insert(key, args[2].clone());
+                        b"+OK\r\n".to_vec()
+                    }
+                }
+                b"DEL" => format!(":{}\r\n", keys.remove(&key).map_or(0, |_| 1)).into_bytes(),
+                _ => b"-ERR unknown command\r\n".to_vec(),
+            }
+        };
+        stream.get_mut().write_all(&reply).await.unwrap();
+    }
+}
+
+async fn read_command(stream: &mut BufReader<TcpStream>) -> Option<Vec<Vec<u8>>> {
+    let mut line = String::new();
+    stream
+        .read_line(&mut line)
+        .await
+        .ok()
+        .filter(|read| *read > 0)?;
+    let count: usize = line.trim_start_matches('*').trim_end().parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        stream.read_line(&mut line).await.ok()?;
+        let size: usize = line.trim_start_matches('$').trim_end().parse().ok()?;
+        let mut arg = vec![0; size + 2];
+        stream.read_exact(&mut arg).await.ok()?;
+        arg.truncate(size);
+        args.push(arg);
+    }
+    Some(args)
+}
+
+fn bulk(value: &[u8]) -> Vec<u8> {
+    let mut reply = format!("${}\r\n", value.len()).into_bytes();
+    reply.extend_from_slice(value);
+    reply.extend_from_slice(b"\r\n");
+    reply
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// The fingerprint of a request body, as order_total computes it.
+fn fingerprint(body: &Value) -> u64 {
+    let digest = Sha256::digest(serde_json::to_vec(body).unwrap());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+fn set(keys: &Keys, key: &str, value: Value) {
+    let value = serde_json::to_vec(&value).unwrap();
+    keys.lock()
+        .unwrap()
+        .insert(format!("{}{}", PREFIX, key), value);
+}
+
+#[tokio::test]
+async fn shares_rates_and_idempotency_keys_through_redis() {
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Rate("0.0825")),
+        (SHARED_ZIP, Stub::Rate("0.5")),
+    ]))
+    .await;
+    let (url, keys) = fake_redis().await;
+    let service = TestService::start_with(&rates.url, |config| {
+        config.cache.backend = order_total::config::CacheBackend::Redis;
+        config.cache.redis.url = url;
+        config.cache.redis.key_prefix = PREFIX.into();
+    })
+    .await;
+
+    // A rate another replica looked up is taken from Redis.
+    let shared = json!({"rate": 0.1, "cached_at": now_millis()});
+    set(&keys, &format!("rate:{}", SHARED_ZIP), shared);
+    let (status, body) = service.compute(&order(SHARED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 2.0);
+    assert_eq!(rates.calls(SHARED_ZIP), 0);
+
+    // A rate looked up from the provider is handed to the others.
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(rates.calls(TAXED_ZIP), 1);
+    let key = format!("{}rate:{}", PREFIX, TAXED_ZIP);
+    let mut stored = None;
+    for _ in 0..50 {
+        stored = keys.lock().unwrap().get(&key).cloned();
+        if stored.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let stored: Value = serde_json::from_slice(&stored.expect("the rate in Redis")).unwrap();
+    assert_eq!(stored["rate"], 0.0825);
+
+    // A key claimed by another replica still handling its request.
+    let order = order(TAXED_ZIP);
+    let in_flight = json!({"fingerprint": fingerprint(&order), "response": null});
+    set(&keys, "idempotency:in-flight", in_flight);
+    let headers = [("Idempotency-Key", "in-flight")];
+    let (status, body) = service.post_with("/v1/compute", &headers, &order).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+
+    // A key another replica answered is answered the same.
+    let answered = json!({
+        "fingerprint": fingerprint(&order),
+        "response": {"status": 200, "content_type": "application/json", "body": "eyJ0b3RhbCI6MX0="},
+    });
+    set(&keys, "idempotency:answered", answered);
+    let headers = [("Idempotency-Key", "answered")];
+    let (status, body) = service.post_with("/v1/compute", &headers, &order).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body, json!({"total": 1}));
+
+    // A new key is claimed, then stored with its response.
+    let headers = [("Idempotency-Key", "new")];
+    let (status, body) = service.post_with("/v1/compute", &headers, &order).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let stored = keys.lock().unwrap()[&format!("{}idempotency:new", PREFIX)].clone();
+    let stored: Value = serde_json::from_slice(&stored).unwrap();
+    assert_eq!(stored["response"]["status"], 200);
+}
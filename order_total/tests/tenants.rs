@@ -0,0 +1,92 @@
+//! Tenants told apart by API key or header, priced at the rates of their own
+//! provider and limited as a whole.
+
+mod common;
+
+use common::{error_code, order, FakeRateService, Stub, TestService};
+use domain::Decimal;
+use hyper::StatusCode;
+use order_total::config::{RateLimitConfig, TenantConfig};
+use serde_json::Value;
+use std::collections::HashMap;
+
+const TAXED_ZIP: &str = "78701";
+const SHARED_KEY: &str = "shared-key";
+const ACME_KEY: &str = "acme-key";
+
+async fn compute(service: &TestService, headers: &[(&str, &str)]) -> (StatusCode, Value) {
+    service
+        .post_with("/v1/compute", headers, &order(TAXED_ZIP))
+        .await
+}
+
+#[tokio::test]
+async fn prices_orders_with_the_settings_of_their_tenant() {
+    let shared = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.0825"))])).await;
+    let acme_rates = FakeRateService::start(HashMap::from([(TAXED_ZIP, Stub::Rate("0.05"))])).await;
+    let acme = TenantConfig {
+        api_keys: vec![ACME_KEY.into()],
+        upstream_url: Some(acme_rates.url.clone()),
+        ..TenantConfig::default()
+    };
+    let globex = TenantConfig {
+        rates: HashMap::from([(TAXED_ZIP.to_string(), Decimal::new(1, 1))]),
+        rate_limit: Some(RateLimitConfig {
+            requests_per_second: 0.01,
+            burst: 2,
+            ..RateLimitConfig::default()
+        }),
+        ..TenantConfig::default()
+    };
+    let service = TestService::start_with(&shared.url, |config| {
+        config.auth.api_keys = vec![SHARED_KEY.into()];
+        config.tenants.insert("acme".into(), acme);
+        config.tenants.insert("globex".into(), globex);
+    })
+    .await;
+
+    // The tenant of a key, priced by its own rate service.
+    let (status, body) = compute(&service, &[("x-api-key", ACME_KEY)]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 1.0);
+    assert_eq!(acme_rates.calls(TAXED_ZIP), 1);
+    assert_eq!(shared.calls(TAXED_ZIP), 0);
+
+    // No tenant: the service's rate service.
+    let (status, body) = compute(&service, &[("x-api-key", SHARED_KEY)]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tax"], 1.65);
+    assert_eq!(shared.calls(TAXED_ZIP), 1);
+
+    // A tenant named by a service-wide key, priced at its table, until its
+    // limit runs out.
+    let as_globex = [("x-api-key", SHARED_KEY), ("x-tenant-id", "globex")];
+    for _ in 0..2 {
+        let (status, body) = compute(&service, &as_globex).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["tax"], 2.0);
+    }
+    let (status, body) = compute(&service, &as_globex).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{}", body);
+
+    let (status, body) = compute(
+        &service,
+        &[("x-api-key", ACME_KEY), ("x-tenant-id", "globex")],
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    let (status, body) = compute(
+        &service,
+        &[("x-api-key", SHARED_KEY), ("x-tenant-id", "initech")],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(error_code(&body), "UNKNOWN_TENANT");
+
+    let metrics = service.get("/metrics").await.unwrap().text().await.unwrap();
+    assert!(
+        metrics.contains(r#"method="POST",route="/compute",status="200",tenant="acme"} 1"#),
+        "{}",
+        metrics
+    );
+}
@@ -0,0 +1,46 @@
+//! Warming the rate cache up at startup, before the service reports ready.
+
+mod common;
+
+use common::{order, FakeRateService, Stub, TestService};
+use hyper::StatusCode;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const TAXED_ZIP: &str = "78701";
+const OTHER_ZIP: &str = "10001";
+
+#[tokio::test]
+async fn looks_the_listed_rates_up_before_reporting_ready() {
+    let delay = Duration::from_millis(100);
+    let rates = FakeRateService::start(HashMap::from([
+        (TAXED_ZIP, Stub::Delayed("0.0825", delay)),
+        (OTHER_ZIP, Stub::Delayed("0.08875", delay)),
+    ]))
+    .await;
+    let path = std::env::temp_dir().join(format!("order_total-zips-{}.txt", std::process::id()));
+    std::fs::write(&path, "# hot zip codes\n78701-1234\n\n10001 # NYC\n78701\n").unwrap();
+    let zips = path.to_str().unwrap().to_string();
+    let service = TestService::start_with(&rates.url, |config| {
+        config.cache.warm_up.path = Some(zips);
+    })
+    .await;
+
+    let mut ready = false;
+    for _ in 0..100 {
+        if service.get("/readyz").await.is_ok() {
+            ready = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let _ = std::fs::remove_file(&path);
+    assert!(ready);
+    // Each zip code once, by its 5-digit part.
+    assert_eq!(rates.calls(TAXED_ZIP), 1);
+    assert_eq!(rates.calls(OTHER_ZIP), 1);
+
+    let (status, body) = service.compute(&order(TAXED_ZIP)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(rates.calls(TAXED_ZIP), 1);
+}
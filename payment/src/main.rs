@@ -0,0 +1,425 @@
+//! A stub payment service: it authorizes amounts up to a limit, declines
+//! larger ones, and captures or voids its authorizations. Authorizations are
+//! kept in memory, so they are lost on restart.
+
+use domain::money::{self, Decimal};
+use domain::{ErrorEnvelope, ErrorResponse};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tracing::{info, Instrument};
+use tracing_subscriber::EnvFilter;
+
+/// Amounts above this are declined, unless `PAYMENT_LIMIT` says otherwise.
+const DEFAULT_LIMIT: &str = "1000";
+
+/// The body of `POST /authorizations`, as sent by order_total.
+#[derive(Deserialize)]
+struct AuthorizationRequest {
+    order_id: i32,
+    #[serde(with = "money::json_number")]
+    amount: Decimal,
+    currency: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AuthorizationStatus {
+    Authorized,
+    Captured,
+    Voided,
+}
+
+impl AuthorizationStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            AuthorizationStatus::Authorized => "authorized",
+            AuthorizationStatus::Captured => "captured",
+            AuthorizationStatus::Voided => "voided",
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Authorization {
+    authorization_id: String,
+    order_id: i32,
+    #[serde(with = "money::json_number")]
+    amount: Decimal,
+    currency: String,
+    status: AuthorizationStatus,
+}
+
+/// The authorizations handed out so far.
+struct Ledger {
+    limit: Decimal,
+    authorizations: HashMap<String, Authorization>,
+    next_id: u64,
+}
+
+impl Ledger {
+    fn new(limit: Decimal) -> Self {
+        Self {
+            limit,
+            authorizations: HashMap::new(),
+            next_id: 1,
+        }
+    }
+}
+
+type SharedLedger = Arc<Mutex<Ledger>>;
+
+/// Routes a request to the authorization it is about.
+async fn handle_request(
+    ledger: &SharedLedger,
+    req: Request<Body>,
+    request_id: Option<&str>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (&method, segments.as_slice()) {
+        (&Method::GET, [""]) => Ok(Response::new(Body::from(
+            "Try authorizing a payment: `curl localhost:8006/authorizations -H 'Content-Type: application/json' \
+             -d '{\"order_id\": 123, \"amount\": 21.65, \"currency\": \"USD\"}'`",
+        ))),
+
+        (&Method::POST, ["authorizations"]) => {
+            let body = hyper::body::to_bytes(req.into_body()).await?;
+            let request = match serde_json::from_slice::<AuthorizationRequest>(&body) {
+                Ok(request) => request,
+                Err(err) => {
+                    let message = format!("Invalid authorization request: {}.", err);
+                    return Ok(error_response(StatusCode::BAD_REQUEST, "INVALID_REQUEST", message, None, request_id));
+                }
+            };
+            Ok(authorize(ledger, request, request_id))
+        }
+
+        (&Method::GET, ["authorizations", id]) => {
+            let ledger = ledger.lock().unwrap();
+            match ledger.authorizations.get(*id) {
+                Some(authorization) => Ok(json_response(StatusCode::OK, serde_json::to_string(authorization)?)),
+                None => Ok(not_found(id, request_id)),
+            }
+        }
+
+        (&Method::POST, ["authorizations", id, "capture"]) => {
+            Ok(settle(ledger, id, AuthorizationStatus::Captured, request_id))
+        }
+
+        (&Method::DELETE, ["authorizations", id]) => Ok(settle(ledger, id, AuthorizationStatus::Voided, request_id)),
+
+        // Return the 404 Not Found for other routes.
+        _ => Ok(error_response(StatusCode::NOT_FOUND, "NOT_FOUND", "No such endpoint.".to_string(), None, request_id)),
+    }
+}
+
+/// Authorizes the amount of `request`, or declines it when it is above the
+/// limit.
+fn authorize(
+    ledger: &SharedLedger,
+    request: AuthorizationRequest,
+    request_id: Option<&str>,
+) -> Response<Body> {
+    if request.amount <= Decimal::ZERO {
+        let message = format!("The amount must be positive, not {}.", request.amount);
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "INVALID_REQUEST",
+            message,
+            None,
+            request_id,
+        );
+    }
+    let mut ledger = ledger.lock().unwrap();
+    if request.amount > ledger.limit {
+        info!(order_id = request.order_id, amount = %request.amount, "payment declined");
+        let message = format!(
+            "{} {} is above the limit of {}.",
+            request.amount, request.currency, ledger.limit
+        );
+        let details =
+            serde_json::json!({ "order_id": request.order_id, "limit": ledger.limit.to_string() });
+        return error_response(
+            StatusCode::PAYMENT_REQUIRED,
+            "PAYMENT_DECLINED",
+            message,
+            Some(details),
+            request_id,
+        );
+    }
+    let authorization = Authorization {
+        authorization_id: format!("auth_{:08}", ledger.next_id),
+        order_id: request.order_id,
+        amount: request.amount,
+        currency: request.currency,
+        status: AuthorizationStatus::Authorized,
+    };
+    ledger.next_id += 1;
+    ledger.authorizations.insert(
+        authorization.authorization_id.clone(),
+        authorization.clone(),
+    );
+    info!(order_id = authorization.order_id, authorization_id = %authorization.authorization_id, "payment authorized");
+    // Serializing a struct of strings and numbers cannot fail.
+    json_response(
+        StatusCode::CREATED,
+        serde_json::to_string(&authorization).unwrap(),
+    )
+}
+
+/// Captures or voids authorization `id`. Doing either twice is harmless, but
+/// a captured authorization can't be voided nor a voided one captured.
+fn settle(
+    ledger: &SharedLedger,
+    id: &str,
+    status: AuthorizationStatus,
+    request_id: Option<&str>,
+) -> Response<Body> {
+    let mut ledger = ledger.lock().unwrap();
+    let authorization = match ledger.authorizations.get_mut(id) {
+        Some(authorization) => authorization,
+        None => return not_found(id, request_id),
+    };
+    if authorization.status != AuthorizationStatus::Authorized && authorization.status != status {
+        let message = format!("Authorization {} is {}.", id, authorization.status.as_str());
+        let details = serde_json::json!({ "authorization_id": id });
+        return error_response(
+            StatusCode::CONFLICT,
+            "INVALID_STATE",
+            message,
+            Some(details),
+            request_id,
+        );
+    }
+    authorization.status = status;
+    info!(authorization_id = %id, status = status.as_str(), "authorization settled");
+    match status {
+        AuthorizationStatus::Voided => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NO_CONTENT;
+            response
+        }
+        _ => json_response(
+            StatusCode::OK,
+            serde_json::to_string(authorization).unwrap(),
+        ),
+    }
+}
+
+fn not_found(id: &str, request_id: Option<&str>) -> Response<Body> {
+    let message = format!("No authorization has id {}.", id);
+    let details = serde_json::json!({ "authorization_id": id });
+    error_response(
+        StatusCode::NOT_FOUND,
+        "AUTHORIZATION_NOT_FOUND",
+        message,
+        Some(details),
+        request_id,
+    )
+}
+
+/// The `{"error": {...}}` response shared with `order_total`.
+fn error_response(
+    status: StatusCode,
+    code: &str,
+    message: String,
+    details: Option<Value>,
+    request_id: Option<&str>,
+) -> Response<Body> {
+    let response = ErrorResponse {
+        error: ErrorEnvelope {
+            code: code.to_string(),
+            message,
+            details,
+            request_id: request_id.map(String::from),
+        },
+    };
+    // Serializing a struct of strings and JSON values cannot fail.
+    json_response(status, serde_json::to_string(&response).unwrap())
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// Runs the handler inside a span carrying the caller's X-Request-Id, so log
+/// lines of both services can be correlated.
+async fn serve_request(
+    ledger: SharedLedger,
+    req: Request<Body>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id.as_deref().unwrap_or("-"),
+        method = %req.method(),
+        path = %req.uri().path()
+    );
+    let handled = handle_request(&ledger, req, request_id.as_deref())
+        .instrument(span.clone())
+        .await;
+    let mut response = handled.unwrap_or_else(|err| {
+        span.in_scope(|| tracing::error!(error = %err, "request failed"));
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "INTERNAL",
+            err.to_string(),
+            None,
+            request_id.as_deref(),
+        )
+    });
+    if let Some(value) = request_id.and_then(|id| id.parse().ok()) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    Ok(response)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(true)
+        .with_span_list(false)
+        .init();
+
+    let limit = std::env::var("PAYMENT_LIMIT").unwrap_or_else(|_| DEFAULT_LIMIT.to_string());
+    let limit = limit
+        .trim()
+        .parse::<Decimal>()
+        .map_err(|err| format!("invalid PAYMENT_LIMIT {:?}: {}", limit, err))?;
+    let ledger = Arc::new(Mutex::new(Ledger::new(limit)));
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], 8006));
+    let make_svc = make_service_fn(move |_| {
+        let ledger = ledger.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| serve_request(ledger.clone(), req))) }
+    });
+    let server = Server::bind(&addr).serve(make_svc);
+    info!(port = 8006, limit = %limit, "server started");
+    if let Err(e) = server.await {
+        tracing::error!(error = %e, "server error");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn send(
+        ledger: &SharedLedger,
+        method: Method,
+        path: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let body = if body.is_null() {
+            Body::empty()
+        } else {
+            Body::from(body.to_string())
+        };
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(body)
+            .unwrap();
+        let response = serve_request(ledger.clone(), request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn ledger() -> SharedLedger {
+        Arc::new(Mutex::new(Ledger::new(Decimal::from(100))))
+    }
+
+    #[tokio::test]
+    async fn authorizes_and_captures_amounts_up_to_the_limit() {
+        let ledger = ledger();
+        let request = serde_json::json!({ "order_id": 123, "amount": 21.65, "currency": "USD" });
+        let (status, body) = send(&ledger, Method::POST, "/authorizations", request).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["authorization_id"], "auth_00000001");
+        assert_eq!(body["amount"], 21.65);
+        assert_eq!(body["status"], "authorized");
+
+        let (status, body) = send(
+            &ledger,
+            Method::POST,
+            "/authorizations/auth_00000001/capture",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "captured");
+        let (status, body) = send(
+            &ledger,
+            Method::DELETE,
+            "/authorizations/auth_00000001",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"]["code"], "INVALID_STATE");
+    }
+
+    #[tokio::test]
+    async fn declines_amounts_above_the_limit() {
+        let ledger = ledger();
+        let request = serde_json::json!({ "order_id": 123, "amount": 216.5, "currency": "USD" });
+        let (status, body) = send(&ledger, Method::POST, "/authorizations", request).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(body["error"]["code"], "PAYMENT_DECLINED");
+        assert!(ledger.lock().unwrap().authorizations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn voids_authorizations() {
+        let ledger = ledger();
+        let request = serde_json::json!({ "order_id": 123, "amount": "10", "currency": "USD" });
+        send(&ledger, Method::POST, "/authorizations", request).await;
+        let (status, _) = send(
+            &ledger,
+            Method::DELETE,
+            "/authorizations/auth_00000001",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, body) = send(
+            &ledger,
+            Method::GET,
+            "/authorizations/auth_00000001",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(body["status"], "voided");
+        let (status, body) = send(
+            &ledger,
+            Method::DELETE,
+            "/authorizations/auth_00000002",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "AUTHORIZATION_NOT_FOUND");
+    }
+}
@@ -0,0 +1,666 @@
+//! Protobuf messages of the order_total gRPC API and of the sales tax rate
+//! service's, written to match `order_total.proto` and `sales_tax_rate.proto`
+//! so no protoc is needed at build time, and their conversions to and from
+//! the `domain` schemas.
+
+use domain::{Decimal, JurisdictionLevel, RateSource, Rounding};
+use std::fmt;
+use std::str::FromStr;
+
+/// The gRPC path of the `ComputeOrderTotal` method.
+pub const COMPUTE_ORDER_TOTAL_PATH: &str = "/order_total.v1.OrderTotal/ComputeOrderTotal";
+/// The gRPC path of the sales tax rate service's `FindRate` method.
+pub const FIND_RATE_PATH: &str = "/sales_tax_rate.v1.SalesTaxRate/FindRate";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ComputeOrderTotalRequest {
+    #[prost(message, optional, tag = "1")]
+    pub order: Option<Order>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ComputeOrderTotalResponse {
+    #[prost(message, optional, tag = "1")]
+    pub order: Option<Order>,
+}
+
+/// `domain::Order` on the wire. Amounts are decimal strings, empty when not
+/// given.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Order {
+    #[prost(int32, tag = "1")]
+    pub order_id: i32,
+    #[prost(int32, optional, tag = "2")]
+    pub product_id: Option<i32>,
+    #[prost(int32, optional, tag = "3")]
+    pub quantity: Option<i32>,
+    #[prost(string, tag = "4")]
+    pub subtotal: String,
+    #[prost(message, repeated, tag = "5")]
+    pub line_items: Vec<LineItem>,
+    #[prost(string, optional, tag = "6")]
+    pub promo_code: Option<String>,
+    #[prost(string, tag = "7")]
+    pub discount: String,
+    #[prost(string, tag = "8")]
+    pub shipping_address: String,
+    #[prost(string, tag = "9")]
+    pub shipping_zip: String,
+    #[prost(string, tag = "10")]
+    pub shipping: String,
+    #[prost(bool, tag = "11")]
+    pub shipping_taxable: bool,
+    #[prost(string, tag = "12")]
+    pub tax: String,
+    #[prost(string, tag = "13")]
+    pub total: String,
+    /// `"fallback"` when the rate came from the fallback rate table,
+    /// `"exempt"` for tax exempt orders, `"override"` for a rate override,
+    /// empty otherwise.
+    #[prost(string, tag = "14")]
+    pub rate_source: String,
+    #[prost(string, optional, tag = "15")]
+    pub currency: Option<String>,
+    #[prost(message, optional, tag = "16")]
+    pub converted: Option<Conversion>,
+    #[prost(string, optional, tag = "17")]
+    pub tax_exempt_id: Option<String>,
+    #[prost(string, optional, tag = "18")]
+    pub exemption_reference: Option<String>,
+    #[prost(message, repeated, tag = "19")]
+    pub tax_breakdown: Vec<TaxComponent>,
+    #[prost(message, optional, tag = "20")]
+    pub normalized_address: Option<NormalizedAddress>,
+    #[prost(string, optional, tag = "21")]
+    pub reservation_id: Option<String>,
+    #[prost(string, optional, tag = "22")]
+    pub saga_id: Option<String>,
+    /// `"half_even"`, `"half_up"` or `"truncate"`.
+    #[prost(string, optional, tag = "23")]
+    pub rounding: Option<String>,
+}
+
+/// `domain::NormalizedAddress` on the wire.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct NormalizedAddress {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(string, tag = "2")]
+    pub zip: String,
+    #[prost(bool, tag = "3")]
+    pub zip_derived: bool,
+}
+
+/// `domain::Conversion` on the wire.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Conversion {
+    #[prost(string, tag = "1")]
+    pub currency: String,
+    #[prost(string, tag = "2")]
+    pub exchange_rate: String,
+    #[prost(string, tag = "3")]
+    pub subtotal: String,
+    #[prost(string, tag = "4")]
+    pub discount: String,
+    #[prost(string, tag = "5")]
+    pub shipping: String,
+    #[prost(string, tag = "6")]
+    pub tax: String,
+    #[prost(string, tag = "7")]
+    pub total: String,
+}
+
+/// `domain::TaxComponent` on the wire.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TaxComponent {
+    /// `state`, `county`, `city` or `special_district`.
+    #[prost(string, tag = "1")]
+    pub level: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub rate: String,
+    #[prost(string, tag = "4")]
+    pub tax: String,
+}
+
+/// `domain::LineItem` on the wire.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LineItem {
+    #[prost(int32, tag = "1")]
+    pub product_id: i32,
+    #[prost(int32, tag = "2")]
+    pub quantity: i32,
+    #[prost(string, tag = "3")]
+    pub unit_price: String,
+    #[prost(bool, optional, tag = "4")]
+    pub taxable: Option<bool>,
+    #[prost(string, tag = "5")]
+    pub unit_weight: String,
+    #[prost(string, tag = "6")]
+    pub discount: String,
+    #[prost(string, tag = "7")]
+    pub tax: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FindRateRequest {
+    #[prost(string, tag = "1")]
+    pub zip: String,
+    /// `YYYY-MM-DD` day the rate was in effect on; today when empty.
+    #[prost(string, tag = "2")]
+    pub as_of: String,
+}
+
+/// `domain::RateQuote` on the wire.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FindRateResponse {
+    #[prost(string, tag = "1")]
+    pub zip: String,
+    #[prost(string, tag = "2")]
+    pub rate: String,
+    #[prost(string, tag = "3")]
+    pub jurisdiction: String,
+    #[prost(message, repeated, tag = "4")]
+    pub components: Vec<RateComponent>,
+}
+
+/// `domain::RateComponent` on the wire.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RateComponent {
+    /// `state`, `county`, `city` or `special_district`.
+    #[prost(string, tag = "1")]
+    pub level: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub rate: String,
+}
+
+/// A field that should hold a decimal amount, or another kind of value, but
+/// doesn't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidField {
+    pub field: String,
+    pub value: String,
+    /// What the field should hold, e.g. `a decimal amount`.
+    pub expected: &'static str,
+}
+
+impl fmt::Display for InvalidField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is not {}: {:?}",
+            self.field, self.expected, self.value
+        )
+    }
+}
+
+impl std::error::Error for InvalidField {}
+
+fn amount(value: Decimal) -> String {
+    value.to_string()
+}
+
+fn optional_amount(value: Option<Decimal>) -> String {
+    value.map(amount).unwrap_or_default()
+}
+
+fn parse_optional(field: &str, value: &str) -> Result<Option<Decimal>, InvalidField> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    Decimal::from_str(value.trim())
+        .map(Some)
+        .map_err(|_| InvalidField {
+            field: field.to_string(),
+            value: value.to_string(),
+            expected: "a decimal amount",
+        })
+}
+
+/// Empty amounts count as zero.
+fn parse(field: &str, value: &str) -> Result<Decimal, InvalidField> {
+    Ok(parse_optional(field, value)?.unwrap_or_default())
+}
+
+impl From<domain::Order> for Order {
+    fn from(order: domain::Order) -> Self {
+        Self {
+            order_id: order.order_id,
+            product_id: order.product_id,
+            quantity: order.quantity,
+            subtotal: optional_amount(order.subtotal),
+            line_items: order.line_items.into_iter().map(LineItem::from).collect(),
+            promo_code: order.promo_code,
+            discount: amount(order.discount),
+            shipping_address: order.shipping_address,
+            shipping_zip: order.shipping_zip,
+            shipping: amount(order.shipping),
+            shipping_taxable: order.shipping_taxable,
+            tax: amount(order.tax),
+            total: amount(order.total),
+            rate_source: order
+                .rate_source
+                .map(|source| source.as_str().to_string())
+                .unwrap_or_default(),
+            currency: order.currency,
+            converted: order.converted.map(Conversion::from),
+            tax_exempt_id: order.tax_exempt_id,
+            exemption_reference: order.exemption_reference,
+            tax_breakdown: order
+                .tax_breakdown
+                .into_iter()
+                .map(TaxComponent::from)
+                .collect(),
+            normalized_address: order.normalized_address.map(NormalizedAddress::from),
+            reservation_id: order.reservation_id,
+            saga_id: order.saga_id,
+            rounding: order.rounding.map(|rounding| rounding.as_str().to_string()),
+        }
+    }
+}
+
+impl From<domain::NormalizedAddress> for NormalizedAddress {
+    fn from(address: domain::NormalizedAddress) -> Self {
+        Self {
+            address: address.address,
+            zip: address.zip,
+            zip_derived: address.zip_derived,
+        }
+    }
+}
+
+impl From<NormalizedAddress> for domain::NormalizedAddress {
+    fn from(address: NormalizedAddress) -> Self {
+        Self {
+            address: address.address,
+            zip: address.zip,
+            zip_derived: address.zip_derived,
+        }
+    }
+}
+
+impl From<domain::TaxComponent> for TaxComponent {
+    fn from(component: domain::TaxComponent) -> Self {
+        Self {
+            level: component.level.as_str().to_string(),
+            name: component.name,
+            rate: amount(component.rate),
+            tax: amount(component.tax),
+        }
+    }
+}
+
+impl From<domain::Conversion> for Conversion {
+    fn from(conversion: domain::Conversion) -> Self {
+        Self {
+            currency: conversion.currency,
+            exchange_rate: amount(conversion.exchange_rate),
+            subtotal: amount(conversion.subtotal),
+            discount: amount(conversion.discount),
+            shipping: amount(conversion.shipping),
+            tax: amount(conversion.tax),
+            total: amount(conversion.total),
+        }
+    }
+}
+
+impl From<domain::LineItem> for LineItem {
+    fn from(item: domain::LineItem) -> Self {
+        Self {
+            product_id: item.product_id,
+            quantity: item.quantity,
+            unit_price: amount(item.unit_price),
+            taxable: Some(item.taxable),
+            unit_weight: optional_amount(item.unit_weight),
+            discount: amount(item.discount),
+            tax: amount(item.tax),
+        }
+    }
+}
+
+impl TryFrom<Order> for domain::Order {
+    type Error = InvalidField;
+
+    fn try_from(order: Order) -> Result<Self, InvalidField> {
+        let line_items = order
+            .line_items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| line_item(index, item))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            order_id: order.order_id,
+            product_id: order.product_id,
+            quantity: order.quantity,
+            subtotal: parse_optional("subtotal", &order.subtotal)?,
+            line_items,
+            promo_code: order.promo_code,
+            discount: parse("discount", &order.discount)?,
+            shipping_address: order.shipping_address,
+            shipping_zip: order.shipping_zip,
+            shipping: parse("shipping", &order.shipping)?,
+            shipping_taxable: order.shipping_taxable,
+            tax: parse("tax", &order.tax)?,
+            total: parse("total", &order.total)?,
+            rate_source: match order.rate_source.as_str() {
+                "fallback" => Some(RateSource::Fallback),
+                "exempt" => Some(RateSource::Exempt),
+                "override" => Some(RateSource::Override),
+                _ => None,
+            },
+            currency: order.currency,
+            converted: order.converted.map(conversion).transpose()?,
+            tax_exempt_id: order.tax_exempt_id,
+            exemption_reference: order.exemption_reference,
+            tax_breakdown: order
+                .tax_breakdown
+                .into_iter()
+                .enumerate()
+                .map(|(index, component)| tax_component(index, component))
+                .collect::<Result<_, _>>()?,
+            normalized_address: order.normalized_address.map(Into::into),
+            reservation_id: order.reservation_id,
+            saga_id: order.saga_id,
+            rounding: order.rounding.and_then(|rounding| {
+                Rounding::ALL
+                    .into_iter()
+                    .find(|known| known.as_str() == rounding)
+            }),
+        })
+    }
+}
+
+fn tax_component(
+    index: usize,
+    component: TaxComponent,
+) -> Result<domain::TaxComponent, InvalidField> {
+    let field = |name: &str| format!("tax_breakdown[{}].{}", index, name);
+    Ok(domain::TaxComponent {
+        level: jurisdiction_level(field("level"), &component.level)?,
+        name: component.name,
+        rate: parse(&field("rate"), &component.rate)?,
+        tax: parse(&field("tax"), &component.tax)?,
+    })
+}
+
+impl From<domain::RateQuote> for FindRateResponse {
+    fn from(quote: domain::RateQuote) -> Self {
+        Self {
+            zip: quote.zip,
+            rate: amount(quote.rate),
+            jurisdiction: quote.jurisdiction,
+            components: quote
+                .components
+                .into_iter()
+                .map(|component| RateComponent {
+                    level: component.level.as_str().to_string(),
+                    name: component.name,
+                    rate: amount(component.rate),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<FindRateResponse> for domain::RateQuote {
+    type Error = InvalidField;
+
+    fn try_from(response: FindRateResponse) -> Result<Self, InvalidField> {
+        let rate = parse_optional("rate", &response.rate)?.ok_or_else(|| InvalidField {
+            field: "rate".into(),
+            value: String::new(),
+            expected: "a decimal amount",
+        })?;
+        Ok(Self {
+            zip: response.zip,
+            rate,
+            jurisdiction: response.jurisdiction,
+            components: response
+                .components
+                .into_iter()
+                .enumerate()
+                .map(|(index, component)| rate_component(index, component))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+fn jurisdiction_level(field: String, value: &str) -> Result<JurisdictionLevel, InvalidField> {
+    JurisdictionLevel::ALL
+        .into_iter()
+        .find(|level| level.as_str() == value)
+        .ok_or_else(|| InvalidField {
+            field,
+            value: value.to_string(),
+            expected: "a jurisdiction level",
+        })
+}
+
+fn rate_component(
+    index: usize,
+    component: RateComponent,
+) -> Result<domain::RateComponent, InvalidField> {
+    let field = |name: &str| format!("components[{}].{}", index, name);
+    Ok(domain::RateComponent {
+        level: jurisdiction_level(field("level"), &component.level)?,
+        name: component.name,
+        rate: parse(&field("rate"), &component.rate)?,
+    })
+}
+
+fn conversion(conversion: Conversion) -> Result<domain::Conversion, InvalidField> {
+    let field = |name: &str| format!("converted.{}", name);
+    Ok(domain::Conversion {
+        currency: conversion.currency,
+        exchange_rate: parse(&field("exchange_rate"), &conversion.exchange_rate)?,
+        subtotal: parse(&field("subtotal"), &conversion.subtotal)?,
+        discount: parse(&field("discount"), &conversion.discount)?,
+        shipping: parse(&field("shipping"), &conversion.shipping)?,
+        tax: parse(&field("tax"), &conversion.tax)?,
+        total: parse(&field("total"), &conversion.total)?,
+    })
+}
+
+fn line_item(index: usize, item: LineItem) -> Result<domain::LineItem, InvalidField> {
+    let field = |name: &str| format!("line_items[{}].{}", index, name);
+    Ok(domain::LineItem {
+        product_id: item.product_id,
+        quantity: item.quantity,
+        unit_price: parse(&field("unit_price"), &item.unit_price)?,
+        taxable: item.taxable.unwrap_or(true),
+        unit_weight: parse_optional(&field("unit_weight"), &item.unit_weight)?,
+        discount: parse(&field("discount"), &item.discount)?,
+        tax: parse(&field("tax"), &item.tax)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn order() -> domain::Order {
+        domain::Order {
+            order_id: 3,
+            product_id: None,
+            quantity: None,
+            subtotal: Some(dec("24.99")),
+            line_items: vec![domain::LineItem {
+                product_id: 5,
+                quantity: 2,
+                unit_price: dec("10.00"),
+                taxable: true,
+                unit_weight: Some(dec("1.5")),
+                discount: dec("0"),
+                tax: dec("1.65"),
+            }],
+            promo_code: Some("SAVE10".into()),
+            discount: dec("0"),
+            shipping_address: "1 Congress Ave".into(),
+            shipping_zip: "78701".into(),
+            shipping: dec("7.00"),
+            shipping_taxable: false,
+            tax: dec("1.65"),
+            total: dec("33.64"),
+            rate_source: Some(RateSource::Fallback),
+            currency: Some("USD".into()),
+            converted: Some(domain::Conversion {
+                currency: "EUR".into(),
+                exchange_rate: dec("1.08"),
+                subtotal: dec("23.14"),
+                discount: dec("0"),
+                shipping: dec("6.48"),
+                tax: dec("1.53"),
+                total: dec("31.15"),
+            }),
+            tax_exempt_id: None,
+            exemption_reference: None,
+            tax_breakdown: vec![
+                domain::TaxComponent {
+                    level: JurisdictionLevel::State,
+                    name: "Texas".into(),
+                    rate: dec("0.0625"),
+                    tax: dec("1.25"),
+                },
+                domain::TaxComponent {
+                    level: JurisdictionLevel::SpecialDistrict,
+                    name: "Capital Metro".into(),
+                    rate: dec("0.02"),
+                    tax: dec("0.40"),
+                },
+            ],
+            normalized_address: Some(domain::NormalizedAddress {
+                address: "1 CONGRESS AVE, AUSTIN, TX 78701".into(),
+                zip: "78701".into(),
+                zip_derived: false,
+            }),
+            reservation_id: Some("RES-7".into()),
+            saga_id: Some("5f0c6a1e-8d2b-4c3a-9e7f-2b1d4a6c8e90".into()),
+            rounding: Some(Rounding::HalfUp),
+        }
+    }
+
+    fn exempt_order() -> domain::Order {
+        let mut order = order();
+        order.line_items[0].tax = dec("0");
+        domain::Order {
+            tax: dec("0"),
+            total: dec("31.99"),
+            rate_source: Some(RateSource::Exempt),
+            converted: None,
+            tax_exempt_id: Some("TX-12345".into()),
+            exemption_reference: Some("REG-0042".into()),
+            tax_breakdown: Vec::new(),
+            ..order
+        }
+    }
+
+    #[test]
+    fn order_round_trips_through_protobuf() {
+        let request = ComputeOrderTotalRequest {
+            order: Some(Order::from(order())),
+        };
+        let decoded = ComputeOrderTotalRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, request);
+        let order_back = domain::Order::try_from(decoded.order.unwrap()).unwrap();
+        assert_eq!(order_back, order());
+    }
+
+    #[test]
+    fn exempt_orders_round_trip_through_protobuf() {
+        let wire = Order::from(exempt_order());
+        assert_eq!(wire.rate_source, "exempt");
+        let decoded = Order::decode(wire.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(domain::Order::try_from(decoded).unwrap(), exempt_order());
+    }
+
+    #[test]
+    fn missing_amounts_and_flags_take_their_defaults() {
+        let order = domain::Order::try_from(Order {
+            order_id: 3,
+            line_items: vec![LineItem {
+                product_id: 5,
+                quantity: 1,
+                unit_price: "4.99".into(),
+                ..LineItem::default()
+            }],
+            shipping_address: "1 Congress Ave".into(),
+            shipping_zip: "78701".into(),
+            ..Order::default()
+        })
+        .unwrap();
+        assert_eq!(order.subtotal, None);
+        assert_eq!(order.total, dec("0"));
+        assert!(order.line_items[0].taxable);
+        assert_eq!(order.line_items[0].unit_weight, None);
+    }
+
+    #[test]
+    fn invalid_amounts_name_their_field() {
+        let mut wire = Order::from(order());
+        wire.line_items[0].unit_price = "ten".into();
+        assert_eq!(
+            domain::Order::try_from(wire).unwrap_err(),
+            InvalidField {
+                field: "line_items[0].unit_price".into(),
+                value: "ten".into(),
+                expected: "a decimal amount",
+            }
+        );
+    }
+
+    #[test]
+    fn rate_quotes_round_trip_through_protobuf() {
+        let quote = domain::RateQuote {
+            zip: "78701".into(),
+            rate: dec("0.0825"),
+            jurisdiction: "Austin, TX".into(),
+            components: vec![
+                domain::RateComponent {
+                    level: JurisdictionLevel::State,
+                    name: "Texas".into(),
+                    rate: dec("0.0625"),
+                },
+                domain::RateComponent {
+                    level: JurisdictionLevel::SpecialDistrict,
+                    name: "Capital Metro".into(),
+                    rate: dec("0.02"),
+                },
+            ],
+        };
+        let wire = FindRateResponse::from(quote.clone());
+        assert_eq!(wire.rate, "0.0825");
+        let decoded = FindRateResponse::decode(wire.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(domain::RateQuote::try_from(decoded).unwrap(), quote);
+    }
+
+    #[test]
+    fn rate_quotes_need_a_rate() {
+        let wire = FindRateResponse {
+            zip: "78701".into(),
+            ..FindRateResponse::default()
+        };
+        assert_eq!(
+            domain::RateQuote::try_from(wire).unwrap_err().to_string(),
+            "rate is not a decimal amount: \"\""
+        );
+    }
+
+    #[test]
+    fn unknown_jurisdiction_levels_are_rejected() {
+        let mut wire = Order::from(order());
+        assert_eq!(wire.tax_breakdown[1].level, "special_district");
+        wire.tax_breakdown[1].level = "borough".into();
+        assert_eq!(
+            domain::Order::try_from(wire).unwrap_err().to_string(),
+            "tax_breakdown[1].level is not a jurisdiction level: \"borough\""
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! The gRPC API: `FindRate` of `proto/sales_tax_rate.proto`, served over
+//! plaintext HTTP/2 on its own port, next to `POST /find_rate`, for
+//! `order_total`'s `grpc://` upstream URLs. tonic's transport doesn't run on
+//! WasmEdge, so the unary call framing is done here on top of hyper.
+
+use domain::RateQuote;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use prost::Message;
+use proto::{FindRateRequest, FindRateResponse, FIND_RATE_PATH};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::{info, Instrument};
+
+use crate::{find_components, find_rate, is_date};
+
+/// The port of the gRPC API.
+pub const PORT: u16 = 50052;
+
+// The gRPC status codes we answer with.
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const NOT_FOUND: u32 = 5;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+
+/// A failed call, as sent in the `grpc-status` and `grpc-message` headers.
+#[derive(Debug)]
+struct Status {
+    code: u32,
+    message: String,
+}
+
+impl Status {
+    fn new(code: u32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// The body of a unary response: at most one message, then the trailers.
+struct UnaryBody {
+    message: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl HttpBody for UnaryBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Infallible>>> {
+        Poll::Ready(self.message.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Infallible>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.message.is_none() && self.trailers.is_none()
+    }
+}
+
+/// Serves the gRPC API on `port` until the server fails.
+pub async fn run(port: u16) -> hyper::Result<()> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_svc = make_service_fn(|_| async move {
+        Ok::<_, Infallible>(service_fn(move |req| async move {
+            Ok::<_, Infallible>(serve_call(req).await)
+        }))
+    });
+    let server = Server::bind(&addr).http2_only(true).serve(make_svc);
+    info!(port, "gRPC server started");
+    server.await
+}
+
+/// Serves one call inside a span carrying the caller's X-Request-Id, W3C trace
+/// id and `grpc-timeout`, as HTTP requests are.
+async fn serve_call(req: Request<Body>) -> Response<UnaryBody> {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let request_id = header("x-request-id");
+    // traceparent is "00-<trace id>-<parent span id>-<flags>".
+    let trace_id = header("traceparent")
+        .and_then(|value| value.split('-').nth(1).map(String::from))
+        .unwrap_or_else(|| "-".to_string());
+    let span = tracing::info_span!(
+        "call",
+        request_id = %request_id.as_deref().unwrap_or("-"),
+        trace_id = %trace_id,
+        grpc_timeout = %header("grpc-timeout").as_deref().unwrap_or("-"),
+        path = %req.uri().path()
+    );
+    let mut response = match call(req).instrument(span.clone()).await {
+        Ok(message) => ok_response(message),
+        Err(status) => {
+            if status.code == INTERNAL {
+                span.in_scope(|| tracing::error!(error = %status.message, "call failed"));
+            }
+            error_response(&status)
+        }
+    };
+    if let Some(value) = request_id.and_then(|id| id.parse().ok()) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    response
+}
+
+/// Looks up the rate of the zip code of a `FindRate` call, as `POST
+/// /find_rate` does for JSON callers.
+async fn call(req: Request<Body>) -> Result<Vec<u8>, Status> {
+    if req.uri().path() != FIND_RATE_PATH {
+        let message = format!("unknown method {}", req.uri().path());
+        return Err(Status::new(UNIMPLEMENTED, message));
+    }
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|err| Status::new(INVALID_ARGUMENT, err.to_string()))?;
+    let request = decode(&body)?;
+    // An empty `as_of` asks for the current rate.
+    let as_of = Some(request.as_of.as_str()).filter(|day| !day.is_empty());
+    if let Some(day) = as_of.filter(|day| !is_date(day)) {
+        let message = format!("as_of {:?} is not a YYYY-MM-DD date", day);
+        return Err(Status::new(INVALID_ARGUMENT, message));
+    }
+    let internal = |err: anyhow::Error| Status::new(INTERNAL, err.to_string());
+    let found = find_rate(&request.zip, as_of).map_err(internal)?;
+    info!(zip = %request.zip, as_of = as_of.unwrap_or("-"), found = found.is_some(), "rate lookup");
+    let found = found.ok_or_else(|| {
+        let message = format!("No sales tax rate for zip code {}.", request.zip);
+        Status::new(NOT_FOUND, message)
+    })?;
+    // The breakdown is that of the current rate.
+    let components = if found.current {
+        find_components(&request.zip).map_err(internal)?
+    } else {
+        Vec::new()
+    };
+    let quote = RateQuote {
+        zip: request.zip,
+        rate: found.rate,
+        jurisdiction: found.jurisdiction,
+        components,
+    };
+    Ok(FindRateResponse::from(quote).encode_to_vec())
+}
+
+/// Reads the single length-prefixed message of a unary call.
+fn decode(body: &[u8]) -> Result<FindRateRequest, Status> {
+    match body {
+        [0, a, b, c, d, message @ ..]
+            if u32::from_be_bytes([*a, *b, *c, *d]) as usize == message.len() =>
+        {
+            FindRateRequest::decode(message)
+                .map_err(|err| Status::new(INVALID_ARGUMENT, err.to_string()))
+        }
+        [1, ..] => Err(Status::new(
+            UNIMPLEMENTED,
+            "compressed messages are not supported",
+        )),
+        _ => Err(Status::new(
+            INVALID_ARGUMENT,
+            "malformed gRPC message frame",
+        )),
+    }
+}
+
+fn ok_response(message: Vec<u8>) -> Response<UnaryBody> {
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(OK));
+    Response::builder()
+        .header(CONTENT_TYPE, "application/grpc")
+        .body(UnaryBody {
+            message: Some(frame.into()),
+            trailers: Some(trailers),
+        })
+        .unwrap()
+}
+
+/// A trailers-only response: the status goes in the headers, without a body.
+fn error_response(status: &Status) -> Response<UnaryBody> {
+    Response::builder()
+        .header(CONTENT_TYPE, "application/grpc")
+        .header("grpc-status", status.code)
+        .header("grpc-message", percent_encode(&status.message))
+        .body(UnaryBody {
+            message: None,
+            trailers: None,
+        })
+        .unwrap()
+}
+
+/// `grpc-message` is percent-encoded UTF-8.
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'%' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Client;
+
+    /// Calls `FindRate` over HTTP/2 on a server of the API, as `order_total`
+    /// does, returning the status and the response message, if any.
+    async fn find_rate_call(
+        addr: SocketAddr,
+        request: FindRateRequest,
+    ) -> (u32, Option<FindRateResponse>) {
+        let message = request.encode_to_vec();
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&message);
+        let request = Request::post(format!("http://{}{}", addr, FIND_RATE_PATH))
+            .header(CONTENT_TYPE, "application/grpc")
+            .header("te", "trailers")
+            .header("grpc-timeout", "2000m")
+            .body(Body::from(frame))
+            .unwrap();
+        let client = Client::builder().http2_only(true).build_http::<Body>();
+        let response = client.request(request).await.unwrap();
+        let (parts, mut body) = response.into_parts();
+        let mut message = Vec::new();
+        while let Some(chunk) = body.data().await {
+            message.extend_from_slice(&chunk.unwrap());
+        }
+        let trailers = body.trailers().await.unwrap();
+        let headers = trailers.as_ref().unwrap_or(&parts.headers);
+        let code = headers["grpc-status"].to_str().unwrap().parse().unwrap();
+        let response =
+            (!message.is_empty()).then(|| FindRateResponse::decode(&message[5..]).unwrap());
+        (code, response)
+    }
+
+    #[tokio::test]
+    async fn serves_find_rate_over_http2() {
+        let make_svc = make_service_fn(|_| async move {
+            Ok::<_, Infallible>(service_fn(move |req| async move {
+                Ok::<_, Infallible>(serve_call(req).await)
+            }))
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .http2_only(true)
+            .serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        let request = |zip: &str, as_of: &str| FindRateRequest {
+            zip: zip.to_string(),
+            as_of: as_of.to_string(),
+        };
+
+        let (code, response) = find_rate_call(addr, request("78701", "")).await;
+        assert_eq!(code, OK);
+        let quote = RateQuote::try_from(response.unwrap()).unwrap();
+        assert_eq!(quote.rate, "0.0825".parse().unwrap());
+        assert!(!quote.components.is_empty());
+
+        let (code, _) = find_rate_call(addr, request("00000", "")).await;
+        assert_eq!(code, NOT_FOUND);
+        let (code, _) = find_rate_call(addr, request("78701", "June 30")).await;
+        assert_eq!(code, INVALID_ARGUMENT);
+    }
+}
@@ -1,66 +1,348 @@
-use std::net::SocketAddr;
-use std::convert::Infallible;
-use std::str;
-use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Method, Request, Response, StatusCode, Server};
 use csv::Reader;
+use domain::{
+    Decimal, ErrorEnvelope, ErrorResponse, JurisdictionLevel, RateComponent, RateQuote,
+    RateRequest, RateResponse,
+};
+use hyper::header::{HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tracing::{info, Instrument};
+use tracing_subscriber::EnvFilter;
+
+mod grpc;
+#[cfg(feature = "tls")]
+mod tls;
 
 /// This is our service handler. It receives a Request, routes on its
 /// path, and returns a Future of a Response.
-async fn handle_request(req: Request<Body>) -> Result<Response<Body>, anyhow::Error> {
+async fn handle_request(
+    req: Request<Body>,
+    request_id: Option<&str>,
+) -> Result<Response<Body>, anyhow::Error> {
     match (req.method(), req.uri().path()) {
         // Serve some instructions at /
         (&Method::GET, "/") => Ok(Response::new(Body::from(
-            "Try POSTing data to /find_rate such as: `curl localhost:8001/get_rate -XPOST -d '78701'`",
+            "Try POSTing data to /find_rate such as: `curl localhost:8001/get_rate -XPOST -d '78701'`, \
+             or as JSON: `curl localhost:8001/find_rate -H 'Content-Type: application/json' -d '{\"zip\": \"78701\"}'`",
         ))),
 
         (&Method::POST, "/find_rate") => {
+            // JSON callers send a `RateRequest` and get a `RateQuote` back; plain-text
+            // callers send the bare zip code and get the bare rate.
+            let header = |name: HeaderName| req.headers().get(name).and_then(|value| value.to_str().ok());
+            let json_request = header(CONTENT_TYPE).is_some_and(is_json);
+            let json_answer = json_request || header(ACCEPT).is_some_and(accepts_json);
             let post_body = hyper::body::to_bytes(req.into_body()).await?;
-            let mut rate = "".to_string();
-
-            let rates_data: &[u8] = include_bytes!("rates_by_zipcode.csv");
-            let mut rdr = Reader::from_reader(rates_data);
-            for result in rdr.records() {
-                let record = result?;
-                // dbg!("{:?}", record.clone());
-                if str::from_utf8(&post_body).unwrap().eq(&record[0]) {
-                    rate = record[1].to_string();
-                    break;
+            // JSON callers may ask for the rate in effect on a past day.
+            let (zip, as_of) = if json_request {
+                match serde_json::from_slice::<RateRequest>(&post_body) {
+                    Ok(RateRequest { as_of: Some(day), .. }) if !is_date(&day) => {
+                        let message = format!("Invalid rate request: as_of {:?} is not a YYYY-MM-DD date.", day);
+                        return Ok(error_response(StatusCode::BAD_REQUEST, "INVALID_REQUEST", message, None, request_id));
+                    }
+                    Ok(request) => (request.zip, request.as_of),
+                    Err(err) => {
+                        let message = format!("Invalid rate request: {}.", err);
+                        return Ok(error_response(StatusCode::BAD_REQUEST, "INVALID_REQUEST", message, None, request_id));
+                    }
                 }
-            }
-
-            if rate.is_empty() {
-                let mut not_found = Response::default();
-                *not_found.status_mut() = StatusCode::NOT_FOUND;
-                Ok(not_found)
             } else {
-                Ok(Response::new(Body::from(rate)))
+                (String::from_utf8_lossy(&post_body).trim().to_string(), None)
+            };
+
+            let found = find_rate(&zip, as_of.as_deref())?;
+            info!(zip = %zip, as_of = as_of.as_deref().unwrap_or("-"), found = found.is_some(), json = json_answer, "rate lookup");
+            match found {
+                Some(found) if json_answer => {
+                    // The breakdown is that of the current rate.
+                    let components = if found.current { find_components(&zip)? } else { Vec::new() };
+                    let quote = RateQuote { zip, rate: found.rate, jurisdiction: found.jurisdiction, components };
+                    Ok(json_response(StatusCode::OK, serde_json::to_string(&quote)?))
+                }
+                Some(FoundRate { rate, .. }) => {
+                    let mut response = Response::new(Body::from(serde_json::to_string(&RateResponse { rate })?));
+                    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+                    Ok(response)
+                }
+                None => {
+                    let message = format!("No sales tax rate for zip code {}.", zip);
+                    let details = serde_json::json!({ "shipping_zip": zip });
+                    Ok(error_response(StatusCode::NOT_FOUND, "RATE_NOT_FOUND", message, Some(details), request_id))
+                }
             }
         }
 
         // Return the 404 Not Found for other routes.
-        _ => {
-            let mut not_found = Response::default();
-            *not_found.status_mut() = StatusCode::NOT_FOUND;
-            Ok(not_found)
+        _ => Ok(error_response(StatusCode::NOT_FOUND, "NOT_FOUND", "No such endpoint.".to_string(), None, request_id)),
+    }
+}
+
+/// A zip code's rate, as found in the rate table.
+struct FoundRate {
+    rate: Decimal,
+    jurisdiction: String,
+    /// Whether it is the zip code's current rate, rather than a past one.
+    current: bool,
+}
+
+/// The rate and jurisdiction of a zip code on the day `as_of`, `YYYY-MM-DD`,
+/// or its current ones. The table has a row per rate a zip code had, each in
+/// effect from its `effective_from` day, or from the start when empty, until
+/// the next one's. Rates are per 5-digit zip code; ZIP+4 codes use their
+/// first part.
+fn find_rate(zip: &str, as_of: Option<&str>) -> Result<Option<FoundRate>, anyhow::Error> {
+    let zip5 = zip.split('-').next().unwrap_or_default();
+    let rates_data: &[u8] = include_bytes!("rates_by_zipcode.csv");
+    let mut rdr = Reader::from_reader(rates_data);
+    // Days compare as text, and the empty one before all others.
+    let mut found: Option<(String, Decimal, String)> = None;
+    let mut latest: Option<String> = None;
+    for result in rdr.records() {
+        let record = result?;
+        if !zip5.eq(&record[0]) {
+            continue;
         }
+        let effective_from = record[3].trim().to_string();
+        if latest
+            .as_ref()
+            .is_none_or(|latest| effective_from > *latest)
+        {
+            latest = Some(effective_from.clone());
+        }
+        let in_effect = as_of.is_none_or(|day| effective_from.as_str() <= day);
+        if in_effect
+            && found
+                .as_ref()
+                .is_none_or(|(from, _, _)| effective_from >= *from)
+        {
+            let rate = record[1].trim().parse::<Decimal>()?;
+            found = Some((effective_from, rate, record[2].to_string()));
+        }
+    }
+    Ok(found.map(|(effective_from, rate, jurisdiction)| FoundRate {
+        rate,
+        jurisdiction,
+        current: latest == Some(effective_from),
+    }))
+}
+
+/// Whether `day` is a `YYYY-MM-DD` date, at least by its shape.
+fn is_date(day: &str) -> bool {
+    let bytes = day.as_bytes();
+    bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, byte)| match i {
+            4 | 7 => *byte == b'-',
+            _ => byte.is_ascii_digit(),
+        })
+}
+
+/// The components of a zip code's rate by jurisdiction, which add up to the
+/// rate; empty for zip codes whose rate isn't broken down.
+fn find_components(zip: &str) -> Result<Vec<RateComponent>, anyhow::Error> {
+    let zip5 = zip.split('-').next().unwrap_or_default();
+    let components_data: &[u8] = include_bytes!("rate_components.csv");
+    let mut rdr = Reader::from_reader(components_data);
+    let mut components = Vec::new();
+    for result in rdr.records() {
+        let record = result?;
+        if zip5.eq(&record[0]) {
+            let level = JurisdictionLevel::ALL
+                .into_iter()
+                .find(|level| level.as_str() == &record[1]);
+            let level = level
+                .ok_or_else(|| anyhow::anyhow!("unknown jurisdiction level {:?}", &record[1]))?;
+            let rate = record[3].trim().parse::<Decimal>()?;
+            components.push(RateComponent {
+                level,
+                name: record[2].to_string(),
+                rate,
+            });
+        }
+    }
+    Ok(components)
+}
+
+/// Whether a media type, e.g. of `Content-Type`, is JSON. Plain-text callers
+/// send none, or the `application/x-www-form-urlencoded` of `curl -d`.
+fn is_json(media_type: &str) -> bool {
+    let essence = media_type.split(';').next().unwrap_or_default();
+    essence.trim().eq_ignore_ascii_case("application/json")
+}
+
+/// Whether an `Accept` header asks for JSON by name; `*/*` keeps the plain-text
+/// answer older callers expect.
+fn accepts_json(accept: &str) -> bool {
+    accept.split(',').any(is_json)
+}
+
+/// The `{"error": {...}}` response shared with `order_total`.
+fn error_response(
+    status: StatusCode,
+    code: &str,
+    message: String,
+    details: Option<Value>,
+    request_id: Option<&str>,
+) -> Response<Body> {
+    let response = ErrorResponse {
+        error: ErrorEnvelope {
+            code: code.to_string(),
+            message,
+            details,
+            request_id: request_id.map(String::from),
+        },
+    };
+    // Serializing a struct of strings and JSON values cannot fail.
+    json_response(status, serde_json::to_string(&response).unwrap())
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// Runs the handler inside a span carrying the caller's X-Request-Id and W3C
+/// trace id, so log lines of both services can be correlated.
+async fn serve_request(req: Request<Body>) -> Result<Response<Body>, anyhow::Error> {
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    // traceparent is "00-<trace id>-<parent span id>-<flags>".
+    let trace_id = req
+        .headers()
+        .get("traceparent")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split('-').nth(1))
+        .unwrap_or("-")
+        .to_string();
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id.as_deref().unwrap_or("-"),
+        trace_id = %trace_id,
+        path = %req.uri().path()
+    );
+    let handled = handle_request(req, request_id.as_deref())
+        .instrument(span.clone())
+        .await;
+    let mut response = handled.unwrap_or_else(|err| {
+        span.in_scope(|| tracing::error!(error = %err, "request failed"));
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "INTERNAL",
+            err.to_string(),
+            None,
+            request_id.as_deref(),
+        )
+    });
+    if let Some(value) = request_id.and_then(|id| id.parse().ok()) {
+        response.headers_mut().insert("x-request-id", value);
     }
+    Ok(response)
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(true)
+        .with_span_list(false)
+        .init();
+
+    // The gRPC API stays plaintext, also when the HTTP API is served over TLS.
+    tokio::spawn(async {
+        if let Err(e) = grpc::run(grpc::PORT).await {
+            tracing::error!(error = %e, "gRPC server error");
+        }
+    });
+
     let addr = SocketAddr::from(([0, 0, 0, 0], 8001));
-    let make_svc = make_service_fn(|_| {
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                handle_request(req)
-            }))
+    #[cfg(feature = "tls")]
+    if let Some(config) = tls::server_config()? {
+        let make_svc = make_service_fn(|_| async move {
+            Ok::<_, Infallible>(service_fn(move |req| serve_request(req)))
+        });
+        let server = Server::builder(tls_stream::bind(addr, config).await?).serve(make_svc);
+        info!(port = 8001, "HTTPS server started");
+        if let Err(e) = server.await {
+            tracing::error!(error = %e, "server error");
         }
+        return Ok(());
+    }
+    #[cfg(not(feature = "tls"))]
+    if std::env::var_os("TLS_CERT_PATH").is_some() {
+        return Err("sales_tax_rate_lookup was built without the `tls` feature".into());
+    }
+
+    let make_svc = make_service_fn(|_| async move {
+        Ok::<_, Infallible>(service_fn(move |req| serve_request(req)))
     });
     let server = Server::bind(&addr).serve(make_svc);
-    dbg!("Server started on port 8001");
+    info!(port = 8001, "server started");
     if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
+        tracing::error!(error = %e, "server error");
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use contract::Contract;
+
+    /// What order_total expects of this service. The rate tables are compiled
+    /// in, so the provider states of its interactions always hold.
+    const ORDER_TOTAL_CONTRACT: &str =
+        include_str!("../../order_total/contracts/sales_tax_rate.json");
+
+    #[tokio::test]
+    async fn honours_the_order_total_contract() {
+        let contract = Contract::parse(ORDER_TOTAL_CONTRACT).unwrap();
+        let mut failures = Vec::new();
+        for interaction in &contract.interactions {
+            let expected = &interaction.request;
+            let mut request = Request::builder()
+                .method(expected.method.as_str())
+                .uri(expected.path.as_str());
+            for (name, value) in &expected.headers {
+                request = request.header(name.as_str(), value.as_str());
+            }
+            let body = expected
+                .body
+                .as_ref()
+                .map(|body| body.to_string())
+                .unwrap_or_default();
+            let response = serve_request(request.body(Body::from(body)).unwrap())
+                .await
+                .unwrap();
+
+            let status = response.status().as_u16();
+            let headers = response.headers().clone();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let header = |name: &str| {
+                headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from)
+            };
+            let mismatches = interaction.response.mismatches(status, header, &body);
+            if !mismatches.is_empty() {
+                failures.push(format!(
+                    "{}: {}",
+                    interaction.description,
+                    mismatches.join(", ")
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,39 @@
+//! HTTPS, configured from the environment. With a client CA, TLS is mutual:
+//! only callers presenting a certificate it signed, such as order_total, get
+//! through.
+
+use anyhow::Context;
+use std::env;
+use std::sync::Arc;
+use tls_stream::rustls::server::WebPkiClientVerifier;
+use tls_stream::rustls::{RootCertStore, ServerConfig};
+use tls_stream::{load_certs, load_key};
+
+/// The server configuration of `TLS_CERT_PATH` and `TLS_KEY_PATH`, verifying
+/// client certificates against `TLS_CLIENT_CA_PATH` when set. `None` when no
+/// certificate is configured.
+pub fn server_config() -> anyhow::Result<Option<Arc<ServerConfig>>> {
+    let (cert_path, key_path) = match (env::var("TLS_CERT_PATH"), env::var("TLS_KEY_PATH")) {
+        (Ok(cert_path), Ok(key_path)) => (cert_path, key_path),
+        (Err(_), Err(_)) => return Ok(None),
+        _ => anyhow::bail!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+    };
+    let builder = ServerConfig::builder();
+    let builder = match env::var("TLS_CLIENT_CA_PATH") {
+        Ok(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(&ca_path)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("invalid CA certificate in {}", ca_path))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        Err(_) => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(load_certs(&cert_path)?, load_key(&key_path)?)
+        .context("invalid TLS certificate or key")?;
+    Ok(Some(Arc::new(config)))
+}
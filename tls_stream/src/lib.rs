@@ -0,0 +1,181 @@
+//! TLS over the `tokio_wasi` fork for hyper servers. tokio-rustls is written
+//! against tokio, so the stream wrapping a rustls connection is implemented
+//! here, along with loading certificates and keys from PEM files.
+
+use anyhow::{bail, Context as _};
+use futures::stream;
+use hyper::server::accept::{self, Accept};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+pub use rustls;
+
+/// The certificates of a PEM file.
+pub fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let mut reader =
+        BufReader::new(File::open(path).with_context(|| format!("cannot open {}", path))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("cannot read certificates from {}", path))?;
+    if certs.is_empty() {
+        bail!("no certificate in {}", path);
+    }
+    Ok(certs)
+}
+
+/// The first private key of a PEM file.
+pub fn load_key(path: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let mut reader =
+        BufReader::new(File::open(path).with_context(|| format!("cannot open {}", path))?);
+    rustls_pemfile::private_key(&mut reader)
+        .with_context(|| format!("cannot read the private key from {}", path))?
+        .with_context(|| format!("no private key in {}", path))
+}
+
+/// Accepts connections on `addr`; the TLS handshake happens on the first
+/// reads and writes of each connection, so a slow client doesn't hold up the
+/// others.
+pub async fn bind(
+    addr: SocketAddr,
+    config: Arc<ServerConfig>,
+) -> io::Result<impl Accept<Conn = TlsStream, Error = io::Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    let incoming = stream::unfold(listener, move |listener| {
+        let config = config.clone();
+        async move {
+            let accepted = match listener.accept().await {
+                Ok((io, remote_addr)) => ServerConnection::new(config)
+                    .map(|conn| TlsStream::new(io, conn, remote_addr))
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err)),
+                Err(err) => Err(err),
+            };
+            Some((accepted, listener))
+        }
+    });
+    Ok(accept::from_stream(incoming))
+}
+
+/// A server-side TLS connection over TCP.
+pub struct TlsStream {
+    io: TcpStream,
+    conn: ServerConnection,
+    remote_addr: SocketAddr,
+    /// TLS records produced by rustls but not yet written to the socket.
+    outgoing: Vec<u8>,
+}
+
+impl TlsStream {
+    fn new(io: TcpStream, conn: ServerConnection, remote_addr: SocketAddr) -> Self {
+        Self {
+            io,
+            conn,
+            remote_addr,
+            outgoing: Vec::new(),
+        }
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// Writes out every TLS record rustls has ready.
+    fn poll_write_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            while !self.outgoing.is_empty() {
+                let written = ready!(Pin::new(&mut self.io).poll_write(cx, &self.outgoing))?;
+                if written == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                self.outgoing.drain(..written);
+            }
+            if !self.conn.wants_write() {
+                return Poll::Ready(Ok(()));
+            }
+            self.conn.write_tls(&mut self.outgoing)?;
+        }
+    }
+
+    /// Reads TLS records from the socket; `false` at the end of the stream.
+    fn poll_read_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        let mut buf = [0; 8192];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        ready!(Pin::new(&mut self.io).poll_read(cx, &mut read_buf))?;
+        let mut records = read_buf.filled();
+        if records.is_empty() {
+            return Poll::Ready(Ok(false));
+        }
+        while !records.is_empty() {
+            self.conn.read_tls(&mut records)?;
+            self.conn
+                .process_new_packets()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        }
+        Poll::Ready(Ok(true))
+    }
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            match this.conn.reader().read(buf.initialize_unfilled()) {
+                Ok(read) => {
+                    buf.advance(read);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+            // Handshake messages have to go out before more comes in.
+            if let Poll::Ready(Err(err)) = this.poll_write_tls(cx) {
+                return Poll::Ready(Err(err));
+            }
+            if !ready!(this.poll_read_tls(cx))? {
+                // The peer closed the connection without a close_notify.
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let written = this.conn.writer().write(buf)?;
+        // The data is taken; sending it may finish on the next flush.
+        if let Poll::Ready(Err(err)) = this.poll_write_tls(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.conn.writer().flush()?;
+        ready!(this.poll_write_tls(cx))?;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.conn.send_close_notify();
+        ready!(this.poll_write_tls(cx))?;
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}